@@ -1,6 +1,7 @@
 #![allow(unexpected_cfgs)]
 
 use crate::error::{AppError, Result};
+use crate::llm::structured::{object_schema, string_array, StructuredOutput};
 use crate::llm::LlmClient;
 use serde::{Deserialize, Serialize};
 use std::path::Path;
@@ -600,23 +601,14 @@ impl AudioTranscriber {
             3. Decisions made\n\
             4. Next steps\n\
             5. Participants (if identifiable)\n\
-            Format as JSON.",
+            Use empty lists for anything the transcription does not mention.",
             transcription
         );
 
-        match self.llm_client.send_request(&prompt).await {
-            Ok(_response) => {
-                // Parse the response as JSON or create a structured summary
-                Ok(MeetingSummary {
-                    key_topics: vec!["Meeting topics".to_string()],
-                    action_items: vec![],
-                    decisions: vec![],
-                    next_steps: vec![],
-                    participants: vec![],
-                })
-            }
-            Err(e) => Err(AppError::Audio(format!("Failed to generate summary: {e}"))),
-        }
+        self.llm_client
+            .generate_structured::<MeetingSummary>(&prompt)
+            .await
+            .map_err(|e| AppError::Audio(format!("Failed to generate summary: {e}")))
     }
 }
 
@@ -636,6 +628,28 @@ pub struct ActionItem {
     pub due_date: Option<String>,
 }
 
+impl StructuredOutput for MeetingSummary {
+    fn schema_name() -> &'static str {
+        "meeting_summary"
+    }
+
+    fn json_schema() -> serde_json::Value {
+        let optional_string = serde_json::json!({ "type": ["string", "null"] });
+        let action_item = object_schema(&[
+            ("description", serde_json::json!({ "type": "string" })),
+            ("assignee", optional_string.clone()),
+            ("due_date", optional_string),
+        ]);
+        object_schema(&[
+            ("key_topics", string_array()),
+            ("action_items", serde_json::json!({ "type": "array", "items": action_item })),
+            ("decisions", string_array()),
+            ("next_steps", string_array()),
+            ("participants", string_array()),
+        ])
+    }
+}
+
 // Audio file information
 #[derive(Debug)]
 #[allow(dead_code)]
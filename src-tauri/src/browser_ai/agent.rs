@@ -1,5 +1,7 @@
 use super::{ChromeController, ScraperEngine};
 use crate::error::{AppError, Result};
+use crate::llm::options::GenerationOptions;
+use crate::llm::structured::{object_schema, string_array, StructuredOutput};
 use crate::llm::LlmClient;
 use crate::models::{
     BrowserAIProgress, BrowserAIProgressLight, BrowserAINewResult, PhaseDetails, ResearchResult, 
@@ -10,6 +12,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -23,6 +26,24 @@ struct ResearchPlan {
     is_time_sensitive: bool,
 }
 
+impl StructuredOutput for ResearchPlan {
+    fn schema_name() -> &'static str {
+        "research_plan"
+    }
+
+    fn json_schema() -> serde_json::Value {
+        let boolean = serde_json::json!({ "type": "boolean" });
+        object_schema(&[
+            ("main_topic", serde_json::json!({ "type": "string" })),
+            ("category", serde_json::json!({ "type": "string" })),
+            ("subtopics", string_array()),
+            ("search_queries", string_array()),
+            ("requires_browser", boolean.clone()),
+            ("is_time_sensitive", boolean),
+        ])
+    }
+}
+
 pub struct BrowserAIAgent {
     scraper: ScraperEngine,
     chrome: ChromeController,
@@ -49,15 +70,20 @@ impl BrowserAIAgent {
         }
     }
 
+    /// Run a research task to completion.
+    ///
+    /// `cancel` is checked between subtasks; a cancelled task is kept with the
+    /// results gathered so far and status `Cancelled`.
     pub async fn start_research(
         &mut self,
+        task_id: Uuid,
         query: String,
         progress_sender: mpsc::Sender<BrowserAIProgressLight>,
         result_sender: Option<mpsc::Sender<BrowserAINewResult>>,
+        cancel: CancellationToken,
     ) -> Result<Uuid> {
         println!("Agent: Starting research for: {query}");
 
-        let task_id = Uuid::new_v4();
         let now = Utc::now();
 
         let mut task = ResearchTask {
@@ -139,6 +165,10 @@ impl BrowserAIAgent {
 
         let total_subtasks = task.subtasks.len();
         for i in 0..total_subtasks {
+            if cancel.is_cancelled() {
+                return self.finish_cancelled(task, &progress_sender).await;
+            }
+
             let subtask_query = task.subtasks[i].query.clone();
 
             // Update progress for current search
@@ -214,6 +244,10 @@ impl BrowserAIAgent {
         let subtasks_count = task.subtasks.len();
 
         for i in 0..subtasks_count {
+            if cancel.is_cancelled() {
+                return self.finish_cancelled(task, &progress_sender).await;
+            }
+
             let subtask_id = task.subtasks[i].id;
             let subtask_query = task.subtasks[i].query.clone();
             let search_results = task.subtasks[i].search_results.clone();
@@ -298,6 +332,10 @@ impl BrowserAIAgent {
             task.subtasks[i].status = TaskStatus::Completed;
         }
 
+        if cancel.is_cancelled() {
+            return self.finish_cancelled(task, &progress_sender).await;
+        }
+
         // Step 4: Synthesize results
         task.status = TaskStatus::Analyzing;
         let _ = self
@@ -343,6 +381,34 @@ impl BrowserAIAgent {
         Ok(task_id)
     }
 
+    /// Stop a cancelled task, keeping the results gathered so far
+    async fn finish_cancelled(
+        &mut self,
+        mut task: ResearchTask,
+        progress_sender: &mpsc::Sender<BrowserAIProgressLight>,
+    ) -> Result<Uuid> {
+        println!("[BrowserAIAgent] Research task {} cancelled", task.id);
+
+        task.status = TaskStatus::Cancelled;
+        task.updated_at = Utc::now();
+        let _ = self
+            .send_light_progress(
+                &task,
+                progress_sender,
+                Some("Research cancelled".to_string()),
+                Some(PhaseDetails {
+                    phase: "Cancelled".to_string(),
+                    details: format!("Stopped after finding {} results", task.results.len()),
+                    estimated_completion: None,
+                }),
+            )
+            .await;
+
+        let task_id = task.id;
+        self.active_tasks.insert(task_id, task);
+        Ok(task_id)
+    }
+
     pub fn get_task(&self, task_id: &Uuid) -> Option<&ResearchTask> {
         self.active_tasks.get(task_id)
     }
@@ -407,23 +473,13 @@ impl BrowserAIAgent {
             Now create a plan for: '{query}'"
         );
 
-        match self.llm_client.send_request(&prompt).await {
-            Ok(response) => {
-                println!("[Research Plan] LLM Response: {}", response);
-                match serde_json::from_str::<ResearchPlan>(&self.extract_json(&response)?) {
-                    Ok(plan) => {
-                        println!("[Research Plan] Successfully parsed plan with {} search queries", plan.search_queries.len());
-                        Ok(plan)
-                    },
-                    Err(e) => {
-                        println!("Failed to parse LLM response, using fallback: {e}");
-                        println!("Response was: {}", response);
-                        Ok(self.create_fallback_plan(query))
-                    }
-                }
+        match self.llm_client.generate_structured::<ResearchPlan>(&prompt).await {
+            Ok(plan) => {
+                println!("[Research Plan] Successfully parsed plan with {} search queries", plan.search_queries.len());
+                Ok(plan)
             }
             Err(e) => {
-                println!("LLM request failed, using fallback plan: {e}");
+                println!("LLM did not return a usable plan, using fallback plan: {e}");
                 Ok(self.create_fallback_plan(query))
             }
         }
@@ -559,7 +615,9 @@ impl BrowserAIAgent {
             content.chars().take(5000).collect::<String>()
         );
 
-        match self.llm_client.send_request(&prompt).await {
+        // Room for the 500 words asked for above
+        let options = GenerationOptions::default().with_max_tokens(800);
+        match self.llm_client.send_request_with_options(&prompt, &options).await {
             Ok(extracted) => Ok(extracted),
             Err(_) => {
                 // Fallback: Return first 500 chars of content
@@ -590,7 +648,9 @@ impl BrowserAIAgent {
             task.query
         );
 
-        match self.llm_client.send_request(&prompt).await {
+        // A synthesis covers every source, so it needs more than the default limit
+        let options = GenerationOptions::default().with_max_tokens(1500);
+        match self.llm_client.send_request_with_options(&prompt, &options).await {
             Ok(synthesis) => Ok(synthesis),
             Err(_) => {
                 // Fallback synthesis
@@ -789,33 +849,6 @@ impl BrowserAIAgent {
         }
     }
 
-    fn extract_json(&self, text: &str) -> Result<String> {
-        // Try to extract JSON from the response
-        // LLMs sometimes wrap JSON in markdown code blocks
-        let cleaned = if text.contains("```json") {
-            text.split("```json")
-                .nth(1)
-                .and_then(|s| s.split("```").next())
-                .unwrap_or(text)
-        } else if text.contains("```") {
-            text.split("```")
-                .nth(1)
-                .and_then(|s| s.split("```").next())
-                .unwrap_or(text)
-        } else {
-            text
-        };
-
-        // Find JSON object in the text
-        if let Some(start) = cleaned.find('{') {
-            if let Some(end) = cleaned.rfind('}') {
-                return Ok(cleaned[start..=end].to_string());
-            }
-        }
-
-        Ok(cleaned.trim().to_string())
-    }
-
     async fn send_progress(
         &mut self,
         task: &ResearchTask,
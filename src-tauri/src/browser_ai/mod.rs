@@ -1,8 +1,9 @@
 mod agent;
 mod chrome;
+mod readability;
 mod scraper;
 mod cdp_client;
 
 pub use agent::BrowserAIAgent;
 pub use chrome::ChromeController;
-pub use scraper::ScraperEngine;
+pub use scraper::{ScraperEngine, WebPage};
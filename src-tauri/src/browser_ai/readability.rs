@@ -0,0 +1,368 @@
+//! Main content of web pages, found the way reader modes find it:
+//! paragraphs score the elements that contain them by their length and
+//! commas, and the container with the most text that is not links wins.
+
+use scraper::{ElementRef, Html, Node, Selector};
+use std::collections::HashMap;
+
+/// The readable part of a web page
+#[derive(Debug, Clone)]
+pub struct Article {
+    pub title: String,
+    /// Main content as markdown, keeping headings, paragraphs, lists,
+    /// quotes and code blocks
+    pub content: String,
+}
+
+/// Elements that never hold the main content
+const SKIPPED_TAGS: &[&str] = &[
+    "head", "script", "style", "noscript", "template", "svg", "canvas", "iframe", "form",
+    "button", "select", "nav", "header", "footer", "aside", "dialog",
+];
+
+/// Words in classes and ids of elements that hold the content
+const POSITIVE_HINTS: &[&str] = &[
+    "article", "body", "content", "entry", "main", "page", "post", "text", "blog", "story",
+];
+
+/// Words in classes and ids of elements around the content
+const NEGATIVE_HINTS: &[&str] = &[
+    "comment", "footer", "sidebar", "nav", "menu", "ads", "advert", "share", "social",
+    "related", "promo", "banner", "sponsor", "widget", "cookie", "popup", "masthead",
+    "breadcrumb", "pagination", "newsletter", "subscribe",
+];
+
+/// Paragraphs shorter than this are not counted towards their container
+const MIN_PARAGRAPH_LEN: usize = 25;
+
+/// Title and main content of the page in `html`
+pub fn extract_article(html: &str) -> Article {
+    let document = Html::parse_document(html);
+    let content = match main_content(&document) {
+        Some(blocks) => {
+            let mut renderer = Renderer::default();
+            for block in blocks {
+                renderer.element(block, true);
+            }
+            renderer.finish()
+        }
+        None => String::new(),
+    };
+
+    Article {
+        title: page_title(&document),
+        content,
+    }
+}
+
+/// The top-scoring container and the siblings that score nearly as well,
+/// falling back to `article`, `main` or the body
+fn main_content(document: &Html) -> Option<Vec<ElementRef<'_>>> {
+    let paragraphs = Selector::parse("p, pre, td, blockquote").unwrap();
+    let mut scores = HashMap::new();
+
+    for paragraph in document.select(&paragraphs) {
+        if is_skipped(paragraph) {
+            continue;
+        }
+        let text = collapsed_text(paragraph);
+        let length = text.chars().count();
+        if length < MIN_PARAGRAPH_LEN {
+            continue;
+        }
+        let score = 1.0 + text.matches(',').count() as f32 + (length / 100).min(3) as f32;
+
+        // The parent gets the full score, further ancestors less
+        for (level, ancestor) in paragraph
+            .ancestors()
+            .filter_map(ElementRef::wrap)
+            .take(3)
+            .enumerate()
+        {
+            let share = match level {
+                0 => 1.0,
+                1 => 2.0,
+                level => level as f32 * 3.0,
+            };
+            *scores
+                .entry(ancestor.id())
+                .or_insert_with(|| initial_score(ancestor)) += score / share;
+        }
+    }
+
+    // Containers full of links are navigation rather than content
+    let candidates: Vec<(ElementRef, f32)> = scores
+        .into_iter()
+        .filter_map(|(id, score)| {
+            let element = ElementRef::wrap(document.tree.get(id)?)?;
+            Some((element, score * (1.0 - link_density(element))))
+        })
+        .collect();
+
+    let Some(&(top, top_score)) = candidates
+        .iter()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+    else {
+        let fallback = Selector::parse("article, main, [role='main'], body").unwrap();
+        return document.select(&fallback).next().map(|element| vec![element]);
+    };
+
+    // Articles split over sibling containers keep all of their parts
+    let threshold = (top_score * 0.2).max(10.0);
+    let Some(parent) = top.parent().and_then(ElementRef::wrap) else {
+        return Some(vec![top]);
+    };
+    Some(
+        parent
+            .child_elements()
+            .filter(|sibling| {
+                sibling.id() == top.id()
+                    || candidates
+                        .iter()
+                        .any(|(candidate, score)| candidate.id() == sibling.id() && *score >= threshold)
+                    || (sibling.value().name() == "p"
+                        && collapsed_text(*sibling).chars().count() > 80
+                        && link_density(*sibling) < 0.25)
+            })
+            .collect(),
+    )
+}
+
+/// Starting score of a container, from its tag and its classes and id
+fn initial_score(element: ElementRef) -> f32 {
+    let tag_score = match element.value().name() {
+        "article" => 10.0,
+        "div" => 5.0,
+        "pre" | "td" | "blockquote" => 3.0,
+        "address" | "ol" | "ul" | "dl" | "dd" | "dt" | "li" | "form" => -3.0,
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" | "th" => -5.0,
+        _ => 0.0,
+    };
+    tag_score + class_weight(element)
+}
+
+/// +25 for a class or id that suggests content, -25 for one that suggests
+/// navigation, comments or ads
+fn class_weight(element: ElementRef) -> f32 {
+    let mut weight = 0.0;
+    for name in [element.value().attr("class"), element.value().id()]
+        .into_iter()
+        .flatten()
+    {
+        let words: Vec<String> = name
+            .split(|ch: char| !ch.is_ascii_alphanumeric())
+            .filter(|word| !word.is_empty())
+            .map(|word| word.to_lowercase())
+            .collect();
+        let has = |hints: &[&str]| {
+            words
+                .iter()
+                .any(|word| hints.iter().any(|hint| word.starts_with(hint)))
+        };
+        if has(NEGATIVE_HINTS) {
+            weight -= 25.0;
+        }
+        if has(POSITIVE_HINTS) {
+            weight += 25.0;
+        }
+    }
+    weight
+}
+
+/// Share of the element's text that is link text
+fn link_density(element: ElementRef) -> f32 {
+    let length = collapsed_text(element).chars().count();
+    if length == 0 {
+        return 0.0;
+    }
+    let links = Selector::parse("a").unwrap();
+    let link_length: usize = element
+        .select(&links)
+        .map(|link| collapsed_text(link).chars().count())
+        .sum();
+    link_length as f32 / length as f32
+}
+
+fn is_skipped(element: ElementRef) -> bool {
+    std::iter::once(element)
+        .chain(element.ancestors().filter_map(ElementRef::wrap))
+        .any(is_hidden_or_chrome)
+}
+
+/// Whether the element is scripts, navigation, page chrome or hidden
+fn is_hidden_or_chrome(element: ElementRef) -> bool {
+    let value = element.value();
+    SKIPPED_TAGS.contains(&value.name())
+        || value.attr("hidden").is_some()
+        || value.attr("aria-hidden") == Some("true")
+        || value
+            .attr("style")
+            .is_some_and(|style| style.replace(' ', "").contains("display:none"))
+}
+
+fn collapsed_text(element: ElementRef) -> String {
+    element.text().collect::<String>().split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Title from the Open Graph tags, the document title or the first heading
+fn page_title(document: &Html) -> String {
+    let og_title = Selector::parse("meta[property='og:title']").unwrap();
+    let title = Selector::parse("title").unwrap();
+    let heading = Selector::parse("h1").unwrap();
+
+    document
+        .select(&og_title)
+        .filter_map(|meta| meta.value().attr("content"))
+        .map(|content| content.split_whitespace().collect::<Vec<_>>().join(" "))
+        .chain(document.select(&title).map(collapsed_text))
+        .chain(document.select(&heading).map(collapsed_text))
+        .find(|title| !title.is_empty())
+        .unwrap_or_default()
+}
+
+/// Writes elements out as markdown blocks
+#[derive(Default)]
+struct Renderer {
+    blocks: Vec<String>,
+    line: String,
+    /// Prefix of the block being written, such as `## ` or `- `
+    prefix: String,
+}
+
+impl Renderer {
+    /// Render `element` unless it is hidden, page chrome, or a list or box
+    /// of links inside the content
+    fn element(&mut self, element: ElementRef, is_root: bool) {
+        if is_hidden_or_chrome(element) {
+            return;
+        }
+        let name = element.value().name();
+        if !is_root
+            && matches!(name, "ul" | "ol" | "div" | "section" | "table")
+            && (class_weight(element) < 0.0 || link_density(element) > 0.5)
+        {
+            return;
+        }
+
+        match name {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.end_block();
+                let level = name[1..].parse().unwrap_or(1);
+                self.prefix = format!("{} ", "#".repeat(level));
+                self.children(element);
+                self.end_block();
+            }
+            "li" => {
+                self.end_block();
+                self.prefix = "- ".to_string();
+                self.children(element);
+                self.end_block();
+            }
+            "pre" => {
+                self.end_block();
+                let code = element.text().collect::<String>();
+                if !code.trim().is_empty() {
+                    self.blocks.push(format!("```\n{}\n```", code.trim_matches('\n')));
+                }
+            }
+            "blockquote" => {
+                self.end_block();
+                let mut quote = Renderer::default();
+                quote.children(element);
+                let quote = quote.finish();
+                if !quote.is_empty() {
+                    let quoted: Vec<String> = quote.lines().map(|line| format!("> {line}").trim_end().to_string()).collect();
+                    self.blocks.push(quoted.join("\n"));
+                }
+            }
+            "br" => self.end_block(),
+            "td" | "th" => {
+                self.text(" ");
+                self.children(element);
+                self.text(" ");
+            }
+            "p" | "div" | "section" | "article" | "main" | "figure" | "figcaption" | "tr"
+            | "ul" | "ol" | "dl" | "dt" | "dd" | "table" | "hr" | "address" | "details"
+            | "summary" => {
+                self.end_block();
+                self.children(element);
+                self.end_block();
+            }
+            _ => self.children(element),
+        }
+    }
+
+    fn children(&mut self, element: ElementRef) {
+        for child in element.children() {
+            match child.value() {
+                Node::Text(text) => self.text(text),
+                Node::Element(_) => {
+                    if let Some(child) = ElementRef::wrap(child) {
+                        self.element(child, false);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Add inline text, collapsing whitespace as browsers do
+    fn text(&mut self, text: &str) {
+        if text.starts_with(char::is_whitespace) && !self.line.ends_with(' ') {
+            self.line.push(' ');
+        }
+        let words: Vec<&str> = text.split_whitespace().collect();
+        self.line.push_str(&words.join(" "));
+        if !words.is_empty() && text.ends_with(char::is_whitespace) {
+            self.line.push(' ');
+        }
+    }
+
+    fn end_block(&mut self) {
+        let line = self.line.trim();
+        if !line.is_empty() {
+            self.blocks.push(format!("{}{line}", self.prefix));
+        }
+        self.line.clear();
+        self.prefix.clear();
+    }
+
+    fn finish(mut self) -> String {
+        self.end_block();
+        self.blocks.join("\n\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_article_keeps_content_and_drops_page_chrome() {
+        let html = r#"<html><head><title>Tide tables explained</title><script>var x = 1;</script></head>
+            <body>
+                <nav><a href="/">Home</a> <a href="/news">News</a></nav>
+                <div class="sidebar"><p>Popular posts, trending now, read more, subscribe today.</p></div>
+                <div id="main-content" class="post">
+                    <h2>How tides work</h2>
+                    <p>Tides rise and fall twice a day, driven by the moon, the sun and the shape of the coast.</p>
+                    <p>Tide tables list the times and heights of high and low water for each harbour.</p>
+                    <ul class="share"><li><a href="/x">Share on X</a></li></ul>
+                    <blockquote><p>Time and tide wait for no one, as the saying goes.</p></blockquote>
+                    <ul><li>Check the <a href="/local">local</a> table before sailing</li></ul>
+                </div>
+                <footer><p>Copyright 2024, all rights reserved, do not copy.</p></footer>
+            </body></html>"#;
+        let article = extract_article(html);
+
+        assert_eq!(article.title, "Tide tables explained");
+        assert_eq!(
+            article.content,
+            "## How tides work\n\n\
+             Tides rise and fall twice a day, driven by the moon, the sun and the shape of the coast.\n\n\
+             Tide tables list the times and heights of high and low water for each harbour.\n\n\
+             > Time and tide wait for no one, as the saying goes.\n\n\
+             - Check the local table before sailing"
+        );
+    }
+}
@@ -1,5 +1,17 @@
-use crate::error::Result;
-use scraper::{Html, Selector};
+use super::readability::extract_article;
+use crate::error::{AppError, Result};
+use chrono::{DateTime, Utc};
+use reqwest::header::CONTENT_TYPE;
+
+/// A web page reduced to its readable content
+#[derive(Debug, Clone)]
+pub struct WebPage {
+    pub url: String,
+    pub title: String,
+    /// Main content as markdown
+    pub content: String,
+    pub fetched_at: DateTime<Utc>,
+}
 
 pub struct ScraperEngine {
     // Playwright will be initialized on demand
@@ -17,40 +29,37 @@ impl ScraperEngine {
         let html = response.text().await?;
 
         // Parse HTML and extract main content
-        let document = Html::parse_document(&html);
-        let content = self.extract_content(&document);
-
-        Ok(content)
+        Ok(extract_article(&html).content)
     }
 
-    fn extract_content(&self, document: &Html) -> String {
-        // Try to find main content areas
-        let selectors = vec![
-            "main",
-            "article",
-            "[role='main']",
-            ".content",
-            "#content",
-            ".post",
-            ".entry-content",
-        ];
-
-        for selector_str in selectors {
-            if let Ok(selector) = Selector::parse(selector_str) {
-                if let Some(element) = document.select(&selector).next() {
-                    return element.text().collect::<Vec<_>>().join(" ");
-                }
-            }
+    /// Fetch `url` and reduce it to its title and readable content
+    pub async fn fetch_page(&self, url: &str) -> Result<WebPage> {
+        let response = reqwest::get(url).await?.error_for_status()?;
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or_default()
+            .to_lowercase();
+        if !content_type.is_empty() && !content_type.contains("html") {
+            return Err(AppError::InvalidInput(format!(
+                "{url} is not a web page ({content_type})"
+            )));
         }
 
-        // Fallback to body content
-        if let Ok(body_selector) = Selector::parse("body") {
-            if let Some(body) = document.select(&body_selector).next() {
-                return body.text().collect::<Vec<_>>().join(" ");
-            }
+        let html = response.text().await?;
+        let article = extract_article(&html);
+        if article.content.trim().is_empty() {
+            return Err(AppError::ProcessingError(format!(
+                "No readable content found at {url}"
+            )));
         }
 
-        // Last resort - return all text
-        document.root_element().text().collect::<Vec<_>>().join(" ")
+        Ok(WebPage {
+            url: url.to_string(),
+            title: article.title,
+            content: article.content,
+            fetched_at: Utc::now(),
+        })
     }
 }
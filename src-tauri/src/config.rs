@@ -11,6 +11,8 @@ pub struct Config {
     pub rag: RagConfig,
     pub audio: AudioConfig,
     pub database: DatabaseConfig,
+    #[serde(default)]
+    pub chat: ChatConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -23,15 +25,168 @@ pub struct ServicesConfig {
     pub candle_model_id: String,
     pub candle_model_revision: String,
     pub candle_cache_dir: String,
+    /// Base URL of an OpenAI-compatible server, including the `/v1` prefix
+    #[serde(default = "default_openai_base_url")]
+    pub openai_base_url: String,
+    #[serde(default = "default_openai_model")]
+    pub openai_model: String,
+    #[serde(default = "default_openai_embedding_model")]
+    pub openai_embedding_model: String,
+    #[serde(default = "default_openai_api_key")]
+    pub openai_api_key: Option<String>,
+    /// Providers tried in order when the configured one cannot answer
+    #[serde(default = "default_fallback_providers")]
+    pub fallback_providers: Vec<InferenceProvider>,
+    /// Consecutive failures before a provider is skipped for the cooldown
+    #[serde(default = "default_provider_failure_threshold")]
+    pub provider_failure_threshold: u32,
+    #[serde(default = "default_provider_cooldown_secs")]
+    pub provider_cooldown_secs: u64,
+    /// Context length used for prompt budgeting instead of the model's own
+    #[serde(default = "default_model_context_length")]
+    pub model_context_length: Option<usize>,
+    /// Where document and query embeddings come from. Unset follows the
+    /// inference provider: the OpenAI-compatible server when that is used,
+    /// Ollama otherwise.
+    #[serde(default = "default_embedding_provider")]
+    pub embedding_provider: Option<EmbeddingProvider>,
+    /// BERT-architecture model used when embeddings are computed with Candle
+    #[serde(default = "default_candle_embedding_model")]
+    pub candle_embedding_model: String,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+impl ServicesConfig {
+    /// The embedding provider in effect
+    pub fn embedding_provider(&self) -> EmbeddingProvider {
+        match (self.embedding_provider, self.inference_provider) {
+            (Some(provider), _) => provider,
+            (None, InferenceProvider::OpenAiCompatible) => EmbeddingProvider::OpenAiCompatible,
+            (None, _) => EmbeddingProvider::Ollama,
+        }
+    }
+}
+
+fn default_openai_base_url() -> String {
+    std::env::var("OPENAI_BASE_URL").unwrap_or_else(|_| "http://localhost:8080/v1".to_string())
+}
+
+fn default_openai_model() -> String {
+    std::env::var("OPENAI_MODEL").unwrap_or_else(|_| "local-model".to_string())
+}
+
+fn default_openai_embedding_model() -> String {
+    std::env::var("OPENAI_EMBEDDING_MODEL").unwrap_or_else(|_| "nomic-embed-text".to_string())
+}
+
+fn default_model_context_length() -> Option<usize> {
+    std::env::var("MODEL_CONTEXT_LENGTH").ok().and_then(|v| v.parse().ok())
+}
+
+fn default_embedding_provider() -> Option<EmbeddingProvider> {
+    std::env::var("EMBEDDING_PROVIDER")
+        .ok()
+        .and_then(|name| EmbeddingProvider::from_name(&name))
+}
+
+fn default_candle_embedding_model() -> String {
+    std::env::var("CANDLE_EMBEDDING_MODEL").unwrap_or_else(|_| "BAAI/bge-base-en-v1.5".to_string())
+}
+
+fn default_openai_api_key() -> Option<String> {
+    std::env::var("OPENAI_API_KEY").ok().filter(|key| !key.is_empty())
+}
+
+/// Comma separated provider names from `INFERENCE_FALLBACK`, Ollama by default
+fn default_fallback_providers() -> Vec<InferenceProvider> {
+    match std::env::var("INFERENCE_FALLBACK") {
+        Ok(names) => names
+            .split(',')
+            .filter_map(|name| InferenceProvider::from_name(name.trim()))
+            .collect(),
+        Err(_) => vec![InferenceProvider::Ollama],
+    }
+}
+
+fn default_provider_failure_threshold() -> u32 {
+    std::env::var("PROVIDER_FAILURE_THRESHOLD")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(3)
+}
+
+fn default_provider_cooldown_secs() -> u64 {
+    std::env::var("PROVIDER_COOLDOWN_SECS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(60)
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub enum InferenceProvider {
     Ollama,
     Candle,
     Crane,
     Callm,
     LlamaCpp,
+    OpenAiCompatible,
+}
+
+impl InferenceProvider {
+    /// Parse the provider names used in environment variables
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "candle" => Some(InferenceProvider::Candle),
+            "crane" => Some(InferenceProvider::Crane),
+            "callm" => Some(InferenceProvider::Callm),
+            "ollama" => Some(InferenceProvider::Ollama),
+            "llama_cpp" => Some(InferenceProvider::LlamaCpp),
+            "openai" => Some(InferenceProvider::OpenAiCompatible),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub enum EmbeddingProvider {
+    Ollama,
+    OpenAiCompatible,
+    Candle,
+}
+
+impl EmbeddingProvider {
+    /// Parse the provider names used in environment variables
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ollama" => Some(EmbeddingProvider::Ollama),
+            "openai" => Some(EmbeddingProvider::OpenAiCompatible),
+            "candle" => Some(EmbeddingProvider::Candle),
+            _ => None,
+        }
+    }
+}
+
+/// How search results are re-scored before they reach a prompt
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub enum RerankProvider {
+    /// Keep the retrieval order
+    #[default]
+    None,
+    /// Local BERT cross-encoder run with Candle
+    CrossEncoder,
+    /// Relevance ratings asked of the default chat model
+    Llm,
+}
+
+impl RerankProvider {
+    /// Parse the provider names used in environment variables
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "none" => Some(RerankProvider::None),
+            "cross-encoder" => Some(RerankProvider::CrossEncoder),
+            "llm" => Some(RerankProvider::Llm),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -51,6 +206,54 @@ pub struct RagConfig {
     pub chunk_overlap: usize,
     pub use_qdrant: bool,
     pub collection_name: String,
+    /// Chunks sent to the embedding backend in one request
+    #[serde(default = "default_embedding_batch_size")]
+    pub embedding_batch_size: usize,
+    /// Embedding requests in flight at once, across all documents
+    #[serde(default = "default_embedding_concurrency")]
+    pub embedding_concurrency: usize,
+    /// Re-scores search candidates before they are used
+    #[serde(default = "default_rerank_provider")]
+    pub rerank_provider: RerankProvider,
+    /// Cross-encoder used when `rerank_provider` is `CrossEncoder`
+    #[serde(default = "default_rerank_model")]
+    pub rerank_model: String,
+    /// Search results re-scored for every request, of which the best are kept
+    #[serde(default = "default_rerank_candidates")]
+    pub rerank_candidates: usize,
+}
+
+fn default_embedding_batch_size() -> usize {
+    std::env::var("RAG_EMBEDDING_BATCH_SIZE")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(32)
+}
+
+fn default_embedding_concurrency() -> usize {
+    std::env::var("RAG_EMBEDDING_CONCURRENCY")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(4)
+}
+
+fn default_rerank_provider() -> RerankProvider {
+    std::env::var("RAG_RERANK_PROVIDER")
+        .ok()
+        .and_then(|name| RerankProvider::from_name(&name))
+        .unwrap_or_default()
+}
+
+fn default_rerank_model() -> String {
+    std::env::var("RAG_RERANK_MODEL")
+        .unwrap_or_else(|_| "cross-encoder/ms-marco-MiniLM-L-6-v2".to_string())
+}
+
+fn default_rerank_candidates() -> usize {
+    std::env::var("RAG_RERANK_CANDIDATES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(20)
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -66,6 +269,29 @@ pub struct DatabaseConfig {
     pub enable_migrations: bool,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatConfig {
+    /// Share of the context left after the instructions and question that
+    /// earlier messages may use
+    #[serde(default = "default_history_share")]
+    pub history_share: f32,
+}
+
+fn default_history_share() -> f32 {
+    std::env::var("CHAT_HISTORY_SHARE")
+        .ok()
+        .and_then(|share| share.parse().ok())
+        .unwrap_or(0.5)
+}
+
+impl Default for ChatConfig {
+    fn default() -> Self {
+        Self {
+            history_share: default_history_share(),
+        }
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
@@ -78,14 +304,10 @@ impl Default for Config {
                     .unwrap_or_else(|_| "llama3.2:1b".to_string()),
                 ollama_embedding_model: std::env::var("OLLAMA_EMBEDDING_MODEL")
                     .unwrap_or_else(|_| "nomic-embed-text:latest".to_string()),
-                inference_provider: match std::env::var("INFERENCE_PROVIDER").as_deref() {
-                    Ok("candle") => InferenceProvider::Candle,
-                    Ok("crane") => InferenceProvider::Crane,
-                    Ok("callm") => InferenceProvider::Callm,
-                    Ok("ollama") => InferenceProvider::Ollama,
-                    Ok("llama_cpp") => InferenceProvider::LlamaCpp,
-                    _ => InferenceProvider::Callm, // Default to Callm
-                },
+                inference_provider: std::env::var("INFERENCE_PROVIDER")
+                    .ok()
+                    .and_then(|name| InferenceProvider::from_name(&name))
+                    .unwrap_or(InferenceProvider::Callm), // Default to Callm
                 candle_model_id: std::env::var("CANDLE_MODEL_ID")
                     .unwrap_or_else(|_| "TinyLlama/TinyLlama-1.1B-Chat-v1.0".to_string()),
                 candle_model_revision: std::env::var("CANDLE_MODEL_REVISION")
@@ -94,6 +316,16 @@ impl Default for Config {
                     .unwrap_or_else(|_| dirs::cache_dir()
                         .map(|d| d.join("huggingface").join("hub").to_string_lossy().to_string())
                         .unwrap_or_else(|| "./models".to_string())),
+                openai_base_url: default_openai_base_url(),
+                openai_model: default_openai_model(),
+                openai_embedding_model: default_openai_embedding_model(),
+                openai_api_key: default_openai_api_key(),
+                fallback_providers: default_fallback_providers(),
+                provider_failure_threshold: default_provider_failure_threshold(),
+                provider_cooldown_secs: default_provider_cooldown_secs(),
+                model_context_length: default_model_context_length(),
+                embedding_provider: default_embedding_provider(),
+                candle_embedding_model: default_candle_embedding_model(),
             },
             tracking: TrackingConfig {
                 enabled: std::env::var("TRACKING_ENABLED")
@@ -140,6 +372,11 @@ impl Default for Config {
                     .unwrap_or(false),
                 collection_name: std::env::var("QDRANT_COLLECTION_NAME")
                     .unwrap_or_else(|_| "documents".to_string()),
+                embedding_batch_size: default_embedding_batch_size(),
+                embedding_concurrency: default_embedding_concurrency(),
+                rerank_provider: default_rerank_provider(),
+                rerank_model: default_rerank_model(),
+                rerank_candidates: default_rerank_candidates(),
             },
             audio: AudioConfig {
                 sample_rate: std::env::var("AUDIO_SAMPLE_RATE")
@@ -163,6 +400,7 @@ impl Default for Config {
                     .parse()
                     .unwrap_or(true),
             },
+            chat: ChatConfig::default(),
         }
     }
 }
@@ -214,6 +452,10 @@ impl Config {
             errors.push("Invalid Qdrant URL format".to_string());
         }
         
+        if !self.services.openai_base_url.starts_with("http://") && !self.services.openai_base_url.starts_with("https://") {
+            errors.push("Invalid OpenAI-compatible base URL format".to_string());
+        }
+        
         // Validate numeric values
         if self.tracking.tracking_interval_ms == 0 {
             errors.push("Tracking interval must be greater than 0".to_string());
@@ -226,6 +468,18 @@ impl Config {
         if self.rag.chunk_overlap >= self.rag.chunk_size {
             errors.push("RAG chunk overlap must be less than chunk size".to_string());
         }
+
+        if self.rag.embedding_batch_size == 0 || self.rag.embedding_concurrency == 0 {
+            errors.push("RAG embedding batch size and concurrency must be greater than 0".to_string());
+        }
+
+        if self.rag.rerank_candidates == 0 {
+            errors.push("RAG rerank candidates must be greater than 0".to_string());
+        }
+        
+        if !(self.chat.history_share > 0.0 && self.chat.history_share <= 1.0) {
+            errors.push("Chat history share must be greater than 0 and at most 1".to_string());
+        }
         
         if self.audio.sample_rate == 0 {
             errors.push("Audio sample rate must be greater than 0".to_string());
@@ -286,6 +540,56 @@ impl Config {
                 } else {
                     file_config.services.candle_cache_dir
                 },
+                openai_base_url: if std::env::var("OPENAI_BASE_URL").is_ok() {
+                    env_config.services.openai_base_url
+                } else {
+                    file_config.services.openai_base_url
+                },
+                openai_model: if std::env::var("OPENAI_MODEL").is_ok() {
+                    env_config.services.openai_model
+                } else {
+                    file_config.services.openai_model
+                },
+                openai_embedding_model: if std::env::var("OPENAI_EMBEDDING_MODEL").is_ok() {
+                    env_config.services.openai_embedding_model
+                } else {
+                    file_config.services.openai_embedding_model
+                },
+                openai_api_key: if std::env::var("OPENAI_API_KEY").is_ok() {
+                    env_config.services.openai_api_key
+                } else {
+                    file_config.services.openai_api_key
+                },
+                fallback_providers: if std::env::var("INFERENCE_FALLBACK").is_ok() {
+                    env_config.services.fallback_providers
+                } else {
+                    file_config.services.fallback_providers
+                },
+                provider_failure_threshold: if std::env::var("PROVIDER_FAILURE_THRESHOLD").is_ok() {
+                    env_config.services.provider_failure_threshold
+                } else {
+                    file_config.services.provider_failure_threshold
+                },
+                provider_cooldown_secs: if std::env::var("PROVIDER_COOLDOWN_SECS").is_ok() {
+                    env_config.services.provider_cooldown_secs
+                } else {
+                    file_config.services.provider_cooldown_secs
+                },
+                model_context_length: if std::env::var("MODEL_CONTEXT_LENGTH").is_ok() {
+                    env_config.services.model_context_length
+                } else {
+                    file_config.services.model_context_length
+                },
+                embedding_provider: if std::env::var("EMBEDDING_PROVIDER").is_ok() {
+                    env_config.services.embedding_provider
+                } else {
+                    file_config.services.embedding_provider
+                },
+                candle_embedding_model: if std::env::var("CANDLE_EMBEDDING_MODEL").is_ok() {
+                    env_config.services.candle_embedding_model
+                } else {
+                    file_config.services.candle_embedding_model
+                },
             },
             tracking: TrackingConfig {
                 enabled: if std::env::var("TRACKING_ENABLED").is_ok() {
@@ -345,6 +649,31 @@ impl Config {
                 } else {
                     file_config.rag.collection_name
                 },
+                embedding_batch_size: if std::env::var("RAG_EMBEDDING_BATCH_SIZE").is_ok() {
+                    env_config.rag.embedding_batch_size
+                } else {
+                    file_config.rag.embedding_batch_size
+                },
+                embedding_concurrency: if std::env::var("RAG_EMBEDDING_CONCURRENCY").is_ok() {
+                    env_config.rag.embedding_concurrency
+                } else {
+                    file_config.rag.embedding_concurrency
+                },
+                rerank_provider: if std::env::var("RAG_RERANK_PROVIDER").is_ok() {
+                    env_config.rag.rerank_provider
+                } else {
+                    file_config.rag.rerank_provider
+                },
+                rerank_model: if std::env::var("RAG_RERANK_MODEL").is_ok() {
+                    env_config.rag.rerank_model
+                } else {
+                    file_config.rag.rerank_model
+                },
+                rerank_candidates: if std::env::var("RAG_RERANK_CANDIDATES").is_ok() {
+                    env_config.rag.rerank_candidates
+                } else {
+                    file_config.rag.rerank_candidates
+                },
             },
             audio: AudioConfig {
                 sample_rate: if std::env::var("AUDIO_SAMPLE_RATE").is_ok() {
@@ -375,6 +704,13 @@ impl Config {
                     file_config.database.enable_migrations
                 },
             },
+            chat: ChatConfig {
+                history_share: if std::env::var("CHAT_HISTORY_SHARE").is_ok() {
+                    env_config.chat.history_share
+                } else {
+                    file_config.chat.history_share
+                },
+            },
         }
     }
     
@@ -1,15 +1,35 @@
 use crate::error::{AppError, Result};
-use crate::rag::{Document, DocumentChunk};
+use crate::rag::{
+    content_hash, Collection, Document, DocumentChunk, IndexedFile, PathDetached, SearchFilters,
+    SearchResult, WatchedFolder, WebSource,
+};
 use chrono::{DateTime, Utc};
-use sqlx::{Row, SqlitePool};
+use sqlx::sqlite::{Sqlite, SqliteRow};
+use sqlx::{QueryBuilder, Row, SqlitePool};
 use std::collections::HashMap;
 use uuid::Uuid;
 
 pub async fn save_document(pool: &SqlitePool, document: &Document) -> Result<()> {
+    let pages_without_text = serde_json::to_string(&document.pages_without_text)
+        .map_err(|e| AppError::Database(format!("Failed to serialize pages: {}", e)))?;
+
+    // An upsert rather than INSERT OR REPLACE, whose implicit delete would
+    // cascade to the document's chunks. The file stamps saved by folder
+    // sync are left as they are.
     sqlx::query(
         r#"
-        INSERT OR REPLACE INTO documents (id, title, content, file_path, goal_id, created_at)
-        VALUES (?, ?, ?, ?, ?, ?)
+        INSERT INTO documents (id, title, content, file_path, goal_id, created_at, content_hash, pages_without_text, author, modified_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ON CONFLICT(id) DO UPDATE SET
+            title = excluded.title,
+            content = excluded.content,
+            file_path = excluded.file_path,
+            goal_id = excluded.goal_id,
+            created_at = excluded.created_at,
+            content_hash = excluded.content_hash,
+            pages_without_text = excluded.pages_without_text,
+            author = excluded.author,
+            modified_at = excluded.modified_at
     "#,
     )
     .bind(document.id.to_string())
@@ -18,27 +38,45 @@ pub async fn save_document(pool: &SqlitePool, document: &Document) -> Result<()>
     .bind(&document.file_path)
     .bind(document.goal_id.map(|id| id.to_string()))
     .bind(document.created_at.to_rfc3339())
+    .bind(&document.content_hash)
+    .bind(pages_without_text)
+    .bind(&document.author)
+    .bind(document.modified_at.map(|time| time.to_rfc3339()))
     .execute(pool)
     .await
     .map_err(|e| AppError::Database(format!("Failed to save document: {}", e)))?;
 
-    Ok(())
+    set_document_tags(pool, document.id, &document.tags).await?;
+    set_document_collections(pool, document.id, &document.collections).await
 }
 
 pub async fn save_document_chunk(pool: &SqlitePool, chunk: &DocumentChunk) -> Result<()> {
-    let embedding_json = serde_json::to_string(&chunk.embedding)
-        .map_err(|e| AppError::Database(format!("Failed to serialize embedding: {}", e)))?;
     let metadata_json = serde_json::to_string(&chunk.metadata)
         .map_err(|e| AppError::Database(format!("Failed to serialize metadata: {}", e)))?;
 
+    // An upsert rather than INSERT OR REPLACE, whose implicit delete would
+    // not reach the full-text index triggers
     sqlx::query(r#"
-        INSERT OR REPLACE INTO document_chunks (id, document_id, content, embedding, chunk_index, metadata)
-        VALUES (?, ?, ?, ?, ?, ?)
+        INSERT INTO document_chunks (id, document_id, content, content_hash, embedding, embedding_blob, embedding_model, embedding_dim, chunk_index, metadata)
+        VALUES (?, ?, ?, ?, '[]', ?, ?, ?, ?, ?)
+        ON CONFLICT(id) DO UPDATE SET
+            document_id = excluded.document_id,
+            content = excluded.content,
+            content_hash = excluded.content_hash,
+            embedding = excluded.embedding,
+            embedding_blob = excluded.embedding_blob,
+            embedding_model = excluded.embedding_model,
+            embedding_dim = excluded.embedding_dim,
+            chunk_index = excluded.chunk_index,
+            metadata = excluded.metadata
     "#)
     .bind(chunk.id.to_string())
     .bind(chunk.document_id.to_string())
     .bind(&chunk.content)
-    .bind(embedding_json)
+    .bind(&chunk.content_hash)
+    .bind(embedding_to_blob(&chunk.embedding))
+    .bind(&chunk.embedding_model)
+    .bind(chunk.embedding.len() as i64)
     .bind(chunk.chunk_index as i64)
     .bind(metadata_json)
     .execute(pool)
@@ -49,13 +87,26 @@ pub async fn save_document_chunk(pool: &SqlitePool, chunk: &DocumentChunk) -> Re
 }
 
 pub async fn load_documents(pool: &SqlitePool, goal_id: Option<Uuid>) -> Result<Vec<Document>> {
+    load_documents_with(pool, goal_id, true).await
+}
+
+/// All documents with their chunks, leaving out the chunk embeddings
+pub async fn load_documents_without_embeddings(pool: &SqlitePool) -> Result<Vec<Document>> {
+    load_documents_with(pool, None, false).await
+}
+
+async fn load_documents_with(
+    pool: &SqlitePool,
+    goal_id: Option<Uuid>,
+    embeddings: bool,
+) -> Result<Vec<Document>> {
     let rows = if let Some(goal_id) = goal_id {
-        sqlx::query("SELECT id, title, content, file_path, goal_id, created_at FROM documents WHERE goal_id = ?")
+        sqlx::query("SELECT id, title, content, file_path, goal_id, created_at, content_hash, pages_without_text, author, modified_at FROM documents WHERE goal_id = ?")
             .bind(goal_id.to_string())
             .fetch_all(pool)
             .await
     } else {
-        sqlx::query("SELECT id, title, content, file_path, goal_id, created_at FROM documents")
+        sqlx::query("SELECT id, title, content, file_path, goal_id, created_at, content_hash, pages_without_text, author, modified_at FROM documents")
             .fetch_all(pool)
             .await
     }.map_err(|e| AppError::Database(format!("Failed to load documents: {}", e)))?;
@@ -63,49 +114,239 @@ pub async fn load_documents(pool: &SqlitePool, goal_id: Option<Uuid>) -> Result<
     let mut documents = Vec::new();
 
     for row in rows {
-        let document_id_str: String = row.get("id");
-        let document_id = Uuid::parse_str(&document_id_str)
-            .map_err(|e| AppError::Database(format!("Invalid document ID: {}", e)))?;
-
-        let goal_id_str: Option<String> = row.get("goal_id");
-        let goal_id = if let Some(goal_str) = goal_id_str {
-            Some(
-                Uuid::parse_str(&goal_str)
-                    .map_err(|e| AppError::Database(format!("Invalid goal ID: {}", e)))?,
-            )
-        } else {
-            None
-        };
+        documents.push(document_from_row(pool, &row, embeddings).await?);
+    }
 
-        let created_at_str: String = row.get("created_at");
-        let created_at = DateTime::parse_from_rfc3339(&created_at_str)
-            .map_err(|e| AppError::Database(format!("Invalid created_at format: {}", e)))?
-            .with_timezone(&Utc);
+    Ok(documents)
+}
 
-        // Load chunks for this document
-        let chunks = load_document_chunks(pool, document_id).await?;
+pub async fn load_document(pool: &SqlitePool, document_id: Uuid) -> Result<Option<Document>> {
+    let row = sqlx::query("SELECT id, title, content, file_path, goal_id, created_at, content_hash, pages_without_text, author, modified_at FROM documents WHERE id = ?")
+        .bind(document_id.to_string())
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to load document: {}", e)))?;
 
-        let document = Document {
-            id: document_id,
-            title: row.get("title"),
-            content: row.get("content"),
-            file_path: row.get("file_path"),
-            goal_id,
-            chunks,
-            created_at,
-        };
+    match row {
+        Some(row) => Ok(Some(document_from_row(pool, &row, true).await?)),
+        None => Ok(None),
+    }
+}
+
+async fn document_from_row(pool: &SqlitePool, row: &SqliteRow, embeddings: bool) -> Result<Document> {
+    let document_id_str: String = row.get("id");
+    let document_id = Uuid::parse_str(&document_id_str)
+        .map_err(|e| AppError::Database(format!("Invalid document ID: {}", e)))?;
+
+    let goal_id_str: Option<String> = row.get("goal_id");
+    let goal_id = if let Some(goal_str) = goal_id_str {
+        Some(
+            Uuid::parse_str(&goal_str)
+                .map_err(|e| AppError::Database(format!("Invalid goal ID: {}", e)))?,
+        )
+    } else {
+        None
+    };
+
+    let created_at_str: String = row.get("created_at");
+    let created_at = DateTime::parse_from_rfc3339(&created_at_str)
+        .map_err(|e| AppError::Database(format!("Invalid created_at format: {}", e)))?
+        .with_timezone(&Utc);
+
+    let modified_at = row
+        .get::<Option<String>, _>("modified_at")
+        .map(|time| {
+            DateTime::parse_from_rfc3339(&time)
+                .map(|time| time.with_timezone(&Utc))
+                .map_err(|e| AppError::Database(format!("Invalid modified_at format: {}", e)))
+        })
+        .transpose()?;
+
+    // Load chunks for this document
+    let chunks = load_document_chunks(pool, document_id, embeddings).await?;
+    let (tags, collections) = load_document_labels(pool, document_id).await?;
+
+    Ok(Document {
+        id: document_id,
+        title: row.get("title"),
+        content: row.get("content"),
+        file_path: row.get("file_path"),
+        goal_id,
+        chunks,
+        created_at,
+        content_hash: row
+            .get::<Option<String>, _>("content_hash")
+            .unwrap_or_default(),
+        pages_without_text: row
+            .get::<Option<String>, _>("pages_without_text")
+            .and_then(|pages| serde_json::from_str(&pages).ok())
+            .unwrap_or_default(),
+        author: row.get("author"),
+        modified_at,
+        tags,
+        collections,
+    })
+}
+
+/// Tags and collection ids of a document
+pub async fn load_document_labels(
+    pool: &SqlitePool,
+    document_id: Uuid,
+) -> Result<(Vec<String>, Vec<Uuid>)> {
+    let tags: Vec<String> =
+        sqlx::query_scalar("SELECT tag FROM document_tags WHERE document_id = ? ORDER BY tag")
+            .bind(document_id.to_string())
+            .fetch_all(pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to load document tags: {}", e)))?;
+
+    let collection_ids: Vec<String> = sqlx::query_scalar(
+        "SELECT collection_id FROM document_collections WHERE document_id = ?",
+    )
+    .bind(document_id.to_string())
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to load document collections: {}", e)))?;
+    let collections = collection_ids
+        .iter()
+        .map(|id| {
+            Uuid::parse_str(id).map_err(|e| AppError::Database(format!("Invalid collection ID: {}", e)))
+        })
+        .collect::<Result<Vec<Uuid>>>()?;
+
+    Ok((tags, collections))
+}
+
+/// Replace the tags of a document
+pub async fn set_document_tags(pool: &SqlitePool, document_id: Uuid, tags: &[String]) -> Result<()> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to start transaction: {}", e)))?;
+    sqlx::query("DELETE FROM document_tags WHERE document_id = ?")
+        .bind(document_id.to_string())
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to clear document tags: {}", e)))?;
+    for tag in tags {
+        sqlx::query("INSERT OR IGNORE INTO document_tags (document_id, tag) VALUES (?, ?)")
+            .bind(document_id.to_string())
+            .bind(tag)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to save document tag: {}", e)))?;
+    }
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to commit transaction: {}", e)))
+}
 
-        documents.push(document);
+/// Replace the collections a document is in
+pub async fn set_document_collections(
+    pool: &SqlitePool,
+    document_id: Uuid,
+    collection_ids: &[Uuid],
+) -> Result<()> {
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to start transaction: {}", e)))?;
+    sqlx::query("DELETE FROM document_collections WHERE document_id = ?")
+        .bind(document_id.to_string())
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to clear document collections: {}", e)))?;
+    for collection_id in collection_ids {
+        sqlx::query(
+            "INSERT OR IGNORE INTO document_collections (document_id, collection_id) VALUES (?, ?)",
+        )
+        .bind(document_id.to_string())
+        .bind(collection_id.to_string())
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to add document to collection: {}", e)))?;
     }
+    tx.commit()
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to commit transaction: {}", e)))
+}
 
-    Ok(documents)
+pub async fn save_collection(pool: &SqlitePool, collection: &Collection) -> Result<()> {
+    sqlx::query("INSERT OR REPLACE INTO collections (id, name, created_at) VALUES (?, ?, ?)")
+        .bind(collection.id.to_string())
+        .bind(&collection.name)
+        .bind(collection.created_at.to_rfc3339())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to save collection: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn load_collections(pool: &SqlitePool) -> Result<Vec<Collection>> {
+    let rows = sqlx::query("SELECT id, name, created_at FROM collections ORDER BY name COLLATE NOCASE")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to load collections: {}", e)))?;
+
+    let mut collections = Vec::new();
+    for row in rows {
+        let id: String = row.get("id");
+        let created_at: String = row.get("created_at");
+        collections.push(Collection {
+            id: Uuid::parse_str(&id)
+                .map_err(|e| AppError::Database(format!("Invalid collection ID: {}", e)))?,
+            name: row.get("name"),
+            created_at: DateTime::parse_from_rfc3339(&created_at)
+                .map_err(|e| AppError::Database(format!("Invalid created_at format: {}", e)))?
+                .with_timezone(&Utc),
+        });
+    }
+
+    Ok(collections)
+}
+
+/// Delete a collection, returning the documents that were in it. The
+/// documents themselves stay indexed.
+pub async fn delete_collection(pool: &SqlitePool, collection_id: Uuid) -> Result<Vec<Uuid>> {
+    let document_ids: Vec<String> =
+        sqlx::query_scalar("SELECT document_id FROM document_collections WHERE collection_id = ?")
+            .bind(collection_id.to_string())
+            .fetch_all(pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to load collection members: {}", e)))?;
+
+    sqlx::query("DELETE FROM document_collections WHERE collection_id = ?")
+        .bind(collection_id.to_string())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to empty collection: {}", e)))?;
+    sqlx::query("DELETE FROM collections WHERE id = ?")
+        .bind(collection_id.to_string())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to delete collection: {}", e)))?;
+
+    document_ids
+        .iter()
+        .map(|id| {
+            Uuid::parse_str(id).map_err(|e| AppError::Database(format!("Invalid document ID: {}", e)))
+        })
+        .collect()
 }
 
+/// Chunks of a document, with empty embeddings unless `embeddings` is set
 pub async fn load_document_chunks(
     pool: &SqlitePool,
     document_id: Uuid,
+    embeddings: bool,
 ) -> Result<Vec<DocumentChunk>> {
-    let rows = sqlx::query("SELECT id, content, embedding, chunk_index, metadata FROM document_chunks WHERE document_id = ? ORDER BY chunk_index")
+    let sql = if embeddings {
+        "SELECT id, content, content_hash, embedding, embedding_blob, embedding_model, chunk_index, metadata FROM document_chunks WHERE document_id = ? ORDER BY chunk_index"
+    } else {
+        "SELECT id, content, content_hash, embedding_model, chunk_index, metadata FROM document_chunks WHERE document_id = ? ORDER BY chunk_index"
+    };
+    let rows = sqlx::query(sql)
         .bind(document_id.to_string())
         .fetch_all(pool)
         .await
@@ -118,9 +359,11 @@ pub async fn load_document_chunks(
         let chunk_id = Uuid::parse_str(&chunk_id_str)
             .map_err(|e| AppError::Database(format!("Invalid chunk ID: {}", e)))?;
 
-        let embedding_json: String = row.get("embedding");
-        let embedding: Vec<f32> = serde_json::from_str(&embedding_json)
-            .map_err(|e| AppError::Database(format!("Failed to deserialize embedding: {}", e)))?;
+        let embedding = if embeddings {
+            embedding_from_row(&row)?
+        } else {
+            Vec::new()
+        };
 
         let metadata_json: String = row.get("metadata");
         let metadata: HashMap<String, String> = serde_json::from_str(&metadata_json)
@@ -130,7 +373,9 @@ pub async fn load_document_chunks(
             id: chunk_id,
             document_id,
             content: row.get("content"),
+            content_hash: row.get::<Option<String>, _>("content_hash").unwrap_or_default(),
             embedding,
+            embedding_model: row.get::<Option<String>, _>("embedding_model").unwrap_or_default(),
             chunk_index: row.get::<i64, _>("chunk_index") as usize,
             metadata,
         };
@@ -141,6 +386,47 @@ pub async fn load_document_chunks(
     Ok(chunks)
 }
 
+/// Embeddings of the given chunks, by chunk id
+pub async fn load_chunk_embeddings(pool: &SqlitePool, chunk_ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<f32>>> {
+    let mut embeddings = HashMap::new();
+
+    // Stay well below SQLite's limit on bound parameters
+    for ids in chunk_ids.chunks(500) {
+        let placeholders = vec!["?"; ids.len()].join(", ");
+        let sql = format!(
+            "SELECT id, embedding, embedding_blob FROM document_chunks WHERE id IN ({placeholders})"
+        );
+        let mut query = sqlx::query(&sql);
+        for id in ids {
+            query = query.bind(id.to_string());
+        }
+        let rows = query
+            .fetch_all(pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to load chunk embeddings: {}", e)))?;
+
+        for row in rows {
+            let chunk_id = Uuid::parse_str(row.get("id"))
+                .map_err(|e| AppError::Database(format!("Invalid chunk ID: {}", e)))?;
+            embeddings.insert(chunk_id, embedding_from_row(&row)?);
+        }
+    }
+
+    Ok(embeddings)
+}
+
+fn embedding_from_row(row: &SqliteRow) -> Result<Vec<f32>> {
+    // Chunks not yet migrated still carry their embedding as JSON text
+    match row.get::<Option<Vec<u8>>, _>("embedding_blob") {
+        Some(blob) => embedding_from_blob(&blob),
+        None => {
+            let embedding_json: String = row.get("embedding");
+            serde_json::from_str(&embedding_json)
+                .map_err(|e| AppError::Database(format!("Failed to deserialize embedding: {}", e)))
+        }
+    }
+}
+
 pub async fn delete_document(pool: &SqlitePool, document_id: Uuid) -> Result<()> {
     // Delete chunks first (due to foreign key constraint)
     sqlx::query("DELETE FROM document_chunks WHERE document_id = ?")
@@ -149,6 +435,23 @@ pub async fn delete_document(pool: &SqlitePool, document_id: Uuid) -> Result<()>
         .await
         .map_err(|e| AppError::Database(format!("Failed to delete document chunks: {}", e)))?;
 
+    for statement in [
+        "DELETE FROM document_tags WHERE document_id = ?",
+        "DELETE FROM document_collections WHERE document_id = ?",
+    ] {
+        sqlx::query(statement)
+            .bind(document_id.to_string())
+            .execute(pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to delete document labels: {}", e)))?;
+    }
+
+    sqlx::query("DELETE FROM document_paths WHERE document_id = ?")
+        .bind(document_id.to_string())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to delete document paths: {}", e)))?;
+
     // Delete document
     sqlx::query("DELETE FROM documents WHERE id = ?")
         .bind(document_id.to_string())
@@ -158,3 +461,1015 @@ pub async fn delete_document(pool: &SqlitePool, document_id: Uuid) -> Result<()>
 
     Ok(())
 }
+
+/// Tag chunks stored before embeddings were tagged with `embedding_model`
+/// when their vectors have its dimension. Those almost always came from the
+/// same model; chunks of any other dimension are left for re-embedding.
+pub async fn adopt_untagged_chunks(
+    pool: &SqlitePool,
+    embedding_model: &str,
+    dimension: usize,
+) -> Result<u64> {
+    let result = sqlx::query(
+        r#"
+        UPDATE document_chunks SET embedding_model = ?, embedding_dim = ?
+        WHERE embedding_model IS NULL
+          AND COALESCE(embedding_dim, json_array_length(embedding)) = ?
+    "#,
+    )
+    .bind(embedding_model)
+    .bind(dimension as i64)
+    .bind(dimension as i64)
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to tag chunk embeddings: {}", e)))?;
+
+    Ok(result.rows_affected())
+}
+
+/// Total chunks and those whose embedding did not come from `embedding_model`
+pub async fn count_chunks_by_model(pool: &SqlitePool, embedding_model: &str) -> Result<(i64, i64)> {
+    let row = sqlx::query(
+        "SELECT COUNT(*) AS total, COUNT(*) FILTER (WHERE embedding_model IS NOT ?) AS stale FROM document_chunks",
+    )
+    .bind(embedding_model)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to count document chunks: {}", e)))?;
+
+    Ok((row.get("total"), row.get("stale")))
+}
+
+/// Delete chunks whose document no longer exists, returning how many there were
+pub async fn delete_orphan_chunks(pool: &SqlitePool) -> Result<u64> {
+    let result = sqlx::query("DELETE FROM document_chunks WHERE document_id NOT IN (SELECT id FROM documents)")
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to delete orphaned chunks: {}", e)))?;
+
+    Ok(result.rows_affected())
+}
+
+/// A document with chunks embedded by a model other than `embedding_model`,
+/// other than those in `skipped`
+pub async fn next_stale_document(
+    pool: &SqlitePool,
+    embedding_model: &str,
+    skipped: &[Uuid],
+) -> Result<Option<Uuid>> {
+    let mut sql = QueryBuilder::<Sqlite>::new(
+        r#"
+        SELECT c.document_id FROM document_chunks c
+        JOIN documents d ON d.id = c.document_id
+        WHERE c.embedding_model IS NOT "#,
+    );
+    sql.push_bind(embedding_model);
+    if !skipped.is_empty() {
+        sql.push(" AND c.document_id NOT IN (");
+        let mut values = sql.separated(", ");
+        for document_id in skipped {
+            values.push_bind(document_id.to_string());
+        }
+        sql.push(")");
+    }
+    sql.push(" LIMIT 1");
+
+    let document_id: Option<String> = sql
+        .build_query_scalar()
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to find stale embeddings: {}", e)))?;
+
+    document_id
+        .map(|id| {
+            Uuid::parse_str(&id).map_err(|e| AppError::Database(format!("Invalid document ID: {}", e)))
+        })
+        .transpose()
+}
+
+/// Stored embeddings from `embedding_model` of chunks with the given content
+/// hashes, so identical text is only embedded once
+pub async fn find_embeddings_by_hash(
+    pool: &SqlitePool,
+    content_hashes: &[String],
+    embedding_model: &str,
+) -> Result<HashMap<String, Vec<f32>>> {
+    let mut embeddings = HashMap::new();
+
+    // Stay well below SQLite's limit on bound parameters
+    for hashes in content_hashes.chunks(500) {
+        let placeholders = vec!["?"; hashes.len()].join(", ");
+        let sql = format!(
+            "SELECT content_hash, embedding_blob FROM document_chunks \
+             WHERE embedding_model = ? AND embedding_blob IS NOT NULL AND content_hash IN ({placeholders})"
+        );
+        let mut query = sqlx::query(&sql).bind(embedding_model);
+        for hash in hashes {
+            query = query.bind(hash);
+        }
+        let rows = query
+            .fetch_all(pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to look up stored embeddings: {}", e)))?;
+
+        for row in rows {
+            let blob: Vec<u8> = row.get("embedding_blob");
+            let embedding = embedding_from_blob(&blob)?;
+            if !embedding.is_empty() {
+                embeddings.insert(row.get("content_hash"), embedding);
+            }
+        }
+    }
+
+    Ok(embeddings)
+}
+
+/// Documents indexed from `file_path` for `goal_id`
+pub async fn find_documents_by_path(
+    pool: &SqlitePool,
+    file_path: &str,
+    goal_id: Option<Uuid>,
+) -> Result<Vec<Uuid>> {
+    let ids: Vec<String> = sqlx::query_scalar(
+        r#"
+        SELECT id FROM documents WHERE file_path = ? AND goal_id IS ?
+        UNION
+        SELECT d.id FROM document_paths p JOIN documents d ON d.id = p.document_id
+        WHERE p.file_path = ? AND d.goal_id IS ?
+    "#,
+    )
+    .bind(file_path)
+    .bind(goal_id.map(|id| id.to_string()))
+    .bind(file_path)
+    .bind(goal_id.map(|id| id.to_string()))
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to find documents by path: {}", e)))?;
+
+    ids.iter()
+        .map(|id| {
+            Uuid::parse_str(id).map_err(|e| AppError::Database(format!("Invalid document ID: {}", e)))
+        })
+        .collect()
+}
+
+/// A document of `goal_id` whose source has the given content hash
+pub async fn find_document_by_content(
+    pool: &SqlitePool,
+    content_hash: &str,
+    goal_id: Option<Uuid>,
+) -> Result<Option<Uuid>> {
+    let id: Option<String> = sqlx::query_scalar(
+        "SELECT id FROM documents WHERE content_hash = ? AND goal_id IS ? ORDER BY created_at LIMIT 1",
+    )
+    .bind(content_hash)
+    .bind(goal_id.map(|id| id.to_string()))
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to find document by content: {}", e)))?;
+
+    id.map(|id| Uuid::parse_str(&id).map_err(|e| AppError::Database(format!("Invalid document ID: {}", e))))
+        .transpose()
+}
+
+/// Record `file_path` as another file with the content of the document
+pub async fn add_document_path(pool: &SqlitePool, document_id: Uuid, file_path: &str) -> Result<()> {
+    sqlx::query("INSERT OR IGNORE INTO document_paths (document_id, file_path) VALUES (?, ?)")
+        .bind(document_id.to_string())
+        .bind(file_path)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to add document path: {}", e)))?;
+
+    Ok(())
+}
+
+/// Stop counting `file_path` among the files of the document. When it was
+/// the path the document is stored under, another of its files takes over.
+pub async fn detach_document_path(
+    pool: &SqlitePool,
+    document_id: Uuid,
+    file_path: &str,
+) -> Result<PathDetached> {
+    let main_path: Option<String> = sqlx::query_scalar("SELECT file_path FROM documents WHERE id = ?")
+        .bind(document_id.to_string())
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to load document path: {}", e)))?;
+    if main_path.as_deref() != Some(file_path) {
+        sqlx::query("DELETE FROM document_paths WHERE document_id = ? AND file_path = ?")
+            .bind(document_id.to_string())
+            .bind(file_path)
+            .execute(pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to delete document path: {}", e)))?;
+        return Ok(PathDetached::Kept);
+    }
+
+    let next = sqlx::query(
+        "SELECT file_path, file_size, file_modified_ns FROM document_paths WHERE document_id = ? ORDER BY file_path LIMIT 1",
+    )
+    .bind(document_id.to_string())
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to load document paths: {}", e)))?;
+    let Some(next) = next else {
+        return Ok(PathDetached::Orphaned);
+    };
+
+    let next_path: String = next.get("file_path");
+    sqlx::query("UPDATE documents SET file_path = ?, file_size = ?, file_modified_ns = ? WHERE id = ?")
+        .bind(&next_path)
+        .bind(next.get::<Option<i64>, _>("file_size"))
+        .bind(next.get::<Option<i64>, _>("file_modified_ns"))
+        .bind(document_id.to_string())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to move document path: {}", e)))?;
+    sqlx::query("DELETE FROM document_paths WHERE document_id = ? AND file_path = ?")
+        .bind(document_id.to_string())
+        .bind(&next_path)
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to delete document path: {}", e)))?;
+
+    Ok(PathDetached::Moved(next_path))
+}
+
+/// Source files of the documents indexed for `goal_id`, without loading
+/// their content or chunks
+pub async fn list_indexed_files(pool: &SqlitePool, goal_id: Option<Uuid>) -> Result<Vec<IndexedFile>> {
+    let rows = sqlx::query(
+        r#"
+        SELECT id, file_path, content_hash, file_size, file_modified_ns FROM documents WHERE goal_id IS ?
+        UNION ALL
+        SELECT d.id, p.file_path, d.content_hash, p.file_size, p.file_modified_ns
+        FROM document_paths p JOIN documents d ON d.id = p.document_id
+        WHERE d.goal_id IS ?
+    "#,
+    )
+    .bind(goal_id.map(|id| id.to_string()))
+    .bind(goal_id.map(|id| id.to_string()))
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to list indexed files: {}", e)))?;
+
+    rows.iter()
+        .map(|row| {
+            let document_id: String = row.get("id");
+            Ok(IndexedFile {
+                document_id: Uuid::parse_str(&document_id)
+                    .map_err(|e| AppError::Database(format!("Invalid document ID: {}", e)))?,
+                file_path: row.get("file_path"),
+                content_hash: row
+                    .get::<Option<String>, _>("content_hash")
+                    .unwrap_or_default(),
+                file_size: row.get("file_size"),
+                file_modified_ns: row.get("file_modified_ns"),
+            })
+        })
+        .collect()
+}
+
+/// Record the size and modification time one of a document's files had
+/// when synced
+pub async fn set_file_stamp(
+    pool: &SqlitePool,
+    document_id: Uuid,
+    file_path: &str,
+    file_size: i64,
+    file_modified_ns: Option<i64>,
+) -> Result<()> {
+    for statement in [
+        "UPDATE documents SET file_size = ?, file_modified_ns = ? WHERE id = ? AND file_path = ?",
+        "UPDATE document_paths SET file_size = ?, file_modified_ns = ? WHERE document_id = ? AND file_path = ?",
+    ] {
+        sqlx::query(statement)
+            .bind(file_size)
+            .bind(file_modified_ns)
+            .bind(document_id.to_string())
+            .bind(file_path)
+            .execute(pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to save file stamp: {}", e)))?;
+    }
+
+    Ok(())
+}
+
+pub async fn save_watched_folder(pool: &SqlitePool, folder: &WatchedFolder) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT OR REPLACE INTO watched_folders (id, path, goal_id, include_subdirs, created_at, last_synced_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+    "#,
+    )
+    .bind(folder.id.to_string())
+    .bind(&folder.path)
+    .bind(folder.goal_id.map(|id| id.to_string()))
+    .bind(folder.include_subdirs)
+    .bind(folder.created_at.to_rfc3339())
+    .bind(folder.last_synced_at.map(|at| at.to_rfc3339()))
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to save watched folder: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn load_watched_folders(pool: &SqlitePool) -> Result<Vec<WatchedFolder>> {
+    let rows = sqlx::query(
+        "SELECT id, path, goal_id, include_subdirs, created_at, last_synced_at FROM watched_folders ORDER BY created_at",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to load watched folders: {}", e)))?;
+
+    rows.iter().map(watched_folder_from_row).collect()
+}
+
+fn watched_folder_from_row(row: &SqliteRow) -> Result<WatchedFolder> {
+    let parse_time = |value: &str| {
+        DateTime::parse_from_rfc3339(value)
+            .map(|at| at.with_timezone(&Utc))
+            .map_err(|e| AppError::Database(format!("Invalid watched folder timestamp: {}", e)))
+    };
+
+    let id: String = row.get("id");
+    let goal_id: Option<String> = row.get("goal_id");
+    let created_at: String = row.get("created_at");
+    let last_synced_at: Option<String> = row.get("last_synced_at");
+
+    Ok(WatchedFolder {
+        id: Uuid::parse_str(&id)
+            .map_err(|e| AppError::Database(format!("Invalid watched folder ID: {}", e)))?,
+        path: row.get("path"),
+        goal_id: goal_id
+            .map(|id| Uuid::parse_str(&id))
+            .transpose()
+            .map_err(|e| AppError::Database(format!("Invalid goal ID: {}", e)))?,
+        include_subdirs: row.get("include_subdirs"),
+        created_at: parse_time(&created_at)?,
+        last_synced_at: last_synced_at.as_deref().map(parse_time).transpose()?,
+    })
+}
+
+pub async fn delete_watched_folder(pool: &SqlitePool, folder_id: Uuid) -> Result<()> {
+    sqlx::query("DELETE FROM watched_folders WHERE id = ?")
+        .bind(folder_id.to_string())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to delete watched folder: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn mark_watched_folder_synced(
+    pool: &SqlitePool,
+    folder_id: Uuid,
+    synced_at: DateTime<Utc>,
+) -> Result<()> {
+    sqlx::query("UPDATE watched_folders SET last_synced_at = ? WHERE id = ?")
+        .bind(synced_at.to_rfc3339())
+        .bind(folder_id.to_string())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to update watched folder: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn save_web_source(pool: &SqlitePool, source: &WebSource) -> Result<()> {
+    sqlx::query(
+        r#"
+        INSERT OR REPLACE INTO web_sources (id, url, goal_id, title, refresh_interval_hours, created_at, last_fetched_at)
+        VALUES (?, ?, ?, ?, ?, ?, ?)
+    "#,
+    )
+    .bind(source.id.to_string())
+    .bind(&source.url)
+    .bind(source.goal_id.map(|id| id.to_string()))
+    .bind(&source.title)
+    .bind(source.refresh_interval_hours)
+    .bind(source.created_at.to_rfc3339())
+    .bind(source.last_fetched_at.map(|at| at.to_rfc3339()))
+    .execute(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to save web source: {}", e)))?;
+
+    Ok(())
+}
+
+pub async fn load_web_sources(pool: &SqlitePool) -> Result<Vec<WebSource>> {
+    let rows = sqlx::query(
+        "SELECT id, url, goal_id, title, refresh_interval_hours, created_at, last_fetched_at FROM web_sources ORDER BY created_at",
+    )
+    .fetch_all(pool)
+    .await
+    .map_err(|e| AppError::Database(format!("Failed to load web sources: {}", e)))?;
+
+    rows.iter().map(web_source_from_row).collect()
+}
+
+fn web_source_from_row(row: &SqliteRow) -> Result<WebSource> {
+    let parse_time = |value: &str| {
+        DateTime::parse_from_rfc3339(value)
+            .map(|at| at.with_timezone(&Utc))
+            .map_err(|e| AppError::Database(format!("Invalid web source timestamp: {}", e)))
+    };
+
+    let id: String = row.get("id");
+    let goal_id: Option<String> = row.get("goal_id");
+    let created_at: String = row.get("created_at");
+    let last_fetched_at: Option<String> = row.get("last_fetched_at");
+
+    Ok(WebSource {
+        id: Uuid::parse_str(&id)
+            .map_err(|e| AppError::Database(format!("Invalid web source ID: {}", e)))?,
+        url: row.get("url"),
+        goal_id: goal_id
+            .map(|id| Uuid::parse_str(&id))
+            .transpose()
+            .map_err(|e| AppError::Database(format!("Invalid goal ID: {}", e)))?,
+        title: row.get("title"),
+        refresh_interval_hours: row.get("refresh_interval_hours"),
+        created_at: parse_time(&created_at)?,
+        last_fetched_at: last_fetched_at.as_deref().map(parse_time).transpose()?,
+    })
+}
+
+pub async fn delete_web_source(pool: &SqlitePool, source_id: Uuid) -> Result<()> {
+    sqlx::query("DELETE FROM web_sources WHERE id = ?")
+        .bind(source_id.to_string())
+        .execute(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to delete web source: {}", e)))?;
+
+    Ok(())
+}
+
+/// Chunks matching the words of `query` in the full-text index, best BM25
+/// match first. Scores are relative to the best match, which scores 1.0.
+pub async fn keyword_search_chunks(
+    pool: &SqlitePool,
+    query: &str,
+    filters: &SearchFilters,
+    limit: usize,
+) -> Result<Vec<SearchResult>> {
+    let Some(match_query) = fts_match_query(query) else {
+        return Ok(Vec::new());
+    };
+
+    let mut sql = QueryBuilder::<Sqlite>::new(
+        r#"
+        SELECT c.id, c.document_id, c.content, c.metadata, bm25(document_chunks_fts) AS rank
+        FROM document_chunks_fts
+        JOIN document_chunks c ON c.rowid = document_chunks_fts.rowid
+        JOIN documents d ON d.id = c.document_id
+        WHERE document_chunks_fts MATCH "#,
+    );
+    sql.push_bind(match_query);
+    push_filter_conditions(&mut sql, filters);
+    sql.push(" ORDER BY rank LIMIT ");
+    sql.push_bind(limit as i64);
+
+    let rows = sql
+        .build()
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to search document text: {}", e)))?;
+
+    // BM25 ranks are negative, lower being better
+    let best = rows.first().map(|row| row.get::<f64, _>("rank")).unwrap_or(0.0);
+    let mut results = Vec::new();
+    for row in rows {
+        let chunk_id: String = row.get("id");
+        let document_id: String = row.get("document_id");
+        let metadata_json: String = row.get("metadata");
+        let rank: f64 = row.get("rank");
+
+        results.push(SearchResult {
+            document_id: Uuid::parse_str(&document_id)
+                .map_err(|e| AppError::Database(format!("Invalid document ID: {}", e)))?,
+            chunk_id: Uuid::parse_str(&chunk_id)
+                .map_err(|e| AppError::Database(format!("Invalid chunk ID: {}", e)))?,
+            content: row.get("content"),
+            score: if best < 0.0 { (rank / best) as f32 } else { 1.0 },
+            metadata: serde_json::from_str(&metadata_json).unwrap_or_default(),
+        });
+    }
+
+    Ok(results)
+}
+
+/// `AND` conditions on the documents table `d` and chunks table `c` for each
+/// of `filters`, the same conditions `SearchFilters::matches` checks
+fn push_filter_conditions(sql: &mut QueryBuilder<'_, Sqlite>, filters: &SearchFilters) {
+    if let Some(goal_id) = filters.goal_id {
+        sql.push(" AND d.goal_id = ").push_bind(goal_id.to_string());
+    }
+    for tags in &filters.tags {
+        sql.push(" AND EXISTS (SELECT 1 FROM document_tags t WHERE t.document_id = d.id AND t.tag IN (");
+        let mut values = sql.separated(", ");
+        for tag in tags {
+            values.push_bind(tag.clone());
+        }
+        sql.push("))");
+    }
+    for collections in &filters.collections {
+        sql.push(
+            " AND EXISTS (SELECT 1 FROM document_collections dc WHERE dc.document_id = d.id AND dc.collection_id IN (",
+        );
+        let mut values = sql.separated(", ");
+        for collection_id in collections {
+            values.push_bind(collection_id.to_string());
+        }
+        sql.push("))");
+    }
+    for author in &filters.authors {
+        sql.push(" AND instr(lower(d.author), ")
+            .push_bind(author.clone())
+            .push(") > 0");
+    }
+    for file_types in &filters.file_types {
+        sql.push(" AND (");
+        let mut types = sql.separated(" OR ");
+        for file_type in file_types {
+            if file_type == "web" {
+                types.push("(d.file_path LIKE 'http://%' OR d.file_path LIKE 'https://%')");
+            } else {
+                types
+                    .push("lower(d.file_path) LIKE '%.' || ")
+                    .push_bind_unseparated(file_type.clone());
+            }
+        }
+        sql.push(")");
+    }
+    // Dates are stored as RFC 3339 in UTC, which sorts as text
+    if let Some(after) = filters.modified_after {
+        sql.push(" AND COALESCE(d.modified_at, d.created_at) >= ")
+            .push_bind(after.to_rfc3339());
+    }
+    if let Some(before) = filters.modified_before {
+        sql.push(" AND COALESCE(d.modified_at, d.created_at) < ")
+            .push_bind(before.to_rfc3339());
+    }
+    if let Some(content_type) = &filters.content_type {
+        sql.push(" AND json_extract(c.metadata, '$.content_type') = ")
+            .push_bind(content_type.clone());
+    }
+}
+
+/// FTS5 query matching any word of `text`. Each word is quoted, so
+/// operators and punctuation in identifiers like `ERR_CONN-42` are searched
+/// for as written rather than parsed.
+fn fts_match_query(text: &str) -> Option<String> {
+    let terms: Vec<String> = text
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    (!terms.is_empty()).then(|| terms.join(" OR "))
+}
+
+/// Move embeddings stored as JSON text into the binary column, a batch at a
+/// time. Returns how many chunks were converted.
+pub async fn migrate_embeddings_to_blobs(pool: &SqlitePool) -> Result<u64> {
+    let mut migrated = 0;
+    loop {
+        let rows = sqlx::query(
+            "SELECT id, embedding FROM document_chunks WHERE embedding_blob IS NULL LIMIT 500",
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to load embeddings to migrate: {}", e)))?;
+        if rows.is_empty() {
+            return Ok(migrated);
+        }
+
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to start transaction: {}", e)))?;
+        for row in rows {
+            let id: String = row.get("id");
+            let embedding_json: String = row.get("embedding");
+            let embedding: Vec<f32> = serde_json::from_str(&embedding_json).unwrap_or_default();
+
+            // Keep the dimension so untagged chunks can still be adopted
+            sqlx::query(
+                "UPDATE document_chunks SET embedding_blob = ?, embedding = '[]', embedding_dim = ? WHERE id = ?",
+            )
+            .bind(embedding_to_blob(&embedding))
+            .bind(embedding.len() as i64)
+            .bind(id)
+            .execute(&mut *tx)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to migrate embedding: {}", e)))?;
+            migrated += 1;
+        }
+        tx.commit()
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to commit transaction: {}", e)))?;
+    }
+}
+
+/// Hash the content of chunks stored before chunks were hashed, a batch at a
+/// time. Returns how many chunks were hashed.
+pub async fn backfill_chunk_hashes(pool: &SqlitePool) -> Result<u64> {
+    let mut hashed = 0;
+    loop {
+        let rows = sqlx::query(
+            "SELECT id, content FROM document_chunks WHERE content_hash IS NULL LIMIT 500",
+        )
+        .fetch_all(pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to load chunks to hash: {}", e)))?;
+        if rows.is_empty() {
+            return Ok(hashed);
+        }
+
+        let mut tx = pool
+            .begin()
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to start transaction: {}", e)))?;
+        for row in rows {
+            let id: String = row.get("id");
+            let content: String = row.get("content");
+
+            sqlx::query("UPDATE document_chunks SET content_hash = ? WHERE id = ?")
+                .bind(content_hash(content.as_bytes()))
+                .bind(id)
+                .execute(&mut *tx)
+                .await
+                .map_err(|e| AppError::Database(format!("Failed to hash chunk: {}", e)))?;
+            hashed += 1;
+        }
+        tx.commit()
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to commit transaction: {}", e)))?;
+    }
+}
+
+/// Little-endian `f32`s, four bytes per dimension
+fn embedding_to_blob(embedding: &[f32]) -> Vec<u8> {
+    embedding.iter().flat_map(|value| value.to_le_bytes()).collect()
+}
+
+fn embedding_from_blob(blob: &[u8]) -> Result<Vec<f32>> {
+    if !blob.len().is_multiple_of(4) {
+        return Err(AppError::Database(format!(
+            "Embedding blob of {} bytes is not a list of floats",
+            blob.len()
+        )));
+    }
+    Ok(blob
+        .chunks_exact(4)
+        .map(|bytes| f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fts_match_query_quotes_each_word() {
+        assert_eq!(
+            fts_match_query("ERR_CONN-42 \"timeout\" NOT"),
+            Some(r#""ERR_CONN-42" OR """timeout""" OR "NOT""#.to_string())
+        );
+        assert_eq!(fts_match_query("  - ? "), None);
+        assert_eq!(
+            embedding_from_blob(&embedding_to_blob(&[1.5, -2.0])).unwrap(),
+            vec![1.5, -2.0]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_find_embeddings_by_hash_matches_the_model() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE document_chunks (id TEXT PRIMARY KEY, document_id TEXT, content TEXT, content_hash TEXT, \
+             embedding TEXT, embedding_blob BLOB, embedding_model TEXT, embedding_dim INTEGER, chunk_index INTEGER, metadata TEXT)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        for (model, embedding) in [("model-a", vec![1.0, 2.0]), ("model-b", vec![3.0, 4.0])] {
+            let chunk = DocumentChunk {
+                id: Uuid::new_v4(),
+                document_id: Uuid::nil(),
+                content: "shared".to_string(),
+                content_hash: content_hash(b"shared"),
+                embedding,
+                embedding_model: model.to_string(),
+                chunk_index: 0,
+                metadata: HashMap::new(),
+            };
+            save_document_chunk(&pool, &chunk).await.unwrap();
+        }
+
+        let hashes = [content_hash(b"shared"), content_hash(b"unseen")];
+        let found = find_embeddings_by_hash(&pool, &hashes, "model-a").await.unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[&hashes[0]], vec![1.0, 2.0]);
+    }
+
+    #[tokio::test]
+    async fn test_chunk_embeddings_load_only_when_asked() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        sqlx::query(
+            "CREATE TABLE document_chunks (id TEXT PRIMARY KEY, document_id TEXT, content TEXT, content_hash TEXT, \
+             embedding TEXT, embedding_blob BLOB, embedding_model TEXT, embedding_dim INTEGER, chunk_index INTEGER, metadata TEXT)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let document_id = Uuid::new_v4();
+        let chunks: Vec<DocumentChunk> = (0..2)
+            .map(|i| DocumentChunk {
+                id: Uuid::new_v4(),
+                document_id,
+                content: format!("chunk {i}"),
+                content_hash: content_hash(format!("chunk {i}").as_bytes()),
+                embedding: vec![i as f32, 1.0],
+                embedding_model: "model-a".to_string(),
+                chunk_index: i,
+                metadata: HashMap::new(),
+            })
+            .collect();
+        for chunk in &chunks {
+            save_document_chunk(&pool, chunk).await.unwrap();
+        }
+
+        let loaded = load_document_chunks(&pool, document_id, false).await.unwrap();
+        assert_eq!(loaded.len(), 2);
+        assert!(loaded.iter().all(|chunk| chunk.embedding.is_empty()));
+
+        let embeddings = load_chunk_embeddings(&pool, &[chunks[1].id]).await.unwrap();
+        assert_eq!(embeddings.len(), 1);
+        assert_eq!(embeddings[&chunks[1].id], vec![1.0, 1.0]);
+    }
+
+    #[tokio::test]
+    async fn test_stale_documents_skip_orphaned_chunks() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        for statement in [
+            "CREATE TABLE documents (id TEXT PRIMARY KEY)",
+            "CREATE TABLE document_chunks (id TEXT PRIMARY KEY, document_id TEXT, embedding_model TEXT)",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+
+        let (document_id, orphan_id) = (Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query("INSERT INTO documents (id) VALUES (?)")
+            .bind(document_id.to_string())
+            .execute(&pool)
+            .await
+            .unwrap();
+        // The orphan sorts first, so it would be picked if it were not skipped
+        for (chunk, document) in [("a", orphan_id), ("b", document_id)] {
+            sqlx::query("INSERT INTO document_chunks (id, document_id, embedding_model) VALUES (?, ?, 'old-model')")
+                .bind(chunk)
+                .bind(document.to_string())
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(next_stale_document(&pool, "new-model", &[]).await.unwrap(), Some(document_id));
+        assert_eq!(next_stale_document(&pool, "new-model", &[document_id]).await.unwrap(), None);
+        assert_eq!(delete_orphan_chunks(&pool).await.unwrap(), 1);
+        assert_eq!(count_chunks_by_model(&pool, "new-model").await.unwrap(), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn test_saving_a_stored_document_again_keeps_its_chunks_and_stamps() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        for statement in [
+            "CREATE TABLE documents (id TEXT PRIMARY KEY, title TEXT, content TEXT, file_path TEXT, goal_id TEXT, \
+             created_at TEXT, content_hash TEXT, pages_without_text TEXT, author TEXT, modified_at TEXT, \
+             file_size INTEGER, file_modified_ns INTEGER)",
+            "CREATE TABLE document_chunks (id TEXT PRIMARY KEY, document_id TEXT, content TEXT, content_hash TEXT, \
+             embedding TEXT, embedding_blob BLOB, embedding_model TEXT, embedding_dim INTEGER, chunk_index INTEGER, \
+             metadata TEXT, FOREIGN KEY (document_id) REFERENCES documents(id) ON DELETE CASCADE)",
+            "CREATE TABLE document_tags (document_id TEXT, tag TEXT, PRIMARY KEY (document_id, tag))",
+            "CREATE TABLE document_collections (document_id TEXT, collection_id TEXT, PRIMARY KEY (document_id, collection_id))",
+            "CREATE TABLE document_paths (document_id TEXT, file_path TEXT, file_size INTEGER, file_modified_ns INTEGER)",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+
+        let mut document = Document {
+            id: Uuid::new_v4(),
+            title: "notes".to_string(),
+            content: "notes".to_string(),
+            file_path: "notes.txt".to_string(),
+            goal_id: None,
+            chunks: Vec::new(),
+            created_at: Utc::now(),
+            content_hash: content_hash(b"notes"),
+            pages_without_text: Vec::new(),
+            author: None,
+            modified_at: None,
+            tags: Vec::new(),
+            collections: Vec::new(),
+        };
+        save_document(&pool, &document).await.unwrap();
+        let chunk = DocumentChunk {
+            id: Uuid::new_v4(),
+            document_id: document.id,
+            content: "notes".to_string(),
+            content_hash: content_hash(b"notes"),
+            embedding: vec![1.0],
+            embedding_model: "model-a".to_string(),
+            chunk_index: 0,
+            metadata: HashMap::new(),
+        };
+        save_document_chunk(&pool, &chunk).await.unwrap();
+
+        set_file_stamp(&pool, document.id, "notes.txt", 5, Some(9)).await.unwrap();
+
+        document.title = "renamed".to_string();
+        save_document(&pool, &document).await.unwrap();
+        assert_eq!(load_document_chunks(&pool, document.id, false).await.unwrap().len(), 1);
+        let stored = load_document(&pool, document.id).await.unwrap().unwrap();
+        assert_eq!(stored.title, "renamed");
+        let files = list_indexed_files(&pool, None).await.unwrap();
+        assert_eq!((files[0].file_size, files[0].file_modified_ns), (Some(5), Some(9)));
+    }
+
+    #[tokio::test]
+    async fn test_same_content_files_share_one_document() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        for statement in [
+            "CREATE TABLE documents (id TEXT PRIMARY KEY, file_path TEXT, goal_id TEXT, content_hash TEXT, \
+             created_at TEXT, file_size INTEGER, file_modified_ns INTEGER)",
+            "CREATE TABLE document_paths (document_id TEXT, file_path TEXT, file_size INTEGER, \
+             file_modified_ns INTEGER, PRIMARY KEY (document_id, file_path))",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+
+        let (document_id, goal_id) = (Uuid::new_v4(), Uuid::new_v4());
+        sqlx::query("INSERT INTO documents (id, file_path, goal_id, content_hash, created_at) VALUES (?, 'a.txt', ?, 'hash', '')")
+            .bind(document_id.to_string())
+            .bind(goal_id.to_string())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert_eq!(find_document_by_content(&pool, "hash", Some(goal_id)).await.unwrap(), Some(document_id));
+        assert_eq!(find_document_by_content(&pool, "hash", None).await.unwrap(), None);
+
+        add_document_path(&pool, document_id, "b.txt").await.unwrap();
+        set_file_stamp(&pool, document_id, "b.txt", 4, Some(7)).await.unwrap();
+        assert_eq!(find_documents_by_path(&pool, "b.txt", Some(goal_id)).await.unwrap(), vec![document_id]);
+        let files = list_indexed_files(&pool, Some(goal_id)).await.unwrap();
+        let alias = files.iter().find(|file| file.file_path == "b.txt").unwrap();
+        assert_eq!((alias.content_hash.as_str(), alias.file_size), ("hash", Some(4)));
+
+        // Losing the main file hands the document to the other one
+        assert_eq!(
+            detach_document_path(&pool, document_id, "a.txt").await.unwrap(),
+            PathDetached::Moved("b.txt".to_string())
+        );
+        let files = list_indexed_files(&pool, Some(goal_id)).await.unwrap();
+        assert_eq!(files.len(), 1);
+        assert_eq!((files[0].file_path.as_str(), files[0].file_modified_ns), ("b.txt", Some(7)));
+        assert_eq!(
+            detach_document_path(&pool, document_id, "b.txt").await.unwrap(),
+            PathDetached::Orphaned
+        );
+    }
+
+    #[tokio::test]
+    async fn test_keyword_search_chunks_applies_filters() {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        for statement in [
+            "CREATE TABLE documents (id TEXT PRIMARY KEY, title TEXT, content TEXT, file_path TEXT, goal_id TEXT, created_at TEXT, \
+             content_hash TEXT, pages_without_text TEXT, author TEXT, modified_at TEXT)",
+            "CREATE TABLE document_chunks (id TEXT PRIMARY KEY, document_id TEXT, content TEXT, content_hash TEXT, \
+             embedding TEXT, embedding_blob BLOB, embedding_model TEXT, embedding_dim INTEGER, chunk_index INTEGER, metadata TEXT)",
+            "CREATE TABLE document_tags (document_id TEXT, tag TEXT, PRIMARY KEY (document_id, tag))",
+            "CREATE TABLE document_collections (document_id TEXT, collection_id TEXT, PRIMARY KEY (document_id, collection_id))",
+            "CREATE VIRTUAL TABLE document_chunks_fts USING fts5(content, content='document_chunks', content_rowid='rowid')",
+            "CREATE TRIGGER document_chunks_fts_insert AFTER INSERT ON document_chunks BEGIN \
+             INSERT INTO document_chunks_fts(rowid, content) VALUES (new.rowid, new.content); END",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+
+        let board_packs = Uuid::new_v4();
+        let documents = [
+            ("/docs/q2-budget.pdf", Some("Kim Lee"), "2024-03-15T09:00:00Z", vec!["budget"], vec![board_packs]),
+            ("/docs/q3-budget.docx", Some("Sam Ortiz"), "2024-08-02T09:00:00Z", vec!["budget"], vec![]),
+            ("https://example.com/budget", None, "2024-04-01T09:00:00Z", vec![], vec![]),
+        ];
+        for (file_path, author, modified_at, tags, collections) in documents {
+            let document = Document {
+                id: Uuid::new_v4(),
+                title: file_path.to_string(),
+                content: String::new(),
+                file_path: file_path.to_string(),
+                goal_id: None,
+                chunks: Vec::new(),
+                created_at: Utc::now(),
+                content_hash: String::new(),
+                pages_without_text: Vec::new(),
+                author: author.map(str::to_string),
+                modified_at: modified_at.parse().ok(),
+                tags: tags.into_iter().map(str::to_string).collect(),
+                collections,
+            };
+            save_document(&pool, &document).await.unwrap();
+            let chunk = DocumentChunk {
+                id: Uuid::new_v4(),
+                document_id: document.id,
+                content: format!("Budget figures from {file_path}"),
+                content_hash: String::new(),
+                embedding: vec![1.0],
+                embedding_model: "model".to_string(),
+                chunk_index: 0,
+                metadata: HashMap::from([("content_type".to_string(), "text".to_string())]),
+            };
+            save_document_chunk(&pool, &chunk).await.unwrap();
+        }
+
+        let search = |filters: SearchFilters| {
+            let pool = pool.clone();
+            async move {
+                let mut paths: Vec<String> = keyword_search_chunks(&pool, "budget", &filters, 10)
+                    .await
+                    .unwrap()
+                    .into_iter()
+                    .map(|result| result.content.replace("Budget figures from ", ""))
+                    .collect();
+                paths.sort();
+                paths
+            }
+        };
+
+        assert_eq!(search(SearchFilters::default()).await.len(), 3);
+        assert_eq!(
+            search(SearchFilters {
+                tags: vec![vec!["budget".to_string()]],
+                file_types: vec![vec!["pdf".to_string(), "web".to_string()]],
+                ..SearchFilters::default()
+            })
+            .await,
+            vec!["/docs/q2-budget.pdf"]
+        );
+        assert_eq!(
+            search(SearchFilters {
+                collections: vec![vec![board_packs]],
+                authors: vec!["kim".to_string()],
+                ..SearchFilters::default()
+            })
+            .await,
+            vec!["/docs/q2-budget.pdf"]
+        );
+        assert_eq!(
+            search(SearchFilters {
+                modified_after: "2024-03-20T00:00:00Z".parse().ok(),
+                file_types: vec![vec!["web".to_string(), "docx".to_string()]],
+                ..SearchFilters::default()
+            })
+            .await,
+            vec!["/docs/q3-budget.docx", "https://example.com/budget"]
+        );
+        let content_type = |kind: &str| SearchFilters {
+            content_type: Some(kind.to_string()),
+            ..SearchFilters::default()
+        };
+        assert_eq!(search(content_type("text")).await.len(), 3);
+        assert!(search(content_type("code")).await.is_empty());
+    }
+}
@@ -3,10 +3,14 @@ use crate::error::{AppError, Result};
 use crate::models::{
     Activity, ChatConversation, ChatConversationSummary, ChatMessage, Goal, SavedResearchTask,
 };
-use crate::rag::{Document, DocumentChunk};
+use crate::rag::{
+    Collection, Document, DocumentChunk, IndexedFile, PathDetached, SearchFilters, SearchResult,
+    WatchedFolder, WebSource,
+};
 use chrono::{DateTime, Utc};
 use dirs::data_dir;
 use sqlx::{sqlite::SqlitePoolOptions, SqlitePool};
+use std::collections::HashMap;
 use std::path::PathBuf;
 use uuid::Uuid;
 
@@ -41,6 +45,11 @@ impl SqliteDatabase {
         Ok(db)
     }
 
+    /// Path of `file_name` in the directory that holds the database
+    pub fn data_file_path(file_name: &str) -> Result<PathBuf> {
+        Ok(Self::get_db_path()?.with_file_name(file_name))
+    }
+
     fn get_db_path() -> Result<PathBuf> {
         let config = Config::get();
         let data_dir = data_dir()
@@ -192,6 +201,74 @@ impl SqliteDatabase {
             AppError::Database(format!("Failed to create document_chunks table: {}", e))
         })?;
 
+        // Record which model produced each embedding
+        sqlx::query("ALTER TABLE document_chunks ADD COLUMN embedding_model TEXT")
+            .execute(&self.pool)
+            .await
+            .ok(); // Ignore error if column already exists
+
+        sqlx::query("ALTER TABLE document_chunks ADD COLUMN embedding_dim INTEGER")
+            .execute(&self.pool)
+            .await
+            .ok(); // Ignore error if column already exists
+
+        // Embeddings as little-endian floats instead of JSON text
+        sqlx::query("ALTER TABLE document_chunks ADD COLUMN embedding_blob BLOB")
+            .execute(&self.pool)
+            .await
+            .ok(); // Ignore error if column already exists
+
+        // Hash of the file a document was read from, to notice when it changes
+        sqlx::query("ALTER TABLE documents ADD COLUMN content_hash TEXT")
+            .execute(&self.pool)
+            .await
+            .ok(); // Ignore error if column already exists
+
+        // Pages of a PDF that had no text to index, as a JSON array
+        sqlx::query("ALTER TABLE documents ADD COLUMN pages_without_text TEXT")
+            .execute(&self.pool)
+            .await
+            .ok(); // Ignore error if column already exists
+
+        // Author and modification date from the file's properties
+        sqlx::query("ALTER TABLE documents ADD COLUMN author TEXT")
+            .execute(&self.pool)
+            .await
+            .ok(); // Ignore error if column already exists
+
+        sqlx::query("ALTER TABLE documents ADD COLUMN modified_at TEXT")
+            .execute(&self.pool)
+            .await
+            .ok(); // Ignore error if column already exists
+
+        // Size and modification time of the source file at the last folder
+        // sync, so unchanged files are not hashed again
+        sqlx::query("ALTER TABLE documents ADD COLUMN file_size INTEGER")
+            .execute(&self.pool)
+            .await
+            .ok(); // Ignore error if column already exists
+
+        sqlx::query("ALTER TABLE documents ADD COLUMN file_modified_ns INTEGER")
+            .execute(&self.pool)
+            .await
+            .ok(); // Ignore error if column already exists
+
+        let migrated = operations::rag::migrate_embeddings_to_blobs(&self.pool).await?;
+        if migrated > 0 {
+            println!("[Database] Moved {} chunk embeddings from JSON to binary storage", migrated);
+        }
+
+        // Hash of each chunk's text, so identical chunks share one embedding
+        sqlx::query("ALTER TABLE document_chunks ADD COLUMN content_hash TEXT")
+            .execute(&self.pool)
+            .await
+            .ok(); // Ignore error if column already exists
+
+        let hashed = operations::rag::backfill_chunk_hashes(&self.pool).await?;
+        if hashed > 0 {
+            println!("[Database] Hashed the content of {} stored chunks", hashed);
+        }
+
         // Create indices for better query performance
         sqlx::query("CREATE INDEX IF NOT EXISTS idx_documents_goal_id ON documents(goal_id)")
             .execute(&self.pool)
@@ -209,6 +286,166 @@ impl SqliteDatabase {
             AppError::Database(format!("Failed to create chunks document_id index: {}", e))
         })?;
 
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_chunks_content_hash ON document_chunks(content_hash)",
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            AppError::Database(format!("Failed to create chunks content_hash index: {}", e))
+        })?;
+
+        sqlx::query("CREATE INDEX IF NOT EXISTS idx_documents_file_path ON documents(file_path)")
+            .execute(&self.pool)
+            .await
+            .map_err(|e| {
+                AppError::Database(format!("Failed to create documents file_path index: {}", e))
+            })?;
+
+        sqlx::query(
+            "CREATE INDEX IF NOT EXISTS idx_documents_content_hash ON documents(goal_id, content_hash)",
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            AppError::Database(format!("Failed to create documents content_hash index: {}", e))
+        })?;
+
+        // Folders kept in sync with the documents of a goal
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS watched_folders (
+                id TEXT PRIMARY KEY NOT NULL,
+                path TEXT NOT NULL,
+                goal_id TEXT,
+                include_subdirs BOOLEAN NOT NULL,
+                created_at TEXT NOT NULL,
+                last_synced_at TEXT,
+                UNIQUE (path, goal_id),
+                FOREIGN KEY (goal_id) REFERENCES goals(id) ON DELETE CASCADE
+            )
+        "#,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            AppError::Database(format!("Failed to create watched_folders table: {}", e))
+        })?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS web_sources (
+                id TEXT PRIMARY KEY NOT NULL,
+                url TEXT NOT NULL,
+                goal_id TEXT,
+                title TEXT,
+                refresh_interval_hours INTEGER,
+                created_at TEXT NOT NULL,
+                last_fetched_at TEXT,
+                UNIQUE (url, goal_id),
+                FOREIGN KEY (goal_id) REFERENCES goals(id) ON DELETE CASCADE
+            )
+        "#,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to create web_sources table: {}", e)))?;
+
+        // User-given tags and named collections of documents
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS document_tags (
+                document_id TEXT NOT NULL,
+                tag TEXT NOT NULL,
+                PRIMARY KEY (document_id, tag)
+            )
+        "#,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to create document_tags table: {}", e)))?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS collections (
+                id TEXT PRIMARY KEY NOT NULL,
+                name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                created_at TEXT NOT NULL
+            )
+        "#,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to create collections table: {}", e)))?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS document_collections (
+                document_id TEXT NOT NULL,
+                collection_id TEXT NOT NULL,
+                PRIMARY KEY (document_id, collection_id),
+                FOREIGN KEY (collection_id) REFERENCES collections(id) ON DELETE CASCADE
+            )
+        "#,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| {
+            AppError::Database(format!("Failed to create document_collections table: {}", e))
+        })?;
+
+        // Further files of a goal with the same content as a stored document,
+        // which are recorded against it instead of being stored again
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS document_paths (
+                document_id TEXT NOT NULL,
+                file_path TEXT NOT NULL,
+                file_size INTEGER,
+                file_modified_ns INTEGER,
+                PRIMARY KEY (document_id, file_path)
+            )
+        "#,
+        )
+        .execute(&self.pool)
+        .await
+        .map_err(|e| AppError::Database(format!("Failed to create document_paths table: {}", e)))?;
+
+        // Full-text index over chunk content for keyword search, kept in step
+        // with document_chunks by triggers
+        let fts_exists = sqlx::query("SELECT 1 FROM sqlite_master WHERE name = 'document_chunks_fts'")
+            .fetch_optional(&self.pool)
+            .await
+            .map_err(|e| AppError::Database(format!("Failed to check full-text index: {}", e)))?
+            .is_some();
+
+        for statement in [
+            "CREATE VIRTUAL TABLE IF NOT EXISTS document_chunks_fts USING fts5(content, content='document_chunks', content_rowid='rowid')",
+            r#"CREATE TRIGGER IF NOT EXISTS document_chunks_fts_insert AFTER INSERT ON document_chunks BEGIN
+                INSERT INTO document_chunks_fts(rowid, content) VALUES (new.rowid, new.content);
+            END"#,
+            r#"CREATE TRIGGER IF NOT EXISTS document_chunks_fts_delete AFTER DELETE ON document_chunks BEGIN
+                INSERT INTO document_chunks_fts(document_chunks_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+            END"#,
+            r#"CREATE TRIGGER IF NOT EXISTS document_chunks_fts_update AFTER UPDATE OF content ON document_chunks BEGIN
+                INSERT INTO document_chunks_fts(document_chunks_fts, rowid, content) VALUES ('delete', old.rowid, old.content);
+                INSERT INTO document_chunks_fts(rowid, content) VALUES (new.rowid, new.content);
+            END"#,
+        ] {
+            sqlx::query(statement)
+                .execute(&self.pool)
+                .await
+                .map_err(|e| AppError::Database(format!("Failed to create full-text index: {}", e)))?;
+        }
+
+        // Index chunks stored before the full-text index existed
+        if !fts_exists {
+            sqlx::query("INSERT INTO document_chunks_fts(document_chunks_fts) VALUES ('rebuild')")
+                .execute(&self.pool)
+                .await
+                .map_err(|e| AppError::Database(format!("Failed to build full-text index: {}", e)))?;
+        }
+
         // Chat conversations table
         sqlx::query(
             r#"
@@ -359,14 +596,165 @@ impl SqliteDatabase {
         operations::rag::load_documents(&self.pool, goal_id).await
     }
 
+    pub async fn load_documents_without_embeddings(&self) -> Result<Vec<Document>> {
+        operations::rag::load_documents_without_embeddings(&self.pool).await
+    }
+
     pub async fn load_document_chunks(&self, document_id: Uuid) -> Result<Vec<DocumentChunk>> {
-        operations::rag::load_document_chunks(&self.pool, document_id).await
+        operations::rag::load_document_chunks(&self.pool, document_id, true).await
+    }
+
+    pub async fn load_chunk_embeddings(&self, chunk_ids: &[Uuid]) -> Result<HashMap<Uuid, Vec<f32>>> {
+        operations::rag::load_chunk_embeddings(&self.pool, chunk_ids).await
     }
 
     pub async fn delete_document(&self, document_id: Uuid) -> Result<()> {
         operations::rag::delete_document(&self.pool, document_id).await
     }
 
+    pub async fn keyword_search_chunks(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>> {
+        operations::rag::keyword_search_chunks(&self.pool, query, filters, limit).await
+    }
+
+    pub async fn load_document_labels(&self, document_id: Uuid) -> Result<(Vec<String>, Vec<Uuid>)> {
+        operations::rag::load_document_labels(&self.pool, document_id).await
+    }
+
+    pub async fn set_document_tags(&self, document_id: Uuid, tags: &[String]) -> Result<()> {
+        operations::rag::set_document_tags(&self.pool, document_id, tags).await
+    }
+
+    pub async fn set_document_collections(
+        &self,
+        document_id: Uuid,
+        collection_ids: &[Uuid],
+    ) -> Result<()> {
+        operations::rag::set_document_collections(&self.pool, document_id, collection_ids).await
+    }
+
+    // Collection operations
+    pub async fn save_collection(&self, collection: &Collection) -> Result<()> {
+        operations::rag::save_collection(&self.pool, collection).await
+    }
+
+    pub async fn load_collections(&self) -> Result<Vec<Collection>> {
+        operations::rag::load_collections(&self.pool).await
+    }
+
+    pub async fn delete_collection(&self, collection_id: Uuid) -> Result<Vec<Uuid>> {
+        operations::rag::delete_collection(&self.pool, collection_id).await
+    }
+
+    pub async fn load_document(&self, document_id: Uuid) -> Result<Option<Document>> {
+        operations::rag::load_document(&self.pool, document_id).await
+    }
+
+    pub async fn adopt_untagged_chunks(&self, embedding_model: &str, dimension: usize) -> Result<u64> {
+        operations::rag::adopt_untagged_chunks(&self.pool, embedding_model, dimension).await
+    }
+
+    pub async fn count_chunks_by_model(&self, embedding_model: &str) -> Result<(i64, i64)> {
+        operations::rag::count_chunks_by_model(&self.pool, embedding_model).await
+    }
+
+    pub async fn delete_orphan_chunks(&self) -> Result<u64> {
+        operations::rag::delete_orphan_chunks(&self.pool).await
+    }
+
+    pub async fn next_stale_document(&self, embedding_model: &str, skipped: &[Uuid]) -> Result<Option<Uuid>> {
+        operations::rag::next_stale_document(&self.pool, embedding_model, skipped).await
+    }
+
+    pub async fn find_embeddings_by_hash(
+        &self,
+        content_hashes: &[String],
+        embedding_model: &str,
+    ) -> Result<HashMap<String, Vec<f32>>> {
+        operations::rag::find_embeddings_by_hash(&self.pool, content_hashes, embedding_model).await
+    }
+
+    pub async fn find_documents_by_path(
+        &self,
+        file_path: &str,
+        goal_id: Option<Uuid>,
+    ) -> Result<Vec<Uuid>> {
+        operations::rag::find_documents_by_path(&self.pool, file_path, goal_id).await
+    }
+
+    pub async fn find_document_by_content(
+        &self,
+        content_hash: &str,
+        goal_id: Option<Uuid>,
+    ) -> Result<Option<Uuid>> {
+        operations::rag::find_document_by_content(&self.pool, content_hash, goal_id).await
+    }
+
+    pub async fn add_document_path(&self, document_id: Uuid, file_path: &str) -> Result<()> {
+        operations::rag::add_document_path(&self.pool, document_id, file_path).await
+    }
+
+    pub async fn detach_document_path(
+        &self,
+        document_id: Uuid,
+        file_path: &str,
+    ) -> Result<PathDetached> {
+        operations::rag::detach_document_path(&self.pool, document_id, file_path).await
+    }
+
+    pub async fn list_indexed_files(&self, goal_id: Option<Uuid>) -> Result<Vec<IndexedFile>> {
+        operations::rag::list_indexed_files(&self.pool, goal_id).await
+    }
+
+    pub async fn set_file_stamp(
+        &self,
+        document_id: Uuid,
+        file_path: &str,
+        file_size: i64,
+        file_modified_ns: Option<i64>,
+    ) -> Result<()> {
+        operations::rag::set_file_stamp(&self.pool, document_id, file_path, file_size, file_modified_ns)
+            .await
+    }
+
+    // Watched folder operations
+    pub async fn save_watched_folder(&self, folder: &WatchedFolder) -> Result<()> {
+        operations::rag::save_watched_folder(&self.pool, folder).await
+    }
+
+    pub async fn load_watched_folders(&self) -> Result<Vec<WatchedFolder>> {
+        operations::rag::load_watched_folders(&self.pool).await
+    }
+
+    pub async fn delete_watched_folder(&self, folder_id: Uuid) -> Result<()> {
+        operations::rag::delete_watched_folder(&self.pool, folder_id).await
+    }
+
+    pub async fn mark_watched_folder_synced(
+        &self,
+        folder_id: Uuid,
+        synced_at: DateTime<Utc>,
+    ) -> Result<()> {
+        operations::rag::mark_watched_folder_synced(&self.pool, folder_id, synced_at).await
+    }
+
+    // Web source operations
+    pub async fn save_web_source(&self, source: &WebSource) -> Result<()> {
+        operations::rag::save_web_source(&self.pool, source).await
+    }
+
+    pub async fn load_web_sources(&self) -> Result<Vec<WebSource>> {
+        operations::rag::load_web_sources(&self.pool).await
+    }
+
+    pub async fn delete_web_source(&self, source_id: Uuid) -> Result<()> {
+        operations::rag::delete_web_source(&self.pool, source_id).await
+    }
+
     // Chat operations
     pub async fn create_conversation(&self, conversation: &ChatConversation) -> Result<()> {
         operations::chat::create_conversation(&self.pool, conversation).await
@@ -56,6 +56,12 @@ pub enum AppError {
     #[error("Vector store error: {0}")]
     VectorStore(String),
 
+    #[error("Embedding error: {0}")]
+    Embedding(String),
+
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+
     #[error("Generic error: {0}")]
     Generic(#[from] anyhow::Error),
 }
@@ -6,11 +6,17 @@ use crate::{
     error::Result,
     goals::GoalService,
     llm::LlmClient,
-    rag::{RAGSystem, RAGSystemWrapper},
+    rag::{RAGSystem, RAGSystemWrapper, Reranker},
+    services::{
+        self,
+        folder_sync::FolderWatcher,
+        jobs::{JobKind, JobRegistry},
+        web_sources::WebSourceRefresher,
+    },
     storage::LocalStorage,
 };
 use std::sync::Arc;
-use tauri::{App, Manager};
+use tauri::{App, AppHandle, Manager};
 use tokio::sync::Mutex;
 
 pub struct AppServices {
@@ -20,7 +26,11 @@ pub struct AppServices {
     pub audio_recorder: Option<Arc<PlatformAudioRecorder>>,
     pub database: Option<Arc<Mutex<SqliteDatabase>>>,
     pub rag_system: Option<Arc<Mutex<RAGSystemWrapper>>>,
+    pub reranker: Arc<Reranker>,
+    pub folder_watcher: Option<Arc<FolderWatcher>>,
+    pub web_source_refresher: Option<Arc<WebSourceRefresher>>,
     pub goal_service: Arc<Mutex<GoalService>>,
+    pub jobs: Arc<JobRegistry>,
 }
 
 impl AppServices {
@@ -81,6 +91,20 @@ impl AppServices {
             }
         };
 
+        let reranker = Arc::new(Reranker::new(llm_client.clone()));
+
+        // Watched folders are stored in the database and synced into the RAG system
+        let folder_watcher = match (&rag_system, &database) {
+            (Some(rag), Some(db)) => Some(Arc::new(FolderWatcher::new(rag.clone(), db.clone()))),
+            _ => None,
+        };
+
+        // Web pages are indexed through the same RAG system and refreshed on schedule
+        let web_source_refresher = match (&rag_system, &database) {
+            (Some(rag), Some(db)) => Some(Arc::new(WebSourceRefresher::new(rag.clone(), db.clone()))),
+            _ => None,
+        };
+
         let goal_service = Arc::new(Mutex::new(GoalService::new()));
 
         if let Some(db) = &database {
@@ -106,7 +130,11 @@ impl AppServices {
             audio_recorder,
             database,
             rag_system,
+            reranker,
+            folder_watcher,
+            web_source_refresher,
             goal_service,
+            jobs: Arc::new(JobRegistry::new()),
         };
 
         services.register_with_app(app);
@@ -130,8 +158,18 @@ impl AppServices {
         if let Some(rag) = &self.rag_system {
             app.manage(rag.clone());
         }
+        app.manage(self.reranker.clone());
+
+        if let Some(watcher) = &self.folder_watcher {
+            app.manage(watcher.clone());
+        }
+
+        if let Some(refresher) = &self.web_source_refresher {
+            app.manage(refresher.clone());
+        }
 
         app.manage(self.goal_service.clone());
+        app.manage(self.jobs.clone());
     }
 
     pub fn spawn_activity_tracking(&self) {
@@ -183,6 +221,44 @@ impl AppServices {
         });
     }
 
+    /// Re-embed documents embedded with another model than the configured one
+    pub fn spawn_reembedding(&self, app: AppHandle) {
+        let (Some(rag_system), Some(database)) = (self.rag_system.clone(), self.database.clone()) else {
+            return;
+        };
+        let Some(job) = self.jobs.try_register(services::rag::REEMBEDDING_TASK_ID, JobKind::Reembedding) else {
+            return;
+        };
+
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = services::rag::run_reembedding(&app, rag_system, database, job).await {
+                tracing::error!("Failed to re-embed documents: {}", e);
+            }
+        });
+    }
+
+    /// Keep watched folders in sync with their documents
+    pub fn spawn_folder_watching(&self, app: AppHandle) {
+        let Some(watcher) = self.folder_watcher.clone() else {
+            return;
+        };
+
+        tauri::async_runtime::spawn(async move {
+            watcher.run(&app).await;
+        });
+    }
+
+    /// Fetch web sources again when their refresh interval has passed
+    pub fn spawn_web_source_refresh(&self, app: AppHandle) {
+        let Some(refresher) = self.web_source_refresher.clone() else {
+            return;
+        };
+
+        tauri::async_runtime::spawn(async move {
+            refresher.run(&app).await;
+        });
+    }
+
     pub fn spawn_migration(&self) {
         let database = self.database.clone();
 
@@ -0,0 +1,456 @@
+use crate::config::{Config, InferenceProvider};
+use crate::error::Result;
+use async_trait::async_trait;
+use futures_util::stream::{self, BoxStream, StreamExt};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use super::callm_backend::CallmBackend;
+use super::conversation::{render_transcript, PromptMessage};
+use super::options::GenerationOptions;
+use super::candle_backend::CandleBackend;
+use super::crane_backend::CraneBackend;
+use super::llama_cpp_metal_backend::LlamaCppMetalBackend;
+use super::ollama_backend::OllamaBackend;
+use super::openai_backend::OpenAiCompatibleBackend;
+use super::token_budget::{context_length_for_model, TokenCounter};
+
+/// Stream of generated text fragments, in the order they were produced
+pub type TokenStream = BoxStream<'static, Result<String>>;
+
+/// Common interface implemented by every inference engine
+#[async_trait]
+pub trait InferenceBackend: Send + Sync {
+    /// The provider this backend serves
+    fn provider(&self) -> InferenceProvider;
+
+    /// The model identifier the backend was created for
+    fn model_id(&self) -> &str;
+
+    /// Generate a complete response for the prompt
+    async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String>;
+
+    /// Generate a response as a stream of text fragments.
+    ///
+    /// Takes the backend by `Arc` so local engines can keep generating on a
+    /// worker thread while the caller consumes the stream. The default
+    /// implementation waits for the full response and yields it as a single
+    /// fragment.
+    async fn stream(self: Arc<Self>, prompt: &str, options: &GenerationOptions) -> Result<TokenStream> {
+        let response = self.generate(prompt, options).await?;
+        Ok(stream::once(async move { Ok(response) }).boxed())
+    }
+
+    /// Generate a reply to a role-tagged conversation.
+    ///
+    /// Engines without native chat support receive the conversation flattened
+    /// into a single prompt.
+    async fn generate_chat(&self, messages: &[PromptMessage], options: &GenerationOptions) -> Result<String> {
+        self.generate(&render_transcript(messages), options).await
+    }
+
+    /// Generate a reply that should be JSON matching `schema`.
+    ///
+    /// Backends that can constrain decoding use the schema; the rest rely on
+    /// the instructions already in the messages.
+    async fn generate_chat_json(
+        &self,
+        messages: &[PromptMessage],
+        _schema_name: &str,
+        _schema: &serde_json::Value,
+        options: &GenerationOptions,
+    ) -> Result<String> {
+        self.generate_chat(messages, options).await
+    }
+
+    /// Stream a reply to a role-tagged conversation
+    async fn stream_chat(
+        self: Arc<Self>,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        let prompt = render_transcript(messages);
+        self.stream(&prompt, options).await
+    }
+
+    /// Tokens the model can attend to, prompt and reply together
+    fn context_length(&self) -> usize {
+        context_length_for_model(self.model_id())
+    }
+
+    /// Counter for measuring prompts in this model's tokens
+    fn token_counter(&self) -> TokenCounter {
+        TokenCounter::Estimate
+    }
+
+    /// Describe the loaded model
+    async fn model_info(&self) -> ModelInfo;
+
+    /// Report whether the backend is able to serve requests
+    async fn health(&self) -> BackendHealth;
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ModelInfo {
+    pub model_type: String,
+    pub device: String,
+    pub cache_dir: String,
+    pub loaded: bool,
+    pub tokenizer_loaded: bool,
+    pub supported_features: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(tag = "status", content = "detail", rename_all = "lowercase")]
+pub enum BackendHealth {
+    /// Model and tokenizer are loaded and the backend can generate
+    Ready,
+    /// The backend answers, but not with real model output
+    Degraded(String),
+    /// The backend cannot serve requests
+    Unavailable(String),
+}
+
+impl BackendHealth {
+    pub fn is_ready(&self) -> bool {
+        matches!(self, BackendHealth::Ready)
+    }
+}
+
+/// Run a blocking generation on a worker thread and expose its text as a stream.
+///
+/// `generate` receives a callback for each decoded fragment; the callback
+/// returns false once the stream has been dropped so the token loop can stop.
+/// An error returned by `generate` ends the stream with that error.
+pub(crate) fn spawn_token_stream<F>(generate: F) -> TokenStream
+where
+    F: FnOnce(&mut (dyn FnMut(&str) -> bool + Send)) -> Result<String> + Send + 'static,
+{
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+    tokio::task::spawn_blocking(move || {
+        let mut on_text = |text: &str| tx.send(Ok(text.to_string())).is_ok();
+        if let Err(e) = generate(&mut on_text) {
+            let _ = tx.send(Err(e));
+        }
+    });
+
+    UnboundedReceiverStream::new(rx).boxed()
+}
+
+/// Decodes a growing list of generated tokens into text deltas.
+///
+/// Tokenizers may need several tokens to produce a complete character, so the
+/// full sequence is decoded each step and only whole new text is returned.
+#[derive(Default)]
+pub(crate) struct TextDeltaDecoder {
+    emitted: usize,
+}
+
+impl TextDeltaDecoder {
+    pub fn next_delta(&mut self, tokenizer: &Tokenizer, tokens: &[u32]) -> Option<String> {
+        let text = tokenizer.decode(tokens, true).ok()?;
+        if text.len() <= self.emitted
+            || text.ends_with('\u{FFFD}')
+            || !text.is_char_boundary(self.emitted)
+        {
+            return None;
+        }
+        let delta = text[self.emitted..].to_string();
+        self.emitted = text.len();
+        Some(delta)
+    }
+}
+
+/// Path of `filename` in a cached snapshot of a Hugging Face model repo
+pub(crate) fn cached_model_file(model_id: &str, cache_dir: &Path, filename: &str) -> Option<PathBuf> {
+    let snapshots = cache_dir
+        .join(format!("models--{}", model_id.replace('/', "--")))
+        .join("snapshots");
+
+    std::fs::read_dir(snapshots)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join(filename))
+        .find(|path| path.exists())
+}
+
+/// Settings needed to construct a backend for a provider
+#[derive(Debug, Clone)]
+pub struct BackendConfig {
+    pub model_id: String,
+    pub model_revision: String,
+    pub cache_dir: PathBuf,
+    pub ollama_url: String,
+    pub openai_base_url: String,
+    pub openai_api_key: Option<String>,
+}
+
+impl BackendConfig {
+    /// Build backend settings for `provider` from the application config.
+    ///
+    /// Local engines share `candle_model_id`; `CANDLE_MODEL_ID` is checked first
+    /// because `set_inference_provider` updates it for the running session.
+    pub fn for_provider(config: &Config, provider: InferenceProvider) -> Self {
+        let model_id = match provider {
+            InferenceProvider::Ollama => config.services.ollama_model.clone(),
+            InferenceProvider::OpenAiCompatible => std::env::var(model_env_var(provider))
+                .unwrap_or_else(|_| config.services.openai_model.clone()),
+            _ => std::env::var(model_env_var(provider))
+                .unwrap_or_else(|_| config.services.candle_model_id.clone()),
+        };
+
+        Self {
+            model_id,
+            model_revision: config.services.candle_model_revision.clone(),
+            cache_dir: PathBuf::from(&config.services.candle_cache_dir),
+            ollama_url: config.services.ollama_url.clone(),
+            openai_base_url: config.services.openai_base_url.clone(),
+            openai_api_key: config.services.openai_api_key.clone(),
+        }
+    }
+}
+
+/// Environment variable `set_inference_provider` uses to switch the model of
+/// `provider` for the running session
+pub fn model_env_var(provider: InferenceProvider) -> &'static str {
+    match provider {
+        InferenceProvider::Ollama => "OLLAMA_MODEL",
+        InferenceProvider::OpenAiCompatible => "OPENAI_MODEL",
+        _ => "CANDLE_MODEL_ID",
+    }
+}
+
+/// Create and load the backend for `provider`
+pub async fn create_backend(
+    provider: InferenceProvider,
+    settings: &BackendConfig,
+) -> Result<Arc<dyn InferenceBackend>> {
+    println!("[Backend] Creating {:?} backend with model: {}", provider, settings.model_id);
+
+    let backend: Arc<dyn InferenceBackend> = match provider {
+        InferenceProvider::Ollama => Arc::new(OllamaBackend::new(
+            &settings.model_id,
+            &settings.ollama_url,
+        )),
+        InferenceProvider::Candle => Arc::new(
+            CandleBackend::new(
+                &settings.model_id,
+                &settings.model_revision,
+                settings.cache_dir.clone(),
+            )
+            .await?,
+        ),
+        InferenceProvider::Crane => Arc::new(
+            CraneBackend::new(&settings.model_id, settings.cache_dir.clone()).await?,
+        ),
+        InferenceProvider::Callm => Arc::new(
+            CallmBackend::new(&settings.model_id, settings.cache_dir.clone()).await?,
+        ),
+        InferenceProvider::LlamaCpp => Arc::new(
+            LlamaCppMetalBackend::new(&settings.model_id, settings.cache_dir.clone()).await?,
+        ),
+        InferenceProvider::OpenAiCompatible => Arc::new(OpenAiCompatibleBackend::new(
+            &settings.model_id,
+            &settings.openai_base_url,
+            settings.openai_api_key.clone(),
+        )),
+    };
+
+    Ok(backend)
+}
+
+/// Loaded backends, at most one per provider
+#[derive(Default)]
+pub struct BackendRegistry {
+    backends: HashMap<InferenceProvider, Arc<dyn InferenceBackend>>,
+}
+
+impl BackendRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a backend under its own provider, replacing any previous one
+    pub fn register(&mut self, backend: Arc<dyn InferenceBackend>) {
+        self.backends.insert(backend.provider(), backend);
+    }
+
+    pub fn get(&self, provider: InferenceProvider) -> Option<Arc<dyn InferenceBackend>> {
+        self.backends.get(&provider).cloned()
+    }
+
+    #[allow(dead_code)]
+    pub fn contains(&self, provider: InferenceProvider) -> bool {
+        self.backends.contains_key(&provider)
+    }
+
+    /// Drop every backend except the one for `provider`, freeing model memory
+    #[allow(dead_code)]
+    pub fn retain_only(&mut self, provider: InferenceProvider) {
+        self.backends.retain(|p, _| *p == provider);
+    }
+}
+
+/// Human readable provider and model names for the settings screen
+pub fn describe_model(provider: InferenceProvider, model_id: &str) -> (&'static str, String) {
+    match provider {
+        InferenceProvider::Candle => {
+            let model = match model_id {
+                "TinyLlama/TinyLlama-1.1B-Chat-v1.0" => "TinyLlama 1.1B Chat",
+                "microsoft/phi-2" => "Phi-2 2.7B",
+                "mistralai/Mistral-7B-v0.1" => "Mistral 7B",
+                _ => model_id,
+            };
+            ("Candle", model.to_string())
+        }
+        InferenceProvider::Crane => {
+            let model = match model_id {
+                "Qwen/Qwen2.5-0.5B-Instruct" => "Qwen2.5 0.5B (Crane)",
+                "Qwen/Qwen2.5-1.5B-Instruct" => "Qwen2.5 1.5B (Crane)",
+                "Qwen/Qwen2.5-3B-Instruct" => "Qwen2.5 3B (Crane)",
+                "Qwen/Qwen2.5-7B-Instruct" => "Qwen2.5 7B (Crane)",
+                _ => model_id,
+            };
+            ("Crane", model.to_string())
+        }
+        InferenceProvider::Callm => {
+            let model = match model_id {
+                "TinyLlama/TinyLlama-1.1B-Chat-v1.0" => "TinyLlama 1.1B (Callm)",
+                "microsoft/phi-2" => "Phi-2 2.7B (Callm)",
+                model if model.starts_with("Qwen/Qwen2") => "Qwen2 (Callm)",
+                _ => model_id,
+            };
+            ("Callm", model.to_string())
+        }
+        InferenceProvider::Ollama => ("Ollama", model_id.to_string()),
+        InferenceProvider::LlamaCpp => {
+            let device_suffix = if cfg!(target_os = "macos") { "Metal" } else { "CPU" };
+            let model = match model_id {
+                "TinyLlama/TinyLlama-1.1B-Chat-v1.0" => format!("TinyLlama 1.1B (LlamaCpp/{})", device_suffix),
+                "Qwen/Qwen2.5-0.5B-Instruct" => format!("Qwen2.5 0.5B (LlamaCpp/{})", device_suffix),
+                _ => format!("{} (LlamaCpp/{})", model_id, device_suffix),
+            };
+            ("LlamaCpp", model)
+        }
+        InferenceProvider::OpenAiCompatible => ("OpenAI-compatible", model_id.to_string()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// Deterministic backend that echoes the prompt back
+    pub(crate) struct MockBackend {
+        pub provider: InferenceProvider,
+        pub model_id: String,
+    }
+
+    #[async_trait]
+    impl InferenceBackend for MockBackend {
+        fn provider(&self) -> InferenceProvider {
+            self.provider
+        }
+
+        fn model_id(&self) -> &str {
+            &self.model_id
+        }
+
+        async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+            Ok(format!("[{}:{}] {}", self.model_id, options.max_tokens, prompt))
+        }
+
+        async fn model_info(&self) -> ModelInfo {
+            ModelInfo {
+                model_type: self.model_id.clone(),
+                device: "mock".to_string(),
+                cache_dir: String::new(),
+                loaded: true,
+                tokenizer_loaded: true,
+                supported_features: vec![],
+            }
+        }
+
+        async fn health(&self) -> BackendHealth {
+            BackendHealth::Ready
+        }
+    }
+
+    #[test]
+    fn test_registry_keyed_by_provider() {
+        let mut registry = BackendRegistry::new();
+        registry.register(Arc::new(MockBackend {
+            provider: InferenceProvider::Candle,
+            model_id: "mock-a".to_string(),
+        }));
+        registry.register(Arc::new(MockBackend {
+            provider: InferenceProvider::Crane,
+            model_id: "mock-b".to_string(),
+        }));
+
+        assert!(registry.contains(InferenceProvider::Candle));
+        assert_eq!(registry.get(InferenceProvider::Candle).unwrap().model_id(), "mock-a");
+
+        registry.retain_only(InferenceProvider::Crane);
+        assert!(!registry.contains(InferenceProvider::Candle));
+        assert!(registry.contains(InferenceProvider::Crane));
+    }
+
+    #[tokio::test]
+    async fn test_default_stream_yields_full_response() {
+        let backend = Arc::new(MockBackend {
+            provider: InferenceProvider::Ollama,
+            model_id: "mock".to_string(),
+        });
+
+        let chunks: Vec<String> = backend
+            .stream("hello", &GenerationOptions::default().with_max_tokens(8))
+            .await
+            .unwrap()
+            .map(|chunk| chunk.unwrap())
+            .collect()
+            .await;
+
+        assert_eq!(chunks, vec!["[mock:8] hello".to_string()]);
+    }
+
+    #[tokio::test]
+    async fn test_spawned_stream_forwards_fragments_then_error() {
+        let chunks: Vec<Result<String>> = spawn_token_stream(|on_text| {
+            assert!(on_text("Hel"));
+            assert!(on_text("lo"));
+            Err(crate::error::AppError::Llm("boom".into()))
+        })
+        .collect()
+        .await;
+
+        assert_eq!(chunks.len(), 3);
+        assert_eq!(chunks[0].as_ref().unwrap(), "Hel");
+        assert_eq!(chunks[1].as_ref().unwrap(), "lo");
+        assert!(chunks[2].is_err());
+    }
+
+    #[tokio::test]
+    async fn test_spawned_stream_stops_when_dropped() {
+        let (done_tx, done_rx) = tokio::sync::oneshot::channel();
+        let mut tokens = spawn_token_stream(move |on_text| {
+            let mut sent = 0;
+            while on_text("token") {
+                sent += 1;
+                std::thread::sleep(std::time::Duration::from_millis(1));
+            }
+            let _ = done_tx.send(sent);
+            Ok(String::new())
+        });
+
+        tokens.next().await.unwrap().unwrap();
+        drop(tokens);
+
+        let sent = done_rx.await.unwrap();
+        assert!(sent >= 1);
+    }
+}
@@ -1,39 +1,55 @@
 use crate::error::{AppError, Result};
-use candle_core::{Device, Tensor, DType};
+use candle_core::{Device, Tensor, DType, D};
 use candle_transformers::models::bert::{BertModel, Config};
-use candle_nn::VarBuilder;
-use hf_hub::{api::tokio::Api, Repo, RepoType};
-use tokenizers::Tokenizer;
-use std::path::PathBuf;
-use std::sync::{Arc, Mutex};
+use candle_nn::{Linear, Module, VarBuilder};
+use hf_hub::{api::tokio::{Api, ApiRepo}, Repo, RepoType};
+use tokenizers::{Tokenizer, TruncationParams};
 
-#[allow(dead_code)]
+/// How the token vectors of a text are combined into one embedding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pooling {
+    /// Average of the token vectors, used by most sentence-transformers models
+    Mean,
+    /// Vector of the leading `[CLS]` token, used by BGE models
+    Cls,
+}
+
+/// `1_Pooling/config.json` from a sentence-transformers repository
+#[derive(serde::Deserialize)]
+struct PoolingConfig {
+    #[serde(default)]
+    pooling_mode_cls_token: bool,
+}
+
+/// BERT-architecture encoder (MiniLM, BGE, E5, GTE) producing sentence
+/// embeddings on Metal when available
 pub struct BertMetalBackend {
     model_id: String,
-    cache_dir: PathBuf,
     device: Device,
     device_type: String,
-    model: Arc<Mutex<Option<BertModel>>>,
-    tokenizer: Option<Tokenizer>,
-    config: Option<Config>,
+    model: BertModel,
+    tokenizer: Tokenizer,
+    pooling: Pooling,
 }
 
-impl BertMetalBackend {
-    pub async fn new(
-        model_id: &str,
-        cache_dir: PathBuf,
-    ) -> Result<Self> {
-        println!("[BertMetalBackend] Initializing with model: {}", model_id);
-        
-        // Create cache directory
-        std::fs::create_dir_all(&cache_dir)
-            .map_err(|e| AppError::Llm(format!("Failed to create cache dir: {}", e)))?;
-        
+/// Device, configuration, tokenizer and weights of a BERT-architecture model
+/// downloaded from the Hugging Face Hub
+struct BertCheckpoint {
+    device: Device,
+    device_type: String,
+    config: Config,
+    tokenizer: Tokenizer,
+    weights: VarBuilder<'static>,
+    repo: ApiRepo,
+}
+
+impl BertCheckpoint {
+    async fn download(model_id: &str) -> Result<Self> {
         // Check if we can use Metal
         let use_metal = std::env::var("CANDLE_USE_METAL")
             .map(|v| v != "0")
             .unwrap_or(true);
-        
+
         let (device, device_type) = if cfg!(target_os = "macos") && use_metal {
             match Device::new_metal(0) {
                 Ok(metal_device) => {
@@ -48,203 +64,263 @@ impl BertMetalBackend {
         } else {
             (Device::Cpu, "CPU".to_string())
         };
-        
+
         println!("[BertMetalBackend] Using device: {}", device_type);
-        
-        let mut backend = Self {
-            model_id: model_id.to_string(),
-            cache_dir,
-            device,
-            device_type,
-            model: Arc::new(Mutex::new(None)),
-            tokenizer: None,
-            config: None,
-        };
-        
-        // Load the model
-        match backend.load_model().await {
-            Ok(_) => {
-                println!("[BertMetalBackend] Model loaded successfully on {}", backend.device_type);
-            }
-            Err(e) => {
-                eprintln!("[BertMetalBackend] Failed to load model: {}", e);
-                return Err(e);
-            }
-        }
-        
-        Ok(backend)
-    }
-    
-    async fn load_model(&mut self) -> Result<()> {
-        println!("[BertMetalBackend] Loading BERT model for Metal acceleration...");
-        
+
         let api = Api::new()
             .map_err(|e| AppError::Llm(format!("Failed to create HF API: {}", e)))?;
-        
-        // Popular BERT models that work well
-        let (model_id, is_sentence_transformer) = match self.model_id.as_str() {
-            // Sentence transformers (great for embeddings)
-            "sentence-transformers/all-MiniLM-L6-v2" => (self.model_id.as_str(), true),
-            "sentence-transformers/all-mpnet-base-v2" => (self.model_id.as_str(), true),
-            // Standard BERT models
-            "bert-base-uncased" => (self.model_id.as_str(), false),
-            "bert-base-cased" => (self.model_id.as_str(), false),
-            "bert-base-multilingual-cased" => (self.model_id.as_str(), false),
-            "distilbert-base-uncased" => (self.model_id.as_str(), false),
-            _ => {
-                println!("[BertMetalBackend] Using default: bert-base-uncased");
-                ("bert-base-uncased", false)
-            }
-        };
-        
         let repo = api.repo(Repo::with_revision(
             model_id.to_string(),
             RepoType::Model,
             "main".to_string(),
         ));
-        
+
         // Download config
         println!("[BertMetalBackend] Downloading config...");
         let config_path = repo.get("config.json").await
             .map_err(|e| AppError::Llm(format!("Failed to download config: {}", e)))?;
-        
-        // Download model weights
+        let config_str = std::fs::read_to_string(&config_path)
+            .map_err(|e| AppError::Llm(format!("Failed to read config: {}", e)))?;
+        let config: Config = serde_json::from_str(&config_str)
+            .map_err(|e| AppError::Llm(format!("{} is not a BERT-architecture model: {}", model_id, e)))?;
+
+        println!("[BertMetalBackend] Config loaded - hidden_size: {}", config.hidden_size);
+
+        // Download model weights, preferring safetensors
         println!("[BertMetalBackend] Downloading model weights...");
-        let weights_file = if is_sentence_transformer {
-            "pytorch_model.bin" // Sentence transformers often use this
-        } else {
-            "model.safetensors" // Newer format
-        };
-        
-        let model_path = match repo.get(weights_file).await {
+        let model_path = match repo.get("model.safetensors").await {
             Ok(path) => path,
-            Err(_) => {
-                // Try alternative format
-                let alt_file = if weights_file == "model.safetensors" {
-                    "pytorch_model.bin"
-                } else {
-                    "model.safetensors"
-                };
-                repo.get(alt_file).await
-                    .map_err(|e| AppError::Llm(format!("Failed to download model weights: {}", e)))?
-            }
+            Err(_) => repo.get("pytorch_model.bin").await
+                .map_err(|e| AppError::Llm(format!("Failed to download model weights: {}", e)))?,
         };
-        
-        // Download tokenizer
+
+        // Download tokenizer, truncating input to what the model can attend to
         println!("[BertMetalBackend] Downloading tokenizer...");
         let tokenizer_path = repo.get("tokenizer.json").await
             .map_err(|e| AppError::Llm(format!("Failed to download tokenizer: {}", e)))?;
-        
-        // Load tokenizer
-        let tokenizer = Tokenizer::from_file(&tokenizer_path)
+        let mut tokenizer = Tokenizer::from_file(&tokenizer_path)
             .map_err(|e| AppError::Llm(format!("Failed to load tokenizer: {}", e)))?;
-        self.tokenizer = Some(tokenizer);
-        
-        // Load config
-        let config_str = std::fs::read_to_string(&config_path)
-            .map_err(|e| AppError::Llm(format!("Failed to read config: {}", e)))?;
-        let config: Config = serde_json::from_str(&config_str)
-            .map_err(|e| AppError::Llm(format!("Failed to parse config: {}", e)))?;
-        
-        // For sentence transformers, we might need mean pooling
-        if is_sentence_transformer {
-            println!("[BertMetalBackend] Configuring for sentence transformers (embeddings)");
-        }
-        
-        self.config = Some(config.clone());
-        
-        println!("[BertMetalBackend] Config loaded - hidden_size: {}", config.hidden_size);
-        
+        tokenizer
+            .with_truncation(Some(TruncationParams {
+                max_length: config.max_position_embeddings,
+                ..Default::default()
+            }))
+            .map_err(|e| AppError::Llm(format!("Failed to configure tokenizer: {}", e)))?;
+        tokenizer.with_padding(None);
+
         // Load model weights
-        println!("[BertMetalBackend] Loading model weights on {:?}...", self.device);
-        
-        let vb = if model_path.to_string_lossy().ends_with(".safetensors") {
+        println!("[BertMetalBackend] Loading model weights on {:?}...", device);
+
+        let weights = if model_path.to_string_lossy().ends_with(".safetensors") {
             unsafe {
-                VarBuilder::from_mmaped_safetensors(&[model_path], DType::F32, &self.device)
+                VarBuilder::from_mmaped_safetensors(&[model_path], DType::F32, &device)
                     .map_err(|e| AppError::Llm(format!("Failed to load weights: {}", e)))?
             }
         } else {
-            VarBuilder::from_pth(model_path, DType::F32, &self.device)
+            VarBuilder::from_pth(model_path, DType::F32, &device)
                 .map_err(|e| AppError::Llm(format!("Failed to load weights: {}", e)))?
         };
-        
-        // Create the model
-        let model = BertModel::load(vb, &config)
+
+        Ok(Self {
+            device,
+            device_type,
+            config,
+            tokenizer,
+            weights,
+            repo,
+        })
+    }
+}
+
+/// Concatenate `sequences` padded with `pad_id` to the longest one, along with
+/// the attention mask that keeps the padding out of attention
+fn pad_batch(sequences: &[&[u32]], pad_id: u32) -> (Vec<u32>, Vec<u32>, usize) {
+    let max_len = sequences.iter().map(|ids| ids.len()).max().unwrap_or(0);
+    let mut ids = Vec::with_capacity(sequences.len() * max_len);
+    let mut mask = Vec::with_capacity(sequences.len() * max_len);
+    for sequence in sequences {
+        ids.extend_from_slice(sequence);
+        ids.extend(std::iter::repeat_n(pad_id, max_len - sequence.len()));
+        mask.extend(std::iter::repeat_n(1u32, sequence.len()));
+        mask.extend(std::iter::repeat_n(0u32, max_len - sequence.len()));
+    }
+    (ids, mask, max_len)
+}
+
+impl BertMetalBackend {
+    pub async fn new(model_id: &str) -> Result<Self> {
+        println!("[BertMetalBackend] Initializing with model: {}", model_id);
+
+        let checkpoint = BertCheckpoint::download(model_id).await?;
+
+        // Sentence transformers say how they were trained to pool; plain BERT
+        // repositories have no pooling config and use the mean
+        let pooling = match checkpoint.repo.get("1_Pooling/config.json").await {
+            Ok(path) => {
+                let pooling_str = std::fs::read_to_string(&path)
+                    .map_err(|e| AppError::Llm(format!("Failed to read pooling config: {}", e)))?;
+                match serde_json::from_str::<PoolingConfig>(&pooling_str) {
+                    Ok(pooling) if pooling.pooling_mode_cls_token => Pooling::Cls,
+                    _ => Pooling::Mean,
+                }
+            }
+            Err(_) => Pooling::Mean,
+        };
+
+        let model = BertModel::load(checkpoint.weights, &checkpoint.config)
             .map_err(|e| AppError::Llm(format!("Failed to create model: {}", e)))?;
-        
-        *self.model.lock().unwrap() = Some(model);
-        
-        println!("[BertMetalBackend] Model loaded successfully on {}!", self.device_type);
-        
-        Ok(())
+
+        println!(
+            "[BertMetalBackend] Model loaded successfully on {} ({:?} pooling)",
+            checkpoint.device_type, pooling
+        );
+
+        Ok(Self {
+            model_id: model_id.to_string(),
+            device: checkpoint.device,
+            device_type: checkpoint.device_type,
+            model,
+            tokenizer: checkpoint.tokenizer,
+            pooling,
+        })
+    }
+
+    pub fn model_id(&self) -> &str {
+        &self.model_id
     }
-    
-    pub async fn generate_embeddings(&self, text: &str) -> Result<Vec<f32>> {
-        println!("[BertMetalBackend] Generating embeddings on {}...", self.device_type);
-        
-        let tokenizer = self.tokenizer.as_ref()
-            .ok_or_else(|| AppError::Llm("Tokenizer not loaded".into()))?;
-        
-        let model = self.model.lock().unwrap();
-        let model = model.as_ref()
-            .ok_or_else(|| AppError::Llm("Model not loaded".into()))?;
-        
-        // Tokenize input
-        let encoding = tokenizer.encode(text, true)
+
+    /// Unit-length embedding of `text`. Runs the model synchronously, so call
+    /// it from a blocking task.
+    pub fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let mut embeddings = self.embed_batch(&[text.to_string()])?;
+        Ok(embeddings.remove(0))
+    }
+
+    /// Unit-length embeddings of `texts` from one forward pass, in input
+    /// order. Runs the model synchronously, so call it from a blocking task.
+    pub fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let encodings = self.tokenizer.encode_batch(texts.to_vec(), true)
             .map_err(|e| AppError::Llm(format!("Failed to encode: {}", e)))?;
-        let tokens = encoding.get_ids();
-        
-        // Convert to tensor
-        let token_ids = Tensor::new(tokens, &self.device)
-            .map_err(|e| AppError::Llm(format!("Failed to create tensor: {}", e)))?
-            .unsqueeze(0)
-            .map_err(|e| AppError::Llm(format!("Failed to unsqueeze: {}", e)))?; // Add batch dimension
-        
-        let token_type_ids = token_ids.zeros_like()
-            .map_err(|e| AppError::Llm(format!("Failed to create zeros: {}", e)))?;
-        
-        // Forward pass (BERT takes optional attention mask as 3rd param)
-        let output = model.forward(&token_ids, &token_type_ids, None)
-            .map_err(|e| AppError::Llm(format!("Forward pass failed: {}", e)))?;
-        
-        // For embeddings, we typically use mean pooling over the sequence
-        let embeddings = output.mean(1)
-            .map_err(|e| AppError::Llm(format!("Mean pooling failed: {}", e)))?; // Average over sequence length
-        
-        // Convert to Vec<f32>
-        let embeddings_vec = embeddings.squeeze(0)
-            .map_err(|e| AppError::Llm(format!("Squeeze failed: {}", e)))?
-            .to_vec1::<f32>()
-            .map_err(|e| AppError::Llm(format!("To vec failed: {}", e)))?;
-        
-        println!("[BertMetalBackend] Generated {} dimensional embeddings", embeddings_vec.len());
-        
-        Ok(embeddings_vec)
+        let token_ids: Vec<&[u32]> = encodings.iter().map(|encoding| encoding.get_ids()).collect();
+
+        self.forward(&token_ids)
+            .map_err(|e| AppError::Llm(format!("Embedding on {} failed: {}", self.device_type, e)))
     }
-    
-    pub async fn generate(&self, prompt: &str, _max_tokens: usize) -> Result<String> {
-        // BERT is not designed for text generation, it's for embeddings/classification
-        // But we can demonstrate it works on Metal by returning embeddings info
-        
-        let embeddings = self.generate_embeddings(prompt).await?;
-        
-        Ok(format!(
-            "[BERT on {}] Generated {}-dimensional embeddings for input. \
-            BERT models are designed for embeddings and classification, not text generation. \
-            First few values: {:?}...",
-            self.device_type,
-            embeddings.len(),
-            &embeddings[..5.min(embeddings.len())]
-        ))
+
+    fn forward(&self, token_ids: &[&[u32]]) -> candle_core::Result<Vec<Vec<f32>>> {
+        // Pad every sequence to the longest one; the mask keeps padding out of
+        // attention and out of the mean
+        let pad_id = self.tokenizer.token_to_id("[PAD]").unwrap_or(0);
+        let (ids, mask, max_len) = pad_batch(token_ids, pad_id);
+
+        let shape = (token_ids.len(), max_len);
+        let input_ids = Tensor::from_vec(ids, shape, &self.device)?;
+        let attention_mask = Tensor::from_vec(mask, shape, &self.device)?;
+        let token_type_ids = input_ids.zeros_like()?;
+        let output = self.model.forward(&input_ids, &token_type_ids, Some(&attention_mask))?;
+
+        let pooled = match self.pooling {
+            Pooling::Mean => {
+                let mask = attention_mask.to_dtype(output.dtype())?.unsqueeze(2)?;
+                let summed = output.broadcast_mul(&mask)?.sum(1)?;
+                summed.broadcast_div(&mask.sum(1)?)?
+            }
+            Pooling::Cls => output.narrow(1, 0, 1)?.squeeze(1)?,
+        };
+
+        // Normalise so dot products are cosine similarities
+        let norm = pooled.sqr()?.sum_keepdim(D::Minus1)?.sqrt()?;
+        pooled.broadcast_div(&norm)?.to_vec2::<f32>()
     }
 }
 
-#[derive(Debug, serde::Serialize)]
-pub struct BertModelInfo {
-    pub model_type: String,
-    pub device: String,
-    pub model_loaded: bool,
-    pub tokenizer_loaded: bool,
-    pub hidden_size: usize,
-    pub supports_metal: bool,
-}
\ No newline at end of file
+/// BERT-architecture cross-encoder (the ms-marco MiniLM rerankers and
+/// similar) scoring how well a passage answers a query
+pub struct BertCrossEncoder {
+    device: Device,
+    device_type: String,
+    model: BertModel,
+    pooler: Linear,
+    classifier: Linear,
+    tokenizer: Tokenizer,
+    pad_id: u32,
+}
+
+impl BertCrossEncoder {
+    pub async fn new(model_id: &str) -> Result<Self> {
+        println!("[BertCrossEncoder] Initializing with model: {}", model_id);
+
+        let checkpoint = BertCheckpoint::download(model_id).await?;
+        let config = &checkpoint.config;
+        let prefix = config.model_type.as_deref().unwrap_or("bert");
+
+        let model = BertModel::load(checkpoint.weights.clone(), config)
+            .map_err(|e| AppError::Llm(format!("Failed to create model: {}", e)))?;
+        // Sequence-classification heads keep BERT's pooler and add a
+        // one-logit classifier
+        let head_error = |e: candle_core::Error| {
+            AppError::Llm(format!("{} is not a single-label cross-encoder: {}", model_id, e))
+        };
+        let pooler = candle_nn::linear(
+            config.hidden_size,
+            config.hidden_size,
+            checkpoint.weights.pp(format!("{prefix}.pooler.dense")),
+        )
+        .map_err(head_error)?;
+        let classifier = candle_nn::linear(config.hidden_size, 1, checkpoint.weights.pp("classifier"))
+            .map_err(head_error)?;
+
+        println!("[BertCrossEncoder] Model loaded successfully on {}", checkpoint.device_type);
+
+        let pad_id = checkpoint.tokenizer.token_to_id("[PAD]").unwrap_or(0);
+        Ok(Self {
+            device: checkpoint.device,
+            device_type: checkpoint.device_type,
+            model,
+            pooler,
+            classifier,
+            tokenizer: checkpoint.tokenizer,
+            pad_id,
+        })
+    }
+
+    /// Relevance of each passage to `query` between 0 and 1, in input order.
+    /// Runs the model synchronously, so call it from a blocking task.
+    pub fn score(&self, query: &str, passages: &[String]) -> Result<Vec<f32>> {
+        if passages.is_empty() {
+            return Ok(Vec::new());
+        }
+        let pairs: Vec<(String, String)> = passages
+            .iter()
+            .map(|passage| (query.to_string(), passage.clone()))
+            .collect();
+        let encodings = self.tokenizer.encode_batch(pairs, true)
+            .map_err(|e| AppError::Llm(format!("Failed to encode: {}", e)))?;
+
+        self.forward(&encodings)
+            .map_err(|e| AppError::Llm(format!("Reranking on {} failed: {}", self.device_type, e)))
+    }
+
+    fn forward(&self, encodings: &[tokenizers::Encoding]) -> candle_core::Result<Vec<f32>> {
+        let token_ids: Vec<&[u32]> = encodings.iter().map(|encoding| encoding.get_ids()).collect();
+        let type_ids: Vec<&[u32]> = encodings.iter().map(|encoding| encoding.get_type_ids()).collect();
+        let (ids, mask, max_len) = pad_batch(&token_ids, self.pad_id);
+        let (type_ids, _, _) = pad_batch(&type_ids, 0);
+
+        let shape = (encodings.len(), max_len);
+        let input_ids = Tensor::from_vec(ids, shape, &self.device)?;
+        let token_type_ids = Tensor::from_vec(type_ids, shape, &self.device)?;
+        let attention_mask = Tensor::from_vec(mask, shape, &self.device)?;
+        let output = self.model.forward(&input_ids, &token_type_ids, Some(&attention_mask))?;
+
+        // Classify the pooled [CLS] vector; the single logit becomes a
+        // probability so scores are comparable across queries
+        let cls = output.narrow(1, 0, 1)?.squeeze(1)?;
+        let pooled = self.pooler.forward(&cls)?.tanh()?;
+        let logits = self.classifier.forward(&pooled)?.squeeze(1)?;
+        candle_nn::ops::sigmoid(&logits)?.to_vec1::<f32>()
+    }
+}
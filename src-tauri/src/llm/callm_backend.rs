@@ -7,19 +7,29 @@ use std::sync::Arc;
 use tokio::sync::Mutex;
 use std::path::PathBuf;
 use std::time::Instant;
+use crate::config::InferenceProvider;
+use async_trait::async_trait;
+use super::backend::{spawn_token_stream, BackendHealth, InferenceBackend, ModelInfo, TextDeltaDecoder, TokenStream};
+use super::chat_template::ChatTemplate;
+use super::conversation::PromptMessage;
+use super::options::{truncate_at_stop, GenerationOptions, StopFilter};
+use super::prompt_cache::{PromptCache, PROMPT_CACHE_ENTRIES};
+use super::token_budget::TokenCounter;
 
 pub struct CallmBackend {
     pub model_id: String,
-    #[allow(dead_code)]
     cache_dir: PathBuf,
     model: Arc<Mutex<Option<QLlamaWeights>>>,
-    tokenizer: Option<Tokenizer>,
+    /// KV cache snapshots for reusing the start of earlier prompts
+    prompt_cache: Mutex<PromptCache<QLlamaWeights>>,
+    tokenizer: Option<Arc<Tokenizer>>,
     device: Device,
     device_type: String,
     temperature: f64,
     top_p: f64,
     seed: u64,
     eos_token_id: Option<u32>,
+    template: ChatTemplate,
 }
 
 impl CallmBackend {
@@ -44,6 +54,7 @@ impl CallmBackend {
             model_id: model_id.to_string(),
             cache_dir: cache_dir.clone(),
             model: Arc::new(Mutex::new(None)),
+            prompt_cache: Mutex::new(PromptCache::new(PROMPT_CACHE_ENTRIES)),
             tokenizer: None,
             device,
             device_type,
@@ -51,6 +62,7 @@ impl CallmBackend {
             top_p: 0.9,
             seed: 42,
             eos_token_id: None,
+            template: ChatTemplate::for_model(model_id, &cache_dir),
         };
         
         // Try to load the model
@@ -217,7 +229,7 @@ impl CallmBackend {
         println!("[CallmBackend] ✓ Model loaded successfully on CPU");
         
         *self.model.lock().await = Some(model_weights);
-        self.tokenizer = Some(tokenizer);
+        self.tokenizer = Some(Arc::new(tokenizer));
         self.eos_token_id = eos_token_id;
         
         println!("[CallmBackend] Model ready for inference on CPU");
@@ -237,14 +249,31 @@ impl CallmBackend {
         Ok(())
     }
     
-    pub async fn generate(&self, prompt: &str, max_tokens: usize) -> Result<String> {
+    pub async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        self.generate_streaming(&[PromptMessage::user(prompt)], options, &mut |_| true).await
+    }
+
+    /// Generate a reply to `messages`, passing each newly decoded piece of text
+    /// to `on_text`. Generation stops early when `on_text` returns false or a
+    /// stop sequence is produced. Unset sampling options use the backend's own
+    /// settings.
+    pub async fn generate_streaming(
+        &self,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+        on_text: &mut (dyn FnMut(&str) -> bool + Send),
+    ) -> Result<String> {
+        let prompt = messages.last().map(|m| m.content.as_str()).unwrap_or_default();
         let start_time = Instant::now();
+        let max_tokens = options.max_tokens.max(1);
+        let temperature = options.temperature.map_or(self.temperature, |t| t.clamp(0.1, 2.0));
+        let top_p = options.top_p.map_or(self.top_p, |p| p.clamp(0.1, 1.0));
         
         println!("[CallmBackend] =================================");
         println!("[CallmBackend] Model: {}", self.model_id);
         println!("[CallmBackend] Device: {}", self.device_type);
-        println!("[CallmBackend] Temperature: {}", self.temperature);
-        println!("[CallmBackend] Top-p: {}", self.top_p);
+        println!("[CallmBackend] Temperature: {}", temperature);
+        println!("[CallmBackend] Top-p: {}", top_p);
         println!("[CallmBackend] Max tokens: {}", max_tokens);
         println!("[CallmBackend] Prompt preview: {}", 
             prompt.chars().take(100).collect::<String>());
@@ -270,18 +299,9 @@ impl CallmBackend {
             }
         };
         
-        // Apply chat template
-        let formatted_prompt = match self.model_id.as_str() {
-            model if model.contains("Qwen") => {
-                format!("<|im_start|>system\nYou are a helpful AI assistant.<|im_end|>\n<|im_start|>user\n{}<|im_end|>\n<|im_start|>assistant\n", prompt)
-            }
-            "TinyLlama/TinyLlama-1.1B-Chat-v1.0" => {
-                format!("<|system|>\nYou are a helpful AI assistant.</s>\n<|user|>\n{}</s>\n<|assistant|>\n", prompt)
-            }
-            _ => {
-                format!("### Instruction:\n{}\n\n### Response:\n", prompt)
-            }
-        };
+        // Apply the model's chat template
+        let formatted_prompt = self.template.render(messages);
+        let stop = self.template.stop_sequences(&options.stop);
         
         // Tokenize
         println!("[CallmBackend] Tokenizing prompt ({} chars)...", formatted_prompt.len());
@@ -290,19 +310,30 @@ impl CallmBackend {
         let tokens = tokens.get_ids();
         println!("[CallmBackend] Tokenized to {} tokens", tokens.len());
         
+        // Continue from the KV cache of an earlier prompt with the same start
+        let mut prompt_cache = self.prompt_cache.lock().await;
+        let reused = match prompt_cache.restore(tokens) {
+            Some((reused, state)) => {
+                println!("[CallmBackend] Reusing KV cache for {} of {} prompt tokens", reused, tokens.len());
+                *model = state;
+                reused
+            }
+            None => 0,
+        };
+        
         let mut generated_tokens = Vec::new();
         let mut logits_processor = candle_transformers::generation::LogitsProcessor::new(
-            self.seed,
-            Some(self.temperature),
-            Some(self.top_p)
+            options.seed.unwrap_or(self.seed),
+            Some(temperature),
+            Some(top_p)
         );
         
         // Process prompt with optimizations
         let prompt_start = Instant::now();
         let mut last_logits = None;
         
-        println!("[CallmBackend] Processing {} prompt tokens...", tokens.len());
-        for (pos, &token) in tokens.iter().enumerate() {
+        println!("[CallmBackend] Processing {} prompt tokens...", tokens.len() - reused);
+        for (pos, &token) in tokens.iter().enumerate().skip(reused) {
             // Use the same device as the model
             let input = Tensor::new(&[token as u32], &self.device)
                 .and_then(|t| t.unsqueeze(0))
@@ -325,8 +356,9 @@ impl CallmBackend {
         let prompt_time = prompt_start.elapsed();
         println!("[CallmBackend] Prompt processed in {:.2}s ({:.1} tokens/s)",
             prompt_time.as_secs_f32(),
-            tokens.len() as f32 / prompt_time.as_secs_f32()
+            (tokens.len() - reused) as f32 / prompt_time.as_secs_f32()
         );
+        prompt_cache.save(tokens.to_vec(), model.clone());
         
         // Generate tokens with hardware acceleration
         if let Some(logits) = last_logits {
@@ -340,11 +372,16 @@ impl CallmBackend {
             let mut current_token = logits_processor.sample(&squeezed_logits)
                 .map_err(|e| AppError::Llm(format!("Failed to sample token: {}", e)))?;
             
+            let mut decoder = TextDeltaDecoder::default();
+            let mut text = StopFilter::new(&stop, on_text);
             generated_tokens.push(current_token);
+            let first_accepted = decoder
+                .next_delta(tokenizer, &generated_tokens)
+                .is_none_or(|delta| text.push(&delta));
             
-            // Generate remaining tokens with a reasonable limit
-            let max_gen_tokens = max_tokens.min(150); // Limit to 150 tokens for faster response
-            for i in 0..max_gen_tokens - 1 {
+            // Generate remaining tokens up to the requested limit
+            let remaining = if first_accepted { max_tokens - 1 } else { 0 };
+            for i in 0..remaining {
                 if Some(current_token) == self.eos_token_id {
                     println!("[CallmBackend] EOS token reached at position {}", i);
                     break;
@@ -373,12 +410,24 @@ impl CallmBackend {
                 current_token = logits_processor.sample(&logits)
                     .map_err(|e| AppError::Llm(format!("Failed to sample: {}", e)))?;
                 generated_tokens.push(current_token);
+                if let Some(delta) = decoder.next_delta(tokenizer, &generated_tokens) {
+                    if !text.push(&delta) {
+                        println!("[CallmBackend] Generation stopped after {} tokens", generated_tokens.len());
+                        break;
+                    }
+                }
                 
                 // Progress indicator
                 if i % 10 == 0 {
                     println!("[CallmBackend] Generated {} tokens...", i + 1);
                 }
             }
+            text.flush();
+            
+            // The last sampled token was never fed through the model
+            let mut processed = tokens.to_vec();
+            processed.extend_from_slice(&generated_tokens[..generated_tokens.len() - 1]);
+            prompt_cache.save(processed, model.clone());
             
             let gen_time = generation_start.elapsed();
             println!("[CallmBackend] Generated {} tokens in {:.2}s ({:.1} tokens/s)",
@@ -395,6 +444,7 @@ impl CallmBackend {
         println!("[CallmBackend] Decoding {} generated tokens...", generated_tokens.len());
         let generated_text = tokenizer.decode(&generated_tokens, true)
             .map_err(|e| AppError::Llm(format!("Failed to decode tokens: {}", e)))?;
+        let generated_text = truncate_at_stop(generated_text, &stop);
         println!("[CallmBackend] Generated text length: {} chars", generated_text.len());
         
         let total_time = start_time.elapsed();
@@ -406,7 +456,9 @@ impl CallmBackend {
         
         if generated_text.is_empty() {
             println!("[CallmBackend] Warning: Empty response generated");
-            Ok("I apologize, but I'm having trouble generating a response. Please try again.".to_string())
+            let response = "I apologize, but I'm having trouble generating a response. Please try again.";
+            on_text(response);
+            Ok(response.to_string())
         } else {
             Ok(generated_text)
         }
@@ -445,13 +497,62 @@ impl CallmBackend {
         self.seed = seed;
         println!("[CallmBackend] Seed set to: {}", self.seed);
     }
-    
-    #[allow(dead_code)]
-    pub async fn get_model_info(&self) -> ModelInfo {
+
+    /// Whether model weights are loaded. A held lock means a generation is
+    /// running, so the model is in use.
+    fn model_loaded(&self) -> bool {
+        self.model.try_lock().map(|model| model.is_some()).unwrap_or(true)
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for CallmBackend {
+    fn provider(&self) -> InferenceProvider {
+        InferenceProvider::Callm
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        CallmBackend::generate(self, prompt, options).await
+    }
+
+    async fn stream(self: Arc<Self>, prompt: &str, options: &GenerationOptions) -> Result<TokenStream> {
+        self.stream_chat(&[PromptMessage::user(prompt)], options).await
+    }
+
+    async fn generate_chat(&self, messages: &[PromptMessage], options: &GenerationOptions) -> Result<String> {
+        self.generate_streaming(messages, options, &mut |_| true).await
+    }
+
+    async fn stream_chat(
+        self: Arc<Self>,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        let messages = messages.to_vec();
+        let options = options.clone();
+        Ok(spawn_token_stream(move |on_text| {
+            tokio::runtime::Handle::current()
+                .block_on(self.generate_streaming(&messages, &options, on_text))
+        }))
+    }
+
+    fn token_counter(&self) -> TokenCounter {
+        match &self.tokenizer {
+            Some(tokenizer) => TokenCounter::Tokenizer(tokenizer.clone()),
+            None => TokenCounter::Estimate,
+        }
+    }
+
+    async fn model_info(&self) -> ModelInfo {
         ModelInfo {
             model_type: self.model_id.clone(),
             device: self.device_type.clone(),
-            model_loaded: self.model.lock().await.is_some(),
+            cache_dir: self.cache_dir.to_string_lossy().to_string(),
+            loaded: self.model_loaded(),
             tokenizer_loaded: self.tokenizer.is_some(),
             supported_features: vec![
                 "hardware_acceleration".to_string(),
@@ -463,19 +564,14 @@ impl CallmBackend {
                 else if self.device_type == "CUDA" { "cuda_acceleration".to_string() }
                 else { "cpu_optimized".to_string() }
             ],
-            temperature: self.temperature,
-            top_p: self.top_p,
         }
     }
-}
 
-#[derive(Debug, serde::Serialize)]
-pub struct ModelInfo {
-    pub model_type: String,
-    pub device: String,
-    pub model_loaded: bool,
-    pub tokenizer_loaded: bool,
-    pub supported_features: Vec<String>,
-    pub temperature: f64,
-    pub top_p: f64,
-}
\ No newline at end of file
+    async fn health(&self) -> BackendHealth {
+        if self.model_loaded() && self.tokenizer.is_some() {
+            BackendHealth::Ready
+        } else {
+            BackendHealth::Unavailable("Model not loaded in memory".to_string())
+        }
+    }
+}
@@ -9,6 +9,15 @@ use candle_transformers::generation::LogitsProcessor;
 use candle_core::quantized::gguf_file;
 use std::sync::Mutex;
 use std::time::Instant;
+use crate::config::InferenceProvider;
+use async_trait::async_trait;
+use std::sync::Arc;
+use super::backend::{spawn_token_stream, BackendHealth, InferenceBackend, ModelInfo, TextDeltaDecoder, TokenStream};
+use super::chat_template::ChatTemplate;
+use super::conversation::PromptMessage;
+use super::options::{truncate_at_stop, GenerationOptions, StopFilter};
+use super::prompt_cache::{PromptCache, PROMPT_CACHE_ENTRIES};
+use super::token_budget::TokenCounter;
 
 pub struct CandleBackend {
     model_id: String,
@@ -17,7 +26,10 @@ pub struct CandleBackend {
     cache_dir: PathBuf,
     device: Device,
     model: Mutex<Option<ModelWeights>>,
-    tokenizer: Option<Tokenizer>,
+    /// KV cache snapshots for reusing the start of earlier prompts
+    prompt_cache: Mutex<PromptCache<ModelWeights>>,
+    tokenizer: Option<Arc<Tokenizer>>,
+    template: ChatTemplate,
 }
 
 impl CandleBackend {
@@ -39,13 +51,16 @@ impl CandleBackend {
         
         println!("[CandleBackend] Using device: {:?}", device);
         
+        let template = ChatTemplate::for_model(model_id, &cache_dir);
         let mut backend = Self {
             model_id: model_id.to_string(),
             revision: revision.to_string(),
             cache_dir,
             device,
             model: Mutex::new(None),
+            prompt_cache: Mutex::new(PromptCache::new(PROMPT_CACHE_ENTRIES)),
             tokenizer: None,
+            template,
         };
         
         // Try to load the model with retry
@@ -180,10 +195,10 @@ impl CandleBackend {
         println!("[CandleBackend] Tokenizer downloaded to: {:?}", tokenizer_path);
         
         // Load tokenizer
-        self.tokenizer = Some(
+        self.tokenizer = Some(Arc::new(
             Tokenizer::from_file(&tokenizer_path)
                 .map_err(|e| AppError::Llm(format!("Failed to load tokenizer: {}", e)))?
-        );
+        ));
         
         println!("[CandleBackend] Tokenizer loaded successfully from: {:?}", tokenizer_path);
         
@@ -218,7 +233,21 @@ impl CandleBackend {
         Ok(())
     }
     
-    pub async fn generate(&self, prompt: &str, max_tokens: usize) -> Result<String> {
+    pub async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        self.generate_streaming(&[PromptMessage::user(prompt)], options, &mut |_| true).await
+    }
+
+    /// Generate a reply to `messages`, passing each newly decoded piece of text
+    /// to `on_text`. Generation stops early when `on_text` returns false or a
+    /// stop sequence is produced.
+    pub async fn generate_streaming(
+        &self,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+        on_text: &mut (dyn FnMut(&str) -> bool + Send),
+    ) -> Result<String> {
+        let max_tokens = options.max_tokens;
+        let prompt = messages.last().map(|m| m.content.as_str()).unwrap_or_default();
         println!("[CandleBackend] =================================");
         println!("[CandleBackend] Model: {}", self.model_id);
         println!("[CandleBackend] Device: {:?}", self.device);
@@ -234,7 +263,9 @@ impl CandleBackend {
             None => {
                 // Fallback to placeholder responses if model isn't loaded
                 println!("[CandleBackend] Model not loaded, using placeholder response");
-                return Ok(self.generate_placeholder_response(prompt, max_tokens));
+                let response = self.generate_placeholder_response(prompt, max_tokens);
+                on_text(&response);
+                return Ok(response);
             }
         };
         
@@ -242,12 +273,14 @@ impl CandleBackend {
             Some(t) => t,
             None => {
                 println!("[CandleBackend] Tokenizer not loaded, using placeholder response");
-                return Ok(self.generate_placeholder_response(prompt, max_tokens));
+                let response = self.generate_placeholder_response(prompt, max_tokens);
+                on_text(&response);
+                return Ok(response);
             }
         };
         
-        // Apply chat template if needed
-        let formatted_prompt = self.apply_chat_template(prompt);
+        // Apply the model's chat template
+        let formatted_prompt = self.template.render(messages);
         
         // Tokenize the prompt
         let encoding = tokenizer.encode(formatted_prompt.as_str(), true)
@@ -256,18 +289,29 @@ impl CandleBackend {
         let tokens = encoding.get_ids().to_vec();
         println!("[CandleBackend] Input tokens: {} tokens", tokens.len());
         
+        // Continue from the KV cache of an earlier prompt with the same start
+        let mut prompt_cache = self.prompt_cache.lock().unwrap();
+        let reused = match prompt_cache.restore(&tokens) {
+            Some((reused, state)) => {
+                println!("[CandleBackend] Reusing KV cache for {} of {} prompt tokens", reused, tokens.len());
+                *model = state;
+                reused
+            }
+            None => 0,
+        };
+        
         // Generate tokens
         let mut generated_tokens = Vec::new();
         let eos_token_id = self.get_eos_token_id(tokenizer);
         
         // Setup sampling parameters
-        let temperature = 0.8;
-        let top_p = 0.95;
+        let temperature = options.temperature.unwrap_or(0.8);
+        let top_p = options.top_p.unwrap_or(0.95);
         let repeat_penalty = 1.1;
         let repeat_last_n = 64;
         
         let mut logits_processor = LogitsProcessor::new(
-            299792458, // seed
+            options.seed.unwrap_or(299792458),
             Some(temperature),
             Some(top_p),
         );
@@ -277,8 +321,9 @@ impl CandleBackend {
         println!("[CandleBackend] Processing prompt tokens...");
         let prompt_start = Instant::now();
         
-        // First try to process the entire prompt at once
-        let prompt_processed = if tokens.len() > 1 {
+        // First try to process the entire prompt at once. The batch mask only
+        // covers the batch itself, so a restored cache is extended token by token.
+        let prompt_processed = if reused == 0 && tokens.len() > 1 {
             match self.process_prompt_batch(model, &tokens, &self.device, &mut logits_processor) {
                 Ok(token) => {
                     println!("[CandleBackend] Successfully processed prompt in batch mode");
@@ -296,7 +341,7 @@ impl CandleBackend {
         
         // Fall back to token-by-token processing if batch failed
         if !prompt_processed {
-            for (pos, &token) in tokens.iter().enumerate() {
+            for (pos, &token) in tokens.iter().enumerate().skip(reused) {
                 let input = Tensor::new(&[token as u32], &self.device)
                     .map_err(|e| AppError::Llm(format!("Failed to create input tensor: {}", e)))?
                     .unsqueeze(0)
@@ -319,10 +364,18 @@ impl CandleBackend {
         let prompt_time = prompt_start.elapsed();
         println!("[CandleBackend] Prompt processing took: {:.2}s ({:.2} tokens/s)", 
             prompt_time.as_secs_f32(), 
-            tokens.len() as f32 / prompt_time.as_secs_f32());
+            (tokens.len() - reused) as f32 / prompt_time.as_secs_f32());
+        prompt_cache.save(tokens.clone(), model.clone());
         
         // Add the first generated token
+        let mut decoder = TextDeltaDecoder::default();
+        let stop = self.template.stop_sequences(&options.stop);
+        let mut text = StopFilter::new(&stop, on_text);
         generated_tokens.push(next_token);
+        let first_accepted = decoder
+            .next_delta(tokenizer, &generated_tokens)
+            .is_none_or(|delta| text.push(&delta));
+        let max_tokens = if first_accepted { max_tokens } else { 1 };
         let mut all_tokens = tokens.clone();
         all_tokens.push(next_token as u32);
         
@@ -360,6 +413,12 @@ impl CandleBackend {
             // Add to generated tokens
             generated_tokens.push(next_token);
             all_tokens.push(next_token);
+            if let Some(delta) = decoder.next_delta(tokenizer, &generated_tokens) {
+                if !text.push(&delta) {
+                    println!("[CandleBackend] Generation stopped after {} tokens", generated_tokens.len());
+                    break;
+                }
+            }
             
             let token_time = token_start.elapsed();
             token_times.push(token_time.as_secs_f32());
@@ -377,6 +436,12 @@ impl CandleBackend {
             }
         }
         
+        text.flush();
+        
+        // The last sampled token was never fed through the model
+        prompt_cache.save(all_tokens[..all_tokens.len() - 1].to_vec(), model.clone());
+        drop(prompt_cache);
+        
         let generation_time = generation_start.elapsed();
         println!("[CandleBackend] Generation took: {:.2}s for {} tokens ({:.2} tokens/s)", 
             generation_time.as_secs_f32(), 
@@ -386,38 +451,13 @@ impl CandleBackend {
         // Decode the generated tokens
         let generated_text = tokenizer.decode(&generated_tokens, true)
             .map_err(|e| AppError::Llm(format!("Failed to decode tokens: {}", e)))?;
+        let generated_text = truncate_at_stop(generated_text, &stop);
         
         println!("[CandleBackend] Generation complete: {} tokens", generated_tokens.len());
         
         Ok(generated_text)
     }
     
-    fn apply_chat_template(&self, prompt: &str) -> String {
-        // Apply appropriate chat template based on the model
-        if self.model_id.starts_with("Qwen/") {
-            // Qwen2.5 uses ChatML format
-            format!(
-                "<|im_start|>system\nYou are Qwen, created by Alibaba Cloud. You are a helpful assistant.<|im_end|>\n<|im_start|>user\n{}<|im_end|>\n<|im_start|>assistant\n",
-                prompt
-            )
-        } else {
-            match self.model_id.as_str() {
-                "TinyLlama/TinyLlama-1.1B-Chat-v1.0" => {
-                    format!(
-                        "<|system|>\nYou are a helpful assistant.</s>\n<|user|>\n{}</s>\n<|assistant|>\n",
-                        prompt
-                    )
-                }
-                "microsoft/phi-2" => {
-                    format!("Instruct: {}\nOutput:", prompt)
-                }
-                _ => {
-                    prompt.to_string()
-                }
-            }
-        }
-    }
-    
     fn generate_placeholder_response(&self, prompt: &str, max_tokens: usize) -> String {
         // Provide model-specific placeholder responses when model isn't loaded
         match self.model_id.as_str() {
@@ -580,30 +620,75 @@ impl CandleBackend {
             prompt.to_string()
         }
     }
-    
-    #[allow(dead_code)]
-    pub async fn get_model_info(&self) -> ModelInfo {
+
+    /// Whether model weights are loaded. A held lock means a generation is
+    /// running, so the model is in use.
+    fn model_loaded(&self) -> bool {
+        self.model.try_lock().map(|model| model.is_some()).unwrap_or(true)
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for CandleBackend {
+    fn provider(&self) -> InferenceProvider {
+        InferenceProvider::Candle
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        CandleBackend::generate(self, prompt, options).await
+    }
+
+    async fn stream(self: Arc<Self>, prompt: &str, options: &GenerationOptions) -> Result<TokenStream> {
+        self.stream_chat(&[PromptMessage::user(prompt)], options).await
+    }
+
+    async fn generate_chat(&self, messages: &[PromptMessage], options: &GenerationOptions) -> Result<String> {
+        self.generate_streaming(messages, options, &mut |_| true).await
+    }
+
+    async fn stream_chat(
+        self: Arc<Self>,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        let messages = messages.to_vec();
+        let options = options.clone();
+        Ok(spawn_token_stream(move |on_text| {
+            tokio::runtime::Handle::current()
+                .block_on(self.generate_streaming(&messages, &options, on_text))
+        }))
+    }
+
+    fn token_counter(&self) -> TokenCounter {
+        match &self.tokenizer {
+            Some(tokenizer) => TokenCounter::Tokenizer(tokenizer.clone()),
+            None => TokenCounter::Estimate,
+        }
+    }
+
+    async fn model_info(&self) -> ModelInfo {
+        let loaded = self.model_loaded();
         ModelInfo {
             model_type: self.model_id.clone(),
             device: format!("{:?}", self.device),
             cache_dir: self.cache_dir.to_string_lossy().to_string(),
-            loaded: self.model.lock().unwrap().is_some(),
+            loaded,
             tokenizer_loaded: self.tokenizer.is_some(),
-            model_loaded: self.model.lock().unwrap().is_some(),
             supported_features: vec![],
         }
     }
-}
 
-#[derive(Debug, serde::Serialize)]
-pub struct ModelInfo {
-    pub model_type: String,
-    pub device: String,
-    pub cache_dir: String,
-    pub loaded: bool,
-    pub tokenizer_loaded: bool,
-    pub model_loaded: bool,
-    pub supported_features: Vec<String>,
+    async fn health(&self) -> BackendHealth {
+        if self.model_loaded() && self.tokenizer.is_some() {
+            BackendHealth::Ready
+        } else {
+            BackendHealth::Degraded("Model not loaded, serving placeholder responses".to_string())
+        }
+    }
 }
 
 // Advanced implementation for future use
@@ -0,0 +1,275 @@
+use std::path::Path;
+
+use super::backend::cached_model_file;
+use super::conversation::{MessageRole, PromptMessage};
+
+/// System prompt used when a conversation does not bring its own
+const DEFAULT_SYSTEM_PROMPT: &str = "You are a helpful AI assistant.";
+
+/// Prompt format a local model was fine-tuned on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatTemplate {
+    /// `<|im_start|>role ... <|im_end|>`, used by Qwen and many others
+    ChatMl,
+    /// `<|user|> ... </s>`, used by TinyLlama and Zephyr
+    Zephyr,
+    /// `<|start_header_id|>role<|end_header_id|>`, used by Llama 3
+    Llama3,
+    /// `[INST] ... [/INST]`, used by Mistral and Llama 2
+    Inst,
+    /// `Instruct: ... Output:`, used by Phi-2
+    Phi2,
+    /// `### Instruction: ... ### Response:` for models without a known format
+    Alpaca,
+}
+
+impl ChatTemplate {
+    /// Template for a model, read from its `tokenizer_config.json` in the
+    /// Hugging Face cache when available, otherwise chosen by model id
+    pub fn for_model(model_id: &str, cache_dir: &Path) -> Self {
+        if let Some(template) = tokenizer_config_template(model_id, cache_dir)
+            .as_deref()
+            .and_then(Self::detect)
+        {
+            println!("[ChatTemplate] Using {:?} template from tokenizer config for {}", template, model_id);
+            return template;
+        }
+
+        let template = Self::for_model_id(model_id);
+        println!("[ChatTemplate] Using built-in {:?} template for {}", template, model_id);
+        template
+    }
+
+    /// Built-in table of known model families
+    pub fn for_model_id(model_id: &str) -> Self {
+        let id = model_id.to_lowercase();
+        if id.contains("qwen") || id.contains("smollm") {
+            ChatTemplate::ChatMl
+        } else if id.contains("tinyllama") || id.contains("zephyr") {
+            ChatTemplate::Zephyr
+        } else if id.contains("llama-3") || id.contains("llama3") {
+            ChatTemplate::Llama3
+        } else if id.contains("mistral") || id.contains("llama-2") {
+            ChatTemplate::Inst
+        } else if id.contains("phi-2") {
+            ChatTemplate::Phi2
+        } else {
+            ChatTemplate::Alpaca
+        }
+    }
+
+    /// Recognise a Jinja chat template by the special tokens it emits
+    pub fn detect(jinja: &str) -> Option<Self> {
+        if jinja.contains("<|im_start|>") {
+            Some(ChatTemplate::ChatMl)
+        } else if jinja.contains("<|start_header_id|>") {
+            Some(ChatTemplate::Llama3)
+        } else if jinja.contains("<|user|>") {
+            Some(ChatTemplate::Zephyr)
+        } else if jinja.contains("[INST]") {
+            Some(ChatTemplate::Inst)
+        } else {
+            None
+        }
+    }
+
+    /// Format a conversation as a prompt that ends where the assistant's
+    /// reply should begin
+    pub fn render(&self, messages: &[PromptMessage]) -> String {
+        let system: Vec<&str> = messages
+            .iter()
+            .filter(|m| m.role == MessageRole::System)
+            .map(|m| m.content.as_str())
+            .collect();
+        // Formats without a system role only get one when the caller asks
+        let custom_system = (!system.is_empty()).then(|| system.join("\n\n"));
+        let system = custom_system.clone().unwrap_or_else(|| DEFAULT_SYSTEM_PROMPT.to_string());
+        let turns = messages.iter().filter(|m| m.role != MessageRole::System);
+
+        let mut prompt = String::new();
+        match self {
+            ChatTemplate::ChatMl => {
+                prompt.push_str(&format!("<|im_start|>system\n{}<|im_end|>\n", system));
+                for message in turns {
+                    prompt.push_str(&format!(
+                        "<|im_start|>{}\n{}<|im_end|>\n",
+                        role_name(message.role),
+                        message.content
+                    ));
+                }
+                prompt.push_str("<|im_start|>assistant\n");
+            }
+            ChatTemplate::Zephyr => {
+                prompt.push_str(&format!("<|system|>\n{}</s>\n", system));
+                for message in turns {
+                    prompt.push_str(&format!(
+                        "<|{}|>\n{}</s>\n",
+                        role_name(message.role),
+                        message.content
+                    ));
+                }
+                prompt.push_str("<|assistant|>\n");
+            }
+            ChatTemplate::Llama3 => {
+                prompt.push_str(&format!(
+                    "<|start_header_id|>system<|end_header_id|>\n\n{}<|eot_id|>",
+                    system
+                ));
+                for message in turns {
+                    prompt.push_str(&format!(
+                        "<|start_header_id|>{}<|end_header_id|>\n\n{}<|eot_id|>",
+                        role_name(message.role),
+                        message.content
+                    ));
+                }
+                prompt.push_str("<|start_header_id|>assistant<|end_header_id|>\n\n");
+            }
+            ChatTemplate::Inst => {
+                // No system role; it goes in front of the first instruction
+                let mut pending_system = custom_system;
+                for message in turns {
+                    match message.role {
+                        MessageRole::Assistant => {
+                            prompt.push_str(&format!(" {}</s>", message.content));
+                        }
+                        _ => match pending_system.take() {
+                            Some(system) => prompt.push_str(&format!(
+                                "[INST] {}\n\n{} [/INST]",
+                                system, message.content
+                            )),
+                            None => prompt.push_str(&format!("[INST] {} [/INST]", message.content)),
+                        },
+                    }
+                }
+            }
+            ChatTemplate::Phi2 => {
+                let mut pending_system = custom_system;
+                for message in turns {
+                    match message.role {
+                        MessageRole::Assistant => {
+                            prompt.push_str(&format!(" {}\n", message.content));
+                        }
+                        _ => match pending_system.take() {
+                            Some(system) => prompt.push_str(&format!(
+                                "Instruct: {}\n{}\nOutput:",
+                                system, message.content
+                            )),
+                            None => prompt.push_str(&format!("Instruct: {}\nOutput:", message.content)),
+                        },
+                    }
+                }
+            }
+            ChatTemplate::Alpaca => {
+                if let Some(system) = custom_system {
+                    prompt.push_str(&format!("{}\n\n", system));
+                }
+                for message in turns {
+                    match message.role {
+                        MessageRole::Assistant => {
+                            prompt.push_str(&format!("{}\n\n", message.content));
+                        }
+                        _ => prompt.push_str(&format!(
+                            "### Instruction:\n{}\n\n### Response:\n",
+                            message.content
+                        )),
+                    }
+                }
+            }
+        }
+        prompt
+    }
+
+    /// Text that marks the end of the assistant's turn when the tokenizer
+    /// does not stop on it as a special token
+    pub fn stop_markers(&self) -> &'static [&'static str] {
+        match self {
+            ChatTemplate::ChatMl => &["<|im_end|>", "<|im_start|>"],
+            ChatTemplate::Zephyr => &["</s>", "<|user|>"],
+            ChatTemplate::Llama3 => &["<|eot_id|>", "<|start_header_id|>"],
+            ChatTemplate::Inst => &["</s>", "[INST]"],
+            ChatTemplate::Phi2 => &["\nInstruct:"],
+            ChatTemplate::Alpaca => &["### Instruction:"],
+        }
+    }
+
+    /// The caller's stop sequences plus the template's end-of-turn markers
+    pub fn stop_sequences(&self, stop: &[String]) -> Vec<String> {
+        stop.iter()
+            .cloned()
+            .chain(self.stop_markers().iter().map(|marker| marker.to_string()))
+            .collect()
+    }
+}
+
+fn role_name(role: MessageRole) -> &'static str {
+    match role {
+        MessageRole::System => "system",
+        MessageRole::User => "user",
+        MessageRole::Assistant => "assistant",
+    }
+}
+
+/// Read `chat_template` from a cached snapshot of the model repo
+fn tokenizer_config_template(model_id: &str, cache_dir: &Path) -> Option<String> {
+    let path = cached_model_file(model_id, cache_dir, "tokenizer_config.json")?;
+    let content = std::fs::read_to_string(path).ok()?;
+    let config: serde_json::Value = serde_json::from_str(&content).ok()?;
+    match config.get("chat_template")? {
+        serde_json::Value::String(template) => Some(template.clone()),
+        // Some repos ship several named templates; the default one is used
+        serde_json::Value::Array(templates) => templates
+            .iter()
+            .find(|t| t.get("name").and_then(|n| n.as_str()) == Some("default"))
+            .or_else(|| templates.first())
+            .and_then(|t| t.get("template"))
+            .and_then(|t| t.as_str())
+            .map(|t| t.to_string()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_chatml_conversation() {
+        let messages = vec![
+            PromptMessage::system("Be brief."),
+            PromptMessage::user("Hi"),
+            PromptMessage::assistant("Hello!"),
+            PromptMessage::user("What is 2+2?"),
+        ];
+        let prompt = ChatTemplate::ChatMl.render(&messages);
+        assert_eq!(
+            prompt,
+            "<|im_start|>system\nBe brief.<|im_end|>\n\
+             <|im_start|>user\nHi<|im_end|>\n\
+             <|im_start|>assistant\nHello!<|im_end|>\n\
+             <|im_start|>user\nWhat is 2+2?<|im_end|>\n\
+             <|im_start|>assistant\n"
+        );
+
+        let phi = ChatTemplate::Phi2.render(&[PromptMessage::user("Hi")]);
+        assert_eq!(phi, "Instruct: Hi\nOutput:");
+    }
+
+    #[test]
+    fn test_template_from_tokenizer_config() {
+        let cache_dir = std::env::temp_dir().join(format!("chat-template-test-{}", std::process::id()));
+        let snapshot = cache_dir.join("models--acme--custom-chat").join("snapshots").join("abc123");
+        std::fs::create_dir_all(&snapshot).unwrap();
+        std::fs::write(
+            snapshot.join("tokenizer_config.json"),
+            r#"{"chat_template": "{% for m in messages %}<|start_header_id|>{{ m.role }}<|end_header_id|>{% endfor %}"}"#,
+        )
+        .unwrap();
+
+        assert_eq!(ChatTemplate::for_model("acme/custom-chat", &cache_dir), ChatTemplate::Llama3);
+        // Not in the cache, so the built-in table decides
+        assert_eq!(ChatTemplate::for_model("Qwen/Qwen2.5-0.5B-Instruct", &cache_dir), ChatTemplate::ChatMl);
+        assert_eq!(ChatTemplate::for_model("acme/other", &cache_dir), ChatTemplate::Alpaca);
+
+        std::fs::remove_dir_all(&cache_dir).unwrap();
+    }
+}
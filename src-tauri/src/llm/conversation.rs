@@ -0,0 +1,313 @@
+use crate::config::Config;
+use crate::error::{AppError, Result};
+use crate::models::ChatMessage;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use super::options::GenerationOptions;
+use super::token_budget::TokenBudget;
+use super::LlmClient;
+
+const HISTORY_SECTION: &str = "conversation history";
+
+/// Longest summary of older turns a model is asked for
+const SUMMARY_REPLY_TOKENS: usize = 256;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageRole {
+    System,
+    User,
+    Assistant,
+}
+
+/// A single role-tagged message sent to a model
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+pub struct PromptMessage {
+    pub role: MessageRole,
+    pub content: String,
+}
+
+impl PromptMessage {
+    pub fn system(content: impl Into<String>) -> Self {
+        Self { role: MessageRole::System, content: content.into() }
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self { role: MessageRole::User, content: content.into() }
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self { role: MessageRole::Assistant, content: content.into() }
+    }
+}
+
+/// Flatten role-tagged messages into a single prompt for engines that only
+/// accept plain text. A lone user message is passed through unchanged.
+pub fn render_transcript(messages: &[PromptMessage]) -> String {
+    if let [message] = messages {
+        if message.role == MessageRole::User {
+            return message.content.clone();
+        }
+    }
+
+    let mut transcript = String::new();
+    for message in messages {
+        let speaker = match message.role {
+            MessageRole::System => "System",
+            MessageRole::User => "User",
+            MessageRole::Assistant => "Assistant",
+        };
+        transcript.push_str(&format!("{}: {}\n\n", speaker, message.content));
+    }
+    transcript.push_str("Assistant:");
+    transcript
+}
+
+/// Build the message list for a chat turn from stored conversation history.
+///
+/// The caller reserves the system prompt, the current message and anything
+/// else the prompt must carry in `budget` first. The newest turns are then
+/// kept verbatim while they fit in the configured share of what is left; older
+/// turns are condensed into a summary so follow-up questions
+/// keep their context. The summary is cached per conversation and only
+/// extended when more turns overflow.
+pub async fn build_chat_messages(
+    llm: &LlmClient,
+    model: &str,
+    system_prompt: &str,
+    history: &[ChatMessage],
+    current_message: &str,
+    budget: &mut TokenBudget,
+) -> Vec<PromptMessage> {
+    // The frontend saves the user message before asking for a reply
+    let history = match history.split_last() {
+        Some((last, rest)) if last.is_user && last.content == current_message => rest,
+        _ => history,
+    };
+
+    let history_tokens = (budget.remaining() as f32 * Config::get().chat.history_share) as usize;
+    // A quarter of the share goes to the summary once not every turn fits
+    let summary_tokens = history_tokens / 4;
+
+    // Turns covered by the cached summary are not sent again
+    let cached = history
+        .first()
+        .and_then(|message| llm.summaries.get(message.conversation_id))
+        .and_then(|summary| {
+            let index = history.iter().position(|message| message.id == summary.through)?;
+            Some((index + 1, summary.text))
+        });
+    let (start, mut summary) = match cached {
+        Some((start, text)) => (start, Some(text)),
+        None => (0, None),
+    };
+
+    let (mut older, mut recent) = history.split_at(start);
+    if summary.is_some() || !split_history(recent, budget, history_tokens).0.is_empty() {
+        let verbatim_tokens = history_tokens - summary_tokens;
+        if !split_history(recent, budget, verbatim_tokens).0.is_empty() {
+            // Fold older turns until the rest use half the room, so the next
+            // few turns fit without another summary
+            let (overflow, kept) = split_history(recent, budget, verbatim_tokens / 2);
+            println!("[Conversation] Summarising {} older messages", overflow.len());
+            match fold_summary(llm, model, summary.as_deref(), overflow).await {
+                Ok(text) => {
+                    if let (Some(first), Some(last)) = (history.first(), overflow.last()) {
+                        llm.summaries.insert(first.conversation_id, ConversationSummary {
+                            through: last.id,
+                            text: text.clone(),
+                        });
+                    }
+                    summary = Some(text);
+                }
+                Err(e) => eprintln!("[Conversation] Failed to summarise history: {}", e),
+            }
+            older = &history[..history.len() - kept.len()];
+            recent = kept;
+        }
+    }
+
+    let dropped: Vec<String> = older.iter().map(|message| message.content.clone()).collect();
+    budget.record_dropped(HISTORY_SECTION, &dropped);
+    for message in recent {
+        budget.reserve(&message.content);
+    }
+
+    let mut messages = vec![PromptMessage::system(system_prompt)];
+
+    if let Some(summary) = summary.filter(|_| !older.is_empty()) {
+        let summary = budget.truncate(&summary, summary_tokens).to_string();
+        budget.summarised(HISTORY_SECTION, &summary);
+        messages.push(PromptMessage::system(format!(
+            "Summary of the earlier conversation:\n{}",
+            summary
+        )));
+    }
+
+    messages.extend(recent.iter().map(|message| {
+        if message.is_user {
+            PromptMessage::user(message.content.clone())
+        } else {
+            PromptMessage::assistant(message.content.clone())
+        }
+    }));
+    messages.push(PromptMessage::user(current_message));
+
+    messages
+}
+
+/// Split history into turns that overflow `max_tokens` and the newest turns
+/// that fit in it
+fn split_history<'a>(
+    history: &'a [ChatMessage],
+    budget: &TokenBudget,
+    max_tokens: usize,
+) -> (&'a [ChatMessage], &'a [ChatMessage]) {
+    let mut used = 0;
+    let mut split = history.len();
+    for (index, message) in history.iter().enumerate().rev() {
+        used += budget.count(&message.content);
+        if used > max_tokens {
+            break;
+        }
+        split = index;
+    }
+    history.split_at(split)
+}
+
+/// Summary of the start of a conversation
+#[derive(Debug, Clone)]
+struct ConversationSummary {
+    /// Last message the summary covers
+    through: Uuid,
+    text: String,
+}
+
+/// Summaries of older turns by conversation
+#[derive(Default)]
+pub struct SummaryCache(Mutex<HashMap<Uuid, ConversationSummary>>);
+
+impl SummaryCache {
+    fn get(&self, conversation_id: Uuid) -> Option<ConversationSummary> {
+        self.0.lock().ok()?.get(&conversation_id).cloned()
+    }
+
+    fn insert(&self, conversation_id: Uuid, summary: ConversationSummary) {
+        if let Ok(mut summaries) = self.0.lock() {
+            summaries.insert(conversation_id, summary);
+        }
+    }
+}
+
+/// Fold `turns` into `previous`, a batch at a time so that each request fits
+/// the model's context next to the summary so far
+async fn fold_summary(
+    llm: &LlmClient,
+    model: &str,
+    previous: Option<&str>,
+    turns: &[ChatMessage],
+) -> Result<String> {
+    let options = GenerationOptions::default().with_max_tokens(SUMMARY_REPLY_TOKENS);
+    let lines: Vec<String> = turns
+        .iter()
+        .map(|message| {
+            let speaker = if message.is_user { "User" } else { "Assistant" };
+            format!("{}: {}\n", speaker, message.content)
+        })
+        .collect();
+
+    let mut summary = previous.map(str::to_string);
+    let mut rest = &lines[..];
+    while !rest.is_empty() {
+        let mut budget = llm.token_budget(model, SUMMARY_REPLY_TOKENS).await;
+        budget.reserve(&summary_prompt(summary.as_deref(), ""));
+        let batch = budget.fit_trimmed("summarised turns", rest);
+        if batch.is_empty() {
+            return Err(AppError::Llm("No room left to summarise the conversation".to_string()));
+        }
+        rest = &rest[batch.len()..];
+
+        let prompt = summary_prompt(summary.as_deref(), &batch.concat());
+        let reply = llm
+            .send_chat_with_model(&[PromptMessage::user(prompt)], model, &options)
+            .await?;
+        summary = Some(reply.trim().to_string());
+    }
+    Ok(summary.unwrap_or_default())
+}
+
+fn summary_prompt(previous: Option<&str>, transcript: &str) -> String {
+    match previous {
+        Some(previous) => format!(
+            "Here is a summary of a conversation so far:\n{}\n\nUpdate it with the later turns \
+            below, in a few sentences. Keep names, numbers, lists and any items the user may \
+            refer back to.\n\n{}",
+            previous, transcript
+        ),
+        None => format!(
+            "Summarise the following conversation in a few sentences. Keep names, numbers, \
+            lists and any items the user may refer back to.\n\n{}",
+            transcript
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::token_budget::TokenCounter;
+    use crate::models::ChatMode;
+    use uuid::Uuid;
+
+    fn message(content: &str, is_user: bool) -> ChatMessage {
+        ChatMessage::new(Uuid::new_v4(), content.to_string(), is_user, ChatMode::General)
+    }
+
+    #[test]
+    fn test_split_history_keeps_newest_turns_within_budget() {
+        let history = vec![
+            message(&"a".repeat(400), true),
+            message(&"b".repeat(400), false),
+            message("short question", true),
+            message("short answer", false),
+        ];
+
+        let budget = TokenBudget::new(TokenCounter::Estimate, 10_000, 0);
+        let (older, recent) = split_history(&history, &budget, 120);
+        assert_eq!(older.len(), 2);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].content, "short question");
+
+        let (older, recent) = split_history(&history, &budget, 10_000);
+        assert!(older.is_empty());
+        assert_eq!(recent.len(), 4);
+    }
+
+    #[test]
+    fn test_summary_prompt_folds_later_turns_into_previous_summary() {
+        let first = summary_prompt(None, "User: hi\n");
+        assert!(first.starts_with("Summarise the following conversation"));
+        assert!(first.ends_with("User: hi\n"));
+
+        let folded = summary_prompt(Some("They asked about pears."), "User: and apples?\n");
+        assert!(folded.contains("They asked about pears."));
+        assert!(folded.ends_with("User: and apples?\n"));
+    }
+
+    #[test]
+    fn test_render_transcript() {
+        assert_eq!(render_transcript(&[PromptMessage::user("hi")]), "hi");
+
+        let transcript = render_transcript(&[
+            PromptMessage::system("Be brief."),
+            PromptMessage::user("List two fruits"),
+            PromptMessage::assistant("Apple, pear"),
+            PromptMessage::user("And the second one?"),
+        ]);
+        assert!(transcript.starts_with("System: Be brief."));
+        assert!(transcript.contains("Assistant: Apple, pear"));
+        assert!(transcript.ends_with("User: And the second one?\n\nAssistant:"));
+    }
+}
@@ -10,6 +10,14 @@ use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use rand::Rng;
 use serde::Deserialize;
+use crate::config::InferenceProvider;
+use async_trait::async_trait;
+use super::backend::{spawn_token_stream, BackendHealth, InferenceBackend, ModelInfo, TextDeltaDecoder, TokenStream};
+use super::chat_template::ChatTemplate;
+use super::conversation::PromptMessage;
+use super::options::{truncate_at_stop, GenerationOptions, StopFilter};
+use super::prompt_cache::{PromptCache, PROMPT_CACHE_ENTRIES};
+use super::token_budget::TokenCounter;
 
 enum ModelType {
     Quantized(QLlamaWeights),
@@ -44,12 +52,16 @@ pub struct CraneBackend {
     device: Device,
     device_type: String,
     model: Arc<Mutex<Option<ModelType>>>,
-    tokenizer: Option<Tokenizer>,
+    /// KV cache snapshots of the quantized model for reusing the start of
+    /// earlier prompts
+    prompt_cache: Mutex<PromptCache<QLlamaWeights>>,
+    tokenizer: Option<Arc<Tokenizer>>,
     eos_token_id: Option<u32>,
     temperature: f64,
     top_p: f64,
     seed: u64,
     use_quantized: bool,
+    template: ChatTemplate,
 }
 
 impl CraneBackend {
@@ -99,12 +111,14 @@ impl CraneBackend {
             device,
             device_type,
             model: Arc::new(Mutex::new(None)),
+            prompt_cache: Mutex::new(PromptCache::new(PROMPT_CACHE_ENTRIES)),
             tokenizer: None,
             eos_token_id: None,
             temperature: 0.7, // More creative than Candle's default
             top_p: 0.9,
             seed: rand::thread_rng().gen(), // Random seed for variety
             use_quantized,
+            template: ChatTemplate::for_model(model_id, &cache_dir),
         };
         
         // Try to load the model
@@ -379,7 +393,7 @@ impl CraneBackend {
         println!("[CraneBackend] Model weights stored");
         
         println!("[CraneBackend] Storing tokenizer...");
-        self.tokenizer = Some(tokenizer);
+        self.tokenizer = Some(Arc::new(tokenizer));
         self.eos_token_id = eos_token_id;
         
         println!("[CraneBackend] Final state check:");
@@ -475,7 +489,7 @@ impl CraneBackend {
         let eos_token_id = tokenizer.token_to_id("</s>")
             .or_else(|| tokenizer.token_to_id("<|endoftext|>"));
         
-        self.tokenizer = Some(tokenizer);
+        self.tokenizer = Some(Arc::new(tokenizer));
         self.eos_token_id = eos_token_id;
         
         // Load config
@@ -534,14 +548,31 @@ impl CraneBackend {
         Ok(())
     }
     
-    pub async fn generate(&self, prompt: &str, max_tokens: usize) -> Result<String> {
+    pub async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        self.generate_streaming(&[PromptMessage::user(prompt)], options, &mut |_| true).await
+    }
+
+    /// Generate a reply to `messages`, passing each newly decoded piece of text
+    /// to `on_text`. Generation stops early when `on_text` returns false or a
+    /// stop sequence is produced. Unset sampling options use the backend's own
+    /// settings.
+    pub async fn generate_streaming(
+        &self,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+        on_text: &mut (dyn FnMut(&str) -> bool + Send),
+    ) -> Result<String> {
+        let prompt = messages.last().map(|m| m.content.as_str()).unwrap_or_default();
         let generation_start = Instant::now();
+        let max_tokens = options.max_tokens.max(1);
+        let temperature = options.temperature.map_or(self.temperature, |t| t.clamp(0.1, 2.0));
+        let top_p = options.top_p.map_or(self.top_p, |p| p.clamp(0.1, 1.0));
         
         println!("[CraneBackend] =================================");
         println!("[CraneBackend] Model: {}", self.model_id);
         println!("[CraneBackend] Device: {}", self.device_type);
-        println!("[CraneBackend] Temperature: {}", self.temperature);
-        println!("[CraneBackend] Top-p: {}", self.top_p);
+        println!("[CraneBackend] Temperature: {}", temperature);
+        println!("[CraneBackend] Top-p: {}", top_p);
         println!("[CraneBackend] Max tokens: {}", max_tokens);
         println!("[CraneBackend] Prompt preview: {}", 
             prompt.chars().take(100).collect::<String>());
@@ -551,7 +582,9 @@ impl CraneBackend {
             Some(t) => t,
             None => {
                 println!("[CraneBackend] Tokenizer not loaded, using placeholder response");
-                return Ok(self.generate_placeholder_response(prompt, max_tokens));
+                let response = self.generate_placeholder_response(prompt, max_tokens);
+                on_text(&response);
+                return Ok(response);
             }
         };
         
@@ -560,40 +593,41 @@ impl CraneBackend {
             Some(m) => m,
             None => {
                 println!("[CraneBackend] Model not loaded, using placeholder response");
-                return Ok(self.generate_placeholder_response(prompt, max_tokens));
+                let response = self.generate_placeholder_response(prompt, max_tokens);
+                on_text(&response);
+                return Ok(response);
             }
         };
         
-        // Apply chat template based on actual model being used
-        let formatted_prompt = match self.model_id.as_str() {
-            model if model.starts_with("Qwen") => {
-                // Qwen models use ChatML format
-                format!("<|im_start|>system\nYou are a helpful AI assistant.<|im_end|>\n<|im_start|>user\n{}<|im_end|>\n<|im_start|>assistant\n", prompt)
-            }
-            "TinyLlama/TinyLlama-1.1B-Chat-v1.0" => {
-                // TinyLlama uses a specific format
-                format!("<|system|>\nYou are a helpful AI assistant.</s>\n<|user|>\n{}</s>\n<|assistant|>\n", prompt)
-            }
-            "microsoft/phi-2" => {
-                // Phi-2 uses a simpler format
-                format!("Instruct: {}\nOutput:", prompt)
-            }
-            _ => {
-                // Generic format
-                format!("### Instruction:\n{}\n\n### Response:\n", prompt)
-            }
-        };
+        // Apply the model's chat template
+        let formatted_prompt = self.template.render(messages);
+        let stop = self.template.stop_sequences(&options.stop);
         
         // Tokenize with optimizations
         let tokens = tokenizer.encode(formatted_prompt.clone(), true)
             .map_err(|e| AppError::Llm(format!("Failed to encode prompt: {}", e)))?;
         let tokens = tokens.get_ids();
         
+        // The standard model runs without a KV cache, so only the quantized one
+        // can continue from an earlier prompt
+        let mut prompt_cache = self.prompt_cache.lock().unwrap();
+        let reused = match model {
+            ModelType::Quantized(m) => match prompt_cache.restore(tokens) {
+                Some((reused, state)) => {
+                    println!("[CraneBackend] Reusing KV cache for {} of {} prompt tokens", reused, tokens.len());
+                    *m = state;
+                    reused
+                }
+                None => 0,
+            },
+            ModelType::Standard { .. } => 0,
+        };
+        
         let mut generated_tokens = Vec::new();
         let mut logits_processor = candle_transformers::generation::LogitsProcessor::new(
-            self.seed,
-            Some(self.temperature),
-            Some(self.top_p)
+            options.seed.unwrap_or(self.seed),
+            Some(temperature),
+            Some(top_p)
         );
         
         // Optimized token generation for Crane
@@ -601,7 +635,7 @@ impl CraneBackend {
         let prompt_start = Instant::now();
         let mut last_logits = None;
         
-        for (pos, &token) in tokens.iter().enumerate() {
+        for (pos, &token) in tokens.iter().enumerate().skip(reused) {
             let input = Tensor::new(&[token as u32], &self.device)
                 .map_err(|e| AppError::Llm(format!("Failed to create input tensor: {}", e)))?;
             let input = input.unsqueeze(0)
@@ -620,9 +654,12 @@ impl CraneBackend {
         let prompt_elapsed = prompt_start.elapsed();
         println!("[CraneBackend] Prompt processing took {:.2}s ({} tokens, {:.1} tokens/s)",
             prompt_elapsed.as_secs_f32(),
-            tokens.len(),
-            tokens.len() as f32 / prompt_elapsed.as_secs_f32()
+            tokens.len() - reused,
+            (tokens.len() - reused) as f32 / prompt_elapsed.as_secs_f32()
         );
+        if let ModelType::Quantized(m) = model {
+            prompt_cache.save(tokens.to_vec(), m.clone());
+        }
         
         // Start generation from the last prompt token
         if let Some(logits) = last_logits {
@@ -633,11 +670,17 @@ impl CraneBackend {
             let mut current_token = logits_processor.sample(&squeezed_logits)
                 .map_err(|e| AppError::Llm(format!("Failed to sample token: {}", e)))?;
             
+            let mut decoder = TextDeltaDecoder::default();
+            let mut text = StopFilter::new(&stop, on_text);
             generated_tokens.push(current_token);
+            let first_accepted = decoder
+                .next_delta(tokenizer, &generated_tokens)
+                .is_none_or(|delta| text.push(&delta));
+            let remaining = if first_accepted { max_tokens - 1 } else { 0 };
             
             // Generate remaining tokens
             let generation_start = Instant::now();
-            for gen_pos in 0..max_tokens - 1 {  // -1 because we already generated one token
+            for gen_pos in 0..remaining {  // we already generated one token
                 if Some(current_token) == self.eos_token_id {
                     println!("[CraneBackend] Hit EOS token at position {}", gen_pos);
                     break;
@@ -666,6 +709,20 @@ impl CraneBackend {
                 current_token = logits_processor.sample(&logits)
                     .map_err(|e| AppError::Llm(format!("Failed to sample: {}", e)))?;
                 generated_tokens.push(current_token);
+                if let Some(delta) = decoder.next_delta(tokenizer, &generated_tokens) {
+                    if !text.push(&delta) {
+                        println!("[CraneBackend] Generation stopped after {} tokens", generated_tokens.len());
+                        break;
+                    }
+                }
+            }
+            text.flush();
+            
+            // The last sampled token was never fed through the model
+            if let ModelType::Quantized(m) = model {
+                let mut processed = tokens.to_vec();
+                processed.extend_from_slice(&generated_tokens[..generated_tokens.len() - 1]);
+                prompt_cache.save(processed, m.clone());
             }
             
             let gen_elapsed = generation_start.elapsed();
@@ -679,6 +736,7 @@ impl CraneBackend {
         // Decode generated tokens
         let generated_text = tokenizer.decode(&generated_tokens, true)
             .map_err(|e| AppError::Llm(format!("Failed to decode tokens: {}", e)))?;
+        let generated_text = truncate_at_stop(generated_text, &stop);
         
         let total_elapsed = generation_start.elapsed();
         println!("[CraneBackend] =================================");
@@ -724,13 +782,63 @@ impl CraneBackend {
         self.seed = seed;
         println!("[CraneBackend] Seed set to: {}", self.seed);
     }
-    
-    #[allow(dead_code)]
-    pub async fn get_model_info(&self) -> ModelInfo {
+
+    /// Whether model weights are loaded. A held lock means a generation is
+    /// running, so the model is in use.
+    fn model_loaded(&self) -> bool {
+        self.model.try_lock().map(|model| model.is_some()).unwrap_or(true)
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for CraneBackend {
+    fn provider(&self) -> InferenceProvider {
+        InferenceProvider::Crane
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        CraneBackend::generate(self, prompt, options).await
+    }
+
+    async fn stream(self: Arc<Self>, prompt: &str, options: &GenerationOptions) -> Result<TokenStream> {
+        self.stream_chat(&[PromptMessage::user(prompt)], options).await
+    }
+
+    async fn generate_chat(&self, messages: &[PromptMessage], options: &GenerationOptions) -> Result<String> {
+        self.generate_streaming(messages, options, &mut |_| true).await
+    }
+
+    async fn stream_chat(
+        self: Arc<Self>,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        let messages = messages.to_vec();
+        let options = options.clone();
+        Ok(spawn_token_stream(move |on_text| {
+            tokio::runtime::Handle::current()
+                .block_on(self.generate_streaming(&messages, &options, on_text))
+        }))
+    }
+
+    fn token_counter(&self) -> TokenCounter {
+        match &self.tokenizer {
+            Some(tokenizer) => TokenCounter::Tokenizer(tokenizer.clone()),
+            None => TokenCounter::Estimate,
+        }
+    }
+
+    async fn model_info(&self) -> ModelInfo {
+        let loaded = self.model_loaded();
         ModelInfo {
             model_type: self.model_id.clone(),
             device: self.device_type.clone(),
-            model_loaded: self.model.lock().unwrap().is_some(),
+            cache_dir: self.cache_dir.to_string_lossy().to_string(),
+            loaded,
             tokenizer_loaded: self.tokenizer.is_some(),
             supported_features: vec![
                 "quantized_models".to_string(),
@@ -739,19 +847,14 @@ impl CraneBackend {
                 "configurable_temperature".to_string(),
                 if self.device_type == "Metal" { "metal_acceleration".to_string() } else { "cpu_optimized".to_string() }
             ],
-            temperature: self.temperature,
-            top_p: self.top_p,
         }
     }
-}
 
-#[derive(Debug, serde::Serialize)]
-pub struct ModelInfo {
-    pub model_type: String,
-    pub device: String,
-    pub model_loaded: bool,
-    pub tokenizer_loaded: bool,
-    pub supported_features: Vec<String>,
-    pub temperature: f64,
-    pub top_p: f64,
-}
\ No newline at end of file
+    async fn health(&self) -> BackendHealth {
+        if self.model_loaded() && self.tokenizer.is_some() {
+            BackendHealth::Ready
+        } else {
+            BackendHealth::Degraded("Model not loaded, serving placeholder responses".to_string())
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use crate::config::InferenceProvider;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The provider and model that produced a reply
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServedBy {
+    pub provider: InferenceProvider,
+    pub model: String,
+    /// True when the configured provider did not answer
+    pub fallback: bool,
+}
+
+/// Configured provider first, then the fallback chain, without duplicates
+pub fn provider_order(primary: InferenceProvider, chain: &[InferenceProvider]) -> Vec<InferenceProvider> {
+    let mut order = vec![primary];
+    for provider in chain {
+        if !order.contains(provider) {
+            order.push(*provider);
+        }
+    }
+    order
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum BreakerState {
+    /// Requests go through
+    Closed,
+    /// Requests are skipped until the cooldown ends
+    Open,
+    /// The cooldown ended and one trial request is in flight
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProviderStatus {
+    pub provider: InferenceProvider,
+    pub state: BreakerState,
+    pub consecutive_failures: u32,
+    pub retry_in_secs: Option<u64>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+struct Breaker {
+    consecutive_failures: u32,
+    open_until: Option<Instant>,
+    trial_in_flight: bool,
+    last_error: Option<String>,
+}
+
+/// A request let through by `CircuitBreakers::allows`, whose outcome is
+/// reported with `succeeded` or `failed`. A half-open trial dropped without
+/// either, such as a cancelled stream, counts as failed, so the breaker is
+/// never left waiting for it.
+#[must_use]
+pub struct Attempt<'a> {
+    breakers: &'a CircuitBreakers,
+    provider: InferenceProvider,
+    trial: bool,
+    settled: bool,
+}
+
+impl Attempt<'_> {
+    pub fn succeeded(mut self) {
+        self.settled = true;
+        self.breakers.record_success(self.provider);
+    }
+
+    pub fn failed(mut self, error: &str) {
+        self.settled = true;
+        self.breakers.record_failure(self.provider, error);
+    }
+}
+
+impl Drop for Attempt<'_> {
+    fn drop(&mut self) {
+        if self.trial && !self.settled {
+            self.breakers
+                .record_failure(self.provider, "Trial request ended without a result");
+        }
+    }
+}
+
+/// Per-provider circuit breakers.
+///
+/// A provider that fails `failure_threshold` times in a row is skipped for
+/// `cooldown`. After that a single trial request is let through; success
+/// closes the breaker and failure opens it for another cooldown.
+pub struct CircuitBreakers {
+    failure_threshold: u32,
+    cooldown: Duration,
+    breakers: Mutex<HashMap<InferenceProvider, Breaker>>,
+}
+
+impl CircuitBreakers {
+    pub fn new(failure_threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            failure_threshold: failure_threshold.max(1),
+            cooldown,
+            breakers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The request to `provider` that may be sent now, if any
+    pub fn allows(&self, provider: InferenceProvider) -> Option<Attempt<'_>> {
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = breakers.entry(provider).or_default();
+        let trial = match breaker.open_until {
+            None => false,
+            Some(until) if Instant::now() < until || breaker.trial_in_flight => return None,
+            Some(_) => {
+                breaker.trial_in_flight = true;
+                true
+            }
+        };
+        Some(Attempt {
+            breakers: self,
+            provider,
+            trial,
+            settled: false,
+        })
+    }
+
+    pub fn record_success(&self, provider: InferenceProvider) {
+        let mut breakers = self.breakers.lock().unwrap();
+        if let Some(breaker) = breakers.get_mut(&provider) {
+            if breaker.open_until.is_some() {
+                println!("[LLM] {:?} recovered, closing its circuit breaker", provider);
+            }
+            *breaker = Breaker::default();
+        }
+    }
+
+    pub fn record_failure(&self, provider: InferenceProvider, error: &str) {
+        let mut breakers = self.breakers.lock().unwrap();
+        let breaker = breakers.entry(provider).or_default();
+        breaker.consecutive_failures += 1;
+        breaker.last_error = Some(error.to_string());
+        if breaker.trial_in_flight || breaker.consecutive_failures >= self.failure_threshold {
+            println!(
+                "[LLM] {:?} failed {} times in a row, skipping it for {}s",
+                provider,
+                breaker.consecutive_failures,
+                self.cooldown.as_secs()
+            );
+            breaker.open_until = Some(Instant::now() + self.cooldown);
+            breaker.trial_in_flight = false;
+        }
+    }
+
+    /// State of every provider that has been used
+    pub fn statuses(&self) -> Vec<ProviderStatus> {
+        let now = Instant::now();
+        let breakers = self.breakers.lock().unwrap();
+        let mut statuses: Vec<ProviderStatus> = breakers
+            .iter()
+            .map(|(provider, breaker)| {
+                let state = match breaker.open_until {
+                    None => BreakerState::Closed,
+                    Some(_) if breaker.trial_in_flight => BreakerState::HalfOpen,
+                    Some(_) => BreakerState::Open,
+                };
+                ProviderStatus {
+                    provider: *provider,
+                    state,
+                    consecutive_failures: breaker.consecutive_failures,
+                    retry_in_secs: breaker
+                        .open_until
+                        .filter(|until| *until > now)
+                        .map(|until| (until - now).as_secs()),
+                    last_error: breaker.last_error.clone(),
+                }
+            })
+            .collect();
+        statuses.sort_by_key(|status| format!("{:?}", status.provider));
+        statuses
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens_after_threshold_and_recovers() {
+        let breakers = CircuitBreakers::new(2, Duration::from_secs(60));
+        let provider = InferenceProvider::Candle;
+
+        breakers.record_failure(provider, "load failed");
+        assert!(breakers.allows(provider).is_some());
+        breakers.record_failure(provider, "load failed");
+        assert!(breakers.allows(provider).is_none());
+        assert_eq!(breakers.statuses()[0].state, BreakerState::Open);
+
+        breakers.record_success(provider);
+        assert!(breakers.allows(provider).is_some());
+        assert_eq!(breakers.statuses()[0].consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_half_open_allows_a_single_trial() {
+        let breakers = CircuitBreakers::new(1, Duration::ZERO);
+        let provider = InferenceProvider::Ollama;
+
+        breakers.record_failure(provider, "connection refused");
+        let trial = breakers.allows(provider);
+        assert!(trial.is_some());
+        // The trial is still running, so nothing else goes through
+        assert!(breakers.allows(provider).is_none());
+        assert_eq!(breakers.statuses()[0].state, BreakerState::HalfOpen);
+
+        trial.unwrap().failed("connection refused");
+        assert_eq!(breakers.statuses()[0].state, BreakerState::Open);
+    }
+
+    #[test]
+    fn test_dropped_trial_counts_as_failed() {
+        let breakers = CircuitBreakers::new(1, Duration::from_secs(60));
+        let provider = InferenceProvider::OpenAiCompatible;
+
+        breakers.record_failure(provider, "timed out");
+        breakers.breakers.lock().unwrap().get_mut(&provider).unwrap().open_until = Some(Instant::now());
+        let trial = breakers.allows(provider);
+        assert!(trial.is_some());
+        drop(trial);
+
+        // The breaker opened again rather than waiting for the trial forever
+        let status = &breakers.statuses()[0];
+        assert_eq!(status.state, BreakerState::Open);
+        assert!(status.retry_in_secs.is_some());
+        assert_eq!(status.consecutive_failures, 2);
+    }
+
+    #[test]
+    fn test_provider_order_starts_with_primary() {
+        let order = provider_order(
+            InferenceProvider::Ollama,
+            &[InferenceProvider::LlamaCpp, InferenceProvider::Ollama, InferenceProvider::LlamaCpp],
+        );
+        assert_eq!(order, vec![InferenceProvider::Ollama, InferenceProvider::LlamaCpp]);
+    }
+}
@@ -1,16 +1,36 @@
 use crate::error::{AppError, Result};
 use std::path::PathBuf;
 use std::time::Instant;
-use llama_cpp::{LlamaModel, LlamaParams, SessionParams};
-use llama_cpp::standard_sampler::StandardSampler;
+use llama_cpp::{LlamaModel, LlamaParams, LlamaSession, SessionParams};
+use llama_cpp::standard_sampler::{SamplerStage, StandardSampler};
+use crate::config::InferenceProvider;
+use async_trait::async_trait;
+use std::sync::{Arc, Mutex};
+use super::backend::{spawn_token_stream, BackendHealth, InferenceBackend, ModelInfo, TokenStream};
+use super::chat_template::ChatTemplate;
+use super::conversation::PromptMessage;
+use super::options::{truncate_at_stop, GenerationOptions, StopFilter};
+use super::token_budget::{context_length_for_model, TokenCounter};
+
+/// Context size of a generation session, prompt and reply together
+const SESSION_CONTEXT: usize = 4096;
+
+/// Markers that end a completion; they are never shown to the user
+const STOP_MARKERS: [&str; 3] = ["</s>", "<|im_end|>", "<|assistant|>"];
 
 pub struct LlamaCppMetalBackend {
     model_id: String,
-    #[allow(dead_code)]
     cache_dir: PathBuf,
     model_path: Option<PathBuf>,
     use_gpu: bool,
     model: Option<LlamaModel>,
+    /// Session kept between requests with the seed it was created with, so
+    /// its KV cache can be reused for a prompt that starts the same way
+    session: Mutex<Option<(LlamaSession, Option<u64>)>>,
+    /// GGUF files carry no `tokenizer.json`, so prompts are measured with the
+    /// original model's tokenizer when it is in the cache
+    token_counter: TokenCounter,
+    template: ChatTemplate,
 }
 
 impl LlamaCppMetalBackend {
@@ -41,12 +61,17 @@ impl LlamaCppMetalBackend {
             println!("[LlamaCppMetalBackend] Using CPU mode");
         }
         
+        let template = ChatTemplate::for_model(model_id, &cache_dir);
+        let token_counter = TokenCounter::for_model(model_id, &cache_dir);
         let mut backend = Self {
             model_id: model_id.to_string(),
             cache_dir,
             model_path: None,
             use_gpu,
             model: None,
+            session: Mutex::new(None),
+            token_counter,
+            template,
         };
         
         // Try to load the model
@@ -138,8 +163,22 @@ impl LlamaCppMetalBackend {
         }
     }
     
-    pub async fn generate(&self, prompt: &str, max_tokens: usize) -> Result<String> {
+    pub async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        self.generate_streaming(&[PromptMessage::user(prompt)], options, &mut |_| true).await
+    }
+
+    /// Generate a reply to `messages`, passing each new piece of text to
+    /// `on_text`. Generation stops early when `on_text` returns false or a stop
+    /// sequence is produced.
+    pub async fn generate_streaming(
+        &self,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+        on_text: &mut (dyn FnMut(&str) -> bool + Send),
+    ) -> Result<String> {
         let start_time = Instant::now();
+        let max_tokens = options.max_tokens;
+        let prompt = messages.last().map(|m| m.content.as_str()).unwrap_or_default();
         
         println!("[LlamaCppMetalBackend] =================================");
         println!("[LlamaCppMetalBackend] Model: {}", self.model_id);
@@ -157,8 +196,8 @@ impl LlamaCppMetalBackend {
         let model = self.model.as_ref()
             .ok_or_else(|| AppError::Llm("Model not loaded".into()))?;
         
-        // Apply chat template based on model
-        let formatted_prompt = self.apply_chat_template(prompt);
+        // Apply the model's chat template
+        let formatted_prompt = self.template.render(messages);
         
         // Tokenize to check prompt length
         let tokens = model.tokenize_bytes(formatted_prompt.as_bytes(), true, false)
@@ -166,20 +205,32 @@ impl LlamaCppMetalBackend {
         
         println!("[LlamaCppMetalBackend] Prompt tokens: {}", tokens.len());
         
-        // Create a session for generation
-        let mut session_params = SessionParams::default();
-        
-        // Set a larger batch size to handle longer prompts
-        session_params.n_batch = 2048; // Increase batch size for longer prompts
-        session_params.n_ctx = 4096;   // Increase context size
-        
-        // Create a new session
-        let mut session = model.create_session(session_params)
-            .map_err(|e| AppError::Llm(format!("Failed to create session: {}", e)))?;
+        // Reuse the previous session unless a different seed was requested;
+        // the seed is fixed when a session is created
+        let mut session_slot = self.session.lock().unwrap();
+        let session = match session_slot.take() {
+            Some((session, seed)) if seed == options.seed => session,
+            _ => {
+                let mut session_params = SessionParams::default();
+                
+                // Set a larger batch size to handle longer prompts
+                session_params.n_batch = 2048; // Increase batch size for longer prompts
+                session_params.n_ctx = SESSION_CONTEXT as u32;
+                if let Some(seed) = options.seed {
+                    // llama.cpp takes a 32-bit seed, u32::MAX meaning random
+                    session_params.seed = (seed % u64::from(u32::MAX)) as u32;
+                }
+                
+                model.create_session(session_params)
+                    .map_err(|e| AppError::Llm(format!("Failed to create session: {}", e)))?
+            }
+        };
+        let session = &mut session_slot.insert((session, options.seed)).0;
         
-        // Truncate prompt if it's too long
-        let final_prompt = if tokens.len() > 2000 {
-            println!("[LlamaCppMetalBackend] Warning: Truncating prompt from {} to ~2000 tokens", tokens.len());
+        // Truncate prompt if it leaves no room for the reply
+        let max_prompt_tokens = SESSION_CONTEXT.saturating_sub(max_tokens).max(SESSION_CONTEXT / 2);
+        let final_prompt = if tokens.len() > max_prompt_tokens {
+            println!("[LlamaCppMetalBackend] Warning: Truncating prompt from {} to ~{} tokens", tokens.len(), max_prompt_tokens);
             // Approximate truncation - take first 75% of the prompt
             let truncate_at = (formatted_prompt.len() * 3) / 4;
             let truncated = &formatted_prompt[..truncate_at];
@@ -188,12 +239,27 @@ impl LlamaCppMetalBackend {
             formatted_prompt
         };
         
-        // Feed the prompt to the context
-        session.advance_context(final_prompt.as_str())
-            .map_err(|e| AppError::Llm(format!("Failed to process prompt: {}", e)))?;
+        // Feed the prompt to the context; only tokens after the part shared
+        // with the previous context are processed
+        let prompt_tokens = model.tokenize_bytes(final_prompt.as_bytes(), false, true)
+            .map_err(|e| AppError::Llm(format!("Failed to tokenize prompt: {}", e)))?;
+        let shared = session
+            .context()
+            .iter()
+            .zip(&prompt_tokens)
+            .take_while(|(cached, token)| cached == token)
+            .count();
+        if shared > 0 {
+            println!("[LlamaCppMetalBackend] Reusing KV cache for {} of {} prompt tokens", shared, prompt_tokens.len());
+        }
+        if let Err(e) = session.set_context_to_tokens(&prompt_tokens) {
+            // Start from a fresh session next time rather than a half-updated one
+            *session_slot = None;
+            return Err(AppError::Llm(format!("Failed to process prompt: {}", e)));
+        }
         
         // Create a sampler with parameters
-        let sampler = StandardSampler::default();
+        let sampler = sampler_for(options);
         
         // Generate tokens using the completion API
         let mut output = String::new();
@@ -207,17 +273,37 @@ impl LlamaCppMetalBackend {
             .map_err(|e| AppError::Llm(format!("Failed to start completion: {}", e)))?
             .into_strings();
         
+        let stop = self.template.stop_sequences(&options.stop);
+        let mut text = StopFilter::new(&stop, on_text);
+        let mut emitted = 0;
         for completion in completions {
             output.push_str(&completion);
             token_count += 1;
             
+            // Hold back text that may be the start of a stop marker
+            let safe_len = stop_safe_len(&output);
+            if safe_len > emitted {
+                let keep_going = text.push(&output[emitted..safe_len]);
+                emitted = safe_len;
+                if !keep_going {
+                    println!("[LlamaCppMetalBackend] Generation stopped after {} tokens", token_count);
+                    break;
+                }
+            }
+            
             // Check for stop tokens
-            if output.contains("</s>") || output.contains("<|im_end|>") || 
-               output.contains("<|assistant|>") || token_count >= max_tokens {
+            if STOP_MARKERS.iter().any(|marker| output.contains(marker)) || token_count >= max_tokens {
                 break;
             }
         }
         
+        // Flush held back text that never became a stop marker
+        let end = first_stop_marker(&output).unwrap_or(output.len());
+        if end > emitted {
+            text.push(&output[emitted..end]);
+        }
+        text.flush();
+        
         let elapsed = start_time.elapsed();
         let tokens_per_second = token_count as f32 / elapsed.as_secs_f32();
         
@@ -235,20 +321,119 @@ impl LlamaCppMetalBackend {
             .trim()
             .to_string();
         
-        Ok(output)
+        Ok(truncate_at_stop(output, &stop))
     }
-    
-    fn apply_chat_template(&self, prompt: &str) -> String {
-        match self.model_id.as_str() {
-            model if model.starts_with("Qwen") => {
-                format!("<|im_start|>system\nYou are a helpful AI assistant.<|im_end|>\n<|im_start|>user\n{}<|im_end|>\n<|im_start|>assistant\n", prompt)
-            }
-            "TinyLlama/TinyLlama-1.1B-Chat-v1.0" => {
-                format!("<|system|>\nYou are a helpful AI assistant.</s>\n<|user|>\n{}</s>\n<|assistant|>\n", prompt)
-            }
-            _ => {
-                format!("### Instruction:\n{}\n\n### Response:\n", prompt)
-            }
+}
+
+/// Sampler using llama.cpp's standard stages, with the requested temperature
+/// and top-p in place of the defaults
+fn sampler_for(options: &GenerationOptions) -> StandardSampler {
+    if options.temperature.is_none() && options.top_p.is_none() {
+        return StandardSampler::default();
+    }
+    StandardSampler::new_softmax(
+        vec![
+            SamplerStage::RepetitionPenalty {
+                repetition_penalty: 1.1,
+                frequency_penalty: 0.0,
+                presence_penalty: 0.0,
+                last_n: 64,
+            },
+            SamplerStage::TopK(40),
+            SamplerStage::TopP(options.top_p.unwrap_or(0.95) as f32),
+            SamplerStage::MinP(0.05),
+            SamplerStage::Temperature(options.temperature.unwrap_or(0.8) as f32),
+        ],
+        1,
+    )
+}
+
+/// Byte offset of the earliest stop marker in `output`
+fn first_stop_marker(output: &str) -> Option<usize> {
+    STOP_MARKERS.iter().filter_map(|marker| output.find(marker)).min()
+}
+
+/// Length of `output` that is safe to show: everything before a stop marker,
+/// minus any trailing text that could still grow into one
+fn stop_safe_len(output: &str) -> usize {
+    if let Some(pos) = first_stop_marker(output) {
+        return pos;
+    }
+    let held_back = STOP_MARKERS
+        .iter()
+        .filter_map(|marker| (1..marker.len()).rev().find(|&n| output.ends_with(&marker[..n])))
+        .max()
+        .unwrap_or(0);
+    output.len() - held_back
+}
+
+#[async_trait]
+impl InferenceBackend for LlamaCppMetalBackend {
+    fn provider(&self) -> InferenceProvider {
+        InferenceProvider::LlamaCpp
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_id
+    }
+
+    fn context_length(&self) -> usize {
+        context_length_for_model(&self.model_id).min(SESSION_CONTEXT)
+    }
+
+    fn token_counter(&self) -> TokenCounter {
+        self.token_counter.clone()
+    }
+
+    async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        LlamaCppMetalBackend::generate(self, prompt, options).await
+    }
+
+    async fn stream(self: Arc<Self>, prompt: &str, options: &GenerationOptions) -> Result<TokenStream> {
+        self.stream_chat(&[PromptMessage::user(prompt)], options).await
+    }
+
+    async fn generate_chat(&self, messages: &[PromptMessage], options: &GenerationOptions) -> Result<String> {
+        self.generate_streaming(messages, options, &mut |_| true).await
+    }
+
+    async fn stream_chat(
+        self: Arc<Self>,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        let messages = messages.to_vec();
+        let options = options.clone();
+        Ok(spawn_token_stream(move |on_text| {
+            tokio::runtime::Handle::current()
+                .block_on(self.generate_streaming(&messages, &options, on_text))
+        }))
+    }
+
+    async fn model_info(&self) -> ModelInfo {
+        let device = if self.use_gpu && cfg!(target_os = "macos") {
+            "Metal"
+        } else {
+            "CPU"
+        };
+        ModelInfo {
+            model_type: self.model_id.clone(),
+            device: device.to_string(),
+            cache_dir: self.cache_dir.to_string_lossy().to_string(),
+            loaded: self.model.is_some(),
+            tokenizer_loaded: self.model.is_some(),
+            supported_features: vec![
+                "gguf_format".to_string(),
+                if device == "Metal" { "metal_acceleration".to_string() } else { "cpu_optimized".to_string() }
+            ],
+        }
+    }
+
+    async fn health(&self) -> BackendHealth {
+        if self.model.is_some() {
+            BackendHealth::Ready
+        } else {
+            BackendHealth::Unavailable("Model not loaded".to_string())
         }
     }
 }
@@ -265,10 +450,13 @@ mod tests {
             model_path: None,
             use_gpu: false,
             model: None,
+            session: Mutex::new(None),
+            token_counter: TokenCounter::Estimate,
+            template: ChatTemplate::for_model_id("TinyLlama/TinyLlama-1.1B-Chat-v1.0"),
         };
         
         let prompt = "Hello, how are you?";
-        let formatted = backend.apply_chat_template(prompt);
+        let formatted = backend.template.render(&[PromptMessage::user(prompt)]);
         
         assert!(formatted.contains("<|system|>"));
         assert!(formatted.contains("<|user|>"));
@@ -284,15 +472,27 @@ mod tests {
             model_path: None,
             use_gpu: false,
             model: None,
+            session: Mutex::new(None),
+            token_counter: TokenCounter::Estimate,
+            template: ChatTemplate::for_model_id("Qwen/Qwen2.5-0.5B-Instruct"),
         };
         
         let prompt = "What is 2+2?";
-        let formatted = backend.apply_chat_template(prompt);
+        let formatted = backend.template.render(&[PromptMessage::user(prompt)]);
         
         assert!(formatted.contains("<|im_start|>"));
         assert!(formatted.contains("<|im_end|>"));
         assert!(formatted.contains(prompt));
     }
+
+    #[test]
+    fn test_stop_safe_len_holds_back_partial_markers() {
+        assert_eq!(stop_safe_len("Hello"), 5);
+        assert_eq!(stop_safe_len("Hello<|im"), 5);
+        assert_eq!(stop_safe_len("Hello</"), 5);
+        assert_eq!(stop_safe_len("Hello<|im_end|> trailing"), 5);
+        assert_eq!(stop_safe_len("a < b"), 5);
+    }
     
     #[test] 
     fn test_gpu_detection() {
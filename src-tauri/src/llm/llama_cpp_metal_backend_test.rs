@@ -32,7 +32,7 @@ mod tests {
             cache_dir,
         ).await.expect("Failed to create backend");
         
-        let result = backend.generate("Hello", 10).await;
+        let result = backend.generate("Hello", &crate::llm::options::GenerationOptions::default().with_max_tokens(10)).await;
         assert!(result.is_ok(), "Generation failed: {:?}", result.err());
         
         let response = result.unwrap();
@@ -2,46 +2,94 @@ use crate::config::{Config, InferenceProvider};
 use crate::error::{AppError, Result};
 use crate::models::{Activity, ProductivityInsights, ProductivityScore};
 use chrono::Utc;
-use genai::chat::{ChatMessage, ChatRequest};
-use genai::Client;
-use std::path::PathBuf;
+use futures_util::stream::{self, StreamExt};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub mod backend;
+pub mod chat_template;
+pub mod conversation;
+pub mod fallback;
+pub mod model_manager;
+pub mod options;
+mod prompt_cache;
+pub mod structured;
+pub mod token_budget;
+use conversation::{PromptMessage, SummaryCache};
+use fallback::{provider_order, Attempt, CircuitBreakers, ProviderStatus, ServedBy};
+use model_manager::ModelManager;
+use options::{GenerationOptions, DEFAULT_MAX_TOKENS};
+use structured::{parse_reply, repair_prompt, schema_instructions, StructuredOutput};
+use token_budget::{BudgetReport, TokenBudget, TokenCounter, DEFAULT_CONTEXT_LENGTH};
+use backend::{create_backend, describe_model, model_env_var, BackendConfig, InferenceBackend, TokenStream};
+
+mod ollama_backend;
+use ollama_backend::OllamaBackend;
 
 mod candle_backend;
-use candle_backend::CandleBackend;
 
 mod crane_backend;
-use crane_backend::CraneBackend;
 
 mod callm_backend;
-use callm_backend::CallmBackend;
 
-mod bert_metal_backend;
+pub mod bert_metal_backend;
 
 pub mod llama_cpp_metal_backend;
-use llama_cpp_metal_backend::LlamaCppMetalBackend;
+
+pub mod openai_backend;
+
+/// Repair round trips allowed when a structured reply does not parse
+const MAX_REPAIR_ATTEMPTS: usize = 2;
 
 pub struct LlmClient {
     model_name: String,
     inference_provider: InferenceProvider,
-    candle_backend: Option<CandleBackend>,
-    crane_backend: Option<CraneBackend>,
-    callm_backend: Option<CallmBackend>,
-    llama_cpp_backend: Option<LlamaCppMetalBackend>,
+    models: ModelManager,
+    /// Providers tried after `inference_provider`, in order
+    fallback_chain: Vec<InferenceProvider>,
+    breakers: CircuitBreakers,
+    last_served: Mutex<Option<ServedBy>>,
+    /// Summaries of older chat turns, by conversation
+    summaries: SummaryCache,
 }
 
 impl LlmClient {
     pub fn new() -> Self {
         let config = Config::get();
         
-        // For synchronous new(), we'll initialize without Candle/Crane
-        // They will be initialized later via init_candle_if_needed()
+        // For synchronous new(), we'll initialize without local backends
+        // They are loaded on first use and then kept by the model manager
         Self {
             model_name: config.services.ollama_model.clone(),
-            inference_provider: config.services.inference_provider.clone(),
-            candle_backend: None,
-            crane_backend: None,
-            callm_backend: None,
-            llama_cpp_backend: None,
+            inference_provider: config.services.inference_provider,
+            models: ModelManager::new(),
+            fallback_chain: config.services.fallback_providers.clone(),
+            breakers: CircuitBreakers::new(
+                config.services.provider_failure_threshold,
+                Duration::from_secs(config.services.provider_cooldown_secs),
+            ),
+            last_served: Mutex::new(None),
+            summaries: SummaryCache::default(),
+        }
+    }
+    
+    /// Create a client that serves every request from an already loaded backend,
+    /// without falling back to other providers
+    #[allow(dead_code)]
+    pub fn with_backend(backend: Arc<dyn InferenceBackend>) -> Self {
+        let models = ModelManager::new();
+        let inference_provider = backend.provider();
+        let model_name = backend.model_id().to_string();
+        models.insert(backend);
+        
+        Self {
+            model_name,
+            inference_provider,
+            models,
+            fallback_chain: Vec::new(),
+            breakers: CircuitBreakers::new(3, Duration::from_secs(60)),
+            last_served: Mutex::new(None),
+            summaries: SummaryCache::default(),
         }
     }
     
@@ -54,289 +102,86 @@ impl LlmClient {
         println!("[LlmClient] Updating inference provider to: {:?}", provider);
         
         // Update the provider
-        self.inference_provider = provider.clone();
+        self.inference_provider = provider;
         
-        // Update model IDs based on provider
-        match provider {
-            InferenceProvider::Ollama => {
-                if let Some(id) = model_id {
-                    self.model_name = id;
-                }
-                // Clear other backends
-                self.candle_backend = None;
-                self.crane_backend = None;
-                self.callm_backend = None;
-            }
-            InferenceProvider::Candle => {
-                // Initialize Candle backend if needed
-                if self.candle_backend.is_none() {
-                    let config = Config::get();
-                    let model_id = model_id.as_ref().unwrap_or(&config.services.candle_model_id);
-                    
-                    println!("[LlmClient] Initializing Candle backend with model: {}", model_id);
-                    match CandleBackend::new(
-                        model_id,
-                        &config.services.candle_model_revision,
-                        PathBuf::from(&config.services.candle_cache_dir),
-                    ).await {
-                        Ok(backend) => {
-                            println!("[LlmClient] Candle backend initialized successfully");
-                            self.candle_backend = Some(backend);
-                        }
-                        Err(e) => {
-                            eprintln!("[LlmClient] Failed to initialize Candle backend: {}", e);
-                            return Err(e);
-                        }
-                    }
-                }
-                // Clear other backends
-                self.crane_backend = None;
-                self.callm_backend = None;
-            }
-            InferenceProvider::Crane => {
-                // Initialize Crane backend if needed
-                if self.crane_backend.is_none() {
-                    let config = Config::get();
-                    let model_id = model_id.as_ref().unwrap_or(&config.services.candle_model_id);
-                    
-                    println!("[LlmClient] Initializing Crane backend with model: {}", model_id);
-                    match CraneBackend::new(
-                        model_id,
-                        PathBuf::from(&config.services.candle_cache_dir),
-                    ).await {
-                        Ok(backend) => {
-                            println!("[LlmClient] Crane backend initialized successfully");
-                            self.crane_backend = Some(backend);
-                        }
-                        Err(e) => {
-                            eprintln!("[LlmClient] Failed to initialize Crane backend: {}", e);
-                            return Err(e);
-                        }
-                    }
-                }
-                // Clear other backends
-                self.candle_backend = None;
-                self.callm_backend = None;
-            }
-            InferenceProvider::Callm => {
-                // Initialize Callm backend if needed
-                if self.callm_backend.is_none() {
-                    let config = Config::get();
-                    let model_id = model_id.as_ref().unwrap_or(&config.services.candle_model_id);
-                    
-                    println!("[LlmClient] Initializing Callm backend with model: {}", model_id);
-                    match CallmBackend::new(
-                        model_id,
-                        PathBuf::from(&config.services.candle_cache_dir),
-                    ).await {
-                        Ok(backend) => {
-                            println!("[LlmClient] Callm backend initialized successfully");
-                            self.callm_backend = Some(backend);
-                        }
-                        Err(e) => {
-                            eprintln!("[LlmClient] Failed to initialize Callm backend: {}", e);
-                            return Err(e);
-                        }
-                    }
-                }
-                // Clear other backends
-                self.candle_backend = None;
-                self.crane_backend = None;
+        if provider == InferenceProvider::Ollama {
+            if let Some(id) = model_id {
+                self.model_name = id;
             }
-            InferenceProvider::LlamaCpp => {
-                // Initialize LlamaCpp backend if needed
-                if self.llama_cpp_backend.is_none() {
-                    let config = Config::get();
-                    let model_id = model_id.as_ref().unwrap_or(&config.services.candle_model_id);
-                    
-                    println!("[LlmClient] Initializing LlamaCpp backend with model: {}", model_id);
-                    match LlamaCppMetalBackend::new(
-                        model_id,
-                        PathBuf::from(&config.services.candle_cache_dir),
-                    ).await {
-                        Ok(backend) => {
-                            println!("[LlmClient] LlamaCpp backend initialized successfully");
-                            self.llama_cpp_backend = Some(backend);
-                        }
-                        Err(e) => {
-                            eprintln!("[LlmClient] Failed to initialize LlamaCpp backend: {}", e);
-                            return Err(e);
-                        }
-                    }
-                }
-                // Clear other backends
-                self.candle_backend = None;
-                self.crane_backend = None;
-                self.callm_backend = None;
+        } else {
+            let mut settings = BackendConfig::for_provider(Config::get(), provider);
+            if let Some(id) = model_id {
+                settings.model_id = id;
             }
+            self.ensure_loaded(provider, &settings).await?;
         }
         
+        // Clear other backends
+        self.models.retain_only(provider);
+        
         println!("[LlmClient] Inference provider updated successfully");
         Ok(())
     }
     
     pub async fn new_async() -> Self {
-        let config = Config::get();
+        let client = Self::new();
+        let provider = client.inference_provider;
         
-        // Initialize appropriate backend based on provider
-        let (candle_backend, crane_backend, callm_backend, llama_cpp_backend) = match config.services.inference_provider {
-            InferenceProvider::Candle => {
-                println!("[LlmClient] Initializing Candle backend...");
-                match CandleBackend::new(
-                    &config.services.candle_model_id,
-                    &config.services.candle_model_revision,
-                    PathBuf::from(&config.services.candle_cache_dir),
-                ).await {
-                    Ok(backend) => {
-                        println!("[LlmClient] Candle backend initialized successfully");
-                        (Some(backend), None, None, None)
-                    }
-                    Err(e) => {
-                        eprintln!("[LlmClient] Failed to initialize Candle backend: {}", e);
-                        eprintln!("[LlmClient] Falling back to Ollama");
-                        (None, None, None, None)
-                    }
-                }
-            }
-            InferenceProvider::Crane => {
-                println!("[LlmClient] Initializing Crane backend...");
-                match CraneBackend::new(
-                    &config.services.candle_model_id, // Reuse same model config
-                    PathBuf::from(&config.services.candle_cache_dir),
-                ).await {
-                    Ok(backend) => {
-                        println!("[LlmClient] Crane backend initialized successfully");
-                        (None, Some(backend), None, None)
-                    }
-                    Err(e) => {
-                        eprintln!("[LlmClient] Failed to initialize Crane backend: {}", e);
-                        eprintln!("[LlmClient] Falling back to Ollama");
-                        (None, None, None, None)
-                    }
-                }
+        // Load the local model up front so the first request finds it warm
+        if provider != InferenceProvider::Ollama {
+            let settings = BackendConfig::for_provider(Config::get(), provider);
+            if client.ensure_loaded(provider, &settings).await.is_err() {
+                eprintln!("[LlmClient] Falling back to Ollama");
             }
-            InferenceProvider::Callm => {
-                println!("[LlmClient] Initializing Callm backend...");
-                match CallmBackend::new(
-                    &config.services.candle_model_id,
-                    PathBuf::from(&config.services.candle_cache_dir),
-                ).await {
-                    Ok(backend) => {
-                        println!("[LlmClient] Callm backend initialized successfully");
-                        (None, None, Some(backend), None)
-                    }
-                    Err(e) => {
-                        eprintln!("[LlmClient] Failed to initialize Callm backend: {}", e);
-                        eprintln!("[LlmClient] Falling back to Ollama");
-                        (None, None, None, None)
-                    }
-                }
-            }
-            InferenceProvider::LlamaCpp => {
-                let device_type = if cfg!(target_os = "macos") { "Metal" } else { "CPU" };
-                println!("[LlmClient] Initializing LlamaCpp backend with {} support...", device_type);
-                match LlamaCppMetalBackend::new(
-                    &config.services.candle_model_id,
-                    PathBuf::from(&config.services.candle_cache_dir),
-                ).await {
-                    Ok(backend) => {
-                        println!("[LlmClient] LlamaCpp backend initialized successfully with {} support", device_type);
-                        (None, None, None, Some(backend))
-                    }
-                    Err(e) => {
-                        eprintln!("[LlmClient] Failed to initialize LlamaCpp backend: {}", e);
-                        eprintln!("[LlmClient] Falling back to Ollama");
-                        (None, None, None, None)
-                    }
-                }
-            }
-            _ => (None, None, None, None),
-        };
-        
-        Self {
-            model_name: config.services.ollama_model.clone(),
-            inference_provider: config.services.inference_provider.clone(),
-            candle_backend,
-            crane_backend,
-            callm_backend,
-            llama_cpp_backend,
         }
+        
+        client
     }
     
     /// Initialize backend if needed (can be called after construction)
-    pub async fn init_backend_if_needed(&mut self) -> Result<()> {
-        let config = Config::get();
+    pub async fn init_backend_if_needed(&self) -> Result<()> {
+        let provider = self.inference_provider;
         
-        match config.services.inference_provider {
-            InferenceProvider::Candle if self.candle_backend.is_none() => {
-                println!("[LlmClient] Late initialization of Candle backend...");
-                match CandleBackend::new(
-                    &config.services.candle_model_id,
-                    &config.services.candle_model_revision,
-                    PathBuf::from(&config.services.candle_cache_dir),
-                ).await {
-                    Ok(backend) => {
-                        println!("[LlmClient] Candle backend initialized successfully");
-                        self.candle_backend = Some(backend);
-                    }
-                    Err(e) => {
-                        eprintln!("[LlmClient] Failed to initialize Candle backend: {}", e);
-                        return Err(e);
-                    }
-                }
-            }
-            InferenceProvider::Crane if self.crane_backend.is_none() => {
-                println!("[LlmClient] Late initialization of Crane backend...");
-                match CraneBackend::new(
-                    &config.services.candle_model_id,
-                    PathBuf::from(&config.services.candle_cache_dir),
-                ).await {
-                    Ok(backend) => {
-                        println!("[LlmClient] Crane backend initialized successfully");
-                        self.crane_backend = Some(backend);
-                    }
-                    Err(e) => {
-                        eprintln!("[LlmClient] Failed to initialize Crane backend: {}", e);
-                        return Err(e);
-                    }
-                }
-            }
-            InferenceProvider::Callm if self.callm_backend.is_none() => {
-                println!("[LlmClient] Late initialization of Callm backend...");
-                match CallmBackend::new(
-                    &config.services.candle_model_id,
-                    PathBuf::from(&config.services.candle_cache_dir),
-                ).await {
-                    Ok(backend) => {
-                        println!("[LlmClient] Callm backend initialized successfully");
-                        self.callm_backend = Some(backend);
-                    }
-                    Err(e) => {
-                        eprintln!("[LlmClient] Failed to initialize Callm backend: {}", e);
-                        return Err(e);
-                    }
+        if provider != InferenceProvider::Ollama && self.models.get(provider).is_none() {
+            println!("[LlmClient] Late initialization of {:?} backend...", provider);
+            let settings = BackendConfig::for_provider(Config::get(), provider);
+            self.ensure_loaded(provider, &settings).await?;
+        }
+        
+        Ok(())
+    }
+    
+    /// The backend for `settings.model_id`, loading it into the model manager
+    /// unless it is already there
+    async fn ensure_loaded(
+        &self,
+        provider: InferenceProvider,
+        settings: &BackendConfig,
+    ) -> Result<Arc<dyn InferenceBackend>> {
+        self.models
+            .get_or_load(provider, &settings.model_id, || Self::load_backend(provider, settings))
+            .await
+    }
+    
+    async fn load_backend(
+        provider: InferenceProvider,
+        settings: &BackendConfig,
+    ) -> Result<Arc<dyn InferenceBackend>> {
+        println!("[LlmClient] Initializing {:?} backend with model: {}", provider, settings.model_id);
+        match create_backend(provider, settings).await {
+            Ok(backend) => {
+                println!("[LlmClient] {:?} backend initialized successfully", provider);
+                let health = backend.health().await;
+                if !health.is_ready() {
+                    eprintln!("[LlmClient] {:?} backend is not ready: {:?}", provider, health);
                 }
+                Ok(backend)
             }
-            InferenceProvider::LlamaCpp if self.llama_cpp_backend.is_none() => {
-                println!("[LlmClient] Late initialization of LlamaCpp backend...");
-                match LlamaCppMetalBackend::new(
-                    &config.services.candle_model_id,
-                    PathBuf::from(&config.services.candle_cache_dir),
-                ).await {
-                    Ok(backend) => {
-                        println!("[LlmClient] LlamaCpp backend initialized successfully");
-                        self.llama_cpp_backend = Some(backend);
-                    }
-                    Err(e) => {
-                        eprintln!("[LlmClient] Failed to initialize LlamaCpp backend: {}", e);
-                        return Err(e);
-                    }
-                }
+            Err(e) => {
+                eprintln!("[LlmClient] Failed to initialize {:?} backend: {}", provider, e);
+                Err(e)
             }
-            _ => {}
         }
-        
-        Ok(())
     }
 
     pub async fn generate_productivity_insights(
@@ -361,12 +206,11 @@ impl LlmClient {
                     "Use the app for work tasks to build data".to_string(),
                 ],
                 timestamp: Utc::now(),
+                omitted_context: Vec::new(),
             });
         }
 
-        let activities_json = serde_json::to_string_pretty(activities)?;
-
-        let prompt = format!(
+        let build_prompt = |activity_data: &str| format!(
             "Analyze the following activity data and provide productivity insights:\n\n{}\n\n\
             Please provide:\n\
             1. A brief summary of the user's productivity patterns\n\
@@ -378,33 +222,18 @@ impl LlmClient {
               \"key_insights\": [\"insight1\", \"insight2\", ...],\n\
               \"suggested_improvements\": [\"improvement1\", \"improvement2\", ...]\n\
             }}",
-            activities_json
+            activity_data
         );
+        let (activity_data, report) = self.fit_activities::<InsightsReply>(&build_prompt(""), activities).await?;
 
-        let response = self.send_request(&prompt).await?;
-        let json_response = self.extract_json(&response)?;
+        let reply: InsightsReply = self.generate_structured(&build_prompt(&activity_data)).await?;
 
         Ok(ProductivityInsights {
-            summary: json_response["summary"].as_str().unwrap_or("").to_string(),
-            key_insights: json_response["key_insights"]
-                .as_array()
-                .map(|arr| {
-                    arr.iter()
-                        .filter_map(|v| v.as_str())
-                        .map(|s| s.to_string())
-                        .collect()
-                })
-                .unwrap_or_default(),
-            suggested_improvements: json_response["suggested_improvements"]
-                .as_array()
-                .map(|arr| {
-                    arr.iter()
-                        .filter_map(|v| v.as_str())
-                        .map(|s| s.to_string())
-                        .collect()
-                })
-                .unwrap_or_default(),
+            summary: reply.summary,
+            key_insights: reply.key_insights,
+            suggested_improvements: reply.suggested_improvements,
             timestamp: Utc::now(),
+            omitted_context: report.dropped.iter().map(ToString::to_string).collect(),
         })
     }
 
@@ -423,9 +252,7 @@ impl LlmClient {
             });
         }
 
-        let activities_json = serde_json::to_string_pretty(activities)?;
-
-        let prompt = format!(
+        let build_prompt = |activity_data: &str| format!(
             "Analyze the following activity data and calculate productivity scores:\n\n{}\n\n\
             Calculate scores (0-100) for:\n\
             1. Overall productivity\n\
@@ -439,43 +266,31 @@ impl LlmClient {
               \"efficiency\": 80,\n\
               \"breaks\": 75\n\
             }}",
-            activities_json
+            activity_data
         );
+        let (activity_data, _) = self.fit_activities::<ScoreReply>(&build_prompt(""), activities).await?;
 
-        let response = self.send_request(&prompt).await?;
-        let json_response = self.extract_json(&response)?;
+        let reply: ScoreReply = self.generate_structured(&build_prompt(&activity_data)).await?;
 
         Ok(ProductivityScore {
-            overall: json_response["overall"].as_f64().unwrap_or(0.0) as f32,
-            focus: json_response["focus"].as_f64().unwrap_or(0.0) as f32,
-            efficiency: json_response["efficiency"].as_f64().unwrap_or(0.0) as f32,
-            breaks: json_response["breaks"].as_f64().unwrap_or(0.0) as f32,
+            overall: reply.overall.clamp(0.0, 100.0),
+            focus: reply.focus.clamp(0.0, 100.0),
+            efficiency: reply.efficiency.clamp(0.0, 100.0),
+            breaks: reply.breaks.clamp(0.0, 100.0),
             timestamp: Utc::now(),
         })
     }
 
     pub async fn generate_recommendations(&self, activities: &[Activity]) -> Result<Vec<String>> {
-        let activities_json = serde_json::to_string_pretty(activities)?;
-
-        let prompt = format!(
+        let build_prompt = |activity_data: &str| format!(
             "Based on the following activity data, provide 5 specific, actionable recommendations:\n\n{}\n\n\
             Format your response as a JSON array of strings:\n\
             [\"recommendation1\", \"recommendation2\", ...]",
-            activities_json
+            activity_data
         );
+        let (activity_data, _) = self.fit_activities::<Vec<String>>(&build_prompt(""), activities).await?;
 
-        let response = self.send_request(&prompt).await?;
-        let json_response = self.extract_json(&response)?;
-
-        Ok(json_response
-            .as_array()
-            .map(|arr| {
-                arr.iter()
-                    .filter_map(|v| v.as_str())
-                    .map(|s| s.to_string())
-                    .collect()
-            })
-            .unwrap_or_default())
+        self.generate_structured::<Vec<String>>(&build_prompt(&activity_data)).await
     }
 
     pub async fn split_research_query(&self, query: &str) -> Result<Vec<String>> {
@@ -487,18 +302,11 @@ impl LlmClient {
             query
         );
 
-        let response = self.send_request(&prompt).await?;
-        let json_response = self.extract_json(&response)?;
-
-        Ok(json_response
-            .as_array()
-            .map(|arr| {
-                arr.iter()
-                    .filter_map(|v| v.as_str())
-                    .map(|s| s.to_string())
-                    .collect()
-            })
-            .unwrap_or_else(|| vec![query.to_string()]))
+        let subtasks: Vec<String> = self.generate_structured(&prompt).await?;
+        if subtasks.is_empty() {
+            return Ok(vec![query.to_string()]);
+        }
+        Ok(subtasks)
     }
 
     pub async fn synthesize_research(&self, query: &str, results: &str) -> Result<String> {
@@ -509,7 +317,50 @@ impl LlmClient {
             query, results
         );
 
-        self.send_request(&prompt).await
+        // Syntheses run long, so allow more than the default token limit
+        let options = GenerationOptions::default().with_max_tokens(1500);
+        self.send_request_with_options(&prompt, &options).await
+    }
+
+    /// Budget for a prompt to `model` that leaves room for `reply_tokens`,
+    /// measured with the tokenizer and context length of the backend that
+    /// will most likely answer it
+    pub async fn token_budget(&self, model: &str, reply_tokens: usize) -> TokenBudget {
+        let configured = Config::get().services.model_context_length;
+        for provider in self.provider_chain() {
+            let backend = match provider {
+                InferenceProvider::Ollama => Some(self.ollama_backend(model)),
+                _ => self.local_backend(provider).await.ok(),
+            };
+            if let Some(backend) = backend {
+                let context_length = configured.unwrap_or_else(|| backend.context_length());
+                return TokenBudget::new(backend.token_counter(), context_length, reply_tokens);
+            }
+        }
+        TokenBudget::new(
+            TokenCounter::Estimate,
+            configured.unwrap_or(DEFAULT_CONTEXT_LENGTH),
+            reply_tokens,
+        )
+    }
+    
+    /// Activity data for a structured prompt, fitted to the context left after
+    /// the rest of the prompt and the schema instructions for `T`
+    async fn fit_activities<T: StructuredOutput>(
+        &self,
+        prompt_without_data: &str,
+        activities: &[Activity],
+    ) -> Result<(String, BudgetReport)> {
+        let mut budget = self.token_budget(&self.model_name, DEFAULT_MAX_TOKENS).await;
+        budget.reserve(&schema_instructions(&T::json_schema()));
+        budget.reserve(prompt_without_data);
+        let activity_data = activity_history(&mut budget, activities)?;
+        Ok((activity_data, budget.report()))
+    }
+    
+    /// Model used when a request does not name one
+    pub fn default_model(&self) -> &str {
+        &self.model_name
     }
 
     pub async fn send_request(&self, prompt: &str) -> Result<String> {
@@ -517,177 +368,235 @@ impl LlmClient {
     }
 
     pub async fn send_request_with_model(&self, prompt: &str, model: &str) -> Result<String> {
-        // Get current configuration to support hot-swapping
-        let config = Config::get();
-        let current_provider = &config.services.inference_provider;
-        
-        // Log the inference provider and model being used
-        println!("[LLM] Current inference provider: {:?}", current_provider);
-        println!("[LLM] Configured provider: {:?}", self.inference_provider);
+        self.send_chat_with_model(&[PromptMessage::user(prompt)], model, &GenerationOptions::default())
+            .await
+    }
+
+    /// Send a prompt to the default model with explicit sampling settings
+    pub async fn send_request_with_options(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        self.send_chat_with_model(&[PromptMessage::user(prompt)], &self.model_name, options)
+            .await
+    }
+
+    /// Send a role-tagged conversation and wait for the full reply
+    pub async fn send_chat_with_model(
+        &self,
+        messages: &[PromptMessage],
+        model: &str,
+        options: &GenerationOptions,
+    ) -> Result<String> {
+        let (reply, _) = self.send_chat_served_by(messages, model, options).await?;
+        Ok(reply)
+    }
+
+    /// Like `send_chat_with_model`, also reporting which provider answered
+    pub async fn send_chat_served_by(
+        &self,
+        messages: &[PromptMessage],
+        model: &str,
+        options: &GenerationOptions,
+    ) -> Result<(String, ServedBy)> {
+        self.complete_chat(messages, model, options, None).await
+    }
+
+    /// Ask for a JSON reply and parse it into `T` using the default model
+    pub async fn generate_structured<T: StructuredOutput>(&self, prompt: &str) -> Result<T> {
+        self.generate_structured_with_model(prompt, &self.model_name).await
+    }
+
+    /// Ask for a JSON reply and parse it into `T`.
+    ///
+    /// The schema for `T` is sent to backends that support constrained output.
+    /// A reply that does not parse is sent back with the error for repair, up to
+    /// `MAX_REPAIR_ATTEMPTS` times.
+    pub async fn generate_structured_with_model<T: StructuredOutput>(
+        &self,
+        prompt: &str,
+        model: &str,
+    ) -> Result<T> {
+        let schema = T::json_schema();
+        let options = GenerationOptions::default();
+        let mut messages = vec![
+            PromptMessage::system(schema_instructions(&schema)),
+            PromptMessage::user(prompt),
+        ];
+
+        let mut attempt = 0;
+        loop {
+            let (reply, _) = self
+                .complete_chat(&messages, model, &options, Some((T::schema_name(), &schema)))
+                .await?;
+
+            let error = match parse_reply::<T>(&reply) {
+                Ok(value) => return Ok(value),
+                Err(error) => error,
+            };
+
+            if attempt == MAX_REPAIR_ATTEMPTS {
+                eprintln!("[LLM] {} reply still invalid after repairs: {}", T::schema_name(), reply);
+                return Err(AppError::Llm(format!(
+                    "Model did not return valid {}: {}",
+                    T::schema_name(),
+                    error
+                )));
+            }
+
+            println!("[LLM] {} reply rejected ({}), asking for a repair", T::schema_name(), error);
+            messages.push(PromptMessage::assistant(reply));
+            messages.push(PromptMessage::user(repair_prompt(&error)));
+            attempt += 1;
+        }
+    }
+
+    /// Try each provider in the fallback chain until one answers
+    async fn complete_chat(
+        &self,
+        messages: &[PromptMessage],
+        model: &str,
+        options: &GenerationOptions,
+        schema: Option<(&str, &serde_json::Value)>,
+    ) -> Result<(String, ServedBy)> {
+        println!("[LLM] Current inference provider: {:?}", self.inference_provider);
         
-        // Check which backend to use based on current configuration
-        match current_provider {
-            InferenceProvider::Candle => {
-                if let Some(candle) = &self.candle_backend {
-                    println!("[LLM] Using Candle backend for inference");
-                    println!("[LLM] Candle model: {}", config.services.candle_model_id);
-                    return candle.generate(prompt, 500).await; // Max 500 tokens
-                } else {
-                    println!("[LLM] Candle backend not available, initializing...");
-                    // Try to initialize Candle backend on demand
-                    if let Ok(backend) = CandleBackend::new(
-                        &config.services.candle_model_id,
-                        &config.services.candle_model_revision,
-                        PathBuf::from(&config.services.candle_cache_dir),
-                    ).await {
-                        println!("[LLM] Candle backend initialized successfully");
-                        return backend.generate(prompt, 500).await;
-                    } else {
-                        println!("[LLM] Failed to initialize Candle backend, falling back to Ollama");
-                    }
+        let mut last_error = None;
+        for provider in self.provider_chain() {
+            let (backend, attempt) = match self.available_backend(provider, model).await {
+                Some(available) => available,
+                None => continue,
+            };
+            println!("[LLM] Using {:?} backend with model {}", provider, backend.model_id());
+            
+            let result = match schema {
+                Some((name, schema)) => backend.generate_chat_json(messages, name, schema, options).await,
+                None => backend.generate_chat(messages, options).await,
+            };
+            match result {
+                Ok(response) => {
+                    attempt.succeeded();
+                    return Ok((response, self.record_served(provider, backend.model_id())));
                 }
-            }
-            InferenceProvider::Crane => {
-                if let Some(crane) = &self.crane_backend {
-                    println!("[LLM] Using Crane backend for inference");
-                    println!("[LLM] Crane model: {}", config.services.candle_model_id);
-                    return crane.generate(prompt, 500).await; // Max 500 tokens
-                } else {
-                    println!("[LLM] Crane backend not available, initializing...");
-                    // Try to initialize Crane backend on demand
-                    if let Ok(backend) = CraneBackend::new(
-                        &config.services.candle_model_id,
-                        PathBuf::from(&config.services.candle_cache_dir),
-                    ).await {
-                        println!("[LLM] Crane backend initialized successfully");
-                        return backend.generate(prompt, 500).await;
-                    } else {
-                        println!("[LLM] Failed to initialize Crane backend, falling back to Ollama");
-                    }
+                Err(e) => {
+                    eprintln!("[LLM] {:?} generation failed: {}", provider, e);
+                    attempt.failed(&e.to_string());
+                    last_error = Some(e);
                 }
             }
-            InferenceProvider::Callm => {
-                // Get the current model from environment variable (which gets updated dynamically)
-                let current_model = std::env::var("CANDLE_MODEL_ID")
-                    .unwrap_or_else(|_| config.services.candle_model_id.clone());
-                
-                println!("[LLM] Using Callm backend for inference");
-                println!("[LLM] Requested model: {}", current_model);
-                
-                // Check if we need to reinitialize with a different model
-                let needs_reinit = if let Some(callm) = &self.callm_backend {
-                    // Check if the model has changed
-                    let backend_model = &callm.model_id;
-                    backend_model != &current_model
-                } else {
-                    true
-                };
-                
-                if needs_reinit {
-                    println!("[LLM] Initializing Callm backend with model: {}", current_model);
-                    // Initialize new backend with current model
-                    match CallmBackend::new(
-                        &current_model,
-                        PathBuf::from(&config.services.candle_cache_dir),
-                    ).await {
-                        Ok(backend) => {
-                            println!("[LLM] Callm backend initialized successfully");
-                            match backend.generate(prompt, 500).await {
-                                Ok(response) => {
-                                    println!("[LLM] Callm generated response successfully");
-                                    return Ok(response);
-                                }
-                                Err(e) => {
-                                    eprintln!("[LLM] Callm generation failed: {}", e);
-                                    eprintln!("[LLM] Falling back to Ollama");
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("[LLM] Failed to initialize Callm backend: {}", e);
-                            eprintln!("[LLM] Falling back to Ollama");
-                        }
-                    }
-                } else if let Some(callm) = &self.callm_backend {
-                    // Use existing backend
-                    match callm.generate(prompt, 500).await {
-                        Ok(response) => {
-                            println!("[LLM] Callm generated response successfully");
-                            return Ok(response);
-                        }
-                        Err(e) => {
-                            eprintln!("[LLM] Callm generation failed: {}", e);
-                            eprintln!("[LLM] Falling back to Ollama");
-                        }
-                    }
+        }
+        
+        Err(last_error.unwrap_or_else(|| AppError::Llm("No inference provider is available".into())))
+    }
+    
+    /// Stream a reply to a role-tagged conversation as it is generated, with the
+    /// same fallback chain as `send_chat_with_model`.
+    pub async fn stream_chat_with_model(
+        &self,
+        messages: &[PromptMessage],
+        model: &str,
+        options: &GenerationOptions,
+    ) -> Result<(TokenStream, ServedBy)> {
+        println!("[LLM] Streaming with inference provider: {:?}", self.inference_provider);
+        
+        let mut last_error = None;
+        for provider in self.provider_chain() {
+            let (backend, attempt) = match self.available_backend(provider, model).await {
+                Some(available) => available,
+                None => continue,
+            };
+            println!("[LLM] Streaming from {:?} backend with model {}", provider, backend.model_id());
+            
+            let model_id = backend.model_id().to_string();
+            let mut tokens = match backend.stream_chat(messages, options).await {
+                Ok(tokens) => tokens,
+                Err(e) => {
+                    eprintln!("[LLM] {:?} streaming failed: {}", provider, e);
+                    attempt.failed(&e.to_string());
+                    last_error = Some(e);
+                    continue;
                 }
-            }
-            InferenceProvider::LlamaCpp => {
-                if let Some(llama_cpp) = &self.llama_cpp_backend {
-                    let device_type = if cfg!(target_os = "macos") { "Metal" } else { "CPU" };
-                    println!("[LLM] Using LlamaCpp backend for inference with {} support", device_type);
-                    println!("[LLM] LlamaCpp model: {}", config.services.candle_model_id);
-                    
-                    // Try to generate with LlamaCpp, fall back to Ollama on error
-                    match llama_cpp.generate(prompt, 500).await {
-                        Ok(response) => return Ok(response),
-                        Err(e) => {
-                            eprintln!("[LLM] LlamaCpp generation failed: {}, falling back to Ollama", e);
-                        }
-                    }
-                } else {
-                    println!("[LLM] LlamaCpp backend not available, initializing...");
-                    // Try to initialize LlamaCpp backend on demand
-                    match LlamaCppMetalBackend::new(
-                        &config.services.candle_model_id,
-                        PathBuf::from(&config.services.candle_cache_dir),
-                    ).await {
-                        Ok(backend) => {
-                            println!("[LLM] LlamaCpp backend initialized successfully");
-                            match backend.generate(prompt, 500).await {
-                                Ok(response) => return Ok(response),
-                                Err(e) => {
-                                    eprintln!("[LLM] LlamaCpp generation failed: {}, falling back to Ollama", e);
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("[LLM] Failed to initialize LlamaCpp backend: {}, falling back to Ollama", e);
-                        }
-                    }
+            };
+            // An error before the first fragment means the backend could not
+            // generate at all, so move on instead of failing the stream
+            let tokens = match tokens.next().await {
+                Some(Ok(first)) => stream::once(async move { Ok(first) }).chain(tokens).boxed(),
+                Some(Err(e)) => {
+                    eprintln!("[LLM] {:?} streaming failed: {}", provider, e);
+                    attempt.failed(&e.to_string());
+                    last_error = Some(e);
+                    continue;
                 }
+                None => stream::empty().boxed(),
+            };
+            attempt.succeeded();
+            return Ok((tokens, self.record_served(provider, &model_id)));
+        }
+        
+        Err(last_error.unwrap_or_else(|| AppError::Llm("No inference provider is available".into())))
+    }
+    
+    /// Configured provider followed by the fallback chain
+    fn provider_chain(&self) -> Vec<InferenceProvider> {
+        provider_order(self.inference_provider, &self.fallback_chain)
+    }
+    
+    /// Backend for `provider` with the attempt to report its outcome to, or
+    /// None when its circuit breaker is open or it cannot be loaded
+    async fn available_backend(
+        &self,
+        provider: InferenceProvider,
+        model: &str,
+    ) -> Option<(Arc<dyn InferenceBackend>, Attempt<'_>)> {
+        let Some(attempt) = self.breakers.allows(provider) else {
+            println!("[LLM] Skipping {:?}, its circuit breaker is open", provider);
+            return None;
+        };
+        if provider == InferenceProvider::Ollama {
+            return Some((self.ollama_backend(model), attempt));
+        }
+        match self.local_backend(provider).await {
+            Ok(backend) => Some((backend, attempt)),
+            Err(e) => {
+                eprintln!("[LLM] Failed to initialize {:?} backend: {}", provider, e);
+                attempt.failed(&e.to_string());
+                None
+            }
+        }
+    }
+    
+    fn record_served(&self, provider: InferenceProvider, model: &str) -> ServedBy {
+        let served = ServedBy {
+            provider,
+            model: model.to_string(),
+            fallback: provider != self.inference_provider,
+        };
+        if served.fallback {
+            println!("[LLM] Reply served by fallback provider {:?} ({})", provider, model);
+        }
+        *self.last_served.lock().unwrap() = Some(served.clone());
+        served
+    }
+    
+    /// Get the loaded backend for a local provider, loading it on demand.
+    ///
+    /// Loaded backends stay in the model manager for later requests. One is
+    /// replaced when the provider's model variable (such as `CANDLE_MODEL_ID`)
+    /// names a different model, so model switches from the settings screen
+    /// apply to the next request.
+    async fn local_backend(&self, provider: InferenceProvider) -> Result<Arc<dyn InferenceBackend>> {
+        if std::env::var(model_env_var(provider)).is_err() {
+            if let Some(backend) = self.models.get(provider) {
+                return Ok(backend);
             }
-            _ => {}
         }
         
-        // Use Ollama as fallback
-        println!("[LLM] Using Ollama for inference");
-        println!("[LLM] Ollama model: {}", model);
-        let client = Client::default();
-
-        let chat_req = ChatRequest::new(vec![ChatMessage::user(prompt)]);
-
-        println!("LLM: Sending request to model...");
-        let chat_response = client.exec_chat(model, chat_req, None).await.map_err(|e| {
-            eprintln!("LLM: Request failed: {}", e);
-            AppError::Llm(format!("LLM request failed: {}", e))
-        })?;
-
-        println!("LLM: Received response, extracting content...");
-        let result = chat_response
-            .content_text_as_str()
-            .ok_or_else(|| AppError::Llm("Empty response from LLM".into()))
-            .map(|s| s.to_string());
-
-        match &result {
-            Ok(content) => println!(
-                "LLM: Successfully extracted content (length: {})",
-                content.len()
-            ),
-            Err(e) => eprintln!("LLM: Failed to extract content: {}", e),
+        let settings = BackendConfig::for_provider(Config::get(), provider);
+        self.ensure_loaded(provider, &settings).await
+    }
+    
+    fn ollama_backend(&self, model: &str) -> Arc<dyn InferenceBackend> {
+        match self.models.get(InferenceProvider::Ollama) {
+            Some(backend) if backend.model_id() == model => backend,
+            _ => Arc::new(OllamaBackend::new(model, &Config::get().services.ollama_url)),
         }
-
-        result
     }
 
     pub async fn get_available_models(&self) -> Result<Vec<String>> {
@@ -737,80 +646,32 @@ impl LlmClient {
         }
     }
 
-    fn extract_json(&self, text: &str) -> Result<serde_json::Value> {
-        // Try to extract JSON from the response
-        // LLMs sometimes wrap JSON in markdown code blocks
-        let cleaned = if text.contains("```json") {
-            text.split("```json")
-                .nth(1)
-                .and_then(|s| s.split("```").next())
-                .unwrap_or(text)
-        } else if text.contains("```") {
-            text.split("```")
-                .nth(1)
-                .and_then(|s| s.split("```").next())
-                .unwrap_or(text)
-        } else {
-            text
-        };
-
-        serde_json::from_str(cleaned.trim())
-            .map_err(|e| AppError::Llm(format!("Failed to parse JSON response: {}", e)))
-    }
-    
     pub async fn get_inference_info(&self) -> InferenceInfo {
-        let config = Config::get();
-        
-        let (provider_str, model_display) = match &self.inference_provider {
-            InferenceProvider::Candle => {
-                let model = match config.services.candle_model_id.as_str() {
-                    "TinyLlama/TinyLlama-1.1B-Chat-v1.0" => "TinyLlama 1.1B Chat",
-                    "microsoft/phi-2" => "Phi-2 2.7B",
-                    "mistralai/Mistral-7B-v0.1" => "Mistral 7B",
-                    _ => &config.services.candle_model_id,
-                };
-                ("Candle", model.to_string())
-            }
-            InferenceProvider::Crane => {
-                let model = match config.services.candle_model_id.as_str() {
-                    "Qwen/Qwen2.5-0.5B-Instruct" => "Qwen2.5 0.5B (Crane)",
-                    "Qwen/Qwen2.5-1.5B-Instruct" => "Qwen2.5 1.5B (Crane)",
-                    "Qwen/Qwen2.5-3B-Instruct" => "Qwen2.5 3B (Crane)",
-                    "Qwen/Qwen2.5-7B-Instruct" => "Qwen2.5 7B (Crane)",
-                    _ => &config.services.candle_model_id,
-                };
-                ("Crane", model.to_string())
-            }
-            InferenceProvider::Callm => {
-                let model = match config.services.candle_model_id.as_str() {
-                    "TinyLlama/TinyLlama-1.1B-Chat-v1.0" => "TinyLlama 1.1B (Callm)",
-                    "microsoft/phi-2" => "Phi-2 2.7B (Callm)",
-                    model if model.starts_with("Qwen/Qwen2") => "Qwen2 (Callm)",
-                    _ => &config.services.candle_model_id,
-                };
-                ("Callm", model.to_string())
-            }
-            InferenceProvider::Ollama => {
-                ("Ollama", self.model_name.clone())
-            }
-            InferenceProvider::LlamaCpp => {
-                let device_suffix = if cfg!(target_os = "macos") { "Metal" } else { "CPU" };
-                let model = match config.services.candle_model_id.as_str() {
-                    "TinyLlama/TinyLlama-1.1B-Chat-v1.0" => format!("TinyLlama 1.1B (LlamaCpp/{})", device_suffix),
-                    "Qwen/Qwen2.5-0.5B-Instruct" => format!("Qwen2.5 0.5B (LlamaCpp/{})", device_suffix),
-                    _ => format!("{} (LlamaCpp/{})", config.services.candle_model_id, device_suffix),
-                };
-                ("LlamaCpp", model)
-            }
+        let model_id = match self.inference_provider {
+            InferenceProvider::Ollama => self.model_name.clone(),
+            InferenceProvider::OpenAiCompatible => Config::get().services.openai_model.clone(),
+            _ => Config::get().services.candle_model_id.clone(),
         };
+        let (provider_str, model_display) = describe_model(self.inference_provider, &model_id);
         
         println!("[LLM] Inference info - Provider: {}, Model: {}", provider_str, model_display);
         
-        // For now, skip backend info to avoid hanging
+        // Backends report model info without waiting on a running generation
+        let candle_info = match self.inference_provider {
+            InferenceProvider::Ollama => None,
+            provider => match self.models.get(provider) {
+                Some(backend) => Some(backend.model_info().await),
+                None => None,
+            },
+        };
+
         InferenceInfo {
             provider: provider_str.to_string(),
             model_name: model_display,
-            candle_info: None, // Skip backend info for now
+            candle_info,
+            fallback_chain: self.fallback_chain.clone(),
+            last_served: self.last_served.lock().unwrap().clone(),
+            provider_status: self.breakers.statuses(),
         }
     }
 }
@@ -819,5 +680,106 @@ impl LlmClient {
 pub struct InferenceInfo {
     pub provider: String,
     pub model_name: String,
-    pub candle_info: Option<candle_backend::ModelInfo>,
+    pub candle_info: Option<backend::ModelInfo>,
+    pub fallback_chain: Vec<InferenceProvider>,
+    /// Provider that answered the most recent request
+    pub last_served: Option<ServedBy>,
+    pub provider_status: Vec<ProviderStatus>,
+}
+
+/// Activities as one JSON object per line, oldest first. The newest entries
+/// are kept when they do not all fit; older ones are summarised per app.
+fn activity_history(budget: &mut TokenBudget, activities: &[Activity]) -> Result<String> {
+    let mut newest_first: Vec<&Activity> = activities.iter().collect();
+    newest_first.sort_by_key(|activity| std::cmp::Reverse(activity.timestamp));
+    let entries = newest_first
+        .iter()
+        .map(serde_json::to_string)
+        .collect::<std::result::Result<Vec<_>, _>>()?;
+
+    let (kept, summary) = budget.fit_summarised("activity history", &entries, |kept| {
+        summarize_activities(&newest_first[kept..])
+    });
+
+    let mut lines: Vec<String> = summary.into_iter().collect();
+    lines.extend(entries[..kept].iter().rev().cloned());
+    Ok(lines.join("\n"))
+}
+
+/// One line describing activities left out of a prompt: their time span and
+/// where the time went
+fn summarize_activities(activities: &[&Activity]) -> String {
+    let mut per_app: Vec<(&str, i64)> = Vec::new();
+    for activity in activities {
+        let app = activity.app_usage.app_name.as_str();
+        match per_app.iter_mut().find(|(name, _)| *name == app) {
+            Some((_, seconds)) => *seconds += activity.duration_seconds,
+            None => per_app.push((app, activity.duration_seconds)),
+        }
+    }
+    per_app.sort_by_key(|(_, seconds)| std::cmp::Reverse(*seconds));
+
+    let first = activities.iter().map(|a| a.timestamp).min();
+    let last = activities.iter().map(|a| a.timestamp).max();
+    let span = match (first, last) {
+        (Some(first), Some(last)) => format!(" from {} to {}", first.format("%H:%M"), last.format("%H:%M")),
+        _ => String::new(),
+    };
+    let apps: Vec<String> = per_app
+        .iter()
+        .take(8)
+        .map(|(app, seconds)| format!("{} {}m", app, seconds / 60))
+        .collect();
+
+    format!(
+        "(Summary of {} earlier activities{} not listed individually. Time per app: {})",
+        activities.len(),
+        span,
+        apps.join(", ")
+    )
+}
+
+#[derive(serde::Deserialize)]
+struct InsightsReply {
+    summary: String,
+    key_insights: Vec<String>,
+    suggested_improvements: Vec<String>,
+}
+
+impl StructuredOutput for InsightsReply {
+    fn schema_name() -> &'static str {
+        "productivity_insights"
+    }
+
+    fn json_schema() -> serde_json::Value {
+        structured::object_schema(&[
+            ("summary", serde_json::json!({ "type": "string" })),
+            ("key_insights", structured::string_array()),
+            ("suggested_improvements", structured::string_array()),
+        ])
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct ScoreReply {
+    overall: f32,
+    focus: f32,
+    efficiency: f32,
+    breaks: f32,
+}
+
+impl StructuredOutput for ScoreReply {
+    fn schema_name() -> &'static str {
+        "productivity_score"
+    }
+
+    fn json_schema() -> serde_json::Value {
+        let score = serde_json::json!({ "type": "number", "minimum": 0, "maximum": 100 });
+        structured::object_schema(&[
+            ("overall", score.clone()),
+            ("focus", score.clone()),
+            ("efficiency", score.clone()),
+            ("breaks", score),
+        ])
+    }
 }
@@ -0,0 +1,138 @@
+use crate::config::InferenceProvider;
+use crate::error::Result;
+use std::future::Future;
+use std::sync::{Arc, RwLock};
+
+use super::backend::{BackendRegistry, InferenceBackend};
+
+/// Owns the loaded backends so they stay warm between requests.
+///
+/// The client is shared as a plain `Arc`, so backends loaded on demand are
+/// stored through interior mutability. Loading is serialised: concurrent
+/// requests for a model that is still loading wait for it instead of loading
+/// the weights a second time.
+pub struct ModelManager {
+    backends: RwLock<BackendRegistry>,
+    loading: tokio::sync::Mutex<()>,
+}
+
+impl ModelManager {
+    pub fn new() -> Self {
+        Self {
+            backends: RwLock::new(BackendRegistry::new()),
+            loading: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// The loaded backend for `provider`, if any
+    pub fn get(&self, provider: InferenceProvider) -> Option<Arc<dyn InferenceBackend>> {
+        self.backends.read().unwrap().get(provider)
+    }
+
+    /// Keep `backend` loaded, replacing the previous one for its provider
+    pub fn insert(&self, backend: Arc<dyn InferenceBackend>) {
+        self.backends.write().unwrap().register(backend);
+    }
+
+    /// The loaded backend for `provider` when it serves `model_id`, otherwise
+    /// the one produced by `load`, which then replaces it
+    pub async fn get_or_load<F, Fut>(
+        &self,
+        provider: InferenceProvider,
+        model_id: &str,
+        load: F,
+    ) -> Result<Arc<dyn InferenceBackend>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<Arc<dyn InferenceBackend>>>,
+    {
+        if let Some(backend) = self.loaded(provider, model_id) {
+            return Ok(backend);
+        }
+
+        let _loading = self.loading.lock().await;
+        // Another request may have finished loading it while this one waited
+        if let Some(backend) = self.loaded(provider, model_id) {
+            return Ok(backend);
+        }
+
+        if let Some(previous) = self.get(provider) {
+            println!("[ModelManager] Replacing {:?} model {} with {}", provider, previous.model_id(), model_id);
+        }
+        let backend = load().await?;
+        self.insert(backend.clone());
+        Ok(backend)
+    }
+
+    /// Unload every backend except the one for `provider`, freeing model memory
+    #[allow(dead_code)]
+    pub fn retain_only(&self, provider: InferenceProvider) {
+        self.backends.write().unwrap().retain_only(provider);
+    }
+
+    fn loaded(&self, provider: InferenceProvider, model_id: &str) -> Option<Arc<dyn InferenceBackend>> {
+        self.get(provider).filter(|backend| backend.model_id() == model_id)
+    }
+}
+
+impl Default for ModelManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::backend::tests::MockBackend;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn mock(model_id: &str) -> Arc<dyn InferenceBackend> {
+        Arc::new(MockBackend {
+            provider: InferenceProvider::Candle,
+            model_id: model_id.to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_load_once() {
+        let manager = Arc::new(ModelManager::new());
+        let loads = Arc::new(AtomicUsize::new(0));
+
+        let requests: Vec<_> = (0..4)
+            .map(|_| {
+                let manager = manager.clone();
+                let loads = loads.clone();
+                tokio::spawn(async move {
+                    manager
+                        .get_or_load(InferenceProvider::Candle, "mock-a", || async move {
+                            loads.fetch_add(1, Ordering::SeqCst);
+                            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                            Ok(mock("mock-a"))
+                        })
+                        .await
+                        .unwrap()
+                })
+            })
+            .collect();
+        for request in requests {
+            assert_eq!(request.await.unwrap().model_id(), "mock-a");
+        }
+
+        assert_eq!(loads.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_model_change_replaces_backend() {
+        let manager = ModelManager::new();
+        manager.insert(mock("mock-a"));
+
+        let backend = manager
+            .get_or_load(InferenceProvider::Candle, "mock-b", || async { Ok(mock("mock-b")) })
+            .await
+            .unwrap();
+
+        assert_eq!(backend.model_id(), "mock-b");
+        assert_eq!(manager.get(InferenceProvider::Candle).unwrap().model_id(), "mock-b");
+    }
+}
@@ -0,0 +1,179 @@
+use crate::config::InferenceProvider;
+use crate::error::{AppError, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use genai::chat::{ChatMessage, ChatOptions, ChatRequest, ChatStreamEvent, JsonSpec};
+use genai::Client;
+use std::sync::Arc;
+
+use super::backend::{BackendHealth, InferenceBackend, ModelInfo, TokenStream};
+use super::conversation::{MessageRole, PromptMessage};
+use super::options::GenerationOptions;
+
+/// Ollama's default `num_ctx`, which applies because requests do not set one
+const OLLAMA_CONTEXT_LENGTH: usize = 2048;
+
+pub struct OllamaBackend {
+    model_name: String,
+    base_url: String,
+    client: Client,
+}
+
+impl OllamaBackend {
+    pub fn new(model_name: &str, base_url: &str) -> Self {
+        Self {
+            model_name: model_name.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            client: Client::default(),
+        }
+    }
+
+    async fn exec_chat(&self, messages: &[PromptMessage], options: &ChatOptions) -> Result<String> {
+        println!("[OllamaBackend] Ollama model: {}", self.model_name);
+
+        let chat_req = chat_request(messages);
+
+        println!("LLM: Sending request to model...");
+        let chat_response = self
+            .client
+            .exec_chat(&self.model_name, chat_req, Some(options))
+            .await
+            .map_err(|e| {
+                eprintln!("LLM: Request failed: {}", e);
+                AppError::Llm(format!("LLM request failed: {}", e))
+            })?;
+
+        println!("LLM: Received response, extracting content...");
+        let result = chat_response
+            .content_text_as_str()
+            .ok_or_else(|| AppError::Llm("Empty response from LLM".into()))
+            .map(|s| s.to_string());
+
+        match &result {
+            Ok(content) => println!(
+                "LLM: Successfully extracted content (length: {})",
+                content.len()
+            ),
+            Err(e) => eprintln!("LLM: Failed to extract content: {}", e),
+        }
+
+        result
+    }
+}
+
+/// Map generation options onto the request options genai sends to Ollama
+fn chat_options(options: &GenerationOptions) -> ChatOptions {
+    let mut chat_options = ChatOptions::default()
+        .with_max_tokens(options.max_tokens as u32)
+        .with_stop_sequences(options.stop.clone());
+    if let Some(temperature) = options.temperature {
+        chat_options = chat_options.with_temperature(temperature);
+    }
+    if let Some(top_p) = options.top_p {
+        chat_options = chat_options.with_top_p(top_p);
+    }
+    chat_options
+}
+
+/// Convert role-tagged messages into a genai chat request
+fn chat_request(messages: &[PromptMessage]) -> ChatRequest {
+    ChatRequest::new(
+        messages
+            .iter()
+            .map(|message| match message.role {
+                MessageRole::System => ChatMessage::system(message.content.as_str()),
+                MessageRole::User => ChatMessage::user(message.content.as_str()),
+                MessageRole::Assistant => ChatMessage::assistant(message.content.as_str()),
+            })
+            .collect(),
+    )
+}
+
+#[async_trait]
+impl InferenceBackend for OllamaBackend {
+    fn provider(&self) -> InferenceProvider {
+        InferenceProvider::Ollama
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_name
+    }
+
+    fn context_length(&self) -> usize {
+        OLLAMA_CONTEXT_LENGTH
+    }
+
+    async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        self.generate_chat(&[PromptMessage::user(prompt)], options).await
+    }
+
+    async fn generate_chat(&self, messages: &[PromptMessage], options: &GenerationOptions) -> Result<String> {
+        self.exec_chat(messages, &chat_options(options)).await
+    }
+
+    async fn generate_chat_json(
+        &self,
+        messages: &[PromptMessage],
+        schema_name: &str,
+        schema: &serde_json::Value,
+        options: &GenerationOptions,
+    ) -> Result<String> {
+        // Ollama constrains decoding to the schema through its OpenAI compatible API
+        let chat_options = chat_options(options)
+            .with_response_format(JsonSpec::new(schema_name, schema.clone()));
+        self.exec_chat(messages, &chat_options).await
+    }
+
+    async fn stream(self: Arc<Self>, prompt: &str, options: &GenerationOptions) -> Result<TokenStream> {
+        self.stream_chat(&[PromptMessage::user(prompt)], options).await
+    }
+
+    async fn stream_chat(
+        self: Arc<Self>,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        println!("[OllamaBackend] Streaming from Ollama model: {}", self.model_name);
+
+        let chat_req = chat_request(messages);
+        let response = self
+            .client
+            .exec_chat_stream(&self.model_name, chat_req, Some(&chat_options(options)))
+            .await
+            .map_err(|e| AppError::Llm(format!("LLM stream request failed: {}", e)))?;
+
+        // Only answer text is forwarded; start, end and reasoning events are dropped
+        let stream = response.stream.filter_map(|event| async move {
+            match event {
+                Ok(ChatStreamEvent::Chunk(chunk)) => Some(Ok(chunk.content)),
+                Ok(_) => None,
+                Err(e) => Some(Err(AppError::Llm(format!("LLM stream failed: {}", e)))),
+            }
+        });
+
+        Ok(stream.boxed())
+    }
+
+    async fn model_info(&self) -> ModelInfo {
+        ModelInfo {
+            model_type: self.model_name.clone(),
+            device: "Ollama server".to_string(),
+            cache_dir: self.base_url.clone(),
+            loaded: true,
+            tokenizer_loaded: true,
+            supported_features: vec!["remote_inference".to_string()],
+        }
+    }
+
+    async fn health(&self) -> BackendHealth {
+        let url = format!("{}/api/tags", self.base_url);
+        match reqwest::Client::new().get(&url).send().await {
+            Ok(response) if response.status().is_success() => BackendHealth::Ready,
+            Ok(response) => BackendHealth::Unavailable(format!(
+                "Ollama returned status {}",
+                response.status()
+            )),
+            Err(e) => BackendHealth::Unavailable(format!("Failed to connect to Ollama: {}", e)),
+        }
+    }
+}
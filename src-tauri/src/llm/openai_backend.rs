@@ -0,0 +1,436 @@
+use crate::config::InferenceProvider;
+use crate::error::{AppError, Result};
+use async_trait::async_trait;
+use futures_util::StreamExt;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+use super::backend::{BackendHealth, InferenceBackend, ModelInfo, TokenStream};
+use super::conversation::{MessageRole, PromptMessage};
+use super::options::GenerationOptions;
+
+/// Backend for servers that implement the OpenAI `/v1/chat/completions` API,
+/// such as llama.cpp `server`, vLLM and LM Studio
+pub struct OpenAiCompatibleBackend {
+    model_name: String,
+    client: OpenAiClient,
+}
+
+impl OpenAiCompatibleBackend {
+    pub fn new(model_name: &str, base_url: &str, api_key: Option<String>) -> Self {
+        Self {
+            model_name: model_name.to_string(),
+            client: OpenAiClient::new(base_url, api_key),
+        }
+    }
+
+    fn chat_body(
+        &self,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+        stream: bool,
+    ) -> Value {
+        let messages: Vec<Value> = messages
+            .iter()
+            .map(|message| {
+                let role = match message.role {
+                    MessageRole::System => "system",
+                    MessageRole::User => "user",
+                    MessageRole::Assistant => "assistant",
+                };
+                json!({ "role": role, "content": message.content })
+            })
+            .collect();
+
+        let mut body = json!({
+            "model": self.model_name,
+            "messages": messages,
+            "max_tokens": options.max_tokens,
+            "stream": stream,
+        });
+        if let Some(temperature) = options.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(top_p) = options.top_p {
+            body["top_p"] = json!(top_p);
+        }
+        if !options.stop.is_empty() {
+            body["stop"] = json!(options.stop);
+        }
+        if let Some(seed) = options.seed {
+            body["seed"] = json!(seed);
+        }
+        body
+    }
+
+    async fn exec_chat(&self, body: &Value) -> Result<String> {
+        println!("[OpenAiCompatibleBackend] Sending request to {} ({})", self.client.base_url, self.model_name);
+
+        let response: ChatCompletion = self.client.post_json("/chat/completions", body).await?;
+        response
+            .choices
+            .into_iter()
+            .next()
+            .and_then(|choice| choice.message.content)
+            .ok_or_else(|| AppError::Llm("Empty response from LLM".into()))
+    }
+}
+
+/// HTTP client for an OpenAI-compatible server, shared by chat and embeddings
+#[derive(Clone)]
+pub struct OpenAiClient {
+    base_url: String,
+    api_key: Option<String>,
+    http: reqwest::Client,
+}
+
+impl OpenAiClient {
+    /// `base_url` includes the API version, e.g. `http://localhost:8080/v1`
+    pub fn new(base_url: &str, api_key: Option<String>) -> Self {
+        Self {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            api_key: api_key.filter(|key| !key.is_empty()),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.base_url, path));
+        match &self.api_key {
+            Some(key) => request.bearer_auth(key),
+            None => request,
+        }
+    }
+
+    async fn send(&self, path: &str, body: &Value) -> Result<reqwest::Response> {
+        let response = self.request(reqwest::Method::POST, path).json(body).send().await?;
+        if !response.status().is_success() {
+            let status = response.status();
+            let detail = response.text().await.unwrap_or_default();
+            return Err(AppError::Llm(format!(
+                "{}{} returned status {}: {}",
+                self.base_url, path, status, detail
+            )));
+        }
+        Ok(response)
+    }
+
+    async fn post_json<T: serde::de::DeserializeOwned>(&self, path: &str, body: &Value) -> Result<T> {
+        let response = self.send(path, body).await?;
+        response
+            .json()
+            .await
+            .map_err(|e| AppError::Llm(format!("Invalid response from {}{}: {}", self.base_url, path, e)))
+    }
+
+    /// Embed each input with `model`, in input order
+    pub async fn embed(&self, model: &str, inputs: &[String]) -> Result<Vec<Vec<f32>>> {
+        let body = json!({ "model": model, "input": inputs });
+        let mut response: EmbeddingResponse = self.post_json("/embeddings", &body).await?;
+        if response.data.len() != inputs.len() {
+            return Err(AppError::Llm(format!(
+                "Expected {} embeddings, got {}",
+                inputs.len(),
+                response.data.len()
+            )));
+        }
+        response.data.sort_by_key(|item| item.index);
+        Ok(response.data.into_iter().map(|item| item.embedding).collect())
+    }
+}
+
+#[derive(Deserialize)]
+struct ChatCompletion {
+    choices: Vec<ChatChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatChoice {
+    message: ChatChoiceMessage,
+}
+
+#[derive(Deserialize)]
+struct ChatChoiceMessage {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatChunk {
+    choices: Vec<ChatChunkChoice>,
+}
+
+#[derive(Deserialize)]
+struct ChatChunkChoice {
+    #[serde(default)]
+    delta: ChatDelta,
+}
+
+#[derive(Deserialize, Default)]
+struct ChatDelta {
+    content: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingItem>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingItem {
+    embedding: Vec<f32>,
+    #[serde(default)]
+    index: usize,
+}
+
+/// Splits a server-sent event stream into the payloads of its `data:` lines
+#[derive(Default)]
+struct SseDecoder {
+    buffer: Vec<u8>,
+}
+
+impl SseDecoder {
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(bytes);
+        let mut payloads = Vec::new();
+        while let Some(end) = self.buffer.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buffer.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if let Some(data) = line.trim_end().strip_prefix("data:") {
+                payloads.push(data.trim_start().to_string());
+            }
+        }
+        payloads
+    }
+}
+
+#[async_trait]
+impl InferenceBackend for OpenAiCompatibleBackend {
+    fn provider(&self) -> InferenceProvider {
+        InferenceProvider::OpenAiCompatible
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_name
+    }
+
+    async fn generate(&self, prompt: &str, options: &GenerationOptions) -> Result<String> {
+        self.generate_chat(&[PromptMessage::user(prompt)], options).await
+    }
+
+    async fn generate_chat(&self, messages: &[PromptMessage], options: &GenerationOptions) -> Result<String> {
+        self.exec_chat(&self.chat_body(messages, options, false)).await
+    }
+
+    async fn generate_chat_json(
+        &self,
+        messages: &[PromptMessage],
+        schema_name: &str,
+        schema: &Value,
+        options: &GenerationOptions,
+    ) -> Result<String> {
+        let mut body = self.chat_body(messages, options, false);
+        body["response_format"] = json!({
+            "type": "json_schema",
+            "json_schema": { "name": schema_name, "schema": schema },
+        });
+        self.exec_chat(&body).await
+    }
+
+    async fn stream(self: Arc<Self>, prompt: &str, options: &GenerationOptions) -> Result<TokenStream> {
+        self.stream_chat(&[PromptMessage::user(prompt)], options).await
+    }
+
+    async fn stream_chat(
+        self: Arc<Self>,
+        messages: &[PromptMessage],
+        options: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        println!("[OpenAiCompatibleBackend] Streaming from {} ({})", self.client.base_url, self.model_name);
+
+        let body = self.chat_body(messages, options, true);
+        let mut response = self.client.send("/chat/completions", &body).await?;
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            let mut decoder = SseDecoder::default();
+            loop {
+                let bytes = match response.chunk().await {
+                    Ok(Some(bytes)) => bytes,
+                    Ok(None) => return,
+                    Err(e) => {
+                        let _ = tx.send(Err(AppError::Llm(format!("LLM stream failed: {}", e))));
+                        return;
+                    }
+                };
+                for payload in decoder.push(&bytes) {
+                    if payload == "[DONE]" {
+                        return;
+                    }
+                    let chunk: ChatChunk = match serde_json::from_str(&payload) {
+                        Ok(chunk) => chunk,
+                        Err(e) => {
+                            let _ = tx.send(Err(AppError::Llm(format!("Invalid stream chunk: {}", e))));
+                            return;
+                        }
+                    };
+                    let text = chunk.choices.into_iter().filter_map(|choice| choice.delta.content);
+                    for text in text.filter(|text| !text.is_empty()) {
+                        // The receiver is gone once the caller drops the stream
+                        if tx.send(Ok(text)).is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(UnboundedReceiverStream::new(rx).boxed())
+    }
+
+    async fn model_info(&self) -> ModelInfo {
+        ModelInfo {
+            model_type: self.model_name.clone(),
+            device: "OpenAI-compatible server".to_string(),
+            cache_dir: self.client.base_url.clone(),
+            loaded: true,
+            tokenizer_loaded: true,
+            supported_features: vec!["remote_inference".to_string(), "embeddings".to_string()],
+        }
+    }
+
+    async fn health(&self) -> BackendHealth {
+        match self.client.request(reqwest::Method::GET, "/models").send().await {
+            Ok(response) if response.status().is_success() => BackendHealth::Ready,
+            Ok(response) => BackendHealth::Unavailable(format!(
+                "{} returned status {}",
+                self.client.base_url,
+                response.status()
+            )),
+            Err(e) => BackendHealth::Unavailable(format!(
+                "Failed to connect to {}: {}",
+                self.client.base_url, e
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Minimal HTTP server that answers every request with `body`, sent in
+    /// `parts` separate writes, and records the raw requests it received
+    async fn mock_server(content_type: &'static str, parts: Vec<&'static str>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base_url = format!("http://{}/v1", listener.local_addr().unwrap());
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let received = Arc::clone(&requests);
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buf = [0u8; 4096];
+                // Read headers, then as much body as Content-Length announces
+                loop {
+                    let n = socket.read(&mut buf).await.unwrap();
+                    if n == 0 {
+                        break;
+                    }
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some(header_end) = text.find("\r\n\r\n") {
+                        let length = text
+                            .lines()
+                            .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().to_string()))
+                            .and_then(|v| v.parse::<usize>().ok())
+                            .unwrap_or(0);
+                        if request.len() >= header_end + 4 + length {
+                            break;
+                        }
+                    }
+                }
+                received.lock().unwrap().push(String::from_utf8_lossy(&request).to_string());
+
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nConnection: close\r\n\r\n",
+                    content_type
+                );
+                socket.write_all(head.as_bytes()).await.unwrap();
+                for part in &parts {
+                    socket.write_all(part.as_bytes()).await.unwrap();
+                    socket.flush().await.unwrap();
+                    tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+                }
+            }
+        });
+
+        (base_url, requests)
+    }
+
+    #[tokio::test]
+    async fn test_chat_completion_request_and_reply() {
+        let (base_url, requests) = mock_server(
+            "application/json",
+            vec![r#"{"choices":[{"message":{"role":"assistant","content":"Four."}}]}"#],
+        )
+        .await;
+        let backend = OpenAiCompatibleBackend::new("qwen2.5", &base_url, Some("secret".to_string()));
+
+        let options = GenerationOptions::default().with_max_tokens(32);
+        let messages = [PromptMessage::system("Be brief."), PromptMessage::user("2+2?")];
+        let reply = backend.generate_chat(&messages, &options).await.unwrap();
+        assert_eq!(reply, "Four.");
+
+        let request = requests.lock().unwrap()[0].clone();
+        assert!(request.starts_with("POST /v1/chat/completions"));
+        assert!(request.to_lowercase().contains("authorization: bearer secret"));
+        let body: Value = serde_json::from_str(&request[request.find("\r\n\r\n").unwrap() + 4..]).unwrap();
+        assert_eq!(body["model"], "qwen2.5");
+        assert_eq!(body["max_tokens"], 32);
+        assert_eq!(body["messages"][0]["role"], "system");
+        assert_eq!(body["messages"][1]["content"], "2+2?");
+    }
+
+    #[tokio::test]
+    async fn test_stream_chat_parses_server_sent_events() {
+        let (base_url, _) = mock_server(
+            "text/event-stream",
+            vec![
+                "data: {\"choices\":[{\"delta\":{\"role\":\"assistant\"}}]}\n\n",
+                "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\ndata: {\"choi",
+                "ces\":[{\"delta\":{\"content\":\"lo\"}}]}\n\n",
+                "data: [DONE]\n\n",
+            ],
+        )
+        .await;
+        let backend = Arc::new(OpenAiCompatibleBackend::new("local", &base_url, None));
+
+        let fragments: Vec<String> = backend
+            .stream("Hi", &GenerationOptions::default())
+            .await
+            .unwrap()
+            .map(|fragment| fragment.unwrap())
+            .collect()
+            .await;
+        assert_eq!(fragments, vec!["Hel", "lo"]);
+    }
+
+    #[tokio::test]
+    async fn test_embeddings_are_returned_in_input_order() {
+        let (base_url, _) = mock_server(
+            "application/json",
+            vec![r#"{"data":[{"index":1,"embedding":[0.0,1.0]},{"index":0,"embedding":[1.0,0.0]}]}"#],
+        )
+        .await;
+        let client = OpenAiClient::new(&base_url, None);
+
+        let inputs = vec!["first".to_string(), "second".to_string()];
+        let embeddings = client.embed("nomic-embed-text", &inputs).await.unwrap();
+        assert_eq!(embeddings, vec![vec![1.0, 0.0], vec![0.0, 1.0]]);
+    }
+}
@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+
+/// Token limit used when a caller does not set one
+pub const DEFAULT_MAX_TOKENS: usize = 500;
+
+/// Sampling settings for a single generation request.
+///
+/// Unset values fall back to the backend's own defaults. The seed is honoured
+/// by the local backends; Ollama requests are sampled with the server's seed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GenerationOptions {
+    pub max_tokens: usize,
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    /// Text that ends the reply; the stop sequence itself is not returned
+    pub stop: Vec<String>,
+    pub seed: Option<u64>,
+}
+
+impl Default for GenerationOptions {
+    fn default() -> Self {
+        Self {
+            max_tokens: DEFAULT_MAX_TOKENS,
+            temperature: None,
+            top_p: None,
+            stop: Vec::new(),
+            seed: None,
+        }
+    }
+}
+
+impl GenerationOptions {
+    pub fn with_max_tokens(mut self, max_tokens: usize) -> Self {
+        self.max_tokens = max_tokens;
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    #[allow(dead_code)]
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+}
+
+/// Byte offset of the earliest stop sequence in `text`
+pub fn find_stop(text: &str, stop: &[String]) -> Option<usize> {
+    stop.iter()
+        .filter(|sequence| !sequence.is_empty())
+        .filter_map(|sequence| text.find(sequence.as_str()))
+        .min()
+}
+
+/// Cut `text` at the first stop sequence
+pub fn truncate_at_stop(mut text: String, stop: &[String]) -> String {
+    if let Some(pos) = find_stop(&text, stop) {
+        text.truncate(pos);
+    }
+    text
+}
+
+/// Applies stop sequences to text that is streamed through `on_text`.
+///
+/// Text that may be the start of a stop sequence is held back until the next
+/// fragment shows whether it is one.
+pub(crate) struct StopFilter<'a> {
+    stop: &'a [String],
+    on_text: &'a mut (dyn FnMut(&str) -> bool + Send),
+    output: String,
+    emitted: usize,
+}
+
+impl<'a> StopFilter<'a> {
+    pub fn new(stop: &'a [String], on_text: &'a mut (dyn FnMut(&str) -> bool + Send)) -> Self {
+        Self {
+            stop,
+            on_text,
+            output: String::new(),
+            emitted: 0,
+        }
+    }
+
+    /// Forward a fragment. Returns false once a stop sequence has been seen or
+    /// the receiver no longer wants text, so generation should end.
+    pub fn push(&mut self, fragment: &str) -> bool {
+        if self.stop.is_empty() {
+            return (self.on_text)(fragment);
+        }
+
+        self.output.push_str(fragment);
+        if let Some(pos) = find_stop(&self.output, self.stop) {
+            self.output.truncate(pos);
+            self.flush();
+            return false;
+        }
+
+        let held_back = self
+            .stop
+            .iter()
+            .filter_map(|sequence| {
+                (1..sequence.len())
+                    .rev()
+                    .filter(|&n| sequence.is_char_boundary(n))
+                    .find(|&n| self.output.ends_with(&sequence[..n]))
+            })
+            .max()
+            .unwrap_or(0);
+        self.emit_to(self.output.len() - held_back)
+    }
+
+    /// Forward text that was held back once generation has ended
+    pub fn flush(&mut self) {
+        self.emit_to(self.output.len());
+    }
+
+    fn emit_to(&mut self, end: usize) -> bool {
+        if end <= self.emitted {
+            return true;
+        }
+        let keep_going = (self.on_text)(&self.output[self.emitted..end]);
+        self.emitted = end;
+        keep_going
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(stop: &[&str], fragments: &[&str]) -> (String, bool) {
+        let stop: Vec<String> = stop.iter().map(|s| s.to_string()).collect();
+        let mut received = String::new();
+        let mut on_text = |text: &str| {
+            received.push_str(text);
+            true
+        };
+        let mut filter = StopFilter::new(&stop, &mut on_text);
+        let mut stopped = false;
+        for fragment in fragments {
+            if !filter.push(fragment) {
+                stopped = true;
+                break;
+            }
+        }
+        filter.flush();
+        (received, stopped)
+    }
+
+    #[test]
+    fn test_stop_sequence_split_across_fragments() {
+        let (text, stopped) = run(&["\nUser:"], &["Hello", " there\nUs", "er: next"]);
+        assert_eq!(text, "Hello there");
+        assert!(stopped);
+    }
+
+    #[test]
+    fn test_partial_match_is_flushed() {
+        let (text, stopped) = run(&["###"], &["a #", "# b"]);
+        assert_eq!(text, "a ## b");
+        assert!(!stopped);
+
+        assert_eq!(truncate_at_stop("one. two".to_string(), &[".".to_string()]), "one");
+    }
+}
@@ -0,0 +1,85 @@
+/// Number of saved model states kept per backend
+pub const PROMPT_CACHE_ENTRIES: usize = 4;
+
+/// Model states saved after processing a token sequence.
+///
+/// Candle models keep their KV cache inside the model and tensors share
+/// storage, so cloning a model is a cheap snapshot of everything it has seen.
+/// A later prompt that starts with the same tokens, such as the next turn of
+/// a conversation, restores the snapshot and only processes the new tokens.
+pub struct PromptCache<S> {
+    capacity: usize,
+    /// Least recently used first
+    entries: Vec<(Vec<u32>, S)>,
+}
+
+impl<S: Clone> PromptCache<S> {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            entries: Vec::new(),
+        }
+    }
+
+    /// The saved state covering the longest prefix of `tokens`, with the number
+    /// of tokens it covers. At least one token is always left to process so the
+    /// caller gets logits to sample from.
+    pub fn restore(&mut self, tokens: &[u32]) -> Option<(usize, S)> {
+        let index = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, (saved, _))| saved.len() < tokens.len() && tokens.starts_with(saved))
+            .max_by_key(|(_, (saved, _))| saved.len())
+            .map(|(index, _)| index)?;
+
+        let entry = self.entries.remove(index);
+        let restored = (entry.0.len(), entry.1.clone());
+        self.entries.push(entry);
+        Some(restored)
+    }
+
+    /// Save the state reached after processing `tokens`
+    pub fn save(&mut self, tokens: Vec<u32>, state: S) {
+        if tokens.is_empty() {
+            return;
+        }
+        self.entries.retain(|(saved, _)| *saved != tokens);
+        if self.entries.len() >= self.capacity {
+            self.entries.remove(0);
+        }
+        self.entries.push((tokens, state));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_longest_prefix() {
+        let mut cache = PromptCache::new(4);
+        cache.save(vec![1, 2], "system");
+        cache.save(vec![1, 2, 3, 4], "first turn");
+        cache.save(vec![9, 9, 9], "other conversation");
+
+        assert_eq!(cache.restore(&[1, 2, 3, 4, 5, 6]), Some((4, "first turn")));
+        assert_eq!(cache.restore(&[1, 2, 7]), Some((2, "system")));
+        // A full match still leaves the last token to process
+        assert_eq!(cache.restore(&[1, 2, 3, 4]), Some((2, "system")));
+        assert_eq!(cache.restore(&[5]), None);
+    }
+
+    #[test]
+    fn test_least_recently_used_is_evicted() {
+        let mut cache = PromptCache::new(2);
+        cache.save(vec![1], 'a');
+        cache.save(vec![2], 'b');
+        assert!(cache.restore(&[1, 0]).is_some());
+        cache.save(vec![3], 'c');
+
+        assert_eq!(cache.restore(&[1, 0]), Some((1, 'a')));
+        assert_eq!(cache.restore(&[2, 0]), None);
+        assert_eq!(cache.restore(&[3, 0]), Some((1, 'c')));
+    }
+}
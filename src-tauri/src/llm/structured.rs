@@ -0,0 +1,127 @@
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+
+/// A type the model can be asked to produce as JSON.
+///
+/// The schema is sent to backends that can constrain their output and is
+/// included in the prompt for the ones that cannot. Parsing into the type is
+/// what validates the reply.
+pub trait StructuredOutput: DeserializeOwned {
+    /// Name for the schema, letters, digits, `-` and `_` only
+    fn schema_name() -> &'static str;
+
+    fn json_schema() -> Value;
+}
+
+impl StructuredOutput for Vec<String> {
+    fn schema_name() -> &'static str {
+        "string_list"
+    }
+
+    fn json_schema() -> Value {
+        string_array()
+    }
+}
+
+/// Schema for an object where every listed property is required
+pub fn object_schema(properties: &[(&str, Value)]) -> Value {
+    let required: Vec<&str> = properties.iter().map(|(name, _)| *name).collect();
+    let properties: serde_json::Map<String, Value> = properties
+        .iter()
+        .map(|(name, schema)| (name.to_string(), schema.clone()))
+        .collect();
+    json!({
+        "type": "object",
+        "properties": properties,
+        "required": required,
+    })
+}
+
+pub fn string_array() -> Value {
+    json!({ "type": "array", "items": { "type": "string" } })
+}
+
+/// Instructions prepended to a structured request
+pub fn schema_instructions(schema: &Value) -> String {
+    format!(
+        "Reply only with JSON that matches this JSON schema, without any other text:\n{}",
+        schema
+    )
+}
+
+/// Prompt asking the model to fix a reply that did not parse
+pub fn repair_prompt(error: &str) -> String {
+    format!(
+        "Your previous reply could not be used: {}. \
+        Reply again with only the corrected JSON, matching the schema exactly.",
+        error
+    )
+}
+
+/// Parse a model reply into `T`, tolerating markdown fences and text around
+/// the JSON. The error describes what was wrong so it can be sent back.
+pub fn parse_reply<T: DeserializeOwned>(reply: &str) -> std::result::Result<T, String> {
+    let json = extract_json_text(reply);
+    if json.is_empty() {
+        return Err("the reply did not contain JSON".to_string());
+    }
+    serde_json::from_str(json).map_err(|e| format!("invalid JSON ({})", e))
+}
+
+/// Find the JSON value in a model reply.
+///
+/// LLMs sometimes wrap JSON in markdown code blocks or add a sentence before it.
+pub fn extract_json_text(text: &str) -> &str {
+    let cleaned = if text.contains("```json") {
+        text.split("```json")
+            .nth(1)
+            .and_then(|s| s.split("```").next())
+            .unwrap_or(text)
+    } else if text.contains("```") {
+        text.split("```")
+            .nth(1)
+            .and_then(|s| s.split("```").next())
+            .unwrap_or(text)
+    } else {
+        text
+    };
+
+    // Cut from the first opening bracket to the last matching closing one
+    let start = cleaned.find(['{', '[']);
+    let end = match start.map(|i| cleaned.as_bytes()[i]) {
+        Some(b'{') => cleaned.rfind('}'),
+        Some(_) => cleaned.rfind(']'),
+        None => None,
+    };
+    match (start, end) {
+        (Some(start), Some(end)) if end > start => &cleaned[start..=end],
+        _ => cleaned.trim(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, serde::Deserialize)]
+    struct Score {
+        overall: f32,
+    }
+
+    #[test]
+    fn test_extract_json_text() {
+        assert_eq!(extract_json_text("```json\n{\"a\": 1}\n```"), "{\"a\": 1}");
+        assert_eq!(extract_json_text("Here you go: [\"x\", \"y\"] Hope it helps"), "[\"x\", \"y\"]");
+        assert_eq!(extract_json_text("Sure! {\"a\": {\"b\": 2}}."), "{\"a\": {\"b\": 2}}");
+    }
+
+    #[test]
+    fn test_parse_reply_reports_type_errors() {
+        let score: Score = parse_reply("{\"overall\": 72.5}").unwrap();
+        assert_eq!(score.overall, 72.5);
+
+        let error = parse_reply::<Score>("{\"overall\": \"high\"}").unwrap_err();
+        assert!(error.contains("invalid type"));
+        assert!(parse_reply::<Score>("I cannot do that").is_err());
+    }
+}
@@ -0,0 +1,315 @@
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+use tokenizers::Tokenizer;
+
+use super::backend::cached_model_file;
+
+/// Context length assumed for models that are not in the table
+pub const DEFAULT_CONTEXT_LENGTH: usize = 2048;
+
+/// Tokens kept free for the chat template's role markers
+const TEMPLATE_OVERHEAD: usize = 64;
+
+/// Characters per token when no tokenizer is available. Deliberately low so
+/// estimates err on the side of a prompt that fits.
+const ESTIMATED_CHARS_PER_TOKEN: usize = 3;
+
+/// Tokens held back for the summary that replaces dropped items
+const SUMMARY_TOKENS: usize = 96;
+
+/// Smallest remainder worth filling with the start of a section that does not
+/// fit as a whole
+const MIN_TRIMMED_TOKENS: usize = 48;
+
+/// Context length of a model, by model id
+pub fn context_length_for_model(model_id: &str) -> usize {
+    let id = model_id.to_lowercase();
+    if id.contains("qwen2") {
+        32768
+    } else if id.contains("llama-3") || id.contains("llama3") || id.contains("mistral") {
+        8192
+    } else {
+        DEFAULT_CONTEXT_LENGTH
+    }
+}
+
+/// Measures text in the active model's tokens
+#[derive(Clone)]
+pub enum TokenCounter {
+    Tokenizer(Arc<Tokenizer>),
+    /// Character based estimate for models whose tokenizer is not available
+    Estimate,
+}
+
+impl TokenCounter {
+    /// Counter using the model's `tokenizer.json` from the Hugging Face cache,
+    /// or an estimate when it has not been downloaded
+    pub fn for_model(model_id: &str, cache_dir: &Path) -> Self {
+        match cached_model_file(model_id, cache_dir, "tokenizer.json")
+            .and_then(|path| Tokenizer::from_file(path).ok())
+        {
+            Some(tokenizer) => TokenCounter::Tokenizer(Arc::new(tokenizer)),
+            None => TokenCounter::Estimate,
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        match self {
+            TokenCounter::Tokenizer(tokenizer) => match tokenizer.encode(text, false) {
+                Ok(encoding) => encoding.len(),
+                Err(_) => estimate(text),
+            },
+            TokenCounter::Estimate => estimate(text),
+        }
+    }
+
+    /// The longest start of `text` that is at most `max_tokens` tokens
+    pub fn truncate<'a>(&self, text: &'a str, max_tokens: usize) -> &'a str {
+        let end = match self {
+            TokenCounter::Tokenizer(tokenizer) => tokenizer
+                .encode(text, false)
+                .ok()
+                .map(|encoding| match encoding.get_offsets().get(max_tokens) {
+                    Some(&(start, _)) => start,
+                    None => text.len(),
+                }),
+            TokenCounter::Estimate => None,
+        };
+        let end = end.unwrap_or_else(|| {
+            text.char_indices()
+                .nth(max_tokens * ESTIMATED_CHARS_PER_TOKEN)
+                .map_or(text.len(), |(index, _)| index)
+        });
+        // Offsets are byte positions, which may fall inside a character
+        let end = (0..=end.min(text.len()))
+            .rev()
+            .find(|&index| text.is_char_boundary(index))
+            .unwrap_or(0);
+        &text[..end]
+    }
+}
+
+fn estimate(text: &str) -> usize {
+    text.chars().count().div_ceil(ESTIMATED_CHARS_PER_TOKEN)
+}
+
+/// Prompt content left out to fit the context window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DroppedContext {
+    pub section: String,
+    /// Items left out entirely or in part
+    pub items: usize,
+    pub tokens: usize,
+    /// Whether the prompt carries a short summary in their place
+    pub summarised: bool,
+}
+
+impl fmt::Display for DroppedContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} item(s) left out (~{} tokens)", self.items, self.section, self.tokens)?;
+        if self.summarised {
+            write!(f, ", summarised instead")?;
+        }
+        Ok(())
+    }
+}
+
+/// How a prompt was fitted into the model's context window
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetReport {
+    pub context_length: usize,
+    pub prompt_tokens: usize,
+    pub dropped: Vec<DroppedContext>,
+}
+
+/// Tracks how much of the context window is left while a prompt is built.
+///
+/// Required text is reserved first; sections are then fitted in priority
+/// order, and whatever does not fit is recorded for the report.
+pub struct TokenBudget {
+    counter: TokenCounter,
+    context_length: usize,
+    capacity: usize,
+    remaining: usize,
+    dropped: Vec<DroppedContext>,
+}
+
+impl TokenBudget {
+    /// Budget for a prompt to a model with `context_length` tokens of context
+    /// that leaves room for a reply of `reply_tokens`
+    pub fn new(counter: TokenCounter, context_length: usize, reply_tokens: usize) -> Self {
+        let capacity = context_length.saturating_sub(reply_tokens + TEMPLATE_OVERHEAD);
+        Self {
+            counter,
+            context_length,
+            capacity,
+            remaining: capacity,
+            dropped: Vec::new(),
+        }
+    }
+
+    pub fn count(&self, text: &str) -> usize {
+        self.counter.count(text)
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+
+    /// Account for text that is always sent, such as instructions and the
+    /// question
+    pub fn reserve(&mut self, text: &str) {
+        self.remaining = self.remaining.saturating_sub(self.count(text));
+    }
+
+    /// Keep `items`, highest priority first, while they fit. Returns how many
+    /// were kept; the rest are recorded as dropped from `section`.
+    pub fn fit(&mut self, section: &str, items: &[String]) -> usize {
+        let mut kept = 0;
+        for item in items {
+            let tokens = self.count(item);
+            if tokens > self.remaining {
+                break;
+            }
+            self.remaining -= tokens;
+            kept += 1;
+        }
+        self.record_dropped(section, &items[kept..]);
+        kept
+    }
+
+    /// Like `fit`, but the first item that does not fit is cut to the space
+    /// that is left instead of being dropped, when enough space is left
+    pub fn fit_trimmed(&mut self, section: &str, items: &[String]) -> Vec<String> {
+        let kept = self.fit(section, items);
+        let mut fitted = items[..kept].to_vec();
+        if kept < items.len() && self.remaining >= MIN_TRIMMED_TOKENS {
+            let trimmed = self.counter.truncate(&items[kept], self.remaining).to_string();
+            self.remaining = self.remaining.saturating_sub(self.count(&trimmed));
+            fitted.push(trimmed);
+        }
+        fitted
+    }
+
+    /// Like `fit`, but when items are dropped `summarise` is called with the
+    /// number kept and its text takes their place. Space for the summary is
+    /// held back while the items are fitted.
+    pub fn fit_summarised(
+        &mut self,
+        section: &str,
+        items: &[String],
+        summarise: impl FnOnce(usize) -> String,
+    ) -> (usize, Option<String>) {
+        let held = SUMMARY_TOKENS.min(self.remaining);
+        self.remaining -= held;
+        let kept = self.fit(section, items);
+        self.remaining += held;
+        if kept == items.len() {
+            return (kept, None);
+        }
+
+        let summary = summarise(kept);
+        let summary = self.counter.truncate(&summary, self.remaining).to_string();
+        self.summarised(section, &summary);
+        (kept, Some(summary))
+    }
+
+    /// The longest start of `text` that is at most `max_tokens` tokens
+    pub fn truncate<'a>(&self, text: &'a str, max_tokens: usize) -> &'a str {
+        self.counter.truncate(text, max_tokens)
+    }
+
+    /// Note that the prompt carries `summary` in place of what was dropped
+    /// from `section`
+    pub fn summarised(&mut self, section: &str, summary: &str) {
+        self.reserve(summary);
+        if let Some(dropped) = self.dropped.iter_mut().find(|d| d.section == section) {
+            dropped.summarised = true;
+        }
+    }
+
+    pub fn report(&self) -> BudgetReport {
+        BudgetReport {
+            context_length: self.context_length,
+            prompt_tokens: self.capacity - self.remaining,
+            dropped: self.dropped.clone(),
+        }
+    }
+
+    /// Record `items` as left out of `section`
+    pub fn record_dropped(&mut self, section: &str, items: &[String]) {
+        if items.is_empty() {
+            return;
+        }
+        let tokens = items.iter().map(|item| self.count(item)).sum();
+        println!("[TokenBudget] Leaving out {} {} item(s) (~{} tokens) to fit the context window", items.len(), section, tokens);
+        self.dropped.push(DroppedContext {
+            section: section.to_string(),
+            items: items.len(),
+            tokens,
+            summarised: false,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn items(lengths: &[usize]) -> Vec<String> {
+        lengths.iter().map(|&len| "x".repeat(len * ESTIMATED_CHARS_PER_TOKEN)).collect()
+    }
+
+    #[test]
+    fn test_fit_keeps_priority_order_and_reports_the_rest() {
+        // 300 tokens of context, 100 for the reply and 64 for the template
+        let mut budget = TokenBudget::new(TokenCounter::Estimate, 300, 100);
+        assert_eq!(budget.remaining(), 136);
+
+        budget.reserve(&"q".repeat(36 * ESTIMATED_CHARS_PER_TOKEN));
+        assert_eq!(budget.fit("documents", &items(&[40, 50, 20, 10])), 2);
+        assert_eq!(budget.remaining(), 10);
+        assert_eq!(budget.fit("activity", &items(&[5, 5, 5])), 2);
+
+        let report = budget.report();
+        assert_eq!(report.prompt_tokens, 136);
+        assert_eq!(
+            report.dropped,
+            vec![
+                DroppedContext { section: "documents".into(), items: 2, tokens: 30, summarised: false },
+                DroppedContext { section: "activity".into(), items: 1, tokens: 5, summarised: false },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fit_summarised_holds_back_room_for_the_summary() {
+        // 200 tokens to fill, 96 of them held back while fitting
+        let mut budget = TokenBudget::new(TokenCounter::Estimate, 364, 100);
+        let (kept, summary) = budget.fit_summarised("activity", &items(&[60, 60, 60]), |kept| {
+            format!("{} older", 3 - kept)
+        });
+
+        assert_eq!(kept, 1);
+        assert_eq!(summary.as_deref(), Some("2 older"));
+        assert!(budget.report().dropped[0].summarised);
+        assert_eq!(budget.remaining(), 200 - 60 - 3);
+    }
+
+    #[test]
+    fn test_fit_trimmed_cuts_the_next_item() {
+        let mut budget = TokenBudget::new(TokenCounter::Estimate, 284, 100);
+        let fitted = budget.fit_trimmed("documents", &items(&[60, 100]));
+
+        assert_eq!(fitted.len(), 2);
+        assert_eq!(budget.count(&fitted[1]), 60);
+        assert_eq!(budget.remaining(), 0);
+        assert_eq!(budget.report().dropped[0].items, 1);
+
+        assert_eq!(TokenCounter::Estimate.truncate("héllo wörld", 2), "héllo ");
+    }
+}
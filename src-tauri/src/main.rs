@@ -65,6 +65,9 @@ fn main() {
             // Start background tasks
             services.spawn_activity_tracking();
             services.spawn_migration();
+            services.spawn_reembedding(app.handle().clone());
+            services.spawn_folder_watching(app.handle().clone());
+            services.spawn_web_source_refresh(app.handle().clone());
             
             // Show the main window
             println!("App setup complete");
@@ -147,6 +150,11 @@ fn main() {
             services::rag::search_documents,
             services::rag::get_goal_context,
             services::rag::list_indexed_documents,
+            services::rag::set_document_tags,
+            services::rag::set_document_collections,
+            services::rag::list_collections,
+            services::rag::create_collection,
+            services::rag::delete_collection,
             services::rag::remove_document,
             services::rag::update_document_index,
             services::rag::get_supported_file_types,
@@ -155,14 +163,27 @@ fn main() {
             services::rag::inspect_rag_database,
             services::rag::cleanup_corrupted_documents,
             services::rag::clear_vector_database,
+            services::rag::get_embedding_status,
+            services::rag::reembed_documents,
             // Streaming chat commands
             services::streaming_chat::stream_general_chat,
             services::streaming_chat::stream_document_chat,
+            // Job commands
+            services::jobs::cancel_job,
+            services::jobs::list_jobs,
             // File manager commands
             services::file_manager::scan_folder_for_documents,
             services::file_manager::get_file_info,
             services::file_manager::index_multiple_documents,
             services::file_manager::get_folder_stats,
+            services::folder_sync::list_watched_folders,
+            services::folder_sync::add_watched_folder,
+            services::folder_sync::remove_watched_folder,
+            services::web_sources::index_url,
+            services::web_sources::list_web_sources,
+            services::web_sources::refresh_web_source,
+            services::web_sources::remove_web_source,
+            services::web_sources::save_research_to_knowledge_base,
             // Inference commands
             services::inference::get_inference_config,
             services::inference::set_inference_provider,
@@ -22,6 +22,7 @@ pub enum TaskStatus {
     Scraping,
     Analyzing,
     Completed,
+    Cancelled,
     Failed(String),
 }
 
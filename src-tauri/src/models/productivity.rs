@@ -16,6 +16,9 @@ pub struct ProductivityInsights {
     pub key_insights: Vec<String>,
     pub suggested_improvements: Vec<String>,
     pub timestamp: DateTime<Utc>,
+    /// Activity context left out of the prompt to fit the model's context window
+    #[serde(default)]
+    pub omitted_context: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -0,0 +1,549 @@
+use crate::error::{AppError, Result};
+use byteorder::{LittleEndian, ReadBytesExt};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::io::{Cursor, Read};
+use uuid::Uuid;
+
+/// Links kept per node above the bottom layer
+const MAX_LINKS: usize = 16;
+
+/// Links kept per node on the bottom layer, which holds every node
+const MAX_LINKS_BOTTOM: usize = 2 * MAX_LINKS;
+
+/// Candidates considered when linking a new node
+const EF_CONSTRUCTION: usize = 100;
+
+/// Candidates considered when searching, raised to the number of results
+/// asked for
+const EF_SEARCH: usize = 64;
+
+/// Highest layer a node is placed on
+const MAX_LEVEL: usize = 16;
+
+/// Identifies the serialised index and its format version
+const MAGIC: &[u8; 8] = b"PAHNSW01";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Scored {
+    similarity: f32,
+    node: u32,
+}
+
+impl Eq for Scored {}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.similarity
+            .total_cmp(&other.similarity)
+            .then(self.node.cmp(&other.node))
+    }
+}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+struct Node {
+    id: Uuid,
+    /// Unit length, so the dot product is the cosine similarity
+    vector: Vec<f32>,
+    /// Neighbours on each layer the node is part of, bottom layer first
+    links: Vec<Vec<u32>>,
+    deleted: bool,
+}
+
+/// Hierarchical navigable small world graph for approximate nearest-neighbour
+/// search by cosine similarity.
+///
+/// Removed vectors are only marked as deleted so the graph stays connected;
+/// `compact_if_needed` rebuilds it once they make up a quarter of the nodes.
+#[derive(Default)]
+pub struct HnswIndex {
+    /// Length of the indexed vectors, set by the first insert
+    dimension: usize,
+    nodes: Vec<Node>,
+    /// Node of every vector that has not been removed
+    live: HashMap<Uuid, u32>,
+    entry_point: Option<u32>,
+}
+
+impl HnswIndex {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.live.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.live.is_empty()
+    }
+
+    /// Length of the indexed vectors, 0 while the index has never held one
+    pub fn dimension(&self) -> usize {
+        self.dimension
+    }
+
+    pub fn contains(&self, id: &Uuid) -> bool {
+        self.live.contains_key(id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = &Uuid> {
+        self.live.keys()
+    }
+
+    /// Add `vector` under `id`, replacing the vector already stored for it
+    pub fn insert(&mut self, id: Uuid, vector: &[f32]) -> Result<()> {
+        if self.dimension == 0 {
+            self.dimension = vector.len();
+        }
+        if vector.is_empty() || vector.len() != self.dimension {
+            return Err(AppError::VectorStore(format!(
+                "Cannot index a {}-dimensional vector alongside {}-dimensional ones",
+                vector.len(),
+                self.dimension
+            )));
+        }
+
+        self.remove(&id);
+        self.insert_unit(id, normalize(vector));
+        Ok(())
+    }
+
+    /// Mark the vector stored under `id` as deleted. Returns whether there was one.
+    pub fn remove(&mut self, id: &Uuid) -> bool {
+        let Some(node) = self.live.remove(id) else {
+            return false;
+        };
+        self.nodes[node as usize].deleted = true;
+        if self.live.is_empty() {
+            self.nodes.clear();
+            self.entry_point = None;
+        }
+        true
+    }
+
+    /// Rebuild the graph without deleted nodes once they make up a quarter of
+    /// it. Returns whether it was rebuilt.
+    pub fn compact_if_needed(&mut self) -> bool {
+        let deleted = self.nodes.len() - self.live.len();
+        if deleted * 4 <= self.nodes.len() {
+            return false;
+        }
+
+        let nodes = std::mem::take(&mut self.nodes);
+        self.live.clear();
+        self.entry_point = None;
+        for node in nodes.into_iter().filter(|node| !node.deleted) {
+            self.insert_unit(node.id, node.vector);
+        }
+        true
+    }
+
+    /// Up to `limit` ids accepted by `accept`, most similar to `query` first,
+    /// with their cosine similarity. Nodes that are not accepted are still
+    /// walked through, so a narrow filter costs time rather than results.
+    pub fn search(
+        &self,
+        query: &[f32],
+        limit: usize,
+        accept: impl Fn(&Uuid) -> bool,
+    ) -> Vec<(Uuid, f32)> {
+        let Some(entry_point) = self.entry_point else {
+            return Vec::new();
+        };
+        if limit == 0 || query.len() != self.dimension {
+            return Vec::new();
+        }
+
+        let query = normalize(query);
+        let mut entry = entry_point;
+        for layer in (1..self.nodes[entry_point as usize].links.len()).rev() {
+            entry = self.greedy_closest(&query, entry, layer);
+        }
+
+        self.search_layer(&query, &[entry], EF_SEARCH.max(limit), 0, |node| {
+            let node = &self.nodes[node as usize];
+            !node.deleted && accept(&node.id)
+        })
+        .into_iter()
+        .take(limit)
+        .map(|scored| (self.nodes[scored.node as usize].id, scored.similarity))
+        .collect()
+    }
+
+    fn insert_unit(&mut self, id: Uuid, vector: Vec<f32>) {
+        let level = level_for(&id);
+        let node = self.nodes.len() as u32;
+        self.nodes.push(Node {
+            id,
+            vector,
+            links: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.live.insert(id, node);
+
+        let Some(entry_point) = self.entry_point else {
+            self.entry_point = Some(node);
+            return;
+        };
+
+        let query = self.nodes[node as usize].vector.clone();
+        let top = self.nodes[entry_point as usize].links.len() - 1;
+        let mut entry = entry_point;
+        for layer in (level + 1..=top).rev() {
+            entry = self.greedy_closest(&query, entry, layer);
+        }
+
+        let mut entries = vec![entry];
+        for layer in (0..=level.min(top)).rev() {
+            let candidates = self.search_layer(&query, &entries, EF_CONSTRUCTION, layer, |other| {
+                other != node && !self.nodes[other as usize].deleted
+            });
+            let max_links = if layer == 0 { MAX_LINKS_BOTTOM } else { MAX_LINKS };
+
+            let neighbours: Vec<u32> = candidates.iter().take(max_links).map(|scored| scored.node).collect();
+            for &neighbour in &neighbours {
+                self.link(neighbour, node, layer, max_links);
+            }
+            self.nodes[node as usize].links[layer] = neighbours;
+
+            if !candidates.is_empty() {
+                entries = candidates.iter().map(|scored| scored.node).collect();
+            }
+        }
+
+        if level > top {
+            self.entry_point = Some(node);
+        }
+    }
+
+    /// Link `from` to `to`, dropping its least similar link when it has too
+    /// many. Deleted neighbours go first.
+    fn link(&mut self, from: u32, to: u32, layer: usize, max_links: usize) {
+        self.nodes[from as usize].links[layer].push(to);
+        if self.nodes[from as usize].links[layer].len() <= max_links {
+            return;
+        }
+
+        let base = &self.nodes[from as usize].vector;
+        let mut scored: Vec<Scored> = self.nodes[from as usize].links[layer]
+            .iter()
+            .map(|&node| {
+                let neighbour = &self.nodes[node as usize];
+                let similarity = if neighbour.deleted {
+                    f32::NEG_INFINITY
+                } else {
+                    dot(base, &neighbour.vector)
+                };
+                Scored { similarity, node }
+            })
+            .collect();
+        scored.sort_by(|a, b| b.cmp(a));
+        scored.truncate(max_links);
+        self.nodes[from as usize].links[layer] = scored.into_iter().map(|scored| scored.node).collect();
+    }
+
+    fn links(&self, node: u32, layer: usize) -> &[u32] {
+        self.nodes[node as usize].links.get(layer).map_or(&[], Vec::as_slice)
+    }
+
+    fn greedy_closest(&self, query: &[f32], entry: u32, layer: usize) -> u32 {
+        let mut best = entry;
+        let mut best_similarity = dot(query, &self.nodes[entry as usize].vector);
+        loop {
+            let mut improved = false;
+            for &neighbour in self.links(best, layer) {
+                let similarity = dot(query, &self.nodes[neighbour as usize].vector);
+                if similarity > best_similarity {
+                    best = neighbour;
+                    best_similarity = similarity;
+                    improved = true;
+                }
+            }
+            if !improved {
+                return best;
+            }
+        }
+    }
+
+    /// The `ef` accepted nodes on `layer` most similar to `query`, most
+    /// similar first
+    fn search_layer(
+        &self,
+        query: &[f32],
+        entries: &[u32],
+        ef: usize,
+        layer: usize,
+        accept: impl Fn(u32) -> bool,
+    ) -> Vec<Scored> {
+        let mut visited: HashSet<u32> = entries.iter().copied().collect();
+        let mut candidates = BinaryHeap::new();
+        let mut results: BinaryHeap<Reverse<Scored>> = BinaryHeap::new();
+
+        for &node in entries {
+            let scored = Scored {
+                similarity: dot(query, &self.nodes[node as usize].vector),
+                node,
+            };
+            candidates.push(scored);
+            if accept(node) {
+                results.push(Reverse(scored));
+            }
+        }
+        while results.len() > ef {
+            results.pop();
+        }
+
+        while let Some(candidate) = candidates.pop() {
+            let worst = results.peek().map(|Reverse(worst)| worst.similarity);
+            if results.len() >= ef && worst.is_some_and(|worst| candidate.similarity < worst) {
+                break;
+            }
+
+            for &neighbour in self.links(candidate.node, layer) {
+                if !visited.insert(neighbour) {
+                    continue;
+                }
+                let scored = Scored {
+                    similarity: dot(query, &self.nodes[neighbour as usize].vector),
+                    node: neighbour,
+                };
+                let worst = results.peek().map(|Reverse(worst)| worst.similarity);
+                if results.len() < ef || worst.is_some_and(|worst| scored.similarity > worst) {
+                    candidates.push(scored);
+                    if accept(neighbour) {
+                        results.push(Reverse(scored));
+                        if results.len() > ef {
+                            results.pop();
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut results: Vec<Scored> = results.into_iter().map(|Reverse(scored)| scored).collect();
+        results.sort_by(|a, b| b.cmp(a));
+        results
+    }
+
+    /// The index in its on-disk format
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(20 + self.nodes.len() * (self.dimension * 4 + 64));
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&(self.dimension as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.entry_point.unwrap_or(u32::MAX).to_le_bytes());
+        bytes.extend_from_slice(&(self.nodes.len() as u32).to_le_bytes());
+
+        for node in &self.nodes {
+            bytes.extend_from_slice(node.id.as_bytes());
+            bytes.push(node.deleted as u8);
+            bytes.push(node.links.len() as u8);
+            for value in &node.vector {
+                bytes.extend_from_slice(&value.to_le_bytes());
+            }
+            for links in &node.links {
+                bytes.extend_from_slice(&(links.len() as u32).to_le_bytes());
+                for link in links {
+                    bytes.extend_from_slice(&link.to_le_bytes());
+                }
+            }
+        }
+        bytes
+    }
+
+    /// Read an index written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let corrupt = |e: std::io::Error| AppError::VectorStore(format!("Vector index file is corrupt: {e}"));
+        let mut reader = Cursor::new(bytes);
+
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic).map_err(corrupt)?;
+        if &magic != MAGIC {
+            return Err(AppError::VectorStore("Not a vector index file".to_string()));
+        }
+        let dimension = reader.read_u32::<LittleEndian>().map_err(corrupt)? as usize;
+        let entry_point = reader.read_u32::<LittleEndian>().map_err(corrupt)?;
+        let count = reader.read_u32::<LittleEndian>().map_err(corrupt)?;
+
+        let mut index = Self {
+            dimension,
+            entry_point: (entry_point != u32::MAX).then_some(entry_point),
+            ..Self::default()
+        };
+        for node in 0..count {
+            let mut id = [0u8; 16];
+            reader.read_exact(&mut id).map_err(corrupt)?;
+            let deleted = reader.read_u8().map_err(corrupt)? != 0;
+            let layers = reader.read_u8().map_err(corrupt)? as usize;
+
+            let mut vector = vec![0f32; dimension];
+            reader.read_f32_into::<LittleEndian>(&mut vector).map_err(corrupt)?;
+            let mut links = Vec::with_capacity(layers);
+            for _ in 0..layers {
+                let len = reader.read_u32::<LittleEndian>().map_err(corrupt)? as usize;
+                let mut layer = vec![0u32; len.min(MAX_LINKS_BOTTOM)];
+                if len != layer.len() {
+                    return Err(AppError::VectorStore("Vector index file is corrupt".to_string()));
+                }
+                reader.read_u32_into::<LittleEndian>(&mut layer).map_err(corrupt)?;
+                links.push(layer);
+            }
+
+            let id = Uuid::from_bytes(id);
+            if !deleted {
+                index.live.insert(id, node);
+            }
+            index.nodes.push(Node { id, vector, links, deleted });
+        }
+
+        let in_range = |node: u32| node < count;
+        let links_in_range = index.nodes.iter().all(|node| node.links.iter().flatten().all(|&link| in_range(link)));
+        if !links_in_range || !index.entry_point.is_none_or(in_range) {
+            return Err(AppError::VectorStore("Vector index file is corrupt".to_string()));
+        }
+        Ok(index)
+    }
+}
+
+/// Layer a node is placed on, drawn from its id so a rebuilt index has the
+/// same shape
+fn level_for(id: &Uuid) -> usize {
+    let bits = id.as_u128();
+    // splitmix64 finaliser, spreading sequential ids over the whole range
+    let mut x = (bits as u64) ^ ((bits >> 64) as u64);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+
+    let uniform = ((x >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+    let level = -uniform.ln() / (MAX_LINKS as f64).ln();
+    (level as usize).min(MAX_LEVEL)
+}
+
+fn normalize(vector: &[f32]) -> Vec<f32> {
+    let norm = dot(vector, vector).sqrt();
+    if norm == 0.0 {
+        return vector.to_vec();
+    }
+    vector.iter().map(|value| value / norm).collect()
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reproducible pseudo-random vectors
+    fn vectors(count: usize, dimension: usize, seed: u64) -> Vec<Vec<f32>> {
+        let mut state = seed;
+        (0..count)
+            .map(|_| {
+                (0..dimension)
+                    .map(|_| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                        ((state >> 33) as f32 / (1u64 << 31) as f32) - 0.5
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn brute_force(data: &[Vec<f32>], query: &[f32], limit: usize, accept: impl Fn(usize) -> bool) -> Vec<Uuid> {
+        let query = normalize(query);
+        let mut scored: Vec<(usize, f32)> = data
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| accept(*i))
+            .map(|(i, vector)| (i, dot(&query, &normalize(vector))))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored.into_iter().take(limit).map(|(i, _)| Uuid::from_u128(i as u128)).collect()
+    }
+
+    fn build(data: &[Vec<f32>]) -> HnswIndex {
+        let mut index = HnswIndex::new();
+        for (i, vector) in data.iter().enumerate() {
+            index.insert(Uuid::from_u128(i as u128), vector).unwrap();
+        }
+        index
+    }
+
+    fn recall(index: &HnswIndex, data: &[Vec<f32>], queries: &[Vec<f32>], accept: impl Fn(usize) -> bool) -> f32 {
+        let mut found = 0;
+        let mut expected = 0;
+        for query in queries {
+            let exact = brute_force(data, query, 10, &accept);
+            let approximate: Vec<Uuid> = index
+                .search(query, 10, |id| accept(id.as_u128() as usize))
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect();
+            found += exact.iter().filter(|id| approximate.contains(id)).count();
+            expected += exact.len();
+        }
+        found as f32 / expected as f32
+    }
+
+    #[test]
+    fn test_search_matches_brute_force() {
+        let data = vectors(1000, 24, 1);
+        let queries = vectors(30, 24, 2);
+        let index = build(&data);
+
+        assert!(recall(&index, &data, &queries, |_| true) > 0.95);
+        // A filter that passes one vector in ten still fills the results
+        assert!(recall(&index, &data, &queries, |i| i % 10 == 3) > 0.95);
+        assert_eq!(index.search(&queries[0], 10, |id| id.as_u128() % 10 == 3).len(), 10);
+    }
+
+    #[test]
+    fn test_removed_vectors_are_not_returned_and_compaction_keeps_the_rest() {
+        let data = vectors(400, 16, 3);
+        let mut index = build(&data);
+
+        for i in 0..150 {
+            assert!(index.remove(&Uuid::from_u128(i)));
+        }
+        assert!(!index.remove(&Uuid::from_u128(0)));
+        assert_eq!(index.len(), 250);
+
+        let removed = |i: usize| i < 150;
+        let results = index.search(&data[10], 20, |_| true);
+        assert!(results.iter().all(|(id, _)| !removed(id.as_u128() as usize)));
+
+        assert!(index.compact_if_needed());
+        assert!(!index.compact_if_needed());
+        assert_eq!(index.nodes.len(), 250);
+        let queries = vectors(20, 16, 4);
+        assert!(recall(&index, &data, &queries, |i| !removed(i)) > 0.95);
+
+        // Inserting an id again replaces its vector
+        index.insert(Uuid::from_u128(200), &data[0]).unwrap();
+        assert_eq!(index.search(&data[0], 1, |_| true)[0].0, Uuid::from_u128(200));
+        assert!(index.insert(Uuid::from_u128(1), &[1.0, 2.0]).is_err());
+    }
+
+    #[test]
+    fn test_bytes_round_trip() {
+        let data = vectors(200, 8, 5);
+        let mut index = build(&data);
+        index.remove(&Uuid::from_u128(7));
+
+        let restored = HnswIndex::from_bytes(&index.to_bytes()).unwrap();
+        assert_eq!(restored.len(), 199);
+        assert_eq!(restored.dimension(), 8);
+        assert_eq!(restored.search(&data[42], 5, |_| true), index.search(&data[42], 5, |_| true));
+
+        let bytes = index.to_bytes();
+        assert!(HnswIndex::from_bytes(&bytes[..bytes.len() / 2]).is_err());
+        assert!(HnswIndex::from_bytes(b"not an index").is_err());
+    }
+}
@@ -0,0 +1,61 @@
+use crate::config::EmbeddingProvider;
+use crate::error::{AppError, Result};
+use crate::llm::bert_metal_backend::BertMetalBackend;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use super::embeddings::EmbeddingBackend;
+
+/// Encoder shared by every `EmbeddingModel`, so creating another RAG system
+/// does not load the weights again
+static ENCODER: Mutex<Option<Arc<BertMetalBackend>>> = Mutex::const_new(None);
+
+/// Embeddings computed in-process with Candle, so indexing and search work
+/// without an embedding server
+pub struct CandleEmbeddings {
+    encoder: Arc<BertMetalBackend>,
+}
+
+impl CandleEmbeddings {
+    /// Load `model_id` from the Hugging Face Hub, or reuse it if it is loaded
+    pub async fn load(model_id: &str) -> Result<Self> {
+        let mut encoder = ENCODER.lock().await;
+        if let Some(loaded) = encoder.as_ref().filter(|loaded| loaded.model_id() == model_id) {
+            return Ok(Self { encoder: loaded.clone() });
+        }
+
+        let loaded = Arc::new(BertMetalBackend::new(model_id).await.map_err(|e| {
+            AppError::Embedding(format!("Failed to load embedding model {model_id}: {e}"))
+        })?);
+        *encoder = Some(loaded.clone());
+        Ok(Self { encoder: loaded })
+    }
+}
+
+#[async_trait]
+impl EmbeddingBackend for CandleEmbeddings {
+    fn provider(&self) -> EmbeddingProvider {
+        EmbeddingProvider::Candle
+    }
+
+    fn model_id(&self) -> &str {
+        self.encoder.model_id()
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let encoder = self.encoder.clone();
+        let text = text.to_string();
+        tokio::task::spawn_blocking(move || encoder.embed(&text))
+            .await
+            .map_err(|e| AppError::Embedding(format!("Embedding task failed: {e}")))?
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let encoder = self.encoder.clone();
+        let texts = texts.to_vec();
+        tokio::task::spawn_blocking(move || encoder.embed_batch(&texts))
+            .await
+            .map_err(|e| AppError::Embedding(format!("Embedding task failed: {e}")))?
+    }
+}
@@ -1,5 +1,50 @@
 use crate::config::Config;
 use crate::error::Result;
+use std::collections::HashMap;
+
+use super::enhanced_document_processor::{DocumentSection, ProcessedDocument};
+
+/// How a document is divided into chunks, chosen from its file type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkingStrategy {
+    /// Fixed-size windows of characters with overlap
+    Size,
+    /// Sections under each heading, labelled with their heading path
+    Markdown,
+    /// Top-level items such as functions and impls, split at blank lines
+    /// before unindented code
+    Code,
+    /// Groups of rows, each repeating the header row
+    Csv,
+    /// Text of each page on its own, labelled with the page number
+    Pages,
+    /// Each chapter, slide, sheet or heading section on its own, labelled
+    /// with the section's metadata. Tables repeat their header rows.
+    Sections,
+}
+
+/// A piece of a document and where it came from
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextChunk {
+    pub content: String,
+    pub metadata: HashMap<String, String>,
+}
+
+impl TextChunk {
+    fn new(content: String) -> Self {
+        Self {
+            content,
+            metadata: HashMap::new(),
+        }
+    }
+
+    /// Label with the 1-based lines `start..end` of the document
+    fn with_lines(mut self, start: usize, end: usize) -> Self {
+        self.metadata.insert("line_start".to_string(), (start + 1).to_string());
+        self.metadata.insert("line_end".to_string(), end.to_string());
+        self
+    }
+}
 
 pub struct TextChunker {
     chunk_size: usize,
@@ -37,6 +82,353 @@ impl TextChunker {
         }
     }
 
+    /// Chunk a processed document with the strategy for its type, falling
+    /// back to fixed-size chunks when the structure yields nothing
+    pub fn chunk_document(&self, document: &ProcessedDocument) -> Result<Vec<TextChunk>> {
+        let chunks = match document.chunking {
+            ChunkingStrategy::Size => Vec::new(),
+            ChunkingStrategy::Markdown => self.chunk_markdown(&document.content),
+            ChunkingStrategy::Code => self.chunk_code(&document.content),
+            ChunkingStrategy::Csv => self.chunk_csv(&document.content),
+            ChunkingStrategy::Pages => self.chunk_pages(&document.pages),
+            ChunkingStrategy::Sections => self.chunk_sections(&document.sections),
+        };
+
+        let mut chunks = if chunks.is_empty() {
+            self.chunk_text(&document.content)?
+                .into_iter()
+                .map(TextChunk::new)
+                .collect()
+        } else {
+            chunks
+        };
+
+        // The kind of content `type:` search filters match on
+        let content_type = match document.chunking {
+            ChunkingStrategy::Code => "code",
+            ChunkingStrategy::Markdown => "markdown",
+            _ => "text",
+        };
+        for chunk in &mut chunks {
+            chunk
+                .metadata
+                .insert("content_type".to_string(), content_type.to_string());
+        }
+        Ok(chunks)
+    }
+
+    fn chunk_markdown(&self, text: &str) -> Vec<TextChunk> {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut chunks = Vec::new();
+        let mut headings: Vec<(usize, &str)> = Vec::new();
+        let mut section_start = 0;
+        let mut in_fence = false;
+
+        for (i, line) in lines.iter().enumerate() {
+            let trimmed = line.trim_start();
+            if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+                in_fence = !in_fence;
+                continue;
+            }
+            if in_fence {
+                continue;
+            }
+            if let Some((level, title)) = markdown_heading(line) {
+                self.push_section(&lines, section_start, i, &headings, &mut chunks);
+                headings.retain(|(outer, _)| *outer < level);
+                headings.push((level, title));
+                section_start = i;
+            }
+        }
+        self.push_section(&lines, section_start, lines.len(), &headings, &mut chunks);
+
+        chunks
+    }
+
+    /// Chunk the section in `lines[start..end]`, unless it is only a heading
+    fn push_section(
+        &self,
+        lines: &[&str],
+        start: usize,
+        end: usize,
+        headings: &[(usize, &str)],
+        chunks: &mut Vec<TextChunk>,
+    ) {
+        let has_body = lines[start..end]
+            .iter()
+            .any(|line| !line.trim().is_empty() && markdown_heading(line).is_none());
+        if !has_body {
+            return;
+        }
+
+        let heading_path = headings
+            .iter()
+            .map(|(_, title)| *title)
+            .collect::<Vec<_>>()
+            .join(" > ");
+        for mut chunk in self.pack_lines(lines, start, end) {
+            if !heading_path.is_empty() {
+                chunk
+                    .metadata
+                    .insert("heading_path".to_string(), heading_path.clone());
+            }
+            chunks.push(chunk);
+        }
+    }
+
+    fn chunk_code(&self, text: &str) -> Vec<TextChunk> {
+        let lines: Vec<&str> = text.lines().collect();
+        let mut items = Vec::new();
+        self.split_code_items(&lines, 0, lines.len(), 0, &mut items);
+
+        // Keep small neighbouring items such as imports together
+        let mut chunks = Vec::new();
+        let mut group: Option<(usize, usize)> = None;
+        for (start, end) in items {
+            if let Some((group_start, _)) = group {
+                if text_len(&lines[group_start..end]) <= self.chunk_size {
+                    group = Some((group_start, end));
+                    continue;
+                }
+            }
+            if let Some((group_start, group_end)) = group.take() {
+                chunks.extend(self.pack_lines(&lines, group_start, group_end));
+            }
+            if text_len(&lines[start..end]) > self.chunk_size {
+                chunks.extend(self.pack_lines(&lines, start, end));
+            } else {
+                group = Some((start, end));
+            }
+        }
+        if let Some((group_start, group_end)) = group {
+            chunks.extend(self.pack_lines(&lines, group_start, group_end));
+        }
+
+        chunks
+    }
+
+    /// Split `lines[start..end]` where a line indented by `indent` follows a
+    /// blank line. Items too large for a chunk are split again one
+    /// indentation level deeper, so an impl block falls apart into methods.
+    fn split_code_items(
+        &self,
+        lines: &[&str],
+        start: usize,
+        end: usize,
+        indent: usize,
+        items: &mut Vec<(usize, usize)>,
+    ) {
+        let mut item_start = start;
+        let mut boundaries = Vec::new();
+        for i in start + 1..end {
+            if !lines[i].trim().is_empty()
+                && indentation(lines[i]) == indent
+                && lines[i - 1].trim().is_empty()
+            {
+                boundaries.push((item_start, i));
+                item_start = i;
+            }
+        }
+        boundaries.push((item_start, end));
+
+        for (item_start, item_end) in boundaries {
+            let deeper = lines[item_start..item_end]
+                .iter()
+                .filter(|line| !line.trim().is_empty())
+                .map(|line| indentation(line))
+                .filter(|&level| level > indent)
+                .min();
+            match deeper {
+                Some(deeper) if text_len(&lines[item_start..item_end]) > self.chunk_size => {
+                    self.split_code_items(lines, item_start, item_end, deeper, items)
+                }
+                _ => items.push((item_start, item_end)),
+            }
+        }
+    }
+
+    fn chunk_csv(&self, text: &str) -> Vec<TextChunk> {
+        let lines: Vec<&str> = text.lines().collect();
+
+        // Group lines into records, keeping quoted line breaks in their record
+        let mut records = Vec::new();
+        let mut record_start = None;
+        let mut quotes = 0;
+        for (i, line) in lines.iter().enumerate() {
+            if record_start.is_none() && line.trim().is_empty() {
+                continue;
+            }
+            let start = *record_start.get_or_insert(i);
+            quotes += line.matches('"').count();
+            if quotes.is_multiple_of(2) {
+                records.push((start, i + 1));
+                record_start = None;
+                quotes = 0;
+            }
+        }
+        if let Some(start) = record_start {
+            records.push((start, lines.len()));
+        }
+
+        let Some((&(header_start, header_end), rows)) = records.split_first() else {
+            return Vec::new();
+        };
+        let header = lines[header_start..header_end].join("\n");
+        if rows.is_empty() {
+            return vec![TextChunk::new(header).with_lines(header_start, header_end)];
+        }
+
+        let mut chunks = Vec::new();
+        let mut content = header.clone();
+        let mut first_row = 0;
+        for (row, &(start, end)) in rows.iter().enumerate() {
+            let record = lines[start..end].join("\n");
+            if row > first_row && content.len() + record.len() + 1 > self.chunk_size {
+                chunks.push(csv_chunk(content, rows, first_row, row));
+                content = header.clone();
+                first_row = row;
+            }
+            content.push('\n');
+            content.push_str(&record);
+        }
+        chunks.push(csv_chunk(content, rows, first_row, rows.len()));
+
+        chunks
+    }
+
+    fn chunk_pages(&self, pages: &[String]) -> Vec<TextChunk> {
+        let mut chunks = Vec::new();
+        for (index, page) in pages.iter().enumerate() {
+            if page.trim().is_empty() {
+                continue;
+            }
+            for content in self.chunk_page(page) {
+                let mut chunk = TextChunk::new(content);
+                chunk
+                    .metadata
+                    .insert("page".to_string(), (index + 1).to_string());
+                chunks.push(chunk);
+            }
+        }
+        chunks
+    }
+
+    /// Chunks of a page's text, with each markdown table on it split into
+    /// rows under its header
+    fn chunk_page(&self, page: &str) -> Vec<String> {
+        let lines: Vec<&str> = page.lines().collect();
+        let is_row = |line: &str| line.trim_start().starts_with('|');
+        let is_separator = |line: &str| {
+            is_row(line) && line.trim().chars().all(|ch| matches!(ch, '|' | '-' | ':' | ' '))
+        };
+
+        let mut chunks = Vec::new();
+        let mut text: Vec<&str> = Vec::new();
+        let mut i = 0;
+        while i < lines.len() {
+            if !(is_row(lines[i]) && lines.get(i + 1).is_some_and(|line| is_separator(line))) {
+                text.push(lines[i]);
+                i += 1;
+                continue;
+            }
+            let text_before = std::mem::take(&mut text).join("\n");
+            if !text_before.trim().is_empty() {
+                chunks.extend(self.chunk_text_by_size(text_before.trim()));
+            }
+            let end = (i..lines.len())
+                .find(|row| !is_row(lines[*row]))
+                .unwrap_or(lines.len());
+            chunks.extend(self.chunk_table(&lines[i..end].join("\n"), 2));
+            i = end;
+        }
+        let text = text.join("\n");
+        if !text.trim().is_empty() {
+            chunks.extend(self.chunk_text_by_size(text.trim()));
+        }
+        chunks
+    }
+
+    fn chunk_sections(&self, sections: &[DocumentSection]) -> Vec<TextChunk> {
+        let mut chunks = Vec::new();
+        for section in sections {
+            let contents = if section.header_lines > 0 {
+                self.chunk_table(&section.content, section.header_lines)
+            } else {
+                self.chunk_text_by_size(&section.content)
+            };
+            for content in contents {
+                chunks.push(TextChunk {
+                    content,
+                    metadata: section.metadata.clone(),
+                });
+            }
+        }
+        chunks
+    }
+
+    /// Groups of whole rows of a table, one per line, each under the first
+    /// `header_lines` lines. Rows longer than a chunk are split by size.
+    fn chunk_table(&self, table: &str, header_lines: usize) -> Vec<String> {
+        let lines: Vec<&str> = table.lines().collect();
+        let header = lines[..header_lines.min(lines.len())].join("\n");
+
+        let mut chunks = Vec::new();
+        let mut current = header.clone();
+        for row in lines.iter().skip(header_lines) {
+            if current.len() > header.len() && current.len() + row.len() + 1 > self.chunk_size {
+                chunks.push(std::mem::replace(&mut current, header.clone()));
+            }
+            if header.len() + row.len() + 1 > self.chunk_size {
+                chunks.extend(self.chunk_text_by_size(row));
+                continue;
+            }
+            current.push('\n');
+            current.push_str(row);
+        }
+        if current.len() > header.len() || chunks.is_empty() {
+            chunks.push(current);
+        }
+        chunks
+    }
+
+    /// Chunks of whole lines from `lines[start..end]` up to the chunk size.
+    /// Lines longer than a chunk are split by size.
+    fn pack_lines(&self, lines: &[&str], start: usize, end: usize) -> Vec<TextChunk> {
+        let mut chunks = Vec::new();
+        let mut current = String::new();
+        let mut current_len = 0;
+        let mut current_start = start;
+
+        for (i, line) in lines.iter().enumerate().take(end).skip(start) {
+            let line_len = line.chars().count();
+            if current.is_empty() && line.trim().is_empty() {
+                current_start = i + 1;
+                continue;
+            }
+            if !current.is_empty() && current_len + line_len + 1 > self.chunk_size {
+                chunks.extend(lines_chunk(&std::mem::take(&mut current), current_start));
+                current_len = 0;
+                current_start = i;
+            }
+            if line_len > self.chunk_size {
+                for piece in self.chunk_text_by_size(line) {
+                    chunks.push(TextChunk::new(piece).with_lines(i, i + 1));
+                }
+                current_start = i + 1;
+                continue;
+            }
+            if !current.is_empty() {
+                current.push('\n');
+                current_len += 1;
+            }
+            current.push_str(line);
+            current_len += line_len;
+        }
+
+        chunks.extend(lines_chunk(&current, current_start));
+        chunks
+    }
+
     fn chunk_text_by_size(&self, text: &str) -> Vec<String> {
         let mut chunks = Vec::new();
         let chars: Vec<char> = text.chars().collect();
@@ -72,7 +464,7 @@ impl TextChunker {
         let sentences = self.split_into_sentences(text);
         let mut chunks = Vec::new();
         let mut current_chunk = String::new();
-        let target_chunk_size = 1000;
+        let target_chunk_size = self.chunk_size;
 
         for sentence in sentences {
             if current_chunk.len() + sentence.len() > target_chunk_size && !current_chunk.is_empty()
@@ -102,7 +494,7 @@ impl TextChunker {
         let paragraphs: Vec<&str> = text.split("\n\n").collect();
         let mut chunks = Vec::new();
         let mut current_chunk = String::new();
-        let target_chunk_size = 1000;
+        let target_chunk_size = self.chunk_size;
 
         for paragraph in paragraphs {
             let paragraph = paragraph.trim();
@@ -190,3 +582,173 @@ impl TextChunker {
         metadata
     }
 }
+
+/// Level and title of an ATX heading line such as `## Setup`
+fn markdown_heading(line: &str) -> Option<(usize, &str)> {
+    let trimmed = line.trim_start();
+    if line.len() - trimmed.len() > 3 {
+        return None;
+    }
+    let level = trimmed.chars().take_while(|&c| c == '#').count();
+    let rest = &trimmed[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+/// Chunk of consecutive lines starting at line `start`, without trailing
+/// blank lines
+fn lines_chunk(content: &str, start: usize) -> Option<TextChunk> {
+    let content = content.trim_end();
+    if content.is_empty() {
+        return None;
+    }
+    let end = start + content.lines().count();
+    Some(TextChunk::new(content.to_string()).with_lines(start, end))
+}
+
+fn indentation(line: &str) -> usize {
+    line.len() - line.trim_start_matches([' ', '\t']).len()
+}
+
+/// Characters in `lines` joined by newlines
+fn text_len(lines: &[&str]) -> usize {
+    lines.iter().map(|line| line.chars().count() + 1).sum::<usize>().saturating_sub(1)
+}
+
+/// Chunk of CSV `rows[first..last]` labelled with their row and line numbers
+fn csv_chunk(content: String, rows: &[(usize, usize)], first: usize, last: usize) -> TextChunk {
+    let mut chunk = TextChunk::new(content).with_lines(rows[first].0, rows[last - 1].1);
+    chunk.metadata.insert("row_start".to_string(), (first + 1).to_string());
+    chunk.metadata.insert("row_end".to_string(), last.to_string());
+    chunk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(content: &str, chunking: ChunkingStrategy) -> ProcessedDocument {
+        ProcessedDocument {
+            title: "test".to_string(),
+            content: content.to_string(),
+            file_type: "text/plain".to_string(),
+            metadata: HashMap::new(),
+            language: None,
+            word_count: 0,
+            char_count: content.len(),
+            processing_time_ms: 0,
+            chunking,
+            pages: Vec::new(),
+            sections: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_markdown_chunks_carry_heading_path() {
+        let chunker = TextChunker::new_with_config(200, 0);
+        let markdown = "Intro text\n\n# Guide\n## Install\nRun the installer.\n```\n# not a heading\n```\n## Usage\nOpen the app.";
+        let chunks = chunker
+            .chunk_document(&document(markdown, ChunkingStrategy::Markdown))
+            .unwrap();
+
+        let paths: Vec<Option<&str>> = chunks
+            .iter()
+            .map(|chunk| chunk.metadata.get("heading_path").map(String::as_str))
+            .collect();
+        assert_eq!(paths, vec![None, Some("Guide > Install"), Some("Guide > Usage")]);
+        assert!(chunks[1].content.contains("# not a heading"));
+        assert_eq!(chunks[2].metadata["line_start"], "9");
+        assert_eq!(chunks[2].metadata["line_end"], "10");
+        assert_eq!(chunks[2].metadata["content_type"], "markdown");
+    }
+
+    #[test]
+    fn test_code_splits_at_item_boundaries() {
+        let chunker = TextChunker::new_with_config(60, 0);
+        let code = "use std::fmt;\n\nfn first() {\n    println!(\"first\");\n}\n\nimpl Thing {\n    fn a(&self) -> u32 {\n        1\n    }\n\n    fn b(&self) -> u32 {\n        2\n    }\n}";
+        let chunks = chunker
+            .chunk_document(&document(code, ChunkingStrategy::Code))
+            .unwrap();
+        let ranges: Vec<(&str, &str)> = chunks
+            .iter()
+            .map(|chunk| (chunk.metadata["line_start"].as_str(), chunk.metadata["line_end"].as_str()))
+            .collect();
+
+        assert_eq!(ranges, vec![("1", "5"), ("7", "10"), ("12", "15")]);
+        assert!(chunks[1].content.starts_with("impl Thing {"));
+        assert!(chunks.iter().all(|chunk| chunk.content.chars().count() <= 60));
+    }
+
+    #[test]
+    fn test_csv_chunks_repeat_the_header() {
+        let chunker = TextChunker::new_with_config(30, 0);
+        let csv = "name,amount\nalpha,1\nbeta,2\n\"gamma\nmultiline\",3\ndelta,4";
+        let chunks = chunker
+            .chunk_document(&document(csv, ChunkingStrategy::Csv))
+            .unwrap();
+
+        assert!(chunks.len() > 1);
+        assert!(chunks.iter().all(|chunk| chunk.content.starts_with("name,amount\n")));
+        assert!(chunks.iter().any(|chunk| chunk.content.contains("\"gamma\nmultiline\",3")));
+        assert_eq!(chunks[0].metadata["row_start"], "1");
+        assert_eq!(chunks.last().unwrap().metadata["row_end"], "4");
+    }
+
+    #[test]
+    fn test_pages_are_numbered() {
+        let chunker = TextChunker::new_with_config(100, 10);
+        let mut pdf = document("first page\nthird page", ChunkingStrategy::Pages);
+        pdf.pages = vec!["first page".to_string(), String::new(), "third page".to_string()];
+        let chunks = chunker.chunk_document(&pdf).unwrap();
+
+        let pages: Vec<&str> = chunks.iter().map(|chunk| chunk.metadata["page"].as_str()).collect();
+        assert_eq!(pages, vec!["1", "3"]);
+    }
+
+    #[test]
+    fn test_page_tables_repeat_their_header() {
+        let chunker = TextChunker::new_with_config(50, 0);
+        let mut pdf = document("", ChunkingStrategy::Pages);
+        pdf.pages = vec!["Tide heights:\n\n| Day | High |\n| --- | --- |\n| Mon | 4.2 |\n| Tue | 4.4 |\n\nAbove datum.".to_string()];
+        let chunks = chunker.chunk_document(&pdf).unwrap();
+
+        let contents: Vec<&str> = chunks.iter().map(|chunk| chunk.content.as_str()).collect();
+        assert_eq!(
+            contents,
+            vec![
+                "Tide heights:",
+                "| Day | High |\n| --- | --- |\n| Mon | 4.2 |",
+                "| Day | High |\n| --- | --- |\n| Tue | 4.4 |",
+                "Above datum."
+            ]
+        );
+        assert!(chunks.iter().all(|chunk| chunk.metadata["page"] == "1"));
+    }
+
+    #[test]
+    fn test_sections_carry_their_labels_and_table_headers() {
+        let chunker = TextChunker::new_with_config(40, 0);
+        let mut deck = document("", ChunkingStrategy::Sections);
+        let labelled = |content: &str, header_lines: usize, key: &str, value: &str| DocumentSection {
+            content: content.to_string(),
+            metadata: HashMap::from([(key.to_string(), value.to_string())]),
+            header_lines,
+        };
+        deck.sections = vec![
+            labelled("Roadmap\nShip the beta", 0, "slide", "1"),
+            labelled("| a | b |\n| --- | --- |\n| 1 | 2 |\n| 3 | 4 |\n| 5 | 6 |", 2, "sheet", "Data"),
+        ];
+        let chunks = chunker.chunk_document(&deck).unwrap();
+
+        assert_eq!(chunks[0].content, "Roadmap\nShip the beta");
+        assert_eq!(chunks[0].metadata["slide"], "1");
+        let tables: Vec<&str> = chunks[1..].iter().map(|chunk| chunk.content.as_str()).collect();
+        assert_eq!(
+            tables,
+            vec!["| a | b |\n| --- | --- |\n| 1 | 2 |", "| a | b |\n| --- | --- |\n| 3 | 4 |", "| a | b |\n| --- | --- |\n| 5 | 6 |"]
+        );
+        assert!(chunks[1..].iter().all(|chunk| chunk.metadata["sheet"] == "Data"));
+    }
+}
@@ -0,0 +1,171 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::SearchResult;
+
+/// Instruction added to prompts whose context lists numbered sources
+pub const CITATION_INSTRUCTIONS: &str = "Cite the sources you use by their numbers in square brackets, \
+    such as [1] or [2][3], right after the statement they support. Only cite sources listed in the context.";
+
+/// Where a chunk sits in its original file, recorded when it was indexed
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    pub file_path: Option<String>,
+    /// 1-based page, for paged formats such as PDF
+    pub page: Option<u32>,
+    /// Chapter, slide, sheet or heading, for formats chunked by section
+    pub section: Option<String>,
+    /// 1-based first and last line, for text and source code
+    pub line_start: Option<usize>,
+    pub line_end: Option<usize>,
+}
+
+impl SourceLocation {
+    pub fn from_metadata(metadata: &HashMap<String, String>) -> Self {
+        let number = |key: &str| metadata.get(key).and_then(|value| value.parse().ok());
+        Self {
+            file_path: metadata.get("file_path").cloned(),
+            page: number("page").map(|page: usize| page as u32),
+            section: metadata.get("section").cloned(),
+            line_start: number("line_start"),
+            line_end: number("line_end"),
+        }
+    }
+
+    /// Short description such as `report.pdf, page 3`, `deck.pptx, slide 2`
+    /// or `main.rs, lines 10-24`
+    pub fn label(&self) -> String {
+        let mut label = self
+            .file_path
+            .as_deref()
+            .map(|path| {
+                // Web pages are cited by their address
+                if path.contains("://") {
+                    return path.to_string();
+                }
+                Path::new(path)
+                    .file_name()
+                    .map(|name| name.to_string_lossy().to_string())
+                    .unwrap_or_else(|| path.to_string())
+            })
+            .unwrap_or_else(|| "Document".to_string());
+
+        if let Some(page) = self.page {
+            label.push_str(&format!(", page {page}"));
+        }
+        if let Some(section) = &self.section {
+            label.push_str(&format!(", {section}"));
+        }
+        match (self.line_start, self.line_end) {
+            (Some(start), Some(end)) if end > start => label.push_str(&format!(", lines {start}-{end}")),
+            (Some(start), _) => label.push_str(&format!(", line {start}")),
+            _ => {}
+        }
+        label
+    }
+}
+
+/// A source the answer refers to with an inline `[n]` marker
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Citation {
+    /// Number of the source in the prompt and in the answer's markers
+    pub number: usize,
+    pub document_id: String,
+    pub chunk_id: String,
+    #[serde(flatten)]
+    pub location: SourceLocation,
+}
+
+/// Context entry for source `number`, headed with its location so the model
+/// can cite it
+pub fn numbered_source(number: usize, result: &SearchResult) -> String {
+    format!(
+        "[{number}] {label}\n{content}\n\n",
+        label = SourceLocation::from_metadata(&result.metadata).label(),
+        content = result.content
+    )
+}
+
+/// Citations for the `[n]` and `[n, m]` markers in `answer`, in order of first
+/// use. `sources` are numbered from 1 as in the prompt; markers without a
+/// source are ignored.
+pub fn extract_citations<'a>(
+    answer: &str,
+    sources: impl IntoIterator<Item = &'a SearchResult>,
+) -> Vec<Citation> {
+    let sources: Vec<&SearchResult> = sources.into_iter().collect();
+    let marker = Regex::new(r"\[(\d+(?:\s*[,;]\s*\d+)*)\]").unwrap();
+
+    let mut citations: Vec<Citation> = Vec::new();
+    for captures in marker.captures_iter(answer) {
+        for number in captures[1].split([',', ';']) {
+            let Ok(number) = number.trim().parse::<usize>() else {
+                continue;
+            };
+            let Some(source) = number.checked_sub(1).and_then(|index| sources.get(index)) else {
+                continue;
+            };
+            if citations.iter().any(|citation| citation.number == number) {
+                continue;
+            }
+            citations.push(Citation {
+                number,
+                document_id: source.document_id.to_string(),
+                chunk_id: source.chunk_id.to_string(),
+                location: SourceLocation::from_metadata(&source.metadata),
+            });
+        }
+    }
+    citations
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    fn source(metadata: &[(&str, &str)]) -> SearchResult {
+        SearchResult {
+            document_id: Uuid::new_v4(),
+            chunk_id: Uuid::new_v4(),
+            content: "text".to_string(),
+            score: 1.0,
+            metadata: metadata
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_extract_citations_maps_markers_to_sources() {
+        let sources = vec![
+            source(&[("file_path", "/docs/report.pdf"), ("page", "3")]),
+            source(&[("file_path", "/src/main.rs"), ("line_start", "10"), ("line_end", "24")]),
+        ];
+        let answer = "Revenue grew [2]. It was driven by exports [1, 2][7] and [2].";
+        let citations = extract_citations(answer, &sources);
+
+        let numbers: Vec<usize> = citations.iter().map(|citation| citation.number).collect();
+        assert_eq!(numbers, vec![2, 1]);
+        assert_eq!(citations[0].chunk_id, sources[1].chunk_id.to_string());
+        assert_eq!(citations[0].location.label(), "main.rs, lines 10-24");
+        assert_eq!(citations[1].location.page, Some(3));
+        assert_eq!(citations[1].location.label(), "report.pdf, page 3");
+    }
+
+    #[test]
+    fn test_numbered_source_heads_content_with_location() {
+        let entry = numbered_source(1, &source(&[("file_path", "notes.md"), ("line_start", "4")]));
+        assert_eq!(entry, "[1] notes.md, line 4\ntext\n\n");
+        assert_eq!(SourceLocation::default().label(), "Document");
+
+        let slide = SourceLocation::from_metadata(&source(&[("file_path", "deck.pptx"), ("section", "slide 2")]).metadata);
+        assert_eq!(slide.label(), "deck.pptx, slide 2");
+
+        let page = SourceLocation::from_metadata(&source(&[("file_path", "https://example.com/guide/setup")]).metadata);
+        assert_eq!(page.label(), "https://example.com/guide/setup");
+    }
+}
@@ -164,7 +164,7 @@ impl DocumentProcessor {
             // Text files
             "txt", "md", "json", "xml", "html", "css", "js", "ts", "py", "rs", "go", "java", "cpp",
             "c", "h", // Documents
-            "pdf", "docx", // Code files
+            "pdf", "docx", "epub", "pptx", "xlsx", "ods", "odt", "rtf", // Code files
             "yml", "yaml", "toml", "ini", "cfg", "conf", // Other
             "log", "csv",
         ]
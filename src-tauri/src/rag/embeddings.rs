@@ -1,7 +1,48 @@
-use crate::config::Config;
-use crate::error::Result;
+use crate::config::{Config, EmbeddingProvider};
+use crate::error::{AppError, Result};
+use crate::llm::openai_backend::OpenAiClient;
+use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use futures_util::future::try_join_all;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{OnceCell, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use super::candle_embeddings::CandleEmbeddings;
+
+/// Longest text sent for embedding, in bytes
+const MAX_EMBEDDING_INPUT: usize = 8000;
+
+/// Attempts at embedding a batch before indexing fails
+const EMBEDDING_ATTEMPTS: u32 = 3;
+
+/// Wait before the first retry, doubled for each one after it
+const RETRY_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Produces embeddings for document chunks and search queries.
+///
+/// Vectors from different models are not comparable, so a failing backend
+/// returns an error rather than a substitute vector.
+#[async_trait]
+pub trait EmbeddingBackend: Send + Sync {
+    fn provider(&self) -> EmbeddingProvider;
+
+    fn model_id(&self) -> &str;
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>>;
+
+    /// Embeddings of `texts` in input order. Backends that can embed several
+    /// texts in one request override this.
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let mut embeddings = Vec::with_capacity(texts.len());
+        for text in texts {
+            embeddings.push(self.embed(text).await?);
+        }
+        Ok(embeddings)
+    }
+}
 
 #[derive(Serialize)]
 struct OllamaEmbedRequest {
@@ -14,18 +55,27 @@ struct OllamaEmbedResponse {
     embedding: Vec<f32>,
 }
 
-pub struct EmbeddingModel {
+#[derive(Serialize)]
+struct OllamaBatchEmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+}
+
+#[derive(Deserialize)]
+struct OllamaBatchEmbedResponse {
+    embeddings: Vec<Vec<f32>>,
+}
+
+/// Embeddings from Ollama's `/api/embeddings`
+pub struct OllamaEmbeddings {
     client: Client,
     model_name: String,
     ollama_url: String,
 }
 
-impl EmbeddingModel {
-    pub async fn new() -> Result<Self> {
+impl OllamaEmbeddings {
+    pub async fn new(ollama_url: &str, model_name: &str) -> Self {
         let client = Client::new();
-        let config = Config::get();
-        let model_name = config.services.ollama_embedding_model.clone();
-        let ollama_url = config.services.ollama_url.clone();
 
         // Test connection to Ollama
         let test_url = format!("{ollama_url}/api/tags");
@@ -42,141 +92,412 @@ impl EmbeddingModel {
             }
         }
 
-        Ok(Self {
+        Self {
             client,
-            model_name,
-            ollama_url,
-        })
+            model_name: model_name.to_string(),
+            ollama_url: ollama_url.to_string(),
+        }
     }
+}
 
-    pub async fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
-        // Clean and prepare text
-        let cleaned_text = text.trim();
-        if cleaned_text.is_empty() {
-            return Ok(vec![0.0; 768]); // nomic-embed-text has 768 dimensions
-        }
+#[async_trait]
+impl EmbeddingBackend for OllamaEmbeddings {
+    fn provider(&self) -> EmbeddingProvider {
+        EmbeddingProvider::Ollama
+    }
 
-        // Truncate very long text to avoid API limits
-        let truncated_text = if cleaned_text.len() > 8000 {
-            &cleaned_text[..8000]
-        } else {
-            cleaned_text
-        };
+    fn model_id(&self) -> &str {
+        &self.model_name
+    }
 
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
         let request = OllamaEmbedRequest {
             model: self.model_name.clone(),
-            prompt: truncated_text.to_string(),
+            prompt: text.to_string(),
         };
 
         let url = format!("{}/api/embeddings", self.ollama_url);
+        let response = self.client.post(&url).json(&request).send().await.map_err(|e| {
+            AppError::Embedding(format!(
+                "Cannot reach Ollama at {} for embeddings: {e}",
+                self.ollama_url
+            ))
+        })?;
 
-        match self.client.post(&url).json(&request).send().await {
-            Ok(response) => {
-                if response.status().is_success() {
-                    match response.json::<OllamaEmbedResponse>().await {
-                        Ok(embed_response) => {
-                            if embed_response.embedding.is_empty() {
-                                eprintln!(
-                                    "Warning: Ollama returned empty embedding for text: {}",
-                                    &truncated_text[..50.min(truncated_text.len())]
-                                );
-                                Ok(self.create_fallback_embedding(truncated_text))
-                            } else {
-                                Ok(embed_response.embedding)
-                            }
-                        }
-                        Err(e) => {
-                            eprintln!("Failed to parse Ollama embedding response: {e}");
-                            Ok(self.create_fallback_embedding(truncated_text))
-                        }
-                    }
-                } else {
-                    eprintln!(
-                        "Ollama embedding request failed with status: {}",
-                        response.status()
-                    );
-                    Ok(self.create_fallback_embedding(truncated_text))
-                }
+        if !response.status().is_success() {
+            return Err(AppError::Embedding(format!(
+                "Ollama embedding request for {} failed with status: {}",
+                self.model_name,
+                response.status()
+            )));
+        }
+
+        let embed_response = response.json::<OllamaEmbedResponse>().await.map_err(|e| {
+            AppError::Embedding(format!("Failed to parse Ollama embedding response: {e}"))
+        })?;
+        if embed_response.embedding.is_empty() {
+            return Err(AppError::Embedding(format!(
+                "Ollama returned an empty embedding from {}",
+                self.model_name
+            )));
+        }
+
+        Ok(embed_response.embedding)
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        let request = OllamaBatchEmbedRequest {
+            model: &self.model_name,
+            input: texts,
+        };
+
+        let url = format!("{}/api/embed", self.ollama_url);
+        let response = self.client.post(&url).json(&request).send().await.map_err(|e| {
+            AppError::Embedding(format!(
+                "Cannot reach Ollama at {} for embeddings: {e}",
+                self.ollama_url
+            ))
+        })?;
+
+        // Ollama before 0.3 only has the single-text endpoint
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            let mut embeddings = Vec::with_capacity(texts.len());
+            for text in texts {
+                embeddings.push(self.embed(text).await?);
             }
-            Err(e) => {
-                eprintln!("Failed to connect to Ollama for embeddings: {e}");
-                Ok(self.create_fallback_embedding(truncated_text))
+            return Ok(embeddings);
+        }
+        if !response.status().is_success() {
+            return Err(AppError::Embedding(format!(
+                "Ollama embedding request for {} failed with status: {}",
+                self.model_name,
+                response.status()
+            )));
+        }
+
+        let embed_response = response.json::<OllamaBatchEmbedResponse>().await.map_err(|e| {
+            AppError::Embedding(format!("Failed to parse Ollama embedding response: {e}"))
+        })?;
+        if embed_response.embeddings.len() != texts.len() {
+            return Err(AppError::Embedding(format!(
+                "Ollama returned {} embeddings for {} texts",
+                embed_response.embeddings.len(),
+                texts.len()
+            )));
+        }
+
+        Ok(embed_response.embeddings)
+    }
+}
+
+/// Embeddings from an OpenAI-compatible server's `/embeddings`
+pub struct OpenAiEmbeddings {
+    client: OpenAiClient,
+    model_name: String,
+}
+
+#[async_trait]
+impl EmbeddingBackend for OpenAiEmbeddings {
+    fn provider(&self) -> EmbeddingProvider {
+        EmbeddingProvider::OpenAiCompatible
+    }
+
+    fn model_id(&self) -> &str {
+        &self.model_name
+    }
+
+    async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+        let mut embeddings = self
+            .client
+            .embed(&self.model_name, &[text.to_string()])
+            .await
+            .map_err(|e| AppError::Embedding(format!("OpenAI-compatible server failed to embed: {e}")))?;
+
+        if embeddings.is_empty() {
+            return Err(AppError::Embedding(format!(
+                "OpenAI-compatible server returned no embedding from {}",
+                self.model_name
+            )));
+        }
+        Ok(embeddings.remove(0))
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+        self.client
+            .embed(&self.model_name, texts)
+            .await
+            .map_err(|e| AppError::Embedding(format!("OpenAI-compatible server failed to embed: {e}")))
+    }
+}
+
+/// Embedding model configured for the RAG system
+#[derive(Clone)]
+pub struct EmbeddingModel {
+    backend: Arc<dyn EmbeddingBackend>,
+    /// Length of the vectors the model produces, known after the first embedding
+    dimension: Arc<OnceCell<usize>>,
+    batch_size: usize,
+    /// Bounds the batches in flight across every document being indexed
+    in_flight: Arc<Semaphore>,
+}
+
+impl EmbeddingModel {
+    pub async fn new() -> Result<Self> {
+        let config = Config::get();
+
+        let backend: Arc<dyn EmbeddingBackend> = match config.services.embedding_provider() {
+            EmbeddingProvider::Ollama => Arc::new(
+                OllamaEmbeddings::new(
+                    &config.services.ollama_url,
+                    &config.services.ollama_embedding_model,
+                )
+                .await,
+            ),
+            EmbeddingProvider::OpenAiCompatible => Arc::new(OpenAiEmbeddings {
+                client: OpenAiClient::new(
+                    &config.services.openai_base_url,
+                    config.services.openai_api_key.clone(),
+                ),
+                model_name: config.services.openai_embedding_model.clone(),
+            }),
+            EmbeddingProvider::Candle => {
+                Arc::new(CandleEmbeddings::load(&config.services.candle_embedding_model).await?)
             }
+        };
+
+        println!(
+            "[EmbeddingModel] Using {:?} embeddings from {}",
+            backend.provider(),
+            backend.model_id()
+        );
+        Ok(Self::with_backend(
+            backend,
+            config.rag.embedding_batch_size,
+            config.rag.embedding_concurrency,
+        ))
+    }
+
+    fn with_backend(backend: Arc<dyn EmbeddingBackend>, batch_size: usize, concurrency: usize) -> Self {
+        Self {
+            backend,
+            dimension: Arc::new(OnceCell::new()),
+            batch_size: batch_size.max(1),
+            in_flight: Arc::new(Semaphore::new(concurrency.max(1))),
+        }
+    }
+
+    /// Model the embeddings come from, recorded with every stored chunk
+    pub fn model_id(&self) -> &str {
+        self.backend.model_id()
+    }
+
+    /// Length of the model's vectors, embedding a probe text the first time
+    pub async fn dimension(&self) -> Result<usize> {
+        self.dimension
+            .get_or_try_init(|| async {
+                let probe = self.backend.embed("dimension probe").await?;
+                Ok(probe.len())
+            })
+            .await
+            .copied()
+    }
+
+    pub async fn embed_text(&self, text: &str) -> Result<Vec<f32>> {
+        // Clean and prepare text
+        let cleaned_text = text.trim();
+        if cleaned_text.is_empty() {
+            return Err(AppError::InvalidInput("Cannot embed empty text".into()));
+        }
+
+        let embedding = self
+            .backend
+            .embed(truncate_input(cleaned_text))
+            .await
+            .inspect_err(|e| eprintln!("[EmbeddingModel] {:?} embedding failed: {e}", self.backend.provider()))?;
+
+        self.check_dimension(&embedding).await?;
+        Ok(embedding)
+    }
+
+    /// Embeddings of `texts` in input order, sent in batches with several in
+    /// flight at once. A failed batch is retried with backoff before the
+    /// whole call fails.
+    pub async fn embed_batch(
+        &self,
+        texts: &[String],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Vec<f32>>> {
+        let mut inputs = Vec::with_capacity(texts.len());
+        for text in texts {
+            let cleaned_text = text.trim();
+            if cleaned_text.is_empty() {
+                return Err(AppError::InvalidInput("Cannot embed empty text".into()));
+            }
+            inputs.push(truncate_input(cleaned_text).to_string());
+        }
+
+        let batches = inputs
+            .chunks(self.batch_size)
+            .map(|batch| self.embed_with_retry(batch, cancel));
+        let embeddings: Vec<Vec<f32>> = try_join_all(batches).await?.into_iter().flatten().collect();
+
+        for embedding in &embeddings {
+            self.check_dimension(embedding).await?;
         }
+        Ok(embeddings)
     }
 
-    fn create_fallback_embedding(&self, text: &str) -> Vec<f32> {
-        // Fallback embedding when Ollama is not available
-        // This is better than the old hash-based approach but still not ideal
-        let mut embedding = vec![0.0; 768]; // Match nomic-embed-text dimensions
-
-        // Basic text statistics
-        let char_count = text.chars().count() as f32;
-        let word_count = text.split_whitespace().count() as f32;
-        let sentence_count = text.split(&['.', '!', '?']).count() as f32;
-
-        // Normalize and set basic features
-        embedding[0] = (char_count / 1000.0).min(1.0);
-        embedding[1] = (word_count / 100.0).min(1.0);
-        embedding[2] = (sentence_count / 10.0).min(1.0);
-
-        // Word frequency features
-        let words: Vec<&str> = text.split_whitespace().collect();
-        let unique_words: std::collections::HashSet<&str> = words.iter().cloned().collect();
-        embedding[3] = (unique_words.len() as f32 / word_count.max(1.0)).min(1.0);
-
-        // Simple keyword presence (better than the old approach)
-        let keywords = [
-            "document",
-            "file",
-            "report",
-            "data",
-            "information",
-            "content",
-            "business",
-            "license",
-            "permit",
-            "form",
-            "application",
-            "request",
-            "date",
-            "time",
-            "location",
-            "address",
-            "name",
-            "number",
-            "id",
-            "meeting",
-            "schedule",
-            "appointment",
-            "reservation",
-            "booking",
-        ];
-
-        let text_lower = text.to_lowercase();
-        for (i, keyword) in keywords.iter().enumerate() {
-            if text_lower.contains(keyword) {
-                embedding[10 + i] = 1.0;
+    async fn embed_with_retry(
+        &self,
+        batch: &[String],
+        cancel: &CancellationToken,
+    ) -> Result<Vec<Vec<f32>>> {
+        let _permit = self
+            .in_flight
+            .acquire()
+            .await
+            .map_err(|e| AppError::Embedding(format!("Embedding queue closed: {e}")))?;
+
+        let mut backoff = RETRY_BACKOFF;
+        let mut attempt = 1;
+        loop {
+            if cancel.is_cancelled() {
+                return Err(AppError::Cancelled("Embedding was cancelled".to_string()));
+            }
+            match self.backend.embed_batch(batch).await {
+                Ok(embeddings) if embeddings.len() == batch.len() => return Ok(embeddings),
+                Ok(embeddings) => {
+                    return Err(AppError::Embedding(format!(
+                        "{} returned {} embeddings for {} texts",
+                        self.model_id(),
+                        embeddings.len(),
+                        batch.len()
+                    )))
+                }
+                Err(e) if attempt < EMBEDDING_ATTEMPTS => {
+                    eprintln!(
+                        "[EmbeddingModel] Batch of {} failed (attempt {attempt}/{EMBEDDING_ATTEMPTS}), retrying in {backoff:?}: {e}",
+                        batch.len()
+                    );
+                    tokio::select! {
+                        _ = tokio::time::sleep(backoff) => {}
+                        _ = cancel.cancelled() => {}
+                    }
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                Err(e) => {
+                    eprintln!("[EmbeddingModel] {:?} embedding failed: {e}", self.backend.provider());
+                    return Err(e);
+                }
             }
         }
+    }
 
-        // Fill remaining dimensions with normalized text hash
-        let text_bytes = text.as_bytes();
-        for i in 50..embedding.len() {
-            let idx = i % text_bytes.len();
-            embedding[i] = (text_bytes[idx] as f32) / 255.0;
+    async fn check_dimension(&self, embedding: &[f32]) -> Result<()> {
+        // The model behind a name can change while the app runs (an Ollama
+        // tag pulled again, a different model loaded on the server)
+        let dimension = *self.dimension.get_or_init(|| async { embedding.len() }).await;
+        if embedding.len() != dimension {
+            return Err(AppError::Embedding(format!(
+                "{} returned a {}-dimensional embedding, expected {}",
+                self.model_id(),
+                embedding.len(),
+                dimension
+            )));
         }
+        Ok(())
+    }
+}
 
-        embedding
+/// Truncate very long text to avoid API limits, on a character boundary
+fn truncate_input(text: &str) -> &str {
+    if text.len() <= MAX_EMBEDDING_INPUT {
+        return text;
     }
+    let end = (0..=MAX_EMBEDDING_INPUT)
+        .rev()
+        .find(|&index| text.is_char_boundary(index))
+        .unwrap_or(0);
+    &text[..end]
 }
 
-impl Clone for EmbeddingModel {
-    fn clone(&self) -> Self {
-        Self {
-            client: self.client.clone(),
-            model_name: self.model_name.clone(),
-            ollama_url: self.ollama_url.clone(),
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_truncate_input_keeps_whole_characters() {
+        let text = "é".repeat(MAX_EMBEDDING_INPUT);
+        let truncated = truncate_input(&text);
+
+        assert_eq!(truncated.len(), MAX_EMBEDDING_INPUT);
+        assert_eq!(truncate_input("short"), "short");
+
+        let text = format!("a{}", "é".repeat(MAX_EMBEDDING_INPUT));
+        assert_eq!(truncate_input(&text).len(), MAX_EMBEDDING_INPUT - 1);
+    }
+
+    /// Embeds a text as its length, failing the first `failures` requests
+    struct FlakyBackend {
+        failures: AtomicUsize,
+        batches: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl EmbeddingBackend for FlakyBackend {
+        fn provider(&self) -> EmbeddingProvider {
+            EmbeddingProvider::Ollama
+        }
+
+        fn model_id(&self) -> &str {
+            "flaky"
+        }
+
+        async fn embed(&self, text: &str) -> Result<Vec<f32>> {
+            Ok(vec![text.len() as f32, 1.0])
+        }
+
+        async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>> {
+            if self.failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+                return Err(AppError::Embedding("server busy".into()));
+            }
+            self.batches.lock().unwrap().push(texts.len());
+            Ok(texts.iter().map(|text| vec![text.len() as f32, 1.0]).collect())
         }
     }
+
+    #[tokio::test]
+    async fn test_embed_batch_keeps_order_and_retries() {
+        let backend = Arc::new(FlakyBackend {
+            failures: AtomicUsize::new(1),
+            batches: Mutex::new(Vec::new()),
+        });
+        let model = EmbeddingModel::with_backend(backend.clone(), 3, 2);
+        let texts: Vec<String> = (1..=7).map(|len| "x".repeat(len)).collect();
+
+        let embeddings = model.embed_batch(&texts, &CancellationToken::new()).await.unwrap();
+
+        let lengths: Vec<f32> = embeddings.iter().map(|embedding| embedding[0]).collect();
+        assert_eq!(lengths, vec![1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+        let mut batches = backend.batches.lock().unwrap().clone();
+        batches.sort();
+        assert_eq!(batches, vec![1, 3, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_embed_batch_stops_when_cancelled() {
+        let backend = Arc::new(FlakyBackend {
+            failures: AtomicUsize::new(0),
+            batches: Mutex::new(Vec::new()),
+        });
+        let model = EmbeddingModel::with_backend(backend, 2, 1);
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+
+        let result = model.embed_batch(&["a".to_string()], &cancel).await;
+        assert!(matches!(result, Err(AppError::Cancelled(_))));
+    }
 }
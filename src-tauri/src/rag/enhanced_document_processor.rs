@@ -1,4 +1,7 @@
 use crate::error::{AppError, Result};
+use super::chunker::ChunkingStrategy;
+use super::office_documents;
+use super::pdf_layout::{self, PdfContent};
 use chrono::{DateTime, Utc};
 use mime_guess::{mime, MimeGuess};
 use pdf_extract;
@@ -18,8 +21,37 @@ pub struct ProcessedDocument {
     pub word_count: usize,
     pub char_count: usize,
     pub processing_time_ms: u64,
+    /// How the content should be chunked, from the file type
+    pub chunking: ChunkingStrategy,
+    /// Text of each page for paged formats such as PDF, empty otherwise
+    pub pages: Vec<String>,
+    /// Chapters, slides, sheets or heading sections for formats chunked by
+    /// section, empty otherwise
+    pub sections: Vec<DocumentSection>,
 }
 
+/// Part of a document such as a chapter, slide or sheet
+#[derive(Debug, Clone, Default)]
+pub struct DocumentSection {
+    pub content: String,
+    /// Where the section is, such as `slide` or `sheet`, with a readable
+    /// `section` label. Copied to each of its chunks.
+    pub metadata: HashMap<String, String>,
+    /// Leading lines of `content` that head a table, repeated at the top of
+    /// each of its chunks
+    pub header_lines: usize,
+}
+
+/// Separates pages in extracted PDF text until it is split into `pages`
+const PAGE_BREAK: char = '\x0C';
+
+/// Extensions of source files chunked at function and type boundaries
+const CODE_EXTENSIONS: &[&str] = &[
+    "rs", "py", "js", "ts", "go", "java", "cpp", "c", "h", "hpp", "cs", "php", "rb", "swift",
+    "kt", "scala", "clj", "hs", "ml", "r", "jl", "dart", "lua", "perl", "sh", "bash", "zsh",
+    "fish", "jsx", "tsx", "vue", "css", "scss", "sass", "less", "sql",
+];
+
 #[derive(Debug, Clone)]
 pub struct DocumentMetadata {
     pub file_name: String,
@@ -40,6 +72,36 @@ pub struct DocumentMetadata {
     pub keywords: Vec<String>,
 }
 
+impl DocumentMetadata {
+    /// Fill in author, title and keywords the file system cannot tell
+    fn add_properties(&mut self, properties: EmbeddedProperties) {
+        self.author = self.author.take().or(properties.author);
+        self.title = self.title.take().or(properties.title);
+        if self.keywords.is_empty() {
+            self.keywords = properties.keywords;
+        }
+    }
+}
+
+/// Author, title and keywords a file records about itself, such as the
+/// document information of a PDF or the package properties of an office file
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EmbeddedProperties {
+    pub author: Option<String>,
+    pub title: Option<String>,
+    pub keywords: Vec<String>,
+}
+
+/// Keywords written as one list separated by commas or semicolons
+pub fn split_keywords(keywords: &str) -> Vec<String> {
+    keywords
+        .split([',', ';'])
+        .map(str::trim)
+        .filter(|keyword| !keyword.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
 pub struct EnhancedDocumentProcessor {
     // Configuration
     max_file_size: u64,
@@ -129,6 +191,10 @@ impl EnhancedDocumentProcessor {
                 "doc",
                 "odt",
                 "rtf",
+                "epub",
+                "pptx",
+                "xlsx",
+                "ods",
                 // Data files
                 "csv",
                 "tsv",
@@ -188,7 +254,7 @@ impl EnhancedDocumentProcessor {
 
         // Extract metadata
         println!("📊 Extracting file metadata...");
-        let metadata = self.extract_file_metadata(path)?;
+        let mut metadata = self.extract_file_metadata(path)?;
         println!(
             "✅ Metadata extracted - Size: {} bytes, Type: {}",
             metadata.file_size, metadata.file_type
@@ -197,20 +263,67 @@ impl EnhancedDocumentProcessor {
         let mime_type = MimeGuess::from_path(path).first_or_octet_stream();
         println!("🔍 Detected MIME type: {mime_type}");
 
-        // Extract content
+        // Extract content. Formats chunked by section are read section by
+        // section, and PDFs page by page.
         println!("📝 Extracting content from file...");
-        let content = self.extract_content(path, &mime_type).await?;
+        let chunking = Self::chunking_strategy(path);
+        let mut sections = Vec::new();
+        let mut pages = Vec::new();
+        let content = match chunking {
+            ChunkingStrategy::Pages => {
+                let (pdf_pages, properties) = self.extract_pdf_pages(path).await?;
+                metadata.add_properties(properties);
+                pages = pdf_pages;
+                pages.join("\n\n")
+            }
+            ChunkingStrategy::Sections => {
+                sections = self.extract_sections(path).await?;
+                sections
+                    .iter()
+                    .map(|section| section.content.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            }
+            _ => self.extract_content(path, &mime_type).await?,
+        };
         println!(
             "✅ Content extracted - Raw length: {} characters",
             content.len()
         );
 
-        // Clean content
+        // Clean content. Structured text keeps its lines and indentation for
+        // the chunker, and paged documents are cleaned page by page.
         println!("🧹 Cleaning content...");
-        let cleaned_content = self.clean_content(&content);
+        let cleaned_content = match chunking {
+            ChunkingStrategy::Size => self.clean_content(&content),
+            ChunkingStrategy::Pages => {
+                for page in &mut pages {
+                    *page = self.clean_structured_content(page);
+                }
+                pages
+                    .iter()
+                    .filter(|page| !page.is_empty())
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            }
+            ChunkingStrategy::Sections => {
+                for section in &mut sections {
+                    section.content = self.clean_structured_content(&section.content);
+                }
+                sections.retain(|section| !section.content.is_empty());
+                sections
+                    .iter()
+                    .map(|section| section.content.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n\n")
+            }
+            _ => self.clean_structured_content(&content),
+        };
         println!(
-            "✅ Content cleaned - Final length: {} characters",
-            cleaned_content.len()
+            "✅ Content cleaned - Final length: {} characters, {:?} chunking",
+            cleaned_content.len(),
+            chunking
         );
 
         // Detect language
@@ -283,6 +396,9 @@ impl EnhancedDocumentProcessor {
             word_count,
             char_count,
             processing_time_ms: processing_time,
+            chunking,
+            pages,
+            sections,
         };
 
         println!("🎉 Document processing completed successfully!");
@@ -307,6 +423,65 @@ impl EnhancedDocumentProcessor {
         Ok(processed_doc)
     }
 
+    /// Document for markdown that did not come from a file, such as the
+    /// readable content of a web page
+    pub fn process_markdown(&self, title: &str, content: &str) -> ProcessedDocument {
+        let start_time = std::time::Instant::now();
+        let content = self.clean_structured_content(content);
+        let language = if self.detect_language {
+            self.detect_language(&content)
+        } else {
+            None
+        };
+        let word_count = content.split_whitespace().count();
+        let char_count = content.chars().count();
+
+        let mut metadata = HashMap::new();
+        metadata.insert("word_count".to_string(), word_count.to_string());
+        metadata.insert("char_count".to_string(), char_count.to_string());
+        if let Some(lang) = &language {
+            metadata.insert("language".to_string(), lang.clone());
+        }
+
+        let title = match title.trim() {
+            "" => Self::generate_title("Web page", &content),
+            title => title.to_string(),
+        };
+
+        ProcessedDocument {
+            title,
+            content,
+            file_type: "text/markdown".to_string(),
+            metadata,
+            language,
+            word_count,
+            char_count,
+            processing_time_ms: start_time.elapsed().as_millis() as u64,
+            chunking: ChunkingStrategy::Markdown,
+            pages: Vec::new(),
+            sections: Vec::new(),
+        }
+    }
+
+    /// Chunking that suits the file's structure, picked by extension
+    pub fn chunking_strategy(path: &Path) -> ChunkingStrategy {
+        match Self::file_extension(path).as_str() {
+            "md" | "markdown" => ChunkingStrategy::Markdown,
+            "csv" | "tsv" => ChunkingStrategy::Csv,
+            "pdf" => ChunkingStrategy::Pages,
+            "epub" | "pptx" | "xlsx" | "ods" | "odt" | "rtf" => ChunkingStrategy::Sections,
+            ext if CODE_EXTENSIONS.contains(&ext) => ChunkingStrategy::Code,
+            _ => ChunkingStrategy::Size,
+        }
+    }
+
+    fn file_extension(path: &Path) -> String {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .unwrap_or_default()
+    }
+
     fn validate_file(&self, path: &Path) -> Result<()> {
         if !path.exists() {
             return Err(AppError::NotFound(format!(
@@ -368,7 +543,7 @@ impl EnhancedDocumentProcessor {
             .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
             .and_then(|d| DateTime::from_timestamp(d.as_secs() as i64, 0));
 
-        Ok(DocumentMetadata {
+        let mut metadata = DocumentMetadata {
             file_name,
             file_path,
             file_type,
@@ -381,7 +556,21 @@ impl EnhancedDocumentProcessor {
             title: None,
             subject: None,
             keywords: Vec::new(),
-        })
+        };
+
+        // Office files keep their author in the package; PDFs are read
+        // along with their pages
+        if matches!(
+            Self::file_extension(path).as_str(),
+            "docx" | "pptx" | "xlsx" | "odt" | "ods" | "odp"
+        ) {
+            match office_documents::package_properties(path) {
+                Ok(properties) => metadata.add_properties(properties),
+                Err(e) => println!("⚠️  Could not read document properties: {e}"),
+            }
+        }
+
+        Ok(metadata)
     }
 
     async fn extract_content(&self, path: &Path, mime_type: &mime::Mime) -> Result<String> {
@@ -449,6 +638,119 @@ impl EnhancedDocumentProcessor {
         }
     }
 
+    /// Sections of the formats chunked by section, picked by extension
+    async fn extract_sections(&self, path: &Path) -> Result<Vec<DocumentSection>> {
+        let extension = Self::file_extension(path);
+        let sections = match extension.as_str() {
+            "epub" => {
+                println!("📚 Processing as EPUB file");
+                office_documents::epub_chapters(path)?
+            }
+            "pptx" => {
+                println!("📊 Processing as PPTX file");
+                office_documents::pptx_slides(path)?
+            }
+            "xlsx" => {
+                println!("📊 Processing as XLSX file");
+                office_documents::xlsx_sheets(path)?
+            }
+            "ods" => {
+                println!("📊 Processing as ODS file");
+                office_documents::ods_sheets(path)?
+            }
+            "odt" => {
+                println!("📄 Processing as ODT file");
+                office_documents::odt_sections(path)?
+            }
+            "rtf" => {
+                println!("📄 Processing as RTF file");
+                office_documents::rtf_sections(path)?
+            }
+            _ => {
+                return Err(AppError::InvalidInput(format!(
+                    "No section extractor for .{extension} files"
+                )))
+            }
+        };
+
+        let length: usize = sections.iter().map(|section| section.content.len()).sum();
+        if length == 0 {
+            return Err(AppError::ProcessingError(format!(
+                "No text found in {}",
+                extension.to_uppercase()
+            )));
+        }
+        if length > self.max_content_length {
+            return Err(AppError::ProcessingError(format!(
+                "Text content too large: {} chars (max: {} chars)",
+                length, self.max_content_length
+            )));
+        }
+
+        println!(
+            "✅ {} processing completed - {} sections, {} characters",
+            extension.to_uppercase(),
+            sections.len(),
+            length
+        );
+        Ok(sections)
+    }
+
+    /// Text of each page of a PDF in reading order with its tables, or as
+    /// flat text when the layout cannot be read, and the PDF's document
+    /// information. Pages without text stay empty so that the others keep
+    /// their numbers.
+    async fn extract_pdf_pages(&self, path: &Path) -> Result<(Vec<String>, EmbeddedProperties)> {
+        println!("📑 Processing as PDF file");
+        let PdfContent {
+            pages: pdf_pages,
+            properties,
+        } = match pdf_layout::read_pdf(path) {
+            Ok(content) => content,
+            Err(e) => {
+                println!("⚠️  {e} - falling back to flat text extraction");
+                let text = self.process_pdf_file(path).await?;
+                let pages = text.split(PAGE_BREAK).map(str::to_string).collect();
+                return Ok((pages, EmbeddedProperties::default()));
+            }
+        };
+
+        let scanned: Vec<usize> = pdf_pages
+            .iter()
+            .enumerate()
+            .filter(|(_, page)| page.text.trim().is_empty() && page.has_images)
+            .map(|(index, _)| index + 1)
+            .collect();
+        let length: usize = pdf_pages.iter().map(|page| page.text.len()).sum();
+        if pdf_pages.iter().all(|page| page.text.trim().is_empty()) {
+            return Err(AppError::ProcessingError(if scanned.is_empty() {
+                "No readable text found in PDF".to_string()
+            } else {
+                format!(
+                    "No text layer in PDF: its {} pages look scanned and need OCR before they can be indexed",
+                    scanned.len()
+                )
+            }));
+        }
+        if length > self.max_content_length {
+            return Err(AppError::ProcessingError(format!(
+                "Text content too large: {} chars (max: {} chars)",
+                length, self.max_content_length
+            )));
+        }
+
+        for number in &scanned {
+            println!("📷 PDF page {number} has images but no text - likely scanned, not indexed");
+        }
+        println!(
+            "✅ PDF processing completed - {} pages ({} without text), {} characters",
+            pdf_pages.len(),
+            pdf_pages.iter().filter(|page| page.text.trim().is_empty()).count(),
+            length
+        );
+        Ok((pdf_pages.into_iter().map(|page| page.text).collect(), properties))
+    }
+
     async fn process_text_file(&self, path: &Path) -> Result<String> {
         println!("📖 Reading text file...");
         let content = fs::read_to_string(path)
@@ -485,8 +787,11 @@ impl EnhancedDocumentProcessor {
 
         println!("📄 PDF has {} pages", pages.len());
 
-        // Extract text from each page
+        // Extract text from each page, keeping page boundaries for chunking
         for (page_num, _) in pages.iter().enumerate() {
+            if page_num > 0 {
+                text.push(PAGE_BREAK);
+            }
             println!("📖 Processing page {}/{}", page_num + 1, pages.len());
             match doc.extract_text(&[(page_num + 1) as u32]) {
                 Ok(page_text) => {
@@ -824,6 +1129,24 @@ impl EnhancedDocumentProcessor {
         cleaned
     }
 
+    /// Light cleaning for text whose line structure carries meaning, such as
+    /// markdown, source code and CSV. Lines are kept where they are so chunk
+    /// line numbers match the file.
+    fn clean_structured_content(&self, content: &str) -> String {
+        // Remove control and zero-width characters, keeping tabs and newlines
+        let content = Regex::new(r"[\x00-\x08\x0B\x0C\x0E-\x1F\x7F\u200B-\u200D\uFEFF]")
+            .unwrap()
+            .replace_all(content, "");
+
+        content
+            .lines()
+            .map(|line| line.trim_end())
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_end()
+            .to_string()
+    }
+
     fn detect_language(&self, content: &str) -> Option<String> {
         // Simple language detection based on common patterns
         // In a real implementation, you might use a proper language detection library
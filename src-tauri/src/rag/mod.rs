@@ -1,24 +1,40 @@
+mod ann_index;
+mod candle_embeddings;
 mod chunker;
+mod citations;
 mod document_processor;
 mod embeddings;
 mod enhanced_document_processor;
+mod office_documents;
+mod pdf_layout;
 mod qdrant_store;
+mod reranker;
 mod retriever;
 mod vector_store;
 
 pub use chunker::TextChunker;
+pub use citations::{
+    extract_citations, numbered_source, Citation, SourceLocation, CITATION_INSTRUCTIONS,
+};
 pub use document_processor::DocumentProcessor;
 pub use embeddings::EmbeddingModel;
 pub use enhanced_document_processor::EnhancedDocumentProcessor;
 pub use qdrant_store::QdrantVectorStore;
-pub use retriever::DocumentRetriever;
+pub use reranker::{RankedResult, Reranker};
+pub use retriever::{DocumentRetriever, SearchFilters};
 pub use vector_store::VectorStore;
 
+use crate::browser_ai::WebPage;
 use crate::database::SqliteDatabase;
-use crate::error::Result;
-use std::collections::HashMap;
+use crate::error::{AppError, Result};
+use enhanced_document_processor::ProcessedDocument;
+use retriever::{reciprocal_rank_fusion, HYBRID_CANDIDATES};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 #[derive(Debug, Clone)]
@@ -30,6 +46,38 @@ pub struct Document {
     pub goal_id: Option<Uuid>,
     pub chunks: Vec<DocumentChunk>,
     pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Hash of the file when it was indexed; empty for documents indexed
+    /// before files were hashed
+    pub content_hash: String,
+    /// Numbers of the PDF pages that had no text to index, such as scans
+    pub pages_without_text: Vec<usize>,
+    /// Author recorded in the file's properties
+    pub author: Option<String>,
+    /// When the file was last modified, if known
+    pub modified_at: Option<chrono::DateTime<chrono::Utc>>,
+    /// Tags given by the user, lowercase
+    pub tags: Vec<String>,
+    /// Ids of the collections the document is in
+    pub collections: Vec<Uuid>,
+}
+
+impl Document {
+    /// Lowercase extension of the source file, or `web` for web pages
+    pub fn file_type(&self) -> String {
+        if self.file_path.starts_with("http://") || self.file_path.starts_with("https://") {
+            return "web".to_string();
+        }
+        std::path::Path::new(&self.file_path)
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default()
+    }
+
+    /// Date searches filter on: when the file was modified, or when the
+    /// document was indexed if that is unknown
+    pub fn date(&self) -> chrono::DateTime<chrono::Utc> {
+        self.modified_at.unwrap_or(self.created_at)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -37,7 +85,12 @@ pub struct DocumentChunk {
     pub id: Uuid,
     pub document_id: Uuid,
     pub content: String,
+    /// Hash of `content`, shared by identical chunks
+    pub content_hash: String,
     pub embedding: Vec<f32>,
+    /// Model that produced `embedding`; empty for chunks stored before
+    /// embeddings were tagged
+    pub embedding_model: String,
     pub chunk_index: usize,
     pub metadata: HashMap<String, String>,
 }
@@ -51,35 +104,385 @@ pub struct SearchResult {
     pub metadata: HashMap<String, String>,
 }
 
+/// The file an indexed document was read from, without its content
+#[derive(Debug, Clone)]
+pub struct IndexedFile {
+    pub document_id: Uuid,
+    pub file_path: String,
+    pub content_hash: String,
+    /// Size of the file when a watched folder was last synced
+    pub file_size: Option<i64>,
+    /// Modification time of the file then, in nanoseconds since the epoch
+    pub file_modified_ns: Option<i64>,
+}
+
+/// What is left of a document once one of its files no longer holds its
+/// content
+#[derive(Debug, Clone, PartialEq)]
+pub enum PathDetached {
+    /// The document is still stored under the same path
+    Kept,
+    /// Another file with the same content now gives the document its path
+    Moved(String),
+    /// No file with the content is left, so the document should be removed
+    Orphaned,
+}
+
+/// A folder whose supported files are kept indexed for a goal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WatchedFolder {
+    pub id: Uuid,
+    pub path: String,
+    pub goal_id: Option<Uuid>,
+    pub include_subdirs: bool,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_synced_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// A web page kept indexed for a goal, fetched again every
+/// `refresh_interval_hours` when that is set
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebSource {
+    pub id: Uuid,
+    pub url: String,
+    pub goal_id: Option<Uuid>,
+    /// Title of the page when it was last fetched
+    pub title: Option<String>,
+    pub refresh_interval_hours: Option<u32>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    pub last_fetched_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl WebSource {
+    /// Whether the page should be fetched again at `now`
+    pub fn is_due(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        match (self.refresh_interval_hours, self.last_fetched_at) {
+            (Some(hours), Some(fetched)) => now - fetched >= chrono::Duration::hours(hours.into()),
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+}
+
+/// A named group of documents. A document can be in several collections.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Collection {
+    pub id: Uuid,
+    pub name: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+/// Tags as stored: trimmed, lowercase and without duplicates
+pub fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut normalized: Vec<String> = tags
+        .iter()
+        .map(|tag| tag.trim().to_lowercase())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    normalized.sort();
+    normalized.dedup();
+    normalized
+}
+
+/// How chunks are matched against a query
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SearchMode {
+    /// Embedding similarity only
+    Vector,
+    /// BM25 full-text ranking only, for exact names, codes and identifiers
+    Keyword,
+    /// Both rankings merged by reciprocal-rank fusion
+    #[default]
+    Hybrid,
+}
+
 pub struct RAGSystem {
     embedding_model: EmbeddingModel,
-    document_processor: EnhancedDocumentProcessor,
+    document_processor: Arc<EnhancedDocumentProcessor>,
     vector_store: QdrantVectorStore,
-    text_chunker: TextChunker,
+    text_chunker: Arc<TextChunker>,
 }
 
 // Keep the old VectorStore as a fallback
 pub struct LegacyRAGSystem {
     embedding_model: EmbeddingModel,
-    document_processor: EnhancedDocumentProcessor,
+    document_processor: Arc<EnhancedDocumentProcessor>,
     vector_store: VectorStore,
-    text_chunker: TextChunker,
+    text_chunker: Arc<TextChunker>,
     retriever: DocumentRetriever,
 }
 
 // Wrapper enum to handle both Qdrant and legacy systems
 pub enum RAGSystemWrapper {
     Qdrant(Box<RAGSystem>),
-    Legacy(LegacyRAGSystem),
+    Legacy(Box<LegacyRAGSystem>),
+}
+
+/// Reads, chunks and embeds documents without touching the vector store, so
+/// several files can be prepared while the RAG system is free for searches
+#[derive(Clone)]
+pub struct DocumentIndexer {
+    embedding_model: EmbeddingModel,
+    document_processor: Arc<EnhancedDocumentProcessor>,
+    text_chunker: Arc<TextChunker>,
+    /// Where stored documents and embeddings are looked up, so unchanged
+    /// files and repeated text are not embedded again
+    database: Option<Arc<Mutex<SqliteDatabase>>>,
+}
+
+impl DocumentIndexer {
+    /// Build the document for `file_path` with embedded chunks, ready to be
+    /// stored. If the file, or another file of the goal, is indexed with the
+    /// same content, the stored document is returned instead. Embedding
+    /// stops at the next batch once `cancel` fires.
+    pub async fn prepare_document(
+        &self,
+        file_path: &str,
+        goal_id: Option<Uuid>,
+        cancel: &CancellationToken,
+    ) -> Result<Document> {
+        println!("🚀 Starting document indexing for: {file_path}");
+
+        let file_hash = file_content_hash(file_path).await?;
+        if let Some(document) = self.indexed_version(file_path, goal_id, &file_hash).await? {
+            println!("⏭️  {file_path} is unchanged since it was indexed");
+            return Ok(document);
+        }
+        if let Some(document) = self.same_content(goal_id, &file_hash).await? {
+            println!("⏭️  {file_path} has the same content as {}", document.file_path);
+            return Ok(document);
+        }
+
+        // Process document
+        let processed_doc = self.document_processor.process_file(file_path).await?;
+        self.embed_document(processed_doc, file_path, goal_id, file_hash, cancel)
+            .await
+    }
+
+    /// Build the document for a fetched web page, stored under its URL with
+    /// the fetch date on each chunk. If the page is indexed with the same
+    /// content, the stored document is returned instead.
+    pub async fn prepare_web_page(
+        &self,
+        page: &WebPage,
+        goal_id: Option<Uuid>,
+        cancel: &CancellationToken,
+    ) -> Result<Document> {
+        println!("🌐 Starting web page indexing for: {}", page.url);
+
+        let page_hash = content_hash(page.content.as_bytes());
+        if let Some(document) = self.indexed_version(&page.url, goal_id, &page_hash).await? {
+            println!("⏭️  {} is unchanged since it was indexed", page.url);
+            return Ok(document);
+        }
+
+        let processed_doc = self
+            .document_processor
+            .process_markdown(&page.title, &page.content);
+        let mut document = self
+            .embed_document(processed_doc, &page.url, goal_id, page_hash, cancel)
+            .await?;
+
+        let fetched_at = page.fetched_at.to_rfc3339();
+        for chunk in &mut document.chunks {
+            chunk
+                .metadata
+                .insert("source_url".to_string(), page.url.clone());
+            chunk
+                .metadata
+                .insert("fetched_at".to_string(), fetched_at.clone());
+        }
+        document.created_at = page.fetched_at;
+        Ok(document)
+    }
+
+    /// Chunk and embed a processed document read from `source`, a file path
+    /// or URL
+    async fn embed_document(
+        &self,
+        processed_doc: ProcessedDocument,
+        source: &str,
+        goal_id: Option<Uuid>,
+        source_hash: String,
+        cancel: &CancellationToken,
+    ) -> Result<Document> {
+        // Chunk the document along its structure
+        let chunks = self.text_chunker.chunk_document(&processed_doc)?;
+        // A new version of an indexed file keeps the tags and collections
+        // the user gave the old one
+        let (tags, collections) = self.previous_labels(source, goal_id).await?;
+        let mut document = Document {
+            id: Uuid::new_v4(),
+            title: processed_doc.title,
+            content: processed_doc.content,
+            file_path: source.to_string(),
+            goal_id,
+            chunks: Vec::new(),
+            created_at: chrono::Utc::now(),
+            content_hash: source_hash,
+            pages_without_text: processed_doc
+                .pages
+                .iter()
+                .enumerate()
+                .filter(|(_, page)| page.trim().is_empty())
+                .map(|(index, _)| index + 1)
+                .collect(),
+            author: processed_doc.metadata.get("author").cloned(),
+            modified_at: processed_doc
+                .metadata
+                .get("modified_time")
+                .and_then(|time| chrono::DateTime::parse_from_rfc3339(time).ok())
+                .map(|time| time.with_timezone(&chrono::Utc)),
+            tags,
+            collections,
+        };
+        println!(
+            "✂️  {} chunked into {} pieces ({:?})",
+            document.title,
+            chunks.len(),
+            processed_doc.chunking
+        );
+
+        // Text already stored with an embedding from this model reuses it;
+        // the rest is embedded in batches, each distinct text once
+        let model_id = self.embedding_model.model_id();
+        let hashes: Vec<String> = chunks
+            .iter()
+            .map(|chunk| content_hash(chunk.content.as_bytes()))
+            .collect();
+        let mut embeddings = self.stored_embeddings(&hashes).await?;
+        let reused = hashes.iter().filter(|hash| embeddings.contains_key(*hash)).count();
+
+        let mut seen = HashSet::new();
+        let missing: Vec<(&String, String)> = chunks
+            .iter()
+            .zip(&hashes)
+            .filter(|(_, hash)| !embeddings.contains_key(*hash) && seen.insert(*hash))
+            .map(|(chunk, hash)| (hash, chunk.content.clone()))
+            .collect();
+        let texts: Vec<String> = missing.iter().map(|(_, text)| text.clone()).collect();
+        let new_embeddings = match self.embedding_model.embed_batch(&texts, cancel).await {
+            Err(AppError::Cancelled(_)) => {
+                println!("🛑 Indexing of {source} cancelled");
+                return Err(AppError::Cancelled(format!("Indexing of {source} was cancelled")));
+            }
+            result => result?,
+        };
+        if new_embeddings.len() != texts.len() {
+            return Err(AppError::Embedding(format!(
+                "Expected {} embeddings for {}, got {}",
+                texts.len(),
+                document.title,
+                new_embeddings.len()
+            )));
+        }
+        println!(
+            "🧠 Generated {} embeddings for {} ({} chunks reused stored ones)",
+            new_embeddings.len(),
+            document.title,
+            reused
+        );
+        for ((hash, _), embedding) in missing.into_iter().zip(new_embeddings) {
+            embeddings.insert(hash.clone(), embedding);
+        }
+
+        document.chunks = chunks
+            .into_iter()
+            .zip(hashes)
+            .enumerate()
+            .map(|(index, (mut chunk, hash))| {
+                // Record where the chunk came from so answers can cite it
+                chunk
+                    .metadata
+                    .insert("file_path".to_string(), source.to_string());
+                DocumentChunk {
+                    id: Uuid::new_v4(),
+                    document_id: document.id,
+                    content: chunk.content,
+                    embedding: embeddings[&hash].clone(),
+                    content_hash: hash,
+                    embedding_model: model_id.to_string(),
+                    chunk_index: index,
+                    metadata: chunk.metadata,
+                }
+            })
+            .collect();
+
+        Ok(document)
+    }
+
+    /// The stored document for `file_path` if it was indexed with this content
+    async fn indexed_version(
+        &self,
+        file_path: &str,
+        goal_id: Option<Uuid>,
+        content_hash: &str,
+    ) -> Result<Option<Document>> {
+        let Some(db) = &self.database else {
+            return Ok(None);
+        };
+        let database = db.lock().await;
+        for document_id in database.find_documents_by_path(file_path, goal_id).await? {
+            if let Some(document) = database.load_document(document_id).await? {
+                if document.content_hash == content_hash {
+                    return Ok(Some(document));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// A document stored for the goal from another file with this content
+    async fn same_content(&self, goal_id: Option<Uuid>, content_hash: &str) -> Result<Option<Document>> {
+        let Some(db) = &self.database else {
+            return Ok(None);
+        };
+        let database = db.lock().await;
+        match database.find_document_by_content(content_hash, goal_id).await? {
+            Some(document_id) => database.load_document(document_id).await,
+            None => Ok(None),
+        }
+    }
+
+    /// Tags and collections of the document stored for `source`, if any
+    async fn previous_labels(
+        &self,
+        source: &str,
+        goal_id: Option<Uuid>,
+    ) -> Result<(Vec<String>, Vec<Uuid>)> {
+        let Some(db) = &self.database else {
+            return Ok((Vec::new(), Vec::new()));
+        };
+        let database = db.lock().await;
+        match database.find_documents_by_path(source, goal_id).await?.first() {
+            Some(document_id) => database.load_document_labels(*document_id).await,
+            None => Ok((Vec::new(), Vec::new())),
+        }
+    }
+
+    /// Stored embeddings from the current model for the given chunk hashes
+    async fn stored_embeddings(&self, hashes: &[String]) -> Result<HashMap<String, Vec<f32>>> {
+        match &self.database {
+            Some(db) => {
+                db.lock()
+                    .await
+                    .find_embeddings_by_hash(hashes, self.embedding_model.model_id())
+                    .await
+            }
+            None => Ok(HashMap::new()),
+        }
+    }
 }
 
 impl RAGSystem {
     pub async fn new() -> Result<Self> {
         let embedding_model = EmbeddingModel::new().await?;
-        let document_processor = EnhancedDocumentProcessor::new();
+        let document_processor = Arc::new(EnhancedDocumentProcessor::new());
+        let dimension = embedding_model.dimension().await?;
 
         // Try to create Qdrant store, fallback to VectorStore if failed
-        let vector_store = match QdrantVectorStore::new().await {
+        let vector_store = match QdrantVectorStore::new(embedding_model.model_id(), dimension).await {
             Ok(store) => {
                 println!("Successfully connected to Qdrant vector database");
                 store
@@ -90,7 +493,7 @@ impl RAGSystem {
             }
         };
 
-        let text_chunker = TextChunker::new();
+        let text_chunker = Arc::new(TextChunker::new());
 
         Ok(Self {
             embedding_model,
@@ -102,13 +505,18 @@ impl RAGSystem {
 
     pub async fn new_with_automatic_fallback() -> Result<RAGSystemWrapper> {
         let embedding_model = EmbeddingModel::new().await?;
-        let document_processor = EnhancedDocumentProcessor::new();
+        let document_processor = Arc::new(EnhancedDocumentProcessor::new());
 
-        // Try Qdrant first, fallback to VectorStore if failed
-        match QdrantVectorStore::new().await {
+        // Try Qdrant first, fallback to VectorStore if failed. The collection
+        // is sized for the model, so it needs one embedding up front.
+        let qdrant_store = match embedding_model.dimension().await {
+            Ok(dimension) => QdrantVectorStore::new(embedding_model.model_id(), dimension).await,
+            Err(e) => Err(e),
+        };
+        match qdrant_store {
             Ok(qdrant_store) => {
                 println!("Successfully connected to Qdrant vector database");
-                let text_chunker = TextChunker::new();
+                let text_chunker = Arc::new(TextChunker::new());
                 Ok(RAGSystemWrapper::Qdrant(Box::new(Self {
                     embedding_model,
                     document_processor,
@@ -120,25 +528,25 @@ impl RAGSystem {
                 eprintln!(
                     "Failed to connect to Qdrant: {e}. Falling back to in-memory vector store"
                 );
-                let vector_store = VectorStore::new().await?;
-                let text_chunker = TextChunker::new();
+                let vector_store = VectorStore::new(embedding_model.model_id()).await?;
+                let text_chunker = Arc::new(TextChunker::new());
                 let retriever = DocumentRetriever::new(vector_store.clone());
-                Ok(RAGSystemWrapper::Legacy(LegacyRAGSystem {
+                Ok(RAGSystemWrapper::Legacy(Box::new(LegacyRAGSystem {
                     embedding_model,
                     document_processor,
                     vector_store,
                     text_chunker,
                     retriever,
-                }))
+                })))
             }
         }
     }
 
     pub async fn new_with_fallback() -> Result<LegacyRAGSystem> {
         let embedding_model = EmbeddingModel::new().await?;
-        let document_processor = EnhancedDocumentProcessor::new();
-        let vector_store = VectorStore::new().await?;
-        let text_chunker = TextChunker::new();
+        let document_processor = Arc::new(EnhancedDocumentProcessor::new());
+        let vector_store = VectorStore::new(embedding_model.model_id()).await?;
+        let text_chunker = Arc::new(TextChunker::new());
         let retriever = DocumentRetriever::new(vector_store.clone());
 
         Ok(LegacyRAGSystem {
@@ -156,77 +564,96 @@ impl RAGSystem {
         file_path: &str,
         goal_id: Option<Uuid>,
     ) -> Result<Document> {
-        println!("🚀 Starting document indexing for: {file_path}");
-
-        // Process document
-        println!("📄 Processing document content...");
-        let processed_doc = self.document_processor.process_file(file_path).await?;
+        self.index_document_with_cancel(file_path, goal_id, &CancellationToken::new())
+            .await
+    }
 
-        // Create document
-        println!("📋 Creating document structure...");
-        let document = Document {
-            id: Uuid::new_v4(),
-            title: processed_doc.title.clone(),
-            content: processed_doc.content.clone(),
-            file_path: file_path.to_string(),
-            goal_id,
-            chunks: Vec::new(),
-            created_at: chrono::Utc::now(),
-        };
-        println!("✅ Document created with ID: {}", document.id);
-
-        // Chunk the document
-        println!("✂️  Chunking document content...");
-        let chunks = self.text_chunker.chunk_text(&processed_doc.content)?;
-        println!("✅ Document chunked into {} pieces", chunks.len());
-
-        // Generate embeddings and create document chunks
-        println!("🧠 Generating embeddings for {} chunks...", chunks.len());
-        let mut document_chunks = Vec::new();
-        for (index, chunk_text) in chunks.iter().enumerate() {
-            println!(
-                "   Processing chunk {}/{} ({} characters)",
-                index + 1,
-                chunks.len(),
-                chunk_text.len()
-            );
-            let embedding = self.embedding_model.embed_text(chunk_text).await?;
-
-            let chunk = DocumentChunk {
-                id: Uuid::new_v4(),
-                document_id: document.id,
-                content: chunk_text.clone(),
-                embedding,
-                chunk_index: index,
-                metadata: HashMap::new(),
-            };
-
-            document_chunks.push(chunk);
-        }
-        println!("✅ All embeddings generated successfully");
+    /// Index a document, checking `cancel` between embedding batches.
+    /// Nothing is stored when indexing is cancelled.
+    pub async fn index_document_with_cancel(
+        &mut self,
+        file_path: &str,
+        goal_id: Option<Uuid>,
+        cancel: &CancellationToken,
+    ) -> Result<Document> {
+        let document = self.indexer().prepare_document(file_path, goal_id, cancel).await?;
 
         // Store in vector database
         println!("💾 Storing document in vector database...");
-        self.vector_store
-            .store_document(&document, &document_chunks)
-            .await?;
+        self.store_prepared_document(file_path, &document).await?;
         println!("✅ Document stored in vector database");
 
-        let mut final_document = document;
-        final_document.chunks = document_chunks;
-
         println!("🎉 Document indexing completed successfully!");
         println!("📊 Final statistics:");
-        println!("   - Document ID: {}", final_document.id);
-        println!("   - Title: {}", final_document.title);
+        println!("   - Document ID: {}", document.id);
+        println!("   - Title: {}", document.title);
         println!(
             "   - Content length: {} characters",
-            final_document.content.len()
+            document.content.len()
         );
-        println!("   - Number of chunks: {}", final_document.chunks.len());
-        println!("   - Goal ID: {:?}", final_document.goal_id);
+        println!("   - Number of chunks: {}", document.chunks.len());
+        println!("   - Goal ID: {:?}", document.goal_id);
 
-        Ok(final_document)
+        Ok(document)
+    }
+
+    /// Handle for preparing documents without holding this system
+    pub fn indexer(&self) -> DocumentIndexer {
+        DocumentIndexer {
+            embedding_model: self.embedding_model.clone(),
+            document_processor: self.document_processor.clone(),
+            text_chunker: self.text_chunker.clone(),
+            database: self.vector_store.database(),
+        }
+    }
+
+    /// Store a document produced by `DocumentIndexer::prepare_document` for
+    /// `source`, replacing earlier versions indexed from it. A document with
+    /// the content of another file of the goal gets `source` recorded as one
+    /// of its files instead.
+    pub async fn store_prepared_document(&mut self, source: &str, document: &Document) -> Result<()> {
+        let previous = self
+            .vector_store
+            .find_documents_by_path(source, document.goal_id)
+            .await?;
+        if document.file_path != source {
+            // Same content as a document read from another file
+            if let Some(db) = self.vector_store.database() {
+                db.lock().await.add_document_path(document.id, source).await?;
+            }
+        } else if !previous.contains(&document.id) {
+            // An unchanged file comes back as the document already stored
+            self.vector_store.store_document(document, &document.chunks).await?;
+        }
+
+        for document_id in previous.into_iter().filter(|id| *id != document.id) {
+            self.remove_source(document_id, source).await?;
+        }
+        Ok(())
+    }
+
+    /// Drop `source` from the files of a document, removing the document once
+    /// no file with its content is left
+    pub async fn remove_source(&mut self, document_id: Uuid, source: &str) -> Result<()> {
+        let Some(db) = self.vector_store.database() else {
+            return self.remove_document(document_id).await;
+        };
+        let detached = db.lock().await.detach_document_path(document_id, source).await?;
+        match detached {
+            PathDetached::Kept => Ok(()),
+            // Stored chunks cite the file, so store them again under the new one
+            PathDetached::Moved(file_path) => {
+                let document = db.lock().await.load_document(document_id).await?;
+                let Some(mut document) = document else {
+                    return Ok(());
+                };
+                for chunk in &mut document.chunks {
+                    chunk.metadata.insert("file_path".to_string(), file_path.clone());
+                }
+                self.vector_store.store_document(&document, &document.chunks).await
+            }
+            PathDetached::Orphaned => self.remove_document(document_id).await,
+        }
     }
 
     /// Search for relevant documents
@@ -236,15 +663,53 @@ impl RAGSystem {
         goal_id: Option<Uuid>,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
-        // Generate query embedding
-        let query_embedding = self.embedding_model.embed_text(query).await?;
+        self.search_with_mode(query, goal_id, limit, SearchMode::default())
+            .await
+    }
 
-        // Search in Qdrant vector store
-        self.vector_store
-            .search_similar(&query_embedding, goal_id, limit)
+    /// Search for relevant documents using the given ranking
+    pub async fn search_with_mode(
+        &self,
+        query: &str,
+        goal_id: Option<Uuid>,
+        limit: usize,
+        mode: SearchMode,
+    ) -> Result<Vec<SearchResult>> {
+        self.search_filtered(query, &SearchFilters::for_goal(goal_id), limit, mode)
             .await
     }
 
+    /// Search the documents that meet `filters` using the given ranking
+    pub async fn search_filtered(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        limit: usize,
+        mode: SearchMode,
+    ) -> Result<Vec<SearchResult>> {
+        let mut results = match mode {
+            SearchMode::Keyword => self.vector_store.keyword_search(query, filters, limit).await?,
+            SearchMode::Vector => {
+                let query_embedding = self.embedding_model.embed_text(query).await?;
+                self.vector_store
+                    .search_similar(&query_embedding, filters, limit)
+                    .await?
+            }
+            SearchMode::Hybrid => {
+                let query_embedding = self.embedding_model.embed_text(query).await?;
+                let candidates = limit * HYBRID_CANDIDATES;
+                let (vector_results, keyword_results) = tokio::try_join!(
+                    self.vector_store
+                        .search_similar(&query_embedding, filters, candidates),
+                    self.vector_store.keyword_search(query, filters, candidates),
+                )?;
+                reciprocal_rank_fusion(vec![vector_results, keyword_results], limit)
+            }
+        };
+        filters.drop_below_min_score(&mut results);
+        Ok(results)
+    }
+
     /// Get document context for a goal
     pub async fn get_goal_context(&self, goal_id: Uuid, limit: usize) -> Result<Vec<SearchResult>> {
         self.vector_store.get_goal_documents(goal_id, limit).await
@@ -255,6 +720,11 @@ impl RAGSystem {
         self.vector_store.remove_document(document_id).await
     }
 
+    /// Pick up tags and collections of a document changed in the database
+    pub async fn refresh_document_labels(&self, document_id: Uuid) -> Result<()> {
+        self.vector_store.refresh_document_labels(document_id).await
+    }
+
     /// List all indexed documents
     pub async fn list_documents(&self, goal_id: Option<Uuid>) -> Result<Vec<Document>> {
         self.vector_store.list_documents(goal_id).await
@@ -266,9 +736,6 @@ impl RAGSystem {
         document_id: Uuid,
         file_path: &str,
     ) -> Result<Document> {
-        // Remove old document
-        self.remove_document(document_id).await?;
-
         // Get goal_id from old document
         let old_docs = self.vector_store.list_documents(None).await?;
         let goal_id = old_docs
@@ -276,6 +743,9 @@ impl RAGSystem {
             .find(|doc| doc.id == document_id)
             .and_then(|doc| doc.goal_id);
 
+        // Remove old document
+        self.remove_document(document_id).await?;
+
         // Re-index updated document
         self.index_document(file_path, goal_id).await
     }
@@ -289,6 +759,23 @@ impl RAGSystem {
     pub async fn load_from_database(&mut self) -> Result<()> {
         self.vector_store.load_from_database().await
     }
+
+    pub fn embedding_model(&self) -> &EmbeddingModel {
+        &self.embedding_model
+    }
+
+    /// Re-embed the chunks of `document` that came from another model and
+    /// store them again. Returns how many were re-embedded.
+    pub async fn reembed_document(
+        &mut self,
+        document: &Document,
+        cancel: &CancellationToken,
+    ) -> Result<usize> {
+        let mut chunks = document.chunks.clone();
+        let reembedded = reembed_chunks(&self.embedding_model, &mut chunks, cancel).await?;
+        self.vector_store.store_document(document, &chunks).await?;
+        Ok(reembedded)
+    }
 }
 
 impl RAGSystemWrapper {
@@ -304,6 +791,49 @@ impl RAGSystemWrapper {
         }
     }
 
+    /// Index a document, stopping early once `cancel` fires
+    pub async fn index_document_with_cancel(
+        &mut self,
+        file_path: &str,
+        goal_id: Option<Uuid>,
+        cancel: &CancellationToken,
+    ) -> Result<Document> {
+        match self {
+            RAGSystemWrapper::Qdrant(rag) => {
+                rag.index_document_with_cancel(file_path, goal_id, cancel).await
+            }
+            RAGSystemWrapper::Legacy(rag) => {
+                rag.index_document_with_cancel(file_path, goal_id, cancel).await
+            }
+        }
+    }
+
+    /// Handle for preparing documents without holding the RAG system lock
+    pub fn indexer(&self) -> DocumentIndexer {
+        match self {
+            RAGSystemWrapper::Qdrant(rag) => rag.indexer(),
+            RAGSystemWrapper::Legacy(rag) => rag.indexer(),
+        }
+    }
+
+    /// Store a document produced by `DocumentIndexer::prepare_document` for
+    /// `source`
+    pub async fn store_prepared_document(&mut self, source: &str, document: &Document) -> Result<()> {
+        match self {
+            RAGSystemWrapper::Qdrant(rag) => rag.store_prepared_document(source, document).await,
+            RAGSystemWrapper::Legacy(rag) => rag.store_prepared_document(source, document).await,
+        }
+    }
+
+    /// Drop `source` from the files of a document, removing the document once
+    /// no file with its content is left
+    pub async fn remove_source(&mut self, document_id: Uuid, source: &str) -> Result<()> {
+        match self {
+            RAGSystemWrapper::Qdrant(rag) => rag.remove_source(document_id, source).await,
+            RAGSystemWrapper::Legacy(rag) => rag.remove_source(document_id, source).await,
+        }
+    }
+
     /// Search for relevant documents
     pub async fn search(
         &self,
@@ -317,6 +847,38 @@ impl RAGSystemWrapper {
         }
     }
 
+    /// Search for relevant documents using the given ranking
+    pub async fn search_with_mode(
+        &self,
+        query: &str,
+        goal_id: Option<Uuid>,
+        limit: usize,
+        mode: SearchMode,
+    ) -> Result<Vec<SearchResult>> {
+        match self {
+            RAGSystemWrapper::Qdrant(rag) => {
+                rag.search_with_mode(query, goal_id, limit, mode).await
+            }
+            RAGSystemWrapper::Legacy(rag) => {
+                rag.search_with_mode(query, goal_id, limit, mode).await
+            }
+        }
+    }
+
+    /// Search the documents that meet `filters` using the given ranking
+    pub async fn search_filtered(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        limit: usize,
+        mode: SearchMode,
+    ) -> Result<Vec<SearchResult>> {
+        match self {
+            RAGSystemWrapper::Qdrant(rag) => rag.search_filtered(query, filters, limit, mode).await,
+            RAGSystemWrapper::Legacy(rag) => rag.search_filtered(query, filters, limit, mode).await,
+        }
+    }
+
     /// Get document context for a goal
     pub async fn get_goal_context(&self, goal_id: Uuid, limit: usize) -> Result<Vec<SearchResult>> {
         match self {
@@ -333,6 +895,14 @@ impl RAGSystemWrapper {
         }
     }
 
+    /// Pick up tags and collections of a document changed in the database
+    pub async fn refresh_document_labels(&self, document_id: Uuid) -> Result<()> {
+        match self {
+            RAGSystemWrapper::Qdrant(rag) => rag.refresh_document_labels(document_id).await,
+            RAGSystemWrapper::Legacy(rag) => rag.refresh_document_labels(document_id).await,
+        }
+    }
+
     /// List all indexed documents
     pub async fn list_documents(&self, goal_id: Option<Uuid>) -> Result<Vec<Document>> {
         match self {
@@ -368,6 +938,26 @@ impl RAGSystemWrapper {
             RAGSystemWrapper::Legacy(rag) => rag.load_from_database().await,
         }
     }
+
+    /// Model that embeds documents and queries
+    pub fn embedding_model(&self) -> &EmbeddingModel {
+        match self {
+            RAGSystemWrapper::Qdrant(rag) => rag.embedding_model(),
+            RAGSystemWrapper::Legacy(rag) => rag.embedding_model(),
+        }
+    }
+
+    /// Move a document's chunks to the current embedding model
+    pub async fn reembed_document(
+        &mut self,
+        document: &Document,
+        cancel: &CancellationToken,
+    ) -> Result<usize> {
+        match self {
+            RAGSystemWrapper::Qdrant(rag) => rag.reembed_document(document, cancel).await,
+            RAGSystemWrapper::Legacy(rag) => rag.reembed_document(document, cancel).await,
+        }
+    }
 }
 
 impl LegacyRAGSystem {
@@ -377,49 +967,79 @@ impl LegacyRAGSystem {
         file_path: &str,
         goal_id: Option<Uuid>,
     ) -> Result<Document> {
-        // Process document
-        let processed_doc = self.document_processor.process_file(file_path).await?;
-
-        // Create document
-        let document = Document {
-            id: Uuid::new_v4(),
-            title: processed_doc.title,
-            content: processed_doc.content.clone(),
-            file_path: file_path.to_string(),
-            goal_id,
-            chunks: Vec::new(),
-            created_at: chrono::Utc::now(),
-        };
-
-        // Chunk the document
-        let chunks = self.text_chunker.chunk_text(&processed_doc.content)?;
-
-        // Generate embeddings and create document chunks
-        let mut document_chunks = Vec::new();
-        for (index, chunk_text) in chunks.iter().enumerate() {
-            let embedding = self.embedding_model.embed_text(chunk_text).await?;
+        self.index_document_with_cancel(file_path, goal_id, &CancellationToken::new())
+            .await
+    }
 
-            let chunk = DocumentChunk {
-                id: Uuid::new_v4(),
-                document_id: document.id,
-                content: chunk_text.clone(),
-                embedding,
-                chunk_index: index,
-                metadata: HashMap::new(),
-            };
+    /// Index a document, checking `cancel` between embedding batches
+    pub async fn index_document_with_cancel(
+        &mut self,
+        file_path: &str,
+        goal_id: Option<Uuid>,
+        cancel: &CancellationToken,
+    ) -> Result<Document> {
+        let document = self.indexer().prepare_document(file_path, goal_id, cancel).await?;
+        self.store_prepared_document(file_path, &document).await?;
+        Ok(document)
+    }
 
-            document_chunks.push(chunk);
+    /// Handle for preparing documents without holding this system
+    pub fn indexer(&self) -> DocumentIndexer {
+        DocumentIndexer {
+            embedding_model: self.embedding_model.clone(),
+            document_processor: self.document_processor.clone(),
+            text_chunker: self.text_chunker.clone(),
+            database: self.vector_store.database(),
         }
+    }
 
-        // Store in vector database
-        self.vector_store
-            .store_document(&document, &document_chunks)
+    /// Store a document produced by `DocumentIndexer::prepare_document` for
+    /// `source`, replacing earlier versions indexed from it. A document with
+    /// the content of another file of the goal gets `source` recorded as one
+    /// of its files instead.
+    pub async fn store_prepared_document(&mut self, source: &str, document: &Document) -> Result<()> {
+        let previous = self
+            .vector_store
+            .find_documents_by_path(source, document.goal_id)
             .await?;
+        if document.file_path != source {
+            // Same content as a document read from another file
+            if let Some(db) = self.vector_store.database() {
+                db.lock().await.add_document_path(document.id, source).await?;
+            }
+        } else if !previous.contains(&document.id) {
+            // An unchanged file comes back as the document already stored
+            self.vector_store.store_document(document, &document.chunks).await?;
+        }
 
-        let mut final_document = document;
-        final_document.chunks = document_chunks;
+        for document_id in previous.into_iter().filter(|id| *id != document.id) {
+            self.remove_source(document_id, source).await?;
+        }
+        Ok(())
+    }
 
-        Ok(final_document)
+    /// Drop `source` from the files of a document, removing the document once
+    /// no file with its content is left
+    pub async fn remove_source(&mut self, document_id: Uuid, source: &str) -> Result<()> {
+        let Some(db) = self.vector_store.database() else {
+            return self.remove_document(document_id).await;
+        };
+        let detached = db.lock().await.detach_document_path(document_id, source).await?;
+        match detached {
+            PathDetached::Kept => Ok(()),
+            // Stored chunks cite the file, so store them again under the new one
+            PathDetached::Moved(file_path) => {
+                let document = db.lock().await.load_document(document_id).await?;
+                let Some(mut document) = document else {
+                    return Ok(());
+                };
+                for chunk in &mut document.chunks {
+                    chunk.metadata.insert("file_path".to_string(), file_path.clone());
+                }
+                self.vector_store.store_document(&document, &document.chunks).await
+            }
+            PathDetached::Orphaned => self.remove_document(document_id).await,
+        }
     }
 
     /// Search for relevant documents
@@ -429,15 +1049,47 @@ impl LegacyRAGSystem {
         goal_id: Option<Uuid>,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
-        // Generate query embedding
-        let query_embedding = self.embedding_model.embed_text(query).await?;
+        self.search_with_mode(query, goal_id, limit, SearchMode::default())
+            .await
+    }
 
-        // Search in vector store
-        let results = self
-            .retriever
-            .search(&query_embedding, goal_id, limit)
-            .await?;
+    /// Search for relevant documents using the given ranking
+    pub async fn search_with_mode(
+        &self,
+        query: &str,
+        goal_id: Option<Uuid>,
+        limit: usize,
+        mode: SearchMode,
+    ) -> Result<Vec<SearchResult>> {
+        self.search_filtered(query, &SearchFilters::for_goal(goal_id), limit, mode)
+            .await
+    }
 
+    /// Search the documents that meet `filters` using the given ranking
+    pub async fn search_filtered(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        limit: usize,
+        mode: SearchMode,
+    ) -> Result<Vec<SearchResult>> {
+        let mut results = match mode {
+            SearchMode::Keyword => self.retriever.keyword_search(query, filters, limit).await?,
+            SearchMode::Vector => {
+                let query_embedding = self.embedding_model.embed_text(query).await?;
+                return self
+                    .retriever
+                    .search_with_filters(&query_embedding, filters.clone(), limit)
+                    .await;
+            }
+            SearchMode::Hybrid => {
+                let query_embedding = self.embedding_model.embed_text(query).await?;
+                self.retriever
+                    .hybrid_search(&query_embedding, query, filters, limit)
+                    .await?
+            }
+        };
+        filters.drop_below_min_score(&mut results);
         Ok(results)
     }
 
@@ -451,6 +1103,11 @@ impl LegacyRAGSystem {
         self.vector_store.remove_document(document_id).await
     }
 
+    /// Pick up tags and collections of a document changed in the database
+    pub async fn refresh_document_labels(&self, document_id: Uuid) -> Result<()> {
+        self.vector_store.refresh_document_labels(document_id).await
+    }
+
     /// List all indexed documents
     pub async fn list_documents(&self, goal_id: Option<Uuid>) -> Result<Vec<Document>> {
         self.vector_store.list_documents(goal_id).await
@@ -462,9 +1119,6 @@ impl LegacyRAGSystem {
         document_id: Uuid,
         file_path: &str,
     ) -> Result<Document> {
-        // Remove old document
-        self.remove_document(document_id).await?;
-
         // Get goal_id from old document
         let old_docs = self.vector_store.list_documents(None).await?;
         let goal_id = old_docs
@@ -472,6 +1126,9 @@ impl LegacyRAGSystem {
             .find(|doc| doc.id == document_id)
             .and_then(|doc| doc.goal_id);
 
+        // Remove old document
+        self.remove_document(document_id).await?;
+
         // Re-index updated document
         self.index_document(file_path, goal_id).await
     }
@@ -483,6 +1140,78 @@ impl LegacyRAGSystem {
 
     /// Load documents from database
     pub async fn load_from_database(&mut self) -> Result<()> {
-        self.vector_store.load_from_database().await
+        // Without the dimension untagged chunks cannot be attributed yet
+        let dimension = match self.embedding_model.dimension().await {
+            Ok(dimension) => Some(dimension),
+            Err(e) => {
+                eprintln!("Cannot check stored embeddings against {}: {e}", self.embedding_model.model_id());
+                None
+            }
+        };
+        self.vector_store.load_from_database(dimension).await
+    }
+
+    pub fn embedding_model(&self) -> &EmbeddingModel {
+        &self.embedding_model
+    }
+
+    /// Re-embed the chunks of `document` that came from another model and
+    /// store them again. Returns how many were re-embedded.
+    pub async fn reembed_document(
+        &mut self,
+        document: &Document,
+        cancel: &CancellationToken,
+    ) -> Result<usize> {
+        let mut chunks = document.chunks.clone();
+        let reembedded = reembed_chunks(&self.embedding_model, &mut chunks, cancel).await?;
+        self.vector_store.store_document(document, &chunks).await?;
+        Ok(reembedded)
+    }
+}
+
+/// Hex SHA-256 of `bytes`, identifying identical files and chunks
+pub fn content_hash(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Hex SHA-256 of the file at `file_path`, to tell when its content changes
+pub async fn file_content_hash(file_path: &str) -> Result<String> {
+    let path = file_path.to_string();
+    tokio::task::spawn_blocking(move || {
+        let bytes = std::fs::read(&path)
+            .map_err(|e| AppError::ProcessingError(format!("Failed to read {path}: {e}")))?;
+        Ok(content_hash(&bytes))
+    })
+    .await
+    .map_err(|e| AppError::ProcessingError(format!("Hashing task failed: {e}")))?
+}
+
+/// `embedding_model` in a form usable in collection and file names
+pub(crate) fn model_slug(embedding_model: &str) -> String {
+    embedding_model
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+        .collect()
+}
+
+/// Embed the `chunks` that came from a model other than `embedding_model` again
+async fn reembed_chunks(
+    embedding_model: &EmbeddingModel,
+    chunks: &mut [DocumentChunk],
+    cancel: &CancellationToken,
+) -> Result<usize> {
+    let model_id = embedding_model.model_id();
+    let mut stale: Vec<&mut DocumentChunk> = chunks
+        .iter_mut()
+        .filter(|chunk| chunk.embedding_model != model_id)
+        .collect();
+    let texts: Vec<String> = stale.iter().map(|chunk| chunk.content.clone()).collect();
+    let embeddings = embedding_model.embed_batch(&texts, cancel).await?;
+
+    for (chunk, embedding) in stale.iter_mut().zip(embeddings) {
+        chunk.embedding = embedding;
+        chunk.embedding_model = model_id.to_string();
     }
+    Ok(stale.len())
 }
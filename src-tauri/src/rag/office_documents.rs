@@ -0,0 +1,1269 @@
+//! Text of e-books, slide decks, spreadsheets and word processor files,
+//! split into the sections a reader would refer to: chapters, slides,
+//! sheets and headings. Each section's metadata names where it is, with a
+//! readable `section` label for citations.
+
+use super::enhanced_document_processor::{split_keywords, DocumentSection, EmbeddedProperties};
+use crate::error::{AppError, Result};
+use quick_xml::escape::resolve_predefined_entity;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::{Reader, XmlVersion};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek};
+use std::path::Path;
+use zip::ZipArchive;
+
+/// Chapters of an EPUB book in reading order, titled by their first heading
+pub fn epub_chapters(path: &Path) -> Result<Vec<DocumentSection>> {
+    read_epub(&mut open_archive(path, "EPUB")?)
+}
+
+/// Text and speaker notes of each slide of a PowerPoint deck
+pub fn pptx_slides(path: &Path) -> Result<Vec<DocumentSection>> {
+    read_pptx(&mut open_archive(path, "PPTX")?)
+}
+
+/// Each sheet of an Excel workbook as a table
+pub fn xlsx_sheets(path: &Path) -> Result<Vec<DocumentSection>> {
+    read_xlsx(&mut open_archive(path, "XLSX")?)
+}
+
+/// Each sheet of an OpenDocument spreadsheet as a table
+pub fn ods_sheets(path: &Path) -> Result<Vec<DocumentSection>> {
+    let content = read_entry(&mut open_archive(path, "ODS")?, "content.xml")?;
+    read_ods(&content)
+}
+
+/// Sections under each heading of an OpenDocument text file
+pub fn odt_sections(path: &Path) -> Result<Vec<DocumentSection>> {
+    let content = read_entry(&mut open_archive(path, "ODT")?, "content.xml")?;
+    read_odt(&content)
+}
+
+/// Sections under each heading of an RTF file, found by outline level
+pub fn rtf_sections(path: &Path) -> Result<Vec<DocumentSection>> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| AppError::ProcessingError(format!("Error reading RTF file: {e}")))?;
+    Ok(read_rtf(&String::from_utf8_lossy(&bytes)))
+}
+
+/// Author, title and keywords from the package properties of an Office Open
+/// XML or OpenDocument file
+pub fn package_properties(path: &Path) -> Result<EmbeddedProperties> {
+    read_package_properties(&mut open_archive(path, "office")?)
+}
+
+fn open_archive(path: &Path, format: &str) -> Result<ZipArchive<File>> {
+    let file = File::open(path)
+        .map_err(|e| AppError::ProcessingError(format!("Error opening {format} file: {e}")))?;
+    ZipArchive::new(file)
+        .map_err(|e| AppError::ProcessingError(format!("Error reading {format} archive: {e}")))
+}
+
+fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Result<String> {
+    read_optional_entry(archive, name)?
+        .ok_or_else(|| AppError::ProcessingError(format!("Archive has no {name}")))
+}
+
+/// Text of the entry `name`, or `None` if the archive does not have it
+fn read_optional_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    name: &str,
+) -> Result<Option<String>> {
+    let mut entry = match archive.by_name(name) {
+        Ok(entry) => entry,
+        Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+        Err(e) => return Err(AppError::ProcessingError(format!("Error reading {name}: {e}"))),
+    };
+    let mut content = String::new();
+    entry
+        .read_to_string(&mut content)
+        .map_err(|e| AppError::ProcessingError(format!("Error reading {name}: {e}")))?;
+    Ok(Some(content))
+}
+
+/// XML with namespace prefixes dropped from element names and entities
+/// resolved. Attributes keep their prefixes, as slides have both `id` and
+/// `r:id`. Empty elements are a `Start` followed by an `End`, and text may
+/// arrive in several pieces.
+#[derive(Debug, PartialEq)]
+enum XmlNode {
+    Start {
+        name: String,
+        attributes: HashMap<String, String>,
+    },
+    End(String),
+    Text(String),
+}
+
+fn parse_xml(xml: &str) -> Result<Vec<XmlNode>> {
+    let mut reader = Reader::from_str(xml.trim_start_matches('\u{FEFF}'));
+    let mut nodes = Vec::new();
+    loop {
+        let event = reader
+            .read_event()
+            .map_err(|e| AppError::ProcessingError(format!("Error parsing XML: {e}")))?;
+        match event {
+            Event::Start(start) => nodes.push(start_node(&start)),
+            Event::Empty(start) => {
+                nodes.push(start_node(&start));
+                nodes.push(XmlNode::End(start.local_name().as_ref().to_string()));
+            }
+            Event::End(end) => nodes.push(XmlNode::End(end.local_name().as_ref().to_string())),
+            Event::Text(text) => nodes.push(XmlNode::Text(text.xml10_content().into_owned())),
+            Event::CData(data) => nodes.push(XmlNode::Text(data.xml10_content().into_owned())),
+            Event::GeneralRef(reference) => {
+                let text = match reference.resolve_char_ref() {
+                    Ok(Some(ch)) => ch.to_string(),
+                    _ if &*reference == "nbsp" => " ".to_string(),
+                    _ => resolve_predefined_entity(&reference)
+                        .unwrap_or_default()
+                        .to_string(),
+                };
+                nodes.push(XmlNode::Text(text));
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(nodes)
+}
+
+fn start_node(start: &BytesStart) -> XmlNode {
+    let attributes = start
+        .attributes()
+        .flatten()
+        .map(|attribute| {
+            let value = attribute
+                .normalized_value(XmlVersion::Implicit1_0)
+                .map(|value| value.into_owned())
+                .unwrap_or_else(|_| attribute.value.to_string());
+            (attribute.key.as_ref().to_string(), value)
+        })
+        .collect();
+    XmlNode::Start {
+        name: start.local_name().as_ref().to_string(),
+        attributes,
+    }
+}
+
+/// Archive path of `href` relative to the entry `from`, as written in EPUB
+/// manifests and package relationships
+fn resolve_href(from: &str, href: &str) -> String {
+    let href = href.split('#').next().unwrap_or_default();
+    let (mut parts, href): (Vec<&str>, &str) = match href.strip_prefix('/') {
+        Some(absolute) => (Vec::new(), absolute),
+        None => (
+            from.rsplit_once('/')
+                .map(|(dir, _)| dir.split('/').collect())
+                .unwrap_or_default(),
+            href,
+        ),
+    };
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    percent_decode(&parts.join("/"))
+}
+
+fn percent_decode(href: &str) -> String {
+    let bytes = href.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Lines of `text` with runs of whitespace collapsed, without empty lines
+fn tidy_lines(text: &str) -> String {
+    text.lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn section(content: String, labels: &[(&str, String)]) -> DocumentSection {
+    DocumentSection {
+        content,
+        metadata: labels
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect(),
+        header_lines: 0,
+    }
+}
+
+/// Properties from `docProps/core.xml` of Office Open XML files or
+/// `meta.xml` of OpenDocument files. OpenDocument's `creator` is whoever
+/// saved the file last, so its `initial-creator` is preferred.
+fn read_package_properties<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<EmbeddedProperties> {
+    let xml = match read_optional_entry(archive, "docProps/core.xml")? {
+        Some(xml) => xml,
+        None => match read_optional_entry(archive, "meta.xml")? {
+            Some(xml) => xml,
+            None => return Ok(EmbeddedProperties::default()),
+        },
+    };
+
+    let mut values: HashMap<String, String> = HashMap::new();
+    let mut keywords = Vec::new();
+    let mut text = String::new();
+    for node in parse_xml(&xml)? {
+        match node {
+            XmlNode::Start { .. } => text.clear(),
+            XmlNode::Text(piece) => text.push_str(&piece),
+            XmlNode::End(name) => {
+                let value = std::mem::take(&mut text).trim().to_string();
+                if value.is_empty() {
+                    continue;
+                }
+                // OpenDocument has an element per keyword
+                if name == "keyword" {
+                    keywords.push(value);
+                } else {
+                    values.entry(name).or_insert(value);
+                }
+            }
+        }
+    }
+    if keywords.is_empty() {
+        keywords = values
+            .get("keywords")
+            .map(|keywords| split_keywords(keywords))
+            .unwrap_or_default();
+    }
+
+    Ok(EmbeddedProperties {
+        author: values
+            .remove("initial-creator")
+            .or_else(|| values.remove("creator")),
+        title: values.remove("title"),
+        keywords,
+    })
+}
+
+fn read_epub<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<Vec<DocumentSection>> {
+    let container = read_entry(archive, "META-INF/container.xml")?;
+    let package_path = parse_xml(&container)?
+        .into_iter()
+        .find_map(|node| match node {
+            XmlNode::Start { name, mut attributes } if name == "rootfile" => {
+                attributes.remove("full-path")
+            }
+            _ => None,
+        })
+        .ok_or_else(|| AppError::ProcessingError("EPUB has no package document".to_string()))?;
+
+    // The manifest names every file of the book, the spine their reading order
+    let package = read_entry(archive, &package_path)?;
+    let mut manifest = HashMap::new();
+    let mut spine = Vec::new();
+    for node in parse_xml(&package)? {
+        let XmlNode::Start { name, mut attributes } = node else {
+            continue;
+        };
+        match name.as_str() {
+            "item" => {
+                if let (Some(id), Some(href)) = (attributes.remove("id"), attributes.remove("href")) {
+                    let media_type = attributes.remove("media-type").unwrap_or_default();
+                    manifest.insert(id, (resolve_href(&package_path, &href), media_type));
+                }
+            }
+            "itemref" => spine.extend(attributes.remove("idref")),
+            _ => {}
+        }
+    }
+
+    let mut chapters = Vec::new();
+    for idref in spine {
+        let Some((href, media_type)) = manifest.get(&idref) else {
+            continue;
+        };
+        if !media_type.contains("html") {
+            continue;
+        }
+        let Some(xhtml) = read_optional_entry(archive, href)? else {
+            continue;
+        };
+        let (title, text) = xhtml_text(&xhtml)?;
+        // Covers and other image-only pages have no text to index
+        if text.is_empty() {
+            continue;
+        }
+
+        let number = (chapters.len() + 1).to_string();
+        let mut labels = vec![("chapter", number.clone())];
+        match title {
+            Some(title) => {
+                labels.push(("section", format!("chapter {number}: {title}")));
+                labels.push(("chapter_title", title));
+            }
+            None => labels.push(("section", format!("chapter {number}"))),
+        }
+        chapters.push(section(text, &labels));
+    }
+    Ok(chapters)
+}
+
+/// Block elements of XHTML, which start a new line
+const XHTML_BLOCKS: &[&str] = &[
+    "p", "div", "br", "li", "tr", "h1", "h2", "h3", "h4", "h5", "h6", "blockquote", "pre",
+    "section", "article", "figcaption", "dt", "dd", "hr", "table",
+];
+
+/// Title and text of an XHTML chapter. The title is the first heading, or
+/// the document title when there is none.
+fn xhtml_text(xhtml: &str) -> Result<(Option<String>, String)> {
+    let mut text = String::new();
+    let mut document_title = String::new();
+    let mut heading: Option<String> = None;
+    let mut first_heading = None;
+    // Elements whose text is not part of the chapter, such as the head
+    let mut hidden = 0;
+    let mut in_title = false;
+
+    for node in parse_xml(xhtml)? {
+        match node {
+            XmlNode::Start { name, .. } => match name.as_str() {
+                "head" | "script" | "style" => hidden += 1,
+                "title" => in_title = true,
+                "h1" | "h2" | "h3" if first_heading.is_none() => {
+                    heading = Some(String::new());
+                    text.push('\n');
+                }
+                name if XHTML_BLOCKS.contains(&name) => text.push('\n'),
+                _ => {}
+            },
+            XmlNode::End(name) => match name.as_str() {
+                "head" | "script" | "style" => hidden -= 1,
+                "title" => in_title = false,
+                "h1" | "h2" | "h3" if heading.is_some() => {
+                    let title = tidy_lines(&heading.take().unwrap_or_default()).replace('\n', " ");
+                    if !title.is_empty() {
+                        first_heading = Some(title);
+                    }
+                    text.push('\n');
+                }
+                name if XHTML_BLOCKS.contains(&name) => text.push('\n'),
+                _ => {}
+            },
+            XmlNode::Text(content) => {
+                if in_title {
+                    document_title.push_str(&content);
+                } else if hidden == 0 {
+                    if let Some(heading) = heading.as_mut() {
+                        heading.push_str(&content);
+                    }
+                    text.push_str(&content.replace('\n', " "));
+                }
+            }
+        }
+    }
+
+    let document_title = document_title.split_whitespace().collect::<Vec<_>>().join(" ");
+    let title = first_heading.or((!document_title.is_empty()).then_some(document_title));
+    Ok((title, tidy_lines(&text)))
+}
+
+/// Relationships of the package part `part`, from their id to their type and
+/// the archive path of their target
+fn relationships<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    part: &str,
+) -> Result<HashMap<String, (String, String)>> {
+    let rels_path = match part.rsplit_once('/') {
+        Some((dir, file)) => format!("{dir}/_rels/{file}.rels"),
+        None => format!("_rels/{part}.rels"),
+    };
+    let Some(rels) = read_optional_entry(archive, &rels_path)? else {
+        return Ok(HashMap::new());
+    };
+
+    let mut relationships = HashMap::new();
+    for node in parse_xml(&rels)? {
+        let XmlNode::Start { name, mut attributes } = node else {
+            continue;
+        };
+        if name != "Relationship" || attributes.get("TargetMode").map(String::as_str) == Some("External") {
+            continue;
+        }
+        if let (Some(id), Some(target)) = (attributes.remove("Id"), attributes.remove("Target")) {
+            let kind = attributes.remove("Type").unwrap_or_default();
+            relationships.insert(id, (kind, resolve_href(part, &target)));
+        }
+    }
+    Ok(relationships)
+}
+
+fn read_pptx<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<Vec<DocumentSection>> {
+    const PRESENTATION: &str = "ppt/presentation.xml";
+    let presentation = read_entry(archive, PRESENTATION)?;
+    let presentation_rels = relationships(archive, PRESENTATION)?;
+
+    // Slides in the order of the deck, which need not be their file order
+    let slide_paths: Vec<String> = parse_xml(&presentation)?
+        .into_iter()
+        .filter_map(|node| match node {
+            XmlNode::Start { name, mut attributes } if name == "sldId" => attributes.remove("r:id"),
+            _ => None,
+        })
+        .filter_map(|id| presentation_rels.get(&id).map(|(_, target)| target.clone()))
+        .collect();
+
+    let mut slides = Vec::new();
+    for (index, slide_path) in slide_paths.iter().enumerate() {
+        let Some(slide) = read_optional_entry(archive, slide_path)? else {
+            continue;
+        };
+        let shapes = slide_shapes(&slide)?;
+        let title = shapes
+            .iter()
+            .find(|shape| matches!(shape.placeholder.as_deref(), Some("title" | "ctrTitle")))
+            .map(|shape| shape.paragraphs.join(" "));
+        let mut content = shapes
+            .iter()
+            .flat_map(|shape| shape.paragraphs.iter().cloned())
+            .collect::<Vec<_>>()
+            .join("\n");
+
+        let notes_path = relationships(archive, slide_path)?
+            .into_values()
+            .find(|(kind, _)| kind.ends_with("/notesSlide"))
+            .map(|(_, target)| target);
+        if let Some(notes) = match notes_path {
+            Some(notes_path) => read_optional_entry(archive, &notes_path)?,
+            None => None,
+        } {
+            // Notes pages also hold the slide image and its number
+            let notes = slide_shapes(&notes)?
+                .into_iter()
+                .filter(|shape| {
+                    !matches!(
+                        shape.placeholder.as_deref(),
+                        Some("sldNum" | "sldImg" | "hdr" | "ftr" | "dt")
+                    )
+                })
+                .flat_map(|shape| shape.paragraphs)
+                .collect::<Vec<_>>()
+                .join("\n");
+            if !notes.is_empty() {
+                content.push_str("\n\nSpeaker notes:\n");
+                content.push_str(&notes);
+            }
+        }
+        if content.trim().is_empty() {
+            continue;
+        }
+
+        let number = (index + 1).to_string();
+        let mut labels = vec![("slide", number.clone()), ("section", format!("slide {number}"))];
+        if let Some(title) = title.filter(|title| !title.is_empty()) {
+            labels.push(("slide_title", title));
+        }
+        slides.push(section(content, &labels));
+    }
+    Ok(slides)
+}
+
+/// A shape on a slide or notes page with its paragraphs of text
+struct Shape {
+    /// Placeholder type such as `title` or `body`
+    placeholder: Option<String>,
+    paragraphs: Vec<String>,
+}
+
+fn slide_shapes(xml: &str) -> Result<Vec<Shape>> {
+    let mut shapes = Vec::new();
+    let mut shape: Option<Shape> = None;
+    let mut paragraph = String::new();
+    let mut in_text = false;
+
+    for node in parse_xml(xml)? {
+        match node {
+            XmlNode::Start { name, mut attributes } => match name.as_str() {
+                // Shapes, pictures and table frames can all hold text
+                "sp" | "graphicFrame" => {
+                    shape = Some(Shape {
+                        placeholder: None,
+                        paragraphs: Vec::new(),
+                    })
+                }
+                "ph" => {
+                    if let Some(shape) = shape.as_mut() {
+                        // A placeholder without a type is a body placeholder
+                        shape.placeholder =
+                            Some(attributes.remove("type").unwrap_or_else(|| "body".to_string()));
+                    }
+                }
+                "t" => in_text = true,
+                "br" => paragraph.push(' '),
+                _ => {}
+            },
+            XmlNode::End(name) => match name.as_str() {
+                "t" => in_text = false,
+                "p" => {
+                    let text = paragraph.split_whitespace().collect::<Vec<_>>().join(" ");
+                    paragraph.clear();
+                    if let (Some(shape), false) = (shape.as_mut(), text.is_empty()) {
+                        shape.paragraphs.push(text);
+                    }
+                }
+                "sp" | "graphicFrame" => shapes.extend(shape.take()),
+                _ => {}
+            },
+            XmlNode::Text(text) if in_text => paragraph.push_str(&text),
+            XmlNode::Text(_) => {}
+        }
+    }
+    Ok(shapes)
+}
+
+fn read_xlsx<R: Read + Seek>(archive: &mut ZipArchive<R>) -> Result<Vec<DocumentSection>> {
+    const WORKBOOK: &str = "xl/workbook.xml";
+    let workbook = read_entry(archive, WORKBOOK)?;
+    let workbook_rels = relationships(archive, WORKBOOK)?;
+
+    // Cells that hold text refer to it by index in the shared strings
+    let shared_strings = match read_optional_entry(archive, "xl/sharedStrings.xml")? {
+        Some(xml) => xlsx_shared_strings(&xml)?,
+        None => Vec::new(),
+    };
+
+    let sheets: Vec<(String, String)> = parse_xml(&workbook)?
+        .into_iter()
+        .filter_map(|node| match node {
+            XmlNode::Start { name, mut attributes } if name == "sheet" => {
+                let sheet_name = attributes.remove("name")?;
+                let (_, target) = workbook_rels.get(&attributes.remove("r:id")?)?;
+                Some((sheet_name, target.clone()))
+            }
+            _ => None,
+        })
+        .collect();
+
+    let mut tables = Vec::new();
+    for (sheet_name, sheet_path) in sheets {
+        let Some(sheet) = read_optional_entry(archive, &sheet_path)? else {
+            continue;
+        };
+        let rows = xlsx_rows(&sheet, &shared_strings)?;
+        tables.extend(sheet_section(&sheet_name, &rows));
+    }
+    Ok(tables)
+}
+
+fn xlsx_shared_strings(xml: &str) -> Result<Vec<String>> {
+    let mut strings = Vec::new();
+    let mut current = String::new();
+    let mut in_text = false;
+    // Phonetic guides repeat the text in another script
+    let mut in_phonetic = false;
+
+    for node in parse_xml(xml)? {
+        match node {
+            XmlNode::Start { name, .. } => match name.as_str() {
+                "t" => in_text = true,
+                "rPh" => in_phonetic = true,
+                _ => {}
+            },
+            XmlNode::End(name) => match name.as_str() {
+                "t" => in_text = false,
+                "rPh" => in_phonetic = false,
+                "si" => strings.push(std::mem::take(&mut current)),
+                _ => {}
+            },
+            XmlNode::Text(text) if in_text && !in_phonetic => current.push_str(&text),
+            XmlNode::Text(_) => {}
+        }
+    }
+    Ok(strings)
+}
+
+/// Rows of a worksheet with each cell in its column
+fn xlsx_rows(xml: &str, shared_strings: &[String]) -> Result<Vec<Vec<String>>> {
+    let mut rows = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut cell_type = String::new();
+    let mut column = 0;
+    let mut value = String::new();
+    let mut in_value = false;
+
+    for node in parse_xml(xml)? {
+        match node {
+            XmlNode::Start { name, mut attributes } => match name.as_str() {
+                "row" => row.clear(),
+                "c" => {
+                    cell_type = attributes.remove("t").unwrap_or_default();
+                    column = attributes
+                        .get("r")
+                        .and_then(|reference| column_index(reference))
+                        .unwrap_or(row.len());
+                    value.clear();
+                }
+                // `v` holds the value, `t` the text of an inline string
+                "v" | "t" => in_value = true,
+                _ => {}
+            },
+            XmlNode::End(name) => match name.as_str() {
+                "v" | "t" => in_value = false,
+                "c" => {
+                    let text = match cell_type.as_str() {
+                        "s" => value
+                            .trim()
+                            .parse::<usize>()
+                            .ok()
+                            .and_then(|index| shared_strings.get(index))
+                            .cloned()
+                            .unwrap_or_default(),
+                        "b" => match value.trim() {
+                            "1" => "TRUE".to_string(),
+                            _ => "FALSE".to_string(),
+                        },
+                        _ => value.clone(),
+                    };
+                    if row.len() <= column {
+                        row.resize(column + 1, String::new());
+                    }
+                    row[column] = text;
+                }
+                "row" => rows.push(std::mem::take(&mut row)),
+                _ => {}
+            },
+            XmlNode::Text(text) if in_value => value.push_str(&text),
+            XmlNode::Text(_) => {}
+        }
+    }
+    Ok(rows)
+}
+
+/// Zero-based column of a cell reference such as `B3`
+fn column_index(reference: &str) -> Option<usize> {
+    let letters: Vec<char> = reference
+        .chars()
+        .take_while(|ch| ch.is_ascii_alphabetic())
+        .collect();
+    if letters.is_empty() {
+        return None;
+    }
+    let number = letters.iter().fold(0, |number, ch| {
+        number * 26 + (ch.to_ascii_uppercase() as usize - 'A' as usize + 1)
+    });
+    Some(number - 1)
+}
+
+fn read_ods(content: &str) -> Result<Vec<DocumentSection>> {
+    let mut tables = Vec::new();
+    let mut sheet_name = String::new();
+    let mut rows: Vec<Vec<String>> = Vec::new();
+    let mut row: Vec<String> = Vec::new();
+    let mut row_repeat = 1;
+    let mut cell = String::new();
+    let mut cell_repeat = 1;
+    // Empty cells are only filled in once a later cell of the row has text,
+    // as rows end in cells repeated to the last column of the sheet
+    let mut pending_empty = 0;
+    let mut in_cell = false;
+    let mut in_annotation = false;
+
+    for node in parse_xml(content)? {
+        match node {
+            XmlNode::Start { name, mut attributes } => match name.as_str() {
+                "table" => {
+                    sheet_name = attributes.remove("table:name").unwrap_or_default();
+                    rows.clear();
+                }
+                "table-row" => {
+                    row.clear();
+                    pending_empty = 0;
+                    row_repeat = repeat_count(&attributes, "table:number-rows-repeated");
+                }
+                "table-cell" | "covered-table-cell" => {
+                    cell.clear();
+                    cell_repeat = repeat_count(&attributes, "table:number-columns-repeated");
+                    in_cell = true;
+                }
+                "annotation" => in_annotation = true,
+                "p" if in_cell && !cell.is_empty() => cell.push(' '),
+                "s" | "tab" | "line-break" if in_cell => cell.push(' '),
+                _ => {}
+            },
+            XmlNode::End(name) => match name.as_str() {
+                "annotation" => in_annotation = false,
+                "table-cell" | "covered-table-cell" => {
+                    in_cell = false;
+                    let text = cell.trim().to_string();
+                    if text.is_empty() {
+                        pending_empty += cell_repeat;
+                    } else {
+                        row.extend(std::iter::repeat_n(String::new(), pending_empty));
+                        row.extend(std::iter::repeat_n(text, cell_repeat));
+                        pending_empty = 0;
+                    }
+                }
+                "table-row" if !row.is_empty() => {
+                    rows.extend(std::iter::repeat_n(row.clone(), row_repeat));
+                }
+                "table" => tables.extend(sheet_section(&sheet_name, &rows)),
+                _ => {}
+            },
+            XmlNode::Text(text) if in_cell && !in_annotation => cell.push_str(&text),
+            XmlNode::Text(_) => {}
+        }
+    }
+    Ok(tables)
+}
+
+fn repeat_count(attributes: &HashMap<String, String>, name: &str) -> usize {
+    attributes
+        .get(name)
+        .and_then(|count| count.parse().ok())
+        .unwrap_or(1)
+}
+
+/// A sheet rendered as a markdown table whose first row is the header, or
+/// `None` for an empty sheet
+fn sheet_section(sheet_name: &str, rows: &[Vec<String>]) -> Option<DocumentSection> {
+    let rows: Vec<&Vec<String>> = rows
+        .iter()
+        .filter(|row| row.iter().any(|cell| !cell.trim().is_empty()))
+        .collect();
+    let width = rows
+        .iter()
+        .filter_map(|row| row.iter().rposition(|cell| !cell.trim().is_empty()))
+        .max()?
+        + 1;
+
+    let render = |cells: &[String]| {
+        let cells: Vec<String> = (0..width)
+            .map(|column| {
+                cells
+                    .get(column)
+                    .map(|cell| cell.split_whitespace().collect::<Vec<_>>().join(" ").replace('|', "\\|"))
+                    .unwrap_or_default()
+            })
+            .collect();
+        format!("| {} |", cells.join(" | "))
+    };
+
+    let mut lines = vec![render(rows[0]), render(&vec!["---".to_string(); width])];
+    lines.extend(rows[1..].iter().map(|row| render(row)));
+
+    let mut table = section(
+        lines.join("\n"),
+        &[
+            ("sheet", sheet_name.to_string()),
+            ("section", format!("sheet {sheet_name}")),
+        ],
+    );
+    table.header_lines = 2;
+    Some(table)
+}
+
+/// Splits headings and paragraphs into sections under each heading, each
+/// labelled with its heading path as in markdown
+#[derive(Default)]
+struct HeadingSections {
+    headings: Vec<(usize, String)>,
+    paragraphs: Vec<String>,
+    sections: Vec<DocumentSection>,
+}
+
+impl HeadingSections {
+    fn heading(&mut self, level: usize, title: &str) {
+        let title = title.split_whitespace().collect::<Vec<_>>().join(" ");
+        if title.is_empty() {
+            return;
+        }
+        self.finish_section();
+        self.headings.retain(|(outer, _)| *outer < level);
+        self.headings.push((level, title));
+    }
+
+    fn paragraph(&mut self, text: &str) {
+        let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+        if !text.is_empty() {
+            self.paragraphs.push(text);
+        }
+    }
+
+    /// End the current section, unless it is only a heading
+    fn finish_section(&mut self) {
+        if self.paragraphs.is_empty() {
+            return;
+        }
+        let mut lines: Vec<String> = self.headings.last().map(|(_, title)| title.clone()).into_iter().collect();
+        lines.append(&mut self.paragraphs);
+
+        let heading_path = self
+            .headings
+            .iter()
+            .map(|(_, title)| title.as_str())
+            .collect::<Vec<_>>()
+            .join(" > ");
+        let labels = if heading_path.is_empty() {
+            Vec::new()
+        } else {
+            vec![("heading_path", heading_path.clone()), ("section", heading_path)]
+        };
+        self.sections.push(section(lines.join("\n"), &labels));
+    }
+
+    fn finish(mut self) -> Vec<DocumentSection> {
+        self.finish_section();
+        self.sections
+    }
+}
+
+fn read_odt(content: &str) -> Result<Vec<DocumentSection>> {
+    let mut sections = HeadingSections::default();
+    let mut in_body = false;
+    // Tracked deletions, comments and footnotes are not part of the text
+    let mut skipped = 0;
+    let mut paragraph_depth = 0;
+    let mut paragraph = String::new();
+    let mut heading_level = None;
+    let mut table_row: Option<Vec<String>> = None;
+
+    for node in parse_xml(content)? {
+        match node {
+            XmlNode::Start { name, mut attributes } => match name.as_str() {
+                "text" => in_body = true,
+                "tracked-changes" | "annotation" | "note" | "sequence-decls" => skipped += 1,
+                "h" | "p" => {
+                    if paragraph_depth == 0 {
+                        paragraph.clear();
+                        if name == "h" {
+                            heading_level =
+                                Some(attributes.remove("text:outline-level").and_then(|level| level.parse().ok()).unwrap_or(1));
+                        }
+                    }
+                    paragraph_depth += 1;
+                }
+                "table-row" => table_row = Some(Vec::new()),
+                "s" | "tab" | "line-break" => paragraph.push(' '),
+                _ => {}
+            },
+            XmlNode::End(name) => match name.as_str() {
+                "tracked-changes" | "annotation" | "note" | "sequence-decls" => skipped -= 1,
+                "h" | "p" => {
+                    paragraph_depth -= 1;
+                    if paragraph_depth > 0 || skipped > 0 || !in_body {
+                        continue;
+                    }
+                    match (heading_level.take(), table_row.as_mut()) {
+                        (_, Some(row)) => row.push(paragraph.trim().to_string()),
+                        (Some(level), None) => sections.heading(level, &paragraph),
+                        (None, None) => sections.paragraph(&paragraph),
+                    }
+                }
+                // Table rows become one line with their cells separated
+                "table-row" => {
+                    if let Some(row) = table_row.take() {
+                        sections.paragraph(&row.join(" | "));
+                    }
+                }
+                "text" => in_body = false,
+                _ => {}
+            },
+            XmlNode::Text(text) if paragraph_depth > 0 && skipped == 0 => paragraph.push_str(&text),
+            XmlNode::Text(_) => {}
+        }
+    }
+    Ok(sections.finish())
+}
+
+/// Destinations of RTF that hold formatting or embedded data rather than text
+const RTF_SKIPPED_DESTINATIONS: &[&str] = &[
+    "fonttbl", "colortbl", "stylesheet", "info", "pict", "object", "header", "headerl",
+    "headerr", "headerf", "footer", "footerl", "footerr", "footerf", "footnote", "fldinst",
+    "listtable", "listoverridetable", "rsidtbl", "revtbl", "filetbl", "themedata",
+    "colorschememapping", "latentstyles", "datastore", "xmlnstbl", "generator", "pgdsctbl",
+];
+
+#[derive(Clone, Copy)]
+struct RtfGroup {
+    skip: bool,
+    /// Characters that stand in for each `\u` character, to be skipped
+    unicode_fallback: usize,
+}
+
+fn read_rtf(rtf: &str) -> Vec<DocumentSection> {
+    let mut sections = HeadingSections::default();
+    let mut group = RtfGroup {
+        skip: false,
+        unicode_fallback: 1,
+    };
+    let mut stack: Vec<RtfGroup> = Vec::new();
+    let mut paragraph = String::new();
+    let mut outline_level: Option<usize> = None;
+    let mut fallback_left = 0;
+    let mut chars = rtf.chars().peekable();
+
+    let mut end_paragraph = |paragraph: &mut String, outline_level: Option<usize>| {
+        match outline_level {
+            Some(level) => sections.heading(level + 1, paragraph),
+            None => sections.paragraph(paragraph),
+        }
+        paragraph.clear();
+    };
+
+    while let Some(ch) = chars.next() {
+        let text: Option<char> = match ch {
+            '{' => {
+                stack.push(group);
+                None
+            }
+            '}' => {
+                group = stack.pop().unwrap_or(group);
+                None
+            }
+            '\r' | '\n' => None,
+            '\\' => match chars.peek().copied() {
+                Some(symbol @ ('\\' | '{' | '}')) => {
+                    chars.next();
+                    Some(symbol)
+                }
+                Some('~') => {
+                    chars.next();
+                    Some(' ')
+                }
+                Some('*') => {
+                    chars.next();
+                    group.skip = true;
+                    None
+                }
+                Some('\'') => {
+                    chars.next();
+                    let hex: String = chars.by_ref().take(2).collect();
+                    u8::from_str_radix(&hex, 16).ok().map(windows_1252)
+                }
+                Some(letter) if letter.is_ascii_alphabetic() => {
+                    let mut word = String::new();
+                    while let Some(&letter) = chars.peek().filter(|ch| ch.is_ascii_alphabetic()) {
+                        word.push(letter);
+                        chars.next();
+                    }
+                    let mut parameter = String::new();
+                    if chars.peek() == Some(&'-') {
+                        parameter.push('-');
+                        chars.next();
+                    }
+                    while let Some(&digit) = chars.peek().filter(|ch| ch.is_ascii_digit()) {
+                        parameter.push(digit);
+                        chars.next();
+                    }
+                    // A space after a control word only ends it
+                    if chars.peek() == Some(&' ') {
+                        chars.next();
+                    }
+                    let parameter: Option<i64> = parameter.parse().ok();
+
+                    match word.as_str() {
+                        word if RTF_SKIPPED_DESTINATIONS.contains(&word) => {
+                            group.skip = true;
+                            None
+                        }
+                        "bin" => {
+                            // Binary data of the given length follows
+                            for _ in 0..parameter.unwrap_or(0) {
+                                chars.next();
+                            }
+                            None
+                        }
+                        "uc" => {
+                            group.unicode_fallback = parameter.unwrap_or(1).max(0) as usize;
+                            None
+                        }
+                        "u" => {
+                            let code = parameter.unwrap_or(0);
+                            let code = if code < 0 { code + 65536 } else { code };
+                            if !group.skip {
+                                if let Some(unicode) = char::from_u32(code as u32) {
+                                    paragraph.push(unicode);
+                                }
+                            }
+                            fallback_left = group.unicode_fallback;
+                            continue;
+                        }
+                        "pard" => {
+                            outline_level = None;
+                            None
+                        }
+                        "outlinelevel" => {
+                            outline_level = parameter.map(|level| level.max(0) as usize);
+                            None
+                        }
+                        "par" | "sect" | "page" | "row" => {
+                            if !group.skip {
+                                end_paragraph(&mut paragraph, outline_level);
+                            }
+                            None
+                        }
+                        "line" | "tab" => Some(' '),
+                        "cell" => Some('|'),
+                        "emdash" => Some('—'),
+                        "endash" => Some('–'),
+                        "bullet" => Some('•'),
+                        "lquote" => Some('‘'),
+                        "rquote" => Some('’'),
+                        "ldblquote" => Some('“'),
+                        "rdblquote" => Some('”'),
+                        _ => None,
+                    }
+                }
+                _ => {
+                    chars.next();
+                    None
+                }
+            },
+            ch => Some(ch),
+        };
+
+        let Some(text) = text else {
+            continue;
+        };
+        if fallback_left > 0 {
+            fallback_left -= 1;
+        } else if !group.skip {
+            paragraph.push(text);
+        }
+    }
+    end_paragraph(&mut paragraph, outline_level);
+
+    sections.finish()
+}
+
+/// Character of a byte in the Windows-1252 code page most RTF files use
+fn windows_1252(byte: u8) -> char {
+    match byte {
+        0x80 => '€',
+        0x85 => '…',
+        0x91 => '‘',
+        0x92 => '’',
+        0x93 => '“',
+        0x94 => '”',
+        0x95 => '•',
+        0x96 => '–',
+        0x97 => '—',
+        byte => byte as char,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Write};
+    use zip::write::SimpleFileOptions;
+
+    fn archive(files: &[(&str, &str)]) -> ZipArchive<Cursor<Vec<u8>>> {
+        let mut writer = zip::ZipWriter::new(Cursor::new(Vec::new()));
+        for (name, content) in files {
+            writer.start_file(*name, SimpleFileOptions::default()).unwrap();
+            writer.write_all(content.as_bytes()).unwrap();
+        }
+        ZipArchive::new(writer.finish().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_package_properties_of_office_and_opendocument_files() {
+        let mut docx = archive(&[(
+            "docProps/core.xml",
+            r#"<cp:coreProperties xmlns:cp="cp" xmlns:dc="dc">
+                <dc:title>Q3 board pack</dc:title><dc:creator>Kim Lee</dc:creator>
+                <cp:keywords>budget; forecast</cp:keywords><cp:lastModifiedBy>Sam</cp:lastModifiedBy>
+            </cp:coreProperties>"#,
+        )]);
+        assert_eq!(
+            read_package_properties(&mut docx).unwrap(),
+            EmbeddedProperties {
+                author: Some("Kim Lee".to_string()),
+                title: Some("Q3 board pack".to_string()),
+                keywords: vec!["budget".to_string(), "forecast".to_string()],
+            }
+        );
+
+        let mut odt = archive(&[(
+            "meta.xml",
+            r#"<office:document-meta><office:meta>
+                <meta:initial-creator>Ana Ruiz</meta:initial-creator><dc:creator>Sam</dc:creator>
+                <meta:keyword>minutes</meta:keyword><meta:keyword>2024</meta:keyword>
+            </office:meta></office:document-meta>"#,
+        )]);
+        let properties = read_package_properties(&mut odt).unwrap();
+        assert_eq!(properties.author.as_deref(), Some("Ana Ruiz"));
+        assert_eq!(properties.title, None);
+        assert_eq!(properties.keywords, vec!["minutes".to_string(), "2024".to_string()]);
+    }
+
+    #[test]
+    fn test_epub_chapters_follow_the_spine() {
+        let mut epub = archive(&[
+            (
+                "META-INF/container.xml",
+                r#"<container><rootfiles><rootfile full-path="OEBPS/content.opf"/></rootfiles></container>"#,
+            ),
+            (
+                "OEBPS/content.opf",
+                r#"<package><manifest>
+                    <item id="one" href="Text/one.xhtml" media-type="application/xhtml+xml"/>
+                    <item id="two" href="Text/two%20b.xhtml" media-type="application/xhtml+xml"/>
+                </manifest><spine><itemref idref="two"/><itemref idref="one"/></spine></package>"#,
+            ),
+            (
+                "OEBPS/Text/one.xhtml",
+                "<html><head><title>Book</title></head><body><h1>Ending</h1><p>It was&nbsp;over &amp; done.</p></body></html>",
+            ),
+            (
+                "OEBPS/Text/two b.xhtml",
+                "<html><head><title>Opening</title><style>p { margin: 0 }</style></head><body><p>First line</p><p>Second line</p></body></html>",
+            ),
+        ]);
+        let chapters = read_epub(&mut epub).unwrap();
+
+        assert_eq!(chapters.len(), 2);
+        assert_eq!(chapters[0].content, "First line\nSecond line");
+        assert_eq!(chapters[0].metadata["section"], "chapter 1: Opening");
+        assert_eq!(chapters[1].content, "Ending\nIt was over & done.");
+        assert_eq!(chapters[1].metadata["chapter_title"], "Ending");
+    }
+
+    #[test]
+    fn test_pptx_slides_include_speaker_notes() {
+        let rels = |target: &str, kind: &str| {
+            format!(r#"<Relationships><Relationship Id="rId1" Type="http://schemas/{kind}" Target="{target}"/></Relationships>"#)
+        };
+        let mut pptx = archive(&[
+            (
+                "ppt/presentation.xml",
+                r#"<p:presentation><p:sldIdLst><p:sldId id="256" r:id="rId1"/></p:sldIdLst></p:presentation>"#,
+            ),
+            ("ppt/_rels/presentation.xml.rels", &rels("slides/slide1.xml", "slide")),
+            (
+                "ppt/slides/slide1.xml",
+                r#"<p:sld><p:cSld><p:spTree>
+                    <p:sp><p:nvSpPr><p:nvPr><p:ph type="title"/></p:nvPr></p:nvSpPr><p:txBody><a:p><a:r><a:t>Quarterly</a:t></a:r><a:r><a:t> results</a:t></a:r></a:p></p:txBody></p:sp>
+                    <p:sp><p:txBody><a:p><a:r><a:t>Revenue up 10%</a:t></a:r></a:p></p:txBody></p:sp>
+                </p:spTree></p:cSld></p:sld>"#,
+            ),
+            ("ppt/slides/_rels/slide1.xml.rels", &rels("../notesSlides/notesSlide1.xml", "notesSlide")),
+            (
+                "ppt/notesSlides/notesSlide1.xml",
+                r#"<p:notes><p:cSld><p:spTree>
+                    <p:sp><p:nvSpPr><p:nvPr><p:ph type="body"/></p:nvPr></p:nvSpPr><p:txBody><a:p><a:r><a:t>Mention the new region</a:t></a:r></a:p></p:txBody></p:sp>
+                    <p:sp><p:nvSpPr><p:nvPr><p:ph type="sldNum"/></p:nvPr></p:nvSpPr><p:txBody><a:p><a:r><a:t>1</a:t></a:r></a:p></p:txBody></p:sp>
+                </p:spTree></p:cSld></p:notes>"#,
+            ),
+        ]);
+        let slides = read_pptx(&mut pptx).unwrap();
+
+        assert_eq!(slides.len(), 1);
+        assert_eq!(
+            slides[0].content,
+            "Quarterly results\nRevenue up 10%\n\nSpeaker notes:\nMention the new region"
+        );
+        assert_eq!(slides[0].metadata["slide"], "1");
+        assert_eq!(slides[0].metadata["slide_title"], "Quarterly results");
+    }
+
+    #[test]
+    fn test_xlsx_sheets_render_as_tables() {
+        let mut xlsx = archive(&[
+            (
+                "xl/workbook.xml",
+                r#"<workbook><sheets><sheet name="Sales" sheetId="1" r:id="rId1"/></sheets></workbook>"#,
+            ),
+            (
+                "xl/_rels/workbook.xml.rels",
+                r#"<Relationships><Relationship Id="rId1" Type="worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#,
+            ),
+            ("xl/sharedStrings.xml", "<sst><si><t>Region</t></si><si><t>Total</t></si><si><r><t>North</t></r><r><t> | East</t></r></si></sst>"),
+            (
+                "xl/worksheets/sheet1.xml",
+                r#"<worksheet><sheetData>
+                    <row r="1"><c r="A1" t="s"><v>0</v></c><c r="B1" t="s"><v>1</v></c></row>
+                    <row r="3"><c r="A3" t="s"><v>2</v></c><c r="C3" t="inlineStr"><is><t>note</t></is></c></row>
+                    <row r="4"><c r="B4"><v>42.5</v></c></row>
+                </sheetData></worksheet>"#,
+            ),
+        ]);
+        let sheets = read_xlsx(&mut xlsx).unwrap();
+
+        assert_eq!(sheets.len(), 1);
+        assert_eq!(
+            sheets[0].content,
+            "| Region | Total |  |\n| --- | --- | --- |\n| North \\| East |  | note |\n|  | 42.5 |  |"
+        );
+        assert_eq!(sheets[0].header_lines, 2);
+        assert_eq!(sheets[0].metadata["section"], "sheet Sales");
+    }
+
+    #[test]
+    fn test_ods_repeated_cells_are_expanded() {
+        let content = r#"<office:document-content><office:body><office:spreadsheet>
+            <table:table table:name="Budget">
+                <table:table-row><table:table-cell><text:p>Item</text:p></table:table-cell><table:table-cell><text:p>Cost</text:p></table:table-cell><table:table-cell table:number-columns-repeated="1020"/></table:table-row>
+                <table:table-row><table:table-cell table:number-columns-repeated="2"><text:p>x</text:p></table:table-cell></table:table-row>
+                <table:table-row table:number-rows-repeated="1048570"><table:table-cell table:number-columns-repeated="1024"/></table:table-row>
+            </table:table>
+        </office:spreadsheet></office:body></office:document-content>"#;
+        let sheets = read_ods(content).unwrap();
+
+        assert_eq!(sheets.len(), 1);
+        assert_eq!(sheets[0].content, "| Item | Cost |\n| --- | --- |\n| x | x |");
+        assert_eq!(sheets[0].metadata["sheet"], "Budget");
+    }
+
+    #[test]
+    fn test_odt_sections_follow_headings() {
+        let content = r#"<office:document-content><office:body><office:text>
+            <text:tracked-changes><text:changed-region><text:p>deleted</text:p></text:changed-region></text:tracked-changes>
+            <text:p>Preface</text:p>
+            <text:h text:outline-level="1">Guide</text:h>
+            <text:h text:outline-level="2">Install</text:h>
+            <text:p>Run the<text:s/>installer.<office:annotation><text:p>check</text:p></office:annotation></text:p>
+        </office:text></office:body></office:document-content>"#;
+        let sections = read_odt(content).unwrap();
+
+        assert_eq!(sections.len(), 2);
+        assert_eq!(sections[0].content, "Preface");
+        assert!(sections[0].metadata.is_empty());
+        assert_eq!(sections[1].content, "Install\nRun the installer.");
+        assert_eq!(sections[1].metadata["heading_path"], "Guide > Install");
+    }
+
+    #[test]
+    fn test_rtf_text_skips_formatting_destinations() {
+        let rtf = r"{\rtf1\ansi{\fonttbl{\f0 Arial;}}{\*\generator Writer;}\uc1
+            \pard\outlinelevel0 Plan\par
+            \pard Caf\'e9 costs \u8364? 5\line and more.\par
+            {\info{\title Hidden}}Last \{line\}\par}";
+        let sections = read_rtf(rtf);
+
+        assert_eq!(sections.len(), 1);
+        assert_eq!(sections[0].content, "Plan\nCafé costs € 5 and more.\nLast {line}");
+        assert_eq!(sections[0].metadata["section"], "Plan");
+    }
+}
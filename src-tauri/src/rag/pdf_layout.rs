@@ -0,0 +1,657 @@
+//! Text of each PDF page in reading order, rebuilt from where its glyphs
+//! are drawn: columns are read one after another, lines that line up in
+//! cells become markdown tables, and pages with no text layer are told
+//! apart so scans are not indexed as empty text.
+
+use super::enhanced_document_processor::{split_keywords, EmbeddedProperties};
+use crate::error::{AppError, Result};
+use pdf_extract::{MediaBox, Object, OutputDev, OutputError, Transform};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+/// One page of a PDF
+#[derive(Debug, Clone)]
+pub struct PdfPage {
+    /// Paragraphs and markdown tables in reading order, empty for pages
+    /// without a text layer
+    pub text: String,
+    /// Whether the page draws images, which for a page without text means
+    /// it is most likely a scan
+    pub has_images: bool,
+}
+
+/// Pages of a PDF and what its document information says about it
+#[derive(Debug, Clone)]
+pub struct PdfContent {
+    pub pages: Vec<PdfPage>,
+    pub properties: EmbeddedProperties,
+}
+
+/// Gap between words, in ems, from which they are separate table cells
+const CELL_GAP: f64 = 1.0;
+
+/// Narrowest gap between columns, in ems
+const MIN_GUTTER: f64 = 1.0;
+
+/// Pages of the PDF at `path` in order, with its author, title and keywords
+pub fn read_pdf(path: &Path) -> Result<PdfContent> {
+    let document = pdf_extract::Document::load(path)
+        .map_err(|e| AppError::ProcessingError(format!("Error loading PDF: {e}")))?;
+    if document.is_encrypted() {
+        return Err(AppError::ProcessingError(
+            "PDF is encrypted and cannot be read".to_string(),
+        ));
+    }
+
+    // pdf-extract panics on some malformed content streams
+    let mut collector = GlyphCollector::default();
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        pdf_extract::output_doc(&document, &mut collector)
+    }))
+    .map_err(|_| AppError::ProcessingError("PDF layout could not be read".to_string()))?
+    .map_err(|e| AppError::ProcessingError(format!("Error reading PDF layout: {e}")))?;
+
+    let page_ids = document.get_pages();
+    let pages = collector
+        .pages
+        .into_iter()
+        .map(|(number, glyphs)| PdfPage {
+            text: page_text(&glyphs),
+            has_images: page_ids
+                .get(&number)
+                .is_some_and(|page_id| has_images(&document, *page_id)),
+        })
+        .collect();
+
+    Ok(PdfContent {
+        pages,
+        properties: document_info(&document),
+    })
+}
+
+/// Author, title and keywords from the document information dictionary
+fn document_info(document: &pdf_extract::Document) -> EmbeddedProperties {
+    let Some(info) = document
+        .trailer
+        .get(b"Info")
+        .ok()
+        .and_then(|info| dereference(document, info).as_dict().ok())
+    else {
+        return EmbeddedProperties::default();
+    };
+    let text = |key: &[u8]| {
+        info.get(key)
+            .ok()
+            .and_then(|value| pdf_extract::decode_text_string(dereference(document, value)).ok())
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    EmbeddedProperties {
+        author: text(b"Author"),
+        title: text(b"Title"),
+        keywords: text(b"Keywords")
+            .map(|keywords| split_keywords(&keywords))
+            .unwrap_or_default(),
+    }
+}
+
+/// Whether the page's resources include an image
+fn has_images(document: &pdf_extract::Document, page_id: pdf_extract::ObjectId) -> bool {
+    let Ok((resources, resource_ids)) = document.get_page_resources(page_id) else {
+        return false;
+    };
+    resources
+        .into_iter()
+        .chain(
+            resource_ids
+                .into_iter()
+                .filter_map(|id| document.get_dictionary(id).ok()),
+        )
+        .filter_map(|resources| resources.get(b"XObject").ok())
+        .filter_map(|xobjects| dereference(document, xobjects).as_dict().ok())
+        .flat_map(|xobjects| xobjects.iter())
+        .filter_map(|(_, xobject)| dereference(document, xobject).as_stream().ok())
+        .any(|stream| {
+            stream
+                .dict
+                .get(b"Subtype")
+                .and_then(Object::as_name)
+                .is_ok_and(|subtype| subtype == b"Image")
+        })
+}
+
+fn dereference<'a>(document: &'a pdf_extract::Document, object: &'a Object) -> &'a Object {
+    document
+        .dereference(object)
+        .map(|(_, object)| object)
+        .unwrap_or(object)
+}
+
+/// A character drawn on a page, in points from the top left corner
+#[derive(Debug, Clone)]
+struct Glyph {
+    x: f64,
+    /// Baseline
+    y: f64,
+    width: f64,
+    size: f64,
+    text: String,
+}
+
+/// Collects the glyphs of each page as pdf-extract draws them
+#[derive(Default)]
+struct GlyphCollector {
+    pages: Vec<(u32, Vec<Glyph>)>,
+    page_top: f64,
+}
+
+impl OutputDev for GlyphCollector {
+    fn begin_page(
+        &mut self,
+        page_num: u32,
+        media_box: &MediaBox,
+        _art_box: Option<(f64, f64, f64, f64)>,
+    ) -> std::result::Result<(), OutputError> {
+        self.page_top = media_box.ury;
+        self.pages.push((page_num, Vec::new()));
+        Ok(())
+    }
+
+    fn end_page(&mut self) -> std::result::Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn output_character(
+        &mut self,
+        trm: &Transform,
+        width: f64,
+        _spacing: f64,
+        font_size: f64,
+        char: &str,
+    ) -> std::result::Result<(), OutputError> {
+        let size = font_size * (trm.m11 * trm.m22 - trm.m12 * trm.m21).abs().sqrt();
+        if let Some((_, glyphs)) = self.pages.last_mut() {
+            glyphs.push(Glyph {
+                x: trm.m31,
+                y: self.page_top - trm.m32,
+                // Fonts without widths would otherwise run into each other
+                width: if width > 0.0 { width * size } else { size * 0.5 },
+                size,
+                text: char.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn begin_word(&mut self) -> std::result::Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_word(&mut self) -> std::result::Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> std::result::Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Word {
+    x0: f64,
+    x1: f64,
+    y: f64,
+    size: f64,
+    text: String,
+}
+
+#[derive(Debug, Clone)]
+struct Line {
+    words: Vec<Word>,
+    y: f64,
+    size: f64,
+}
+
+impl Line {
+    fn text(&self) -> String {
+        self.words
+            .iter()
+            .map(|word| word.text.as_str())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    /// Runs of words separated by gaps too wide for a space
+    fn cells(&self) -> Vec<Word> {
+        let mut cells: Vec<Word> = Vec::new();
+        for word in &self.words {
+            match cells.last_mut() {
+                Some(cell) if word.x0 - cell.x1 < CELL_GAP * cell.size.max(word.size) => {
+                    cell.text.push(' ');
+                    cell.text.push_str(&word.text);
+                    cell.x1 = word.x1;
+                }
+                _ => cells.push(word.clone()),
+            }
+        }
+        cells
+    }
+}
+
+/// Text of a page in reading order
+fn page_text(glyphs: &[Glyph]) -> String {
+    let mut lines = lines(words(glyphs));
+
+    // Page numbers above or below the text are not content
+    let is_page_number = |line: &Line| {
+        let text = line.text();
+        text.len() <= 4 && text.chars().all(|ch| ch.is_ascii_digit())
+    };
+    if lines.first().is_some_and(is_page_number) {
+        lines.remove(0);
+    }
+    if lines.last().is_some_and(is_page_number) {
+        lines.pop();
+    }
+
+    let words = lines.into_iter().flat_map(|line| line.words).collect();
+    let mut blocks = Vec::new();
+    for column in columns(words) {
+        render_column(&column, &mut blocks);
+    }
+    blocks.join("\n\n")
+}
+
+/// Glyphs joined into words in the order they are drawn
+fn words(glyphs: &[Glyph]) -> Vec<Word> {
+    let mut words: Vec<Word> = Vec::new();
+    let mut in_word = false;
+    for glyph in glyphs {
+        if glyph.text.trim().is_empty() {
+            in_word = false;
+            continue;
+        }
+        match words.last_mut() {
+            Some(word)
+                if in_word
+                    && (glyph.y - word.y).abs() < word.size * 0.5
+                    && glyph.x > word.x1 - word.size * 0.5
+                    && glyph.x - word.x1 < word.size * 0.15 =>
+            {
+                word.text.push_str(&glyph.text);
+                word.x1 = word.x1.max(glyph.x + glyph.width);
+                word.size = word.size.max(glyph.size);
+            }
+            _ => words.push(Word {
+                x0: glyph.x,
+                x1: glyph.x + glyph.width,
+                y: glyph.y,
+                size: glyph.size,
+                text: glyph.text.clone(),
+            }),
+        }
+        in_word = true;
+    }
+    words
+}
+
+/// Words grouped into lines from top to bottom, each left to right
+fn lines(mut words: Vec<Word>) -> Vec<Line> {
+    words.sort_by(|a, b| a.y.total_cmp(&b.y));
+    let mut lines: Vec<Line> = Vec::new();
+    for word in words {
+        match lines.last_mut() {
+            Some(line) if (word.y - line.y).abs() < line.size.max(word.size) * 0.4 => {
+                line.size = line.size.max(word.size);
+                line.words.push(word);
+            }
+            _ => lines.push(Line {
+                y: word.y,
+                size: word.size,
+                words: vec![word],
+            }),
+        }
+    }
+    for line in &mut lines {
+        line.words.sort_by(|a, b| a.x0.total_cmp(&b.x0));
+    }
+    lines
+}
+
+/// Lines of each column in reading order. Lines that span the columns,
+/// such as titles, split the page into bands whose columns are read in
+/// turn.
+fn columns(words: Vec<Word>) -> Vec<Vec<Line>> {
+    let lines = lines(words);
+    let Some(gutter) = gutter(&lines) else {
+        return vec![lines];
+    };
+
+    let mut columns = Vec::new();
+    let mut spanning: Vec<Line> = Vec::new();
+    let (mut left, mut right) = (Vec::new(), Vec::new());
+    for line in lines {
+        if line.words.iter().any(|word| word.x0 < gutter && word.x1 > gutter) {
+            columns.extend(columns_of(std::mem::take(&mut left)));
+            columns.extend(columns_of(std::mem::take(&mut right)));
+            spanning.push(line);
+            continue;
+        }
+        if !spanning.is_empty() {
+            columns.push(std::mem::take(&mut spanning));
+        }
+        for word in line.words {
+            if word.x1 <= gutter {
+                left.push(word);
+            } else {
+                right.push(word);
+            }
+        }
+    }
+    if !spanning.is_empty() {
+        columns.push(spanning);
+    }
+    columns.extend(columns_of(left));
+    columns.extend(columns_of(right));
+    columns
+}
+
+/// Columns of one side of a gutter, which may have gutters of its own
+fn columns_of(words: Vec<Word>) -> Vec<Vec<Line>> {
+    if words.is_empty() {
+        Vec::new()
+    } else {
+        columns(words)
+    }
+}
+
+/// Middle of the widest vertical strip that almost no line crosses, if the
+/// text on both sides of it reads as columns of prose rather than cells of
+/// a table
+fn gutter(lines: &[Line]) -> Option<f64> {
+    let words = || lines.iter().flat_map(|line| &line.words);
+    let left = words().map(|word| word.x0).min_by(f64::total_cmp)?;
+    let right = words().map(|word| word.x1).max_by(f64::total_cmp)?;
+    let mut sizes: Vec<f64> = words().map(|word| word.size).collect();
+    sizes.sort_by(f64::total_cmp);
+    let em = sizes[sizes.len() / 2];
+
+    let width = right - left;
+    if lines.len() < 6 || width < em * 10.0 {
+        return None;
+    }
+
+    // Widest strip in the middle of the page crossed by at most a tenth of
+    // the lines, or a title, scanned a point at a time
+    let crossing = |x: f64| {
+        lines
+            .iter()
+            .filter(|line| line.words.iter().any(|word| word.x0 < x && word.x1 > x))
+            .count()
+    };
+    let max_crossing = (lines.len() / 10).max(1);
+    let mut best: Option<(f64, f64)> = None;
+    let mut start = None;
+    let mut x = left + width * 0.2;
+    while x <= right - width * 0.2 {
+        match (crossing(x) <= max_crossing, start) {
+            (true, None) => start = Some(x),
+            (false, Some(from)) => {
+                if best.is_none_or(|(a, b)| x - from > b - a) {
+                    best = Some((from, x));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+        x += 1.0;
+    }
+    if let Some(from) = start {
+        if best.is_none_or(|(a, b)| x - from > b - a) {
+            best = Some((from, x));
+        }
+    }
+    let (from, to) = best.filter(|(from, to)| to - from >= em * MIN_GUTTER)?;
+    let gutter = (from + to) / 2.0;
+
+    // Lines of prose fill most of their column; cells of a table do not.
+    // Lines across the gutter belong to neither side.
+    let reads_as_prose = |side: Vec<(f64, f64)>| {
+        let Some(x0) = side.iter().map(|(x0, _)| *x0).min_by(f64::total_cmp) else {
+            return false;
+        };
+        let x1 = side.iter().map(|(_, x1)| *x1).max_by(f64::total_cmp).unwrap_or(x0);
+        let full = side
+            .iter()
+            .filter(|(a, b)| b - a >= (x1 - x0) * 0.75)
+            .count();
+        side.len() >= 3 && full * 2 >= side.len()
+    };
+    let side = |on_left: bool| {
+        lines
+            .iter()
+            .filter(|line| !line.words.iter().any(|word| word.x0 < gutter && word.x1 > gutter))
+            .filter_map(|line| {
+                let mut spans = line
+                    .words
+                    .iter()
+                    .filter(|word| (word.x1 <= gutter) == on_left)
+                    .map(|word| (word.x0, word.x1));
+                let first = spans.next()?;
+                Some(spans.fold(first, |(x0, _), (_, x1)| (x0, x1)))
+            })
+            .collect::<Vec<_>>()
+    };
+    (reads_as_prose(side(true)) && reads_as_prose(side(false))).then_some(gutter)
+}
+
+/// Append the paragraphs and tables of a column to `blocks`
+fn render_column(lines: &[Line], blocks: &mut Vec<String>) {
+    let mut paragraph = String::new();
+    let mut previous: Option<&Line> = None;
+    let mut i = 0;
+    while i < lines.len() {
+        if let Some((table, rows)) = table_at(lines, i) {
+            push_paragraph(&mut paragraph, blocks);
+            blocks.push(table);
+            previous = None;
+            i += rows;
+            continue;
+        }
+
+        // A wide gap or a change of font size starts a new paragraph
+        let line = &lines[i];
+        if let Some(previous) = previous {
+            if line.y - previous.y > previous.size * 1.8
+                || (line.size - previous.size).abs() > previous.size * 0.15
+            {
+                push_paragraph(&mut paragraph, blocks);
+            }
+        }
+
+        let text = line.text();
+        let hyphenated = paragraph.ends_with('-')
+            && paragraph[..paragraph.len() - 1].ends_with(char::is_alphabetic)
+            && text.starts_with(char::is_lowercase);
+        if hyphenated {
+            paragraph.pop();
+        } else if !paragraph.is_empty() {
+            paragraph.push(' ');
+        }
+        paragraph.push_str(&text);
+
+        previous = Some(line);
+        i += 1;
+    }
+    push_paragraph(&mut paragraph, blocks);
+}
+
+fn push_paragraph(paragraph: &mut String, blocks: &mut Vec<String>) {
+    if !paragraph.trim().is_empty() {
+        blocks.push(paragraph.trim().to_string());
+    }
+    paragraph.clear();
+}
+
+/// The table starting at `lines[start]` as markdown, with the number of
+/// lines it takes. Rows are consecutive lines split into cells that line up
+/// with the columns of the first row.
+fn table_at(lines: &[Line], start: usize) -> Option<(String, usize)> {
+    let first = lines[start].cells();
+    if first.len() < 2 {
+        return None;
+    }
+    let mut columns: Vec<(f64, f64)> = first.iter().map(|cell| (cell.x0, cell.x1)).collect();
+    let mut rows = vec![first.into_iter().map(|cell| cell.text).collect::<Vec<_>>()];
+
+    for pair in lines[start..].windows(2) {
+        let (previous, line) = (&pair[0], &pair[1]);
+        if line.y - previous.y > previous.size * 2.5 {
+            break;
+        }
+        let cells = line.cells();
+        if cells.len() < 2 || cells.len() > columns.len() {
+            break;
+        }
+
+        // Each cell goes under the column it overlaps most, left to right
+        let mut row = vec![String::new(); columns.len()];
+        let mut next_column = 0;
+        let mut placed = Vec::new();
+        for cell in &cells {
+            let overlap = |(x0, x1): (f64, f64)| cell.x1.min(x1) - cell.x0.max(x0);
+            let Some(column) = (next_column..columns.len())
+                .filter(|column| overlap(columns[*column]) > 0.0)
+                .max_by(|a, b| overlap(columns[*a]).total_cmp(&overlap(columns[*b])))
+            else {
+                break;
+            };
+            row[column] = cell.text.clone();
+            placed.push((column, cell.x0, cell.x1));
+            next_column = column + 1;
+        }
+        if placed.len() < cells.len() {
+            break;
+        }
+        for (column, x0, x1) in placed {
+            let span = &mut columns[column];
+            *span = (span.0.min(x0), span.1.max(x1));
+        }
+        rows.push(row);
+    }
+
+    // Two aligned columns are often a list with its markers, not a table
+    let is_list = columns.len() == 2
+        && rows.iter().all(|row| {
+            let marker = row[0].trim();
+            (marker.chars().count() <= 3 && !marker.chars().any(char::is_alphanumeric))
+                || (marker.ends_with(['.', ')']) && marker.chars().count() <= 4)
+        });
+    let enough_rows = if columns.len() == 2 { 3 } else { 2 };
+    if rows.len() < enough_rows || is_list {
+        return None;
+    }
+
+    let render = |cells: &[String]| {
+        let cells: Vec<String> = cells.iter().map(|cell| cell.replace('|', "\\|")).collect();
+        format!("| {} |", cells.join(" | "))
+    };
+    let mut table = vec![render(&rows[0]), render(&vec!["---".to_string(); columns.len()])];
+    table.extend(rows[1..].iter().map(|row| render(row)));
+    Some((table.join("\n"), rows.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Glyphs of `text` at (`x`, `y`), each half an em wide
+    fn glyphs(x: f64, y: f64, text: &str) -> Vec<Glyph> {
+        text.chars()
+            .enumerate()
+            .map(|(i, ch)| Glyph {
+                x: x + i as f64 * 5.0,
+                y,
+                width: 5.0,
+                size: 10.0,
+                text: ch.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_columns_are_read_one_after_another() {
+        let mut page = glyphs(100.0, 50.0, "Annual report on coastal erosion");
+        let left = [
+            "The coast lost ground",
+            "in every winter storm",
+            "and the dunes moved",
+            "inland by two metres",
+            "over the last decade",
+        ];
+        let right = [
+            "Sea walls slowed this",
+            "where they were built",
+            "but shifted the loss",
+            "to nearby beaches",
+            "further down the coast.",
+        ];
+        for (i, (left, right)) in left.iter().zip(right).enumerate() {
+            let y = 80.0 + i as f64 * 12.0;
+            page.extend(glyphs(50.0, y, left));
+            page.extend(glyphs(200.0, y, right));
+        }
+        page.extend(glyphs(150.0, 300.0, "7"));
+
+        assert_eq!(
+            page_text(&page),
+            "Annual report on coastal erosion\n\n\
+             The coast lost ground in every winter storm and the dunes moved inland by two metres over the last decade\n\n\
+             Sea walls slowed this where they were built but shifted the loss to nearby beaches further down the coast."
+        );
+    }
+
+    #[test]
+    fn test_aligned_cells_become_a_markdown_table() {
+        let mut page = glyphs(50.0, 50.0, "Tide heights at the harbour:");
+        for (i, (day, high, low)) in [("Day", "High", "Low"), ("Monday", "4.2 m", "0.8 m"), ("Tuesday", "4.4 m", "")]
+            .iter()
+            .enumerate()
+        {
+            let y = 70.0 + i as f64 * 12.0;
+            page.extend(glyphs(50.0, y, day));
+            page.extend(glyphs(150.0, y, high));
+            page.extend(glyphs(250.0, y, low));
+        }
+        page.extend(glyphs(50.0, 130.0, "Heights are above chart datum."));
+
+        assert_eq!(
+            page_text(&page),
+            "Tide heights at the harbour:\n\n\
+             | Day | High | Low |\n\
+             | --- | --- | --- |\n\
+             | Monday | 4.2 m | 0.8 m |\n\
+             | Tuesday | 4.4 m |  |\n\n\
+             Heights are above chart datum."
+        );
+    }
+
+    #[test]
+    fn test_list_markers_are_not_a_table() {
+        let mut page = Vec::new();
+        for (i, item) in ["Check the tide table", "Tell someone your route", "Carry a radio"]
+            .iter()
+            .enumerate()
+        {
+            let y = 50.0 + i as f64 * 12.0;
+            page.extend(glyphs(50.0, y, &format!("{}.", i + 1)));
+            page.extend(glyphs(80.0, y, item));
+        }
+
+        assert_eq!(
+            page_text(&page),
+            "1. Check the tide table 2. Tell someone your route 3. Carry a radio"
+        );
+    }
+}
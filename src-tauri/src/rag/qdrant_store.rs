@@ -1,13 +1,14 @@
 use crate::config::Config;
 use crate::database::SqliteDatabase;
 use crate::error::{AppError, Result};
-use crate::rag::{Document, DocumentChunk, SearchResult};
+use crate::rag::{model_slug, Document, DocumentChunk, SearchFilters, SearchResult};
 use qdrant_client::{
     qdrant::{
-        points_selector::PointsSelectorOneOf, vectors_config::Config as VectorConfig, Condition, CreateCollection,
-        Datatype, DeletePoints, Distance, FieldCondition, Filter, Match, PointStruct,
-        PointsSelector, ScrollPoints, SearchParams, SearchPoints, UpsertPoints, Value,
-        VectorParams, VectorsConfig,
+        condition::ConditionOneOf, points_selector::PointsSelectorOneOf, r#match::MatchValue,
+        value::Kind, vectors_config::Config as VectorConfig, Condition, CreateCollection,
+        Datatype, DeletePoints, Distance, FieldCondition, Filter, ListValue, Match, PointStruct,
+        PointsSelector, Range, RepeatedStrings, ScrollPoints, SearchParams, SearchPoints,
+        SetPayloadPoints, UpsertPoints, Value, VectorParams, VectorsConfig,
     },
     Qdrant,
 };
@@ -16,16 +17,17 @@ use std::sync::Arc;
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
-const EMBEDDING_SIZE: u64 = 768; // Adjust based on your embedding model
-
 pub struct QdrantVectorStore {
     client: Qdrant,
     database: Option<Arc<Mutex<SqliteDatabase>>>,
+    /// Collection for `embedding_model`, named after it and its dimension
     collection_name: String,
+    embedding_model: String,
+    dimension: usize,
 }
 
 impl QdrantVectorStore {
-    pub async fn new() -> Result<Self> {
+    pub async fn new(embedding_model: &str, dimension: usize) -> Result<Self> {
         let config = Config::get();
         
         // Try to connect to Qdrant instance
@@ -36,7 +38,9 @@ impl QdrantVectorStore {
         let store = Self {
             client,
             database: None,
-            collection_name: config.rag.collection_name.clone(),
+            collection_name: collection_name(&config.rag.collection_name, embedding_model, dimension),
+            embedding_model: embedding_model.to_string(),
+            dimension,
         };
 
         // Initialize collection
@@ -49,6 +53,10 @@ impl QdrantVectorStore {
         self.database = Some(database);
     }
 
+    pub fn database(&self) -> Option<Arc<Mutex<SqliteDatabase>>> {
+        self.database.clone()
+    }
+
     async fn initialize_collection(&self) -> Result<()> {
         // Check if collection exists
         let collections = self
@@ -66,7 +74,7 @@ impl QdrantVectorStore {
             // Create collection
             let config = VectorsConfig {
                 config: Some(VectorConfig::Params(VectorParams {
-                    size: EMBEDDING_SIZE,
+                    size: self.dimension as u64,
                     distance: Distance::Cosine as i32,
                     hnsw_config: None,
                     quantization_config: None,
@@ -97,7 +105,10 @@ impl QdrantVectorStore {
                 .await
                 .map_err(|e| AppError::VectorStore(format!("Failed to create collection: {e}")))?;
 
-            println!("Created Qdrant collection: {}", self.collection_name);
+            println!(
+                "Created Qdrant collection {} for {} ({} dimensions)",
+                self.collection_name, self.embedding_model, self.dimension
+            );
         } else {
             println!("Qdrant collection {} already exists", self.collection_name);
         }
@@ -105,15 +116,19 @@ impl QdrantVectorStore {
         Ok(())
     }
 
+    /// Upload stored chunks embedded by this collection's model. Chunks from
+    /// before embeddings were tagged are attributed to it when they have its
+    /// dimension; the rest wait for re-embedding.
     pub async fn load_from_database(&self) -> Result<()> {
         if let Some(db) = &self.database {
             let database = db.lock().await;
+            database.adopt_untagged_chunks(&self.embedding_model, self.dimension).await?;
             let documents = database.load_documents(None).await?;
 
             let mut points = Vec::new();
 
             for document in documents {
-                for chunk in &document.chunks {
+                for chunk in document.chunks.iter().filter(|chunk| chunk.embedding_model == self.embedding_model) {
                     let point = PointStruct {
                         id: Some(chunk.id.to_string().into()),
                         vectors: Some(chunk.embedding.clone().into()),
@@ -188,41 +203,10 @@ impl QdrantVectorStore {
     pub async fn search_similar(
         &self,
         query_embedding: &[f32],
-        goal_id: Option<Uuid>,
+        filters: &SearchFilters,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
-        let mut filter = None;
-
-        // Add goal filter if specified
-        if let Some(goal_id) = goal_id {
-            filter = Some(Filter {
-                should: vec![],
-                must: vec![Condition {
-                    condition_one_of: Some(
-                        qdrant_client::qdrant::condition::ConditionOneOf::Field(FieldCondition {
-                            key: "goal_id".to_string(),
-                            r#match: Some(Match {
-                                match_value: Some(
-                                    qdrant_client::qdrant::r#match::MatchValue::Keyword(
-                                        goal_id.to_string(),
-                                    ),
-                                ),
-                            }),
-                            range: None,
-                            geo_bounding_box: None,
-                            geo_radius: None,
-                            geo_polygon: None,
-                            values_count: None,
-                            is_empty: None,
-                            is_null: None,
-                            datetime_range: None,
-                        }),
-                    ),
-                }],
-                must_not: vec![],
-                min_should: None,
-            });
-        }
+        let filter = payload_filter(filters);
 
         let search_result = self
             .client
@@ -257,7 +241,7 @@ impl QdrantVectorStore {
                 document_id: Uuid::parse_str(
                     payload
                         .get("document_id")
-                        .map(|v| v.to_string())
+                        .map(payload_text)
                         .as_deref()
                         .unwrap_or(""),
                 )
@@ -279,7 +263,7 @@ impl QdrantVectorStore {
                 .unwrap_or_default(),
                 content: payload
                     .get("content")
-                    .map(|v| v.to_string())
+                    .map(payload_text)
                     .as_deref()
                     .unwrap_or("")
                     .to_string(),
@@ -292,6 +276,57 @@ impl QdrantVectorStore {
         Ok(results)
     }
 
+    /// Chunks whose text matches the words of `query`, from the database's
+    /// full-text index
+    pub async fn keyword_search(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>> {
+        match &self.database {
+            Some(db) => db.lock().await.keyword_search_chunks(query, filters, limit).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Copy the tags and collections of a document from the database to the
+    /// payload of its chunks after they were changed there
+    pub async fn refresh_document_labels(&self, document_id: Uuid) -> Result<()> {
+        let Some(db) = &self.database else {
+            return Ok(());
+        };
+        let (tags, collections) = db.lock().await.load_document_labels(document_id).await?;
+
+        let mut payload = HashMap::new();
+        payload.insert("tags".to_string(), list_value(tags));
+        payload.insert(
+            "collections".to_string(),
+            list_value(collections.iter().map(Uuid::to_string).collect()),
+        );
+        self.client
+            .set_payload(SetPayloadPoints {
+                collection_name: self.collection_name.clone(),
+                wait: Some(true),
+                payload,
+                points_selector: Some(PointsSelector {
+                    points_selector_one_of: Some(PointsSelectorOneOf::Filter(Filter {
+                        should: vec![],
+                        must: vec![keyword_condition("document_id", vec![document_id.to_string()])],
+                        must_not: vec![],
+                        min_should: None,
+                    })),
+                }),
+                ordering: None,
+                shard_key_selector: None,
+                key: None,
+            })
+            .await
+            .map_err(|e| AppError::VectorStore(format!("Failed to update labels in Qdrant: {e}")))?;
+
+        Ok(())
+    }
+
     pub async fn get_goal_documents(
         &self,
         goal_id: Uuid,
@@ -348,7 +383,7 @@ impl QdrantVectorStore {
                 document_id: Uuid::parse_str(
                     payload
                         .get("document_id")
-                        .map(|v| v.to_string())
+                        .map(payload_text)
                         .as_deref()
                         .unwrap_or(""),
                 )
@@ -370,7 +405,7 @@ impl QdrantVectorStore {
                 .unwrap_or_default(),
                 content: payload
                     .get("content")
-                    .map(|v| v.to_string())
+                    .map(payload_text)
                     .as_deref()
                     .unwrap_or("")
                     .to_string(),
@@ -460,6 +495,19 @@ impl QdrantVectorStore {
         }
     }
 
+    /// Documents previously indexed from `file_path` for `goal_id`
+    pub async fn find_documents_by_path(
+        &self,
+        file_path: &str,
+        goal_id: Option<Uuid>,
+    ) -> Result<Vec<Uuid>> {
+        match &self.database {
+            Some(db) => db.lock().await.find_documents_by_path(file_path, goal_id).await,
+            // Without the database nothing survives a restart to be replaced
+            None => Ok(Vec::new()),
+        }
+    }
+
     pub async fn get_document(&self, document_id: Uuid) -> Result<Option<Document>> {
         if let Some(db) = &self.database {
             let database = db.lock().await;
@@ -501,10 +549,18 @@ impl Clone for QdrantVectorStore {
             client,
             database: self.database.clone(),
             collection_name: self.collection_name.clone(),
+            embedding_model: self.embedding_model.clone(),
+            dimension: self.dimension,
         }
     }
 }
 
+/// Collection holding vectors from one embedding model, so switching models
+/// never mixes vectors of different models or sizes
+fn collection_name(base: &str, embedding_model: &str, dimension: usize) -> String {
+    format!("{base}-{}-{dimension}", model_slug(embedding_model))
+}
+
 fn create_chunk_payload(document: &Document, chunk: &DocumentChunk) -> HashMap<String, Value> {
     let mut payload = HashMap::new();
 
@@ -517,6 +573,10 @@ fn create_chunk_payload(document: &Document, chunk: &DocumentChunk) -> HashMap<S
         "chunk_index".to_string(),
         Value::from(chunk.chunk_index as i64),
     );
+    payload.insert(
+        "embedding_model".to_string(),
+        Value::from(chunk.embedding_model.clone()),
+    );
     payload.insert(
         "document_title".to_string(),
         Value::from(document.title.clone()),
@@ -530,6 +590,21 @@ fn create_chunk_payload(document: &Document, chunk: &DocumentChunk) -> HashMap<S
         payload.insert("goal_id".to_string(), Value::from(goal_id.to_string()));
     }
 
+    // Fields search filters match on; see `payload_filter`
+    payload.insert("tags".to_string(), list_value(document.tags.clone()));
+    payload.insert(
+        "collections".to_string(),
+        list_value(document.collections.iter().map(Uuid::to_string).collect()),
+    );
+    if let Some(author) = &document.author {
+        payload.insert("author".to_string(), Value::from(author.to_lowercase()));
+    }
+    payload.insert("file_type".to_string(), Value::from(document.file_type()));
+    payload.insert(
+        "document_date".to_string(),
+        Value::from(document.date().timestamp()),
+    );
+
     // Add chunk metadata
     for (key, value) in &chunk.metadata {
         payload.insert(format!("meta_{key}"), Value::from(value.clone()));
@@ -538,17 +613,137 @@ fn create_chunk_payload(document: &Document, chunk: &DocumentChunk) -> HashMap<S
     payload
 }
 
+/// Payload conditions for `filters`, the same ones `SearchFilters::matches`
+/// checks, or `None` when there are none
+fn payload_filter(filters: &SearchFilters) -> Option<Filter> {
+    let mut must = Vec::new();
+    if let Some(goal_id) = filters.goal_id {
+        must.push(keyword_condition("goal_id", vec![goal_id.to_string()]));
+    }
+    for tags in &filters.tags {
+        must.push(keyword_condition("tags", tags.clone()));
+    }
+    for collections in &filters.collections {
+        must.push(keyword_condition(
+            "collections",
+            collections.iter().map(Uuid::to_string).collect(),
+        ));
+    }
+    // Without a full-text index, text matches are substring matches
+    for author in &filters.authors {
+        must.push(field_condition(
+            "author",
+            Some(Match {
+                match_value: Some(MatchValue::Text(author.clone())),
+            }),
+            None,
+        ));
+    }
+    for file_types in &filters.file_types {
+        must.push(keyword_condition("file_type", file_types.clone()));
+    }
+    if filters.modified_after.is_some() || filters.modified_before.is_some() {
+        must.push(field_condition(
+            "document_date",
+            None,
+            Some(Range {
+                gte: filters.modified_after.map(|after| after.timestamp() as f64),
+                lt: filters.modified_before.map(|before| before.timestamp() as f64),
+                gt: None,
+                lte: None,
+            }),
+        ));
+    }
+
+    if let Some(content_type) = &filters.content_type {
+        must.push(keyword_condition("meta_content_type", vec![content_type.clone()]));
+    }
+
+    (!must.is_empty()).then_some(Filter {
+        should: vec![],
+        must,
+        must_not: vec![],
+        min_should: None,
+    })
+}
+
+/// Condition that `key` is one of `values`, or for a list contains one
+fn keyword_condition(key: &str, mut values: Vec<String>) -> Condition {
+    let match_value = if values.len() == 1 {
+        MatchValue::Keyword(values.remove(0))
+    } else {
+        MatchValue::Keywords(RepeatedStrings { strings: values })
+    };
+    field_condition(
+        key,
+        Some(Match {
+            match_value: Some(match_value),
+        }),
+        None,
+    )
+}
+
+fn field_condition(key: &str, r#match: Option<Match>, range: Option<Range>) -> Condition {
+    Condition {
+        condition_one_of: Some(ConditionOneOf::Field(FieldCondition {
+            key: key.to_string(),
+            r#match,
+            range,
+            geo_bounding_box: None,
+            geo_radius: None,
+            geo_polygon: None,
+            values_count: None,
+            is_empty: None,
+            is_null: None,
+            datetime_range: None,
+        })),
+    }
+}
+
+fn list_value(values: Vec<String>) -> Value {
+    Value {
+        kind: Some(Kind::ListValue(ListValue {
+            values: values.into_iter().map(Value::from).collect(),
+        })),
+    }
+}
+
 fn extract_metadata_from_payload(payload: &HashMap<String, Value>) -> HashMap<String, String> {
     let mut metadata = HashMap::new();
 
     for (key, value) in payload {
         if key.starts_with("meta_") {
             let meta_key = key.strip_prefix("meta_").unwrap_or(key);
-            metadata.insert(meta_key.to_string(), value.to_string());
-        } else if key == "chunk_index" {
-            metadata.insert(key.clone(), value.to_string());
+            metadata.insert(meta_key.to_string(), payload_text(value));
+        } else if matches!(key.as_str(), "chunk_index" | "file_path" | "document_title") {
+            metadata.insert(key.clone(), payload_text(value));
         }
     }
 
     metadata
 }
+
+/// A payload value as plain text; `Value`'s `Display` quotes strings
+fn payload_text(value: &Value) -> String {
+    match &value.kind {
+        Some(Kind::StringValue(text)) => text.clone(),
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_collection_name_is_tagged_with_model_and_dimension() {
+        assert_eq!(
+            collection_name("documents", "nomic-embed-text:latest", 768),
+            "documents-nomic-embed-text-latest-768"
+        );
+        assert_eq!(
+            collection_name("documents", "BAAI/bge-small-en-v1.5", 384),
+            "documents-baai-bge-small-en-v1-5-384"
+        );
+    }
+}
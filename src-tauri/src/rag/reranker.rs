@@ -0,0 +1,265 @@
+use crate::config::{Config, RerankProvider};
+use crate::error::{AppError, Result};
+use crate::llm::bert_metal_backend::BertCrossEncoder;
+use crate::llm::structured::{self, StructuredOutput};
+use crate::llm::LlmClient;
+use async_trait::async_trait;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+use super::SearchResult;
+
+/// Characters of each passage shown to the LLM when it rates relevance
+const LLM_PASSAGE_CHARS: usize = 1200;
+
+/// Scores passages for relevance to a query between 0 and 1
+#[async_trait]
+trait RelevanceScorer: Send + Sync {
+    async fn score(&self, query: &str, passages: &[String]) -> Result<Vec<f32>>;
+}
+
+/// Local cross-encoder, downloaded and loaded the first time it is needed
+struct CrossEncoderScorer {
+    model_id: String,
+    encoder: OnceCell<Arc<BertCrossEncoder>>,
+}
+
+#[async_trait]
+impl RelevanceScorer for CrossEncoderScorer {
+    async fn score(&self, query: &str, passages: &[String]) -> Result<Vec<f32>> {
+        let encoder = self
+            .encoder
+            .get_or_try_init(|| async {
+                BertCrossEncoder::new(&self.model_id).await.map(Arc::new)
+            })
+            .await?
+            .clone();
+        let query = query.to_string();
+        let passages = passages.to_vec();
+        tokio::task::spawn_blocking(move || encoder.score(&query, &passages))
+            .await
+            .map_err(|e| AppError::Llm(format!("Reranking task failed: {e}")))?
+    }
+}
+
+/// Asks the default chat model to rate every passage in one request
+struct LlmScorer {
+    llm: Arc<LlmClient>,
+}
+
+#[derive(serde::Deserialize)]
+struct RelevanceReply {
+    scores: Vec<f32>,
+}
+
+impl StructuredOutput for RelevanceReply {
+    fn schema_name() -> &'static str {
+        "passage_relevance"
+    }
+
+    fn json_schema() -> serde_json::Value {
+        structured::object_schema(&[(
+            "scores",
+            serde_json::json!({
+                "type": "array",
+                "items": { "type": "number", "minimum": 0, "maximum": 10 }
+            }),
+        )])
+    }
+}
+
+#[async_trait]
+impl RelevanceScorer for LlmScorer {
+    async fn score(&self, query: &str, passages: &[String]) -> Result<Vec<f32>> {
+        let mut prompt = format!(
+            "Rate how relevant each numbered passage is to the question, from 0 (unrelated) \
+            to 10 (answers it directly). Give one score per passage, in order.\n\n\
+            Question: {query}\n"
+        );
+        for (i, passage) in passages.iter().enumerate() {
+            let excerpt: String = passage.chars().take(LLM_PASSAGE_CHARS).collect();
+            prompt.push_str(&format!("\n[{}] {}\n", i + 1, excerpt));
+        }
+
+        let reply: RelevanceReply = self.llm.generate_structured(&prompt).await?;
+        if reply.scores.len() != passages.len() {
+            return Err(AppError::Llm(format!(
+                "Expected {} relevance scores, got {}",
+                passages.len(),
+                reply.scores.len()
+            )));
+        }
+        Ok(reply
+            .scores
+            .into_iter()
+            .map(|score| (score / 10.0).clamp(0.0, 1.0))
+            .collect())
+    }
+}
+
+/// A search result and the score reranking gave it, if it was reranked
+#[derive(Debug, Clone)]
+pub struct RankedResult {
+    pub result: SearchResult,
+    pub rerank_score: Option<f32>,
+}
+
+/// Optional second search stage that re-scores the top candidates with a
+/// model reading the query and passage together, keeping the best ones
+pub struct Reranker {
+    scorer: Option<Arc<dyn RelevanceScorer>>,
+    candidates: usize,
+}
+
+impl Reranker {
+    pub fn new(llm: Arc<LlmClient>) -> Self {
+        let config = Config::get();
+        let scorer: Option<Arc<dyn RelevanceScorer>> = match config.rag.rerank_provider {
+            RerankProvider::None => None,
+            RerankProvider::CrossEncoder => Some(Arc::new(CrossEncoderScorer {
+                model_id: config.rag.rerank_model.clone(),
+                encoder: OnceCell::new(),
+            })),
+            RerankProvider::Llm => Some(Arc::new(LlmScorer { llm })),
+        };
+
+        println!(
+            "[Reranker] Using {:?} reranking of {} candidates",
+            config.rag.rerank_provider, config.rag.rerank_candidates
+        );
+        Self::with_scorer(scorer, config.rag.rerank_candidates)
+    }
+
+    fn with_scorer(scorer: Option<Arc<dyn RelevanceScorer>>, candidates: usize) -> Self {
+        Self { scorer, candidates }
+    }
+
+    /// Results to ask the search for so that `limit` remain after reranking
+    pub fn candidate_count(&self, limit: usize) -> usize {
+        match self.scorer {
+            Some(_) => self.candidates.max(limit),
+            None => limit,
+        }
+    }
+
+    /// The `limit` results most relevant to `query`. If scoring fails the
+    /// search order is kept, since reranking only refines it.
+    pub async fn rerank(
+        &self,
+        query: &str,
+        results: Vec<SearchResult>,
+        limit: usize,
+    ) -> Vec<RankedResult> {
+        let scores = match &self.scorer {
+            Some(scorer) if !results.is_empty() => {
+                let passages: Vec<String> = results.iter().map(|r| r.content.clone()).collect();
+                match scorer.score(query, &passages).await {
+                    Ok(scores) => Some(scores),
+                    Err(e) => {
+                        eprintln!("[Reranker] Reranking failed, keeping search order: {e}");
+                        None
+                    }
+                }
+            }
+            _ => None,
+        };
+
+        let mut ranked: Vec<RankedResult> = match scores {
+            Some(scores) => results
+                .into_iter()
+                .zip(scores)
+                .map(|(result, score)| RankedResult {
+                    result,
+                    rerank_score: Some(score),
+                })
+                .collect(),
+            None => results
+                .into_iter()
+                .map(|result| RankedResult {
+                    result,
+                    rerank_score: None,
+                })
+                .collect(),
+        };
+
+        // Stable, so equally rated results keep their search order
+        ranked.sort_by(|a, b| {
+            b.rerank_score
+                .unwrap_or(0.0)
+                .total_cmp(&a.rerank_score.unwrap_or(0.0))
+        });
+        ranked.truncate(limit);
+        ranked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    /// Rates passages by how often they mention the query
+    struct MentionScorer;
+
+    #[async_trait]
+    impl RelevanceScorer for MentionScorer {
+        async fn score(&self, query: &str, passages: &[String]) -> Result<Vec<f32>> {
+            Ok(passages
+                .iter()
+                .map(|passage| passage.matches(query).count() as f32 / 10.0)
+                .collect())
+        }
+    }
+
+    struct FailingScorer;
+
+    #[async_trait]
+    impl RelevanceScorer for FailingScorer {
+        async fn score(&self, _query: &str, _passages: &[String]) -> Result<Vec<f32>> {
+            Err(AppError::Llm("model unavailable".to_string()))
+        }
+    }
+
+    fn results(contents: &[&str]) -> Vec<SearchResult> {
+        contents
+            .iter()
+            .enumerate()
+            .map(|(i, content)| SearchResult {
+                document_id: Uuid::nil(),
+                chunk_id: Uuid::from_u128(i as u128),
+                content: content.to_string(),
+                score: 1.0 - i as f32 / 10.0,
+                metadata: HashMap::new(),
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_rerank_keeps_the_best_scored_results() {
+        let reranker = Reranker::with_scorer(Some(Arc::new(MentionScorer)), 10);
+        assert_eq!(reranker.candidate_count(3), 10);
+
+        let ranked = reranker
+            .rerank("tax", results(&["weather", "tax tax", "tax", "tax tax tax"]), 2)
+            .await;
+        let contents: Vec<&str> = ranked.iter().map(|r| r.result.content.as_str()).collect();
+
+        assert_eq!(contents, vec!["tax tax tax", "tax tax"]);
+        assert_eq!(ranked[0].rerank_score, Some(0.3));
+        assert!((ranked[0].result.score - 0.7).abs() < 1e-6);
+    }
+
+    #[tokio::test]
+    async fn test_rerank_falls_back_to_search_order() {
+        let reranker = Reranker::with_scorer(Some(Arc::new(FailingScorer)), 10);
+        let ranked = reranker.rerank("tax", results(&["a", "b", "c"]), 2).await;
+        let contents: Vec<&str> = ranked.iter().map(|r| r.result.content.as_str()).collect();
+
+        assert_eq!(contents, vec!["a", "b"]);
+        assert!(ranked.iter().all(|r| r.rerank_score.is_none()));
+
+        let disabled = Reranker::with_scorer(None, 10);
+        assert_eq!(disabled.candidate_count(3), 3);
+    }
+}
@@ -1,5 +1,7 @@
 use crate::error::{AppError, Result};
-use crate::rag::{SearchResult, VectorStore};
+use crate::rag::{normalize_tags, Collection, Document, SearchResult, VectorStore};
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use std::collections::HashMap;
 use uuid::Uuid;
 
 pub struct DocumentRetriever {
@@ -14,60 +16,33 @@ impl DocumentRetriever {
     pub async fn search(
         &self,
         query_embedding: &[f32],
-        goal_id: Option<Uuid>,
+        filters: &SearchFilters,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
         self.vector_store
-            .search_similar(query_embedding, goal_id, limit)
+            .search_similar(query_embedding, filters, limit)
             .await
     }
 
-    pub async fn get_goal_documents(
+    /// Vector search over the documents that meet `filters`, without the
+    /// results scoring below `filters.min_score`
+    pub async fn search_with_filters(
         &self,
-        goal_id: Uuid,
+        query_embedding: &[f32],
+        filters: SearchFilters,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
-        self.vector_store.get_goal_documents(goal_id, limit).await
+        let mut results = self.search(query_embedding, &filters, limit).await?;
+        filters.drop_below_min_score(&mut results);
+        Ok(results)
     }
 
-    pub async fn search_with_filters(
+    pub async fn get_goal_documents(
         &self,
-        query_embedding: &[f32],
-        filters: SearchFilters,
+        goal_id: Uuid,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
-        let mut results = self
-            .vector_store
-            .search_similar(query_embedding, filters.goal_id, limit * 2)
-            .await?;
-
-        // Apply additional filters
-        if let Some(content_type) = &filters.content_type {
-            results.retain(|result| {
-                result
-                    .metadata
-                    .get("content_type")
-                    .map(|ct| ct == content_type)
-                    .unwrap_or(false)
-            });
-        }
-
-        if let Some(min_score) = filters.min_score {
-            results.retain(|result| result.score >= min_score);
-        }
-
-        if let Some(file_types) = &filters.file_types {
-            results.retain(|result| {
-                result
-                    .metadata
-                    .get("file_type")
-                    .map(|ft| file_types.contains(ft))
-                    .unwrap_or(false)
-            });
-        }
-
-        results.truncate(limit);
-        Ok(results)
+        self.vector_store.get_goal_documents(goal_id, limit).await
     }
 
     pub async fn get_document_context(
@@ -105,114 +80,378 @@ impl DocumentRetriever {
         Ok(results)
     }
 
+    /// Vector and keyword results for `query`, merged by reciprocal-rank
+    /// fusion
     pub async fn hybrid_search(
         &self,
         query_embedding: &[f32],
-        keywords: &[String],
-        goal_id: Option<Uuid>,
+        query: &str,
+        filters: &SearchFilters,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
-        // Vector search
-        let vector_results = self
-            .vector_store
-            .search_similar(query_embedding, goal_id, limit)
-            .await?;
+        let candidates = limit * HYBRID_CANDIDATES;
+        let (vector_results, keyword_results) = tokio::try_join!(
+            self.vector_store.search_similar(query_embedding, filters, candidates),
+            self.keyword_search(query, filters, candidates),
+        )?;
 
-        // Keyword search
-        let keyword_results = self.keyword_search(keywords, goal_id, limit).await?;
-
-        // Combine and re-rank results
-        let combined_results = self.combine_search_results(vector_results, keyword_results, limit);
-
-        Ok(combined_results)
+        Ok(reciprocal_rank_fusion(vec![vector_results, keyword_results], limit))
     }
 
-    async fn keyword_search(
+    /// BM25-ranked chunks containing the words of `query`
+    pub async fn keyword_search(
         &self,
-        keywords: &[String],
-        goal_id: Option<Uuid>,
+        query: &str,
+        filters: &SearchFilters,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
-        let documents = self.vector_store.list_documents(goal_id).await?;
-        let mut results = Vec::new();
+        self.vector_store.keyword_search(query, filters, limit).await
+    }
+}
 
-        for document in documents {
-            let chunks = self.vector_store.get_document_chunks(document.id).await?;
+/// Results taken from each ranking for every hybrid result asked for
+pub const HYBRID_CANDIDATES: usize = 3;
 
-            for chunk in chunks {
-                let mut score = 0.0;
-                let content_lower = chunk.content.to_lowercase();
+/// Damps the advantage of the very top ranks, as in the original RRF paper
+const RRF_K: f32 = 60.0;
 
-                for keyword in keywords {
-                    let keyword_lower = keyword.to_lowercase();
-                    let matches = content_lower.matches(&keyword_lower).count();
-                    score += matches as f32 * 0.1; // Simple scoring
-                }
+/// Merge rankings by reciprocal-rank fusion: each result scores
+/// `1 / (RRF_K + rank)` in every ranking it appears in. Scores are scaled so
+/// a result ranked first everywhere scores 1.0.
+pub fn reciprocal_rank_fusion(rankings: Vec<Vec<SearchResult>>, limit: usize) -> Vec<SearchResult> {
+    let best_possible = rankings.len() as f32 / (RRF_K + 1.0);
+    let mut fused: HashMap<Uuid, SearchResult> = HashMap::new();
+
+    for ranking in rankings {
+        for (rank, result) in ranking.into_iter().enumerate() {
+            let score = 1.0 / (RRF_K + rank as f32 + 1.0) / best_possible;
+            fused
+                .entry(result.chunk_id)
+                .and_modify(|existing| existing.score += score)
+                .or_insert(SearchResult { score, ..result });
+        }
+    }
+
+    let mut results: Vec<SearchResult> = fused.into_values().collect();
+    results.sort_by(|a, b| b.score.total_cmp(&a.score).then(a.chunk_id.cmp(&b.chunk_id)));
+    results.truncate(limit);
+    results
+}
+
+/// Conditions on the documents a search looks in. They are handed to the
+/// vector store and the keyword index rather than applied to results, so a
+/// narrow filter still fills the result list.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchFilters {
+    pub goal_id: Option<Uuid>,
+    /// The document must have one of the tags of each entry
+    pub tags: Vec<Vec<String>>,
+    /// The document must be in one of the collections of each entry
+    pub collections: Vec<Vec<Uuid>>,
+    /// Lowercase text the document's author must contain, for each entry
+    pub authors: Vec<String>,
+    /// The document must have one of the file types of each entry, as given
+    /// by `Document::file_type`
+    pub file_types: Vec<Vec<String>>,
+    /// Earliest document date, inclusive
+    pub modified_after: Option<DateTime<Utc>>,
+    /// Latest document date, exclusive
+    pub modified_before: Option<DateTime<Utc>>,
+    /// Kind of text the chunk must hold, one of `CONTENT_TYPES`, as the
+    /// chunker records it in the chunk's `content_type` metadata
+    pub content_type: Option<String>,
+    /// Lowest score a result may have. Scores only exist once results are
+    /// ranked, so this is applied to the ranking rather than handed down.
+    pub min_score: Option<f32>,
+}
+
+impl SearchFilters {
+    /// No conditions but the goal
+    pub fn for_goal(goal_id: Option<Uuid>) -> Self {
+        Self {
+            goal_id,
+            ..Self::default()
+        }
+    }
+
+    /// Parse a filter such as
+    /// `tag:budget,planning collection:"Board packs" author:kim type:pdf after:2024-01-01`.
+    /// Terms must all hold, while the comma-separated values of a term are
+    /// alternatives. Collections are named as in `collections`. `type:` also
+    /// takes a kind of content such as `type:code`, and `min_score:0.5` drops
+    /// results scoring lower.
+    pub fn parse(filter: &str, goal_id: Option<Uuid>, collections: &[Collection]) -> Result<Self> {
+        let mut filters = Self::for_goal(goal_id);
 
-                if score > 0.0 {
-                    let result = SearchResult {
-                        document_id: chunk.document_id,
-                        chunk_id: chunk.id,
-                        content: chunk.content.clone(),
-                        score,
-                        metadata: chunk.metadata.clone(),
+        for term in split_outside_quotes(filter, char::is_whitespace, true) {
+            let Some((key, value)) = term.split_once(':') else {
+                return Err(AppError::InvalidInput(format!(
+                    "Filter term \"{term}\" should look like key:value, with key one of {}",
+                    FILTER_KEYS.join(", ")
+                )));
+            };
+            let values = split_outside_quotes(value, |ch| ch == ',', false);
+            if values.is_empty() {
+                return Err(AppError::InvalidInput(format!("Filter term \"{term}\" has no value")));
+            }
+
+            match key.to_lowercase().as_str() {
+                "tag" => filters.tags.push(normalize_tags(&values)),
+                "collection" => {
+                    let ids = values
+                        .iter()
+                        .map(|name| {
+                            collections
+                                .iter()
+                                .find(|collection| collection.name.eq_ignore_ascii_case(name.trim()))
+                                .map(|collection| collection.id)
+                                .ok_or_else(|| {
+                                    AppError::InvalidInput(format!("No collection named \"{name}\""))
+                                })
+                        })
+                        .collect::<Result<Vec<Uuid>>>()?;
+                    filters.collections.push(ids);
+                }
+                "author" => filters.authors.extend(values.iter().map(|author| author.to_lowercase())),
+                "type" => {
+                    let types: Vec<String> = values
+                        .iter()
+                        .map(|file_type| file_type.trim_start_matches('.').to_lowercase())
+                        .collect();
+                    // Kinds of content rather than file types, such as `type:code`
+                    if types.iter().any(|kind| CONTENT_TYPES.contains(&kind.as_str())) {
+                        let [content_type] = types.as_slice() else {
+                            return Err(AppError::InvalidInput(format!(
+                                "Filter term \"{term}\" can name one of {} and no other type",
+                                CONTENT_TYPES.join(", ")
+                            )));
+                        };
+                        filters.content_type = Some(content_type.clone());
+                        continue;
+                    }
+                    if let Some(invalid) = types
+                        .iter()
+                        .find(|file_type| !file_type.chars().all(|ch| ch.is_ascii_alphanumeric()))
+                    {
+                        return Err(AppError::InvalidInput(format!("\"{invalid}\" is not a file type")));
+                    }
+                    filters.file_types.push(types);
+                }
+                "after" => {
+                    let after = parse_date(&values)?;
+                    filters.modified_after = filters.modified_after.max(Some(after));
+                }
+                "before" => {
+                    let before = parse_date(&values)?;
+                    filters.modified_before = Some(filters.modified_before.map_or(before, |current| current.min(before)));
+                }
+                "min_score" => {
+                    let min_score = match values.as_slice() {
+                        [value] => value.parse::<f32>().ok().filter(|score| (0.0..=1.0).contains(score)),
+                        _ => None,
                     };
-                    results.push(result);
+                    let Some(min_score) = min_score else {
+                        return Err(AppError::InvalidInput(format!(
+                            "Filter term \"{term}\" should give a single score from 0 to 1"
+                        )));
+                    };
+                    filters.min_score = Some(filters.min_score.map_or(min_score, |current| current.max(min_score)));
+                }
+                _ => {
+                    return Err(AppError::InvalidInput(format!(
+                        "Unknown filter \"{key}\", expected one of {}",
+                        FILTER_KEYS.join(", ")
+                    )))
                 }
             }
         }
 
-        // Sort by score
-        results.sort_by(|a, b| {
-            b.score
-                .partial_cmp(&a.score)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
-        results.truncate(limit);
-
-        Ok(results)
+        Ok(filters)
     }
 
-    fn combine_search_results(
-        &self,
-        vector_results: Vec<SearchResult>,
-        keyword_results: Vec<SearchResult>,
-        limit: usize,
-    ) -> Vec<SearchResult> {
-        let mut combined = std::collections::HashMap::new();
+    /// Whether a chunk of `document` with `chunk_metadata` meets every
+    /// condition but `min_score`
+    pub fn matches(&self, document: &Document, chunk_metadata: &HashMap<String, String>) -> bool {
+        let author = document.author.as_deref().unwrap_or_default().to_lowercase();
+        let file_type = document.file_type();
+        let date = document.date();
+
+        self.goal_id.is_none_or(|goal_id| document.goal_id == Some(goal_id))
+            && self
+                .tags
+                .iter()
+                .all(|tags| tags.iter().any(|tag| document.tags.contains(tag)))
+            && self
+                .collections
+                .iter()
+                .all(|ids| ids.iter().any(|id| document.collections.contains(id)))
+            && self.authors.iter().all(|name| author.contains(name.as_str()))
+            && self.file_types.iter().all(|types| types.contains(&file_type))
+            && self.modified_after.is_none_or(|after| date >= after)
+            && self.modified_before.is_none_or(|before| date < before)
+            && self
+                .content_type
+                .as_ref()
+                .is_none_or(|kind| chunk_metadata.get("content_type") == Some(kind))
+    }
 
-        // Add vector results
-        for result in vector_results {
-            combined.insert(result.chunk_id, result);
+    /// Remove ranked results scoring below `min_score`
+    pub fn drop_below_min_score(&self, results: &mut Vec<SearchResult>) {
+        if let Some(min_score) = self.min_score {
+            results.retain(|result| result.score >= min_score);
         }
+    }
+}
+
+/// Kinds of chunk content a `type:` filter term can name
+pub const CONTENT_TYPES: &[&str] = &["code", "markdown", "text"];
+
+/// Keys of the filter terms `SearchFilters::parse` understands
+const FILTER_KEYS: &[&str] = &["tag", "collection", "author", "type", "after", "before", "min_score"];
 
-        // Add keyword results, combining scores if chunk already exists
-        for result in keyword_results {
-            if let Some(existing) = combined.get_mut(&result.chunk_id) {
-                existing.score = (existing.score + result.score) / 2.0; // Average the scores
-            } else {
-                combined.insert(result.chunk_id, result);
+/// Pieces of `text` between separators that are not in double quotes,
+/// trimmed and without empty pieces. The quotes are kept if `keep_quotes`.
+fn split_outside_quotes(text: &str, is_separator: impl Fn(char) -> bool, keep_quotes: bool) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut piece = String::new();
+    let mut quoted = false;
+    for ch in text.chars() {
+        if ch == '"' {
+            quoted = !quoted;
+            if keep_quotes {
+                piece.push(ch);
             }
+        } else if !quoted && is_separator(ch) {
+            pieces.push(std::mem::take(&mut piece));
+        } else {
+            piece.push(ch);
         }
+    }
+    pieces.push(piece);
+    pieces
+        .into_iter()
+        .map(|piece| piece.trim().to_string())
+        .filter(|piece| !piece.is_empty())
+        .collect()
+}
 
-        // Convert to vector and sort
-        let mut results: Vec<SearchResult> = combined.into_values().collect();
-        results.sort_by(|a, b| {
-            b.score
-                .partial_cmp(&a.score)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
-        results.truncate(limit);
+/// Start of the day written as YYYY-MM-DD, in UTC
+fn parse_date(values: &[String]) -> Result<DateTime<Utc>> {
+    let [value] = values else {
+        return Err(AppError::InvalidInput("Date filters take a single date".to_string()));
+    };
+    NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map(|date| date.and_time(NaiveTime::MIN).and_utc())
+        .map_err(|_| AppError::InvalidInput(format!("\"{value}\" is not a date like 2024-01-31")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        results
+    fn ranking(ids: &[u128]) -> Vec<SearchResult> {
+        ids.iter()
+            .map(|&id| SearchResult {
+                document_id: Uuid::nil(),
+                chunk_id: Uuid::from_u128(id),
+                content: id.to_string(),
+                score: 0.5,
+                metadata: HashMap::new(),
+            })
+            .collect()
     }
-}
 
-#[derive(Debug, Clone)]
-pub struct SearchFilters {
-    pub goal_id: Option<Uuid>,
-    pub content_type: Option<String>,
-    pub file_types: Option<Vec<String>>,
-    pub min_score: Option<f32>,
-    pub date_range: Option<(chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>)>,
+    #[test]
+    fn test_reciprocal_rank_fusion_favours_results_in_both_rankings() {
+        let fused = reciprocal_rank_fusion(vec![ranking(&[1, 2, 3]), ranking(&[3, 4, 1])], 3);
+        let ids: Vec<u128> = fused.iter().map(|result| result.chunk_id.as_u128()).collect();
+
+        assert_eq!(ids, vec![1, 3, 2]);
+        assert!(fused[0].score < 1.0 && fused[0].score > 0.95);
+
+        let single = reciprocal_rank_fusion(vec![ranking(&[7])], 5);
+        assert_eq!(single[0].score, 1.0);
+    }
+
+    #[test]
+    fn test_search_filters_parse_and_match_documents() {
+        let board_packs = Collection {
+            id: Uuid::from_u128(1),
+            name: "Board packs".to_string(),
+            created_at: Utc::now(),
+        };
+        let filters = SearchFilters::parse(
+            r#"tag:Budget,planning collection:"board packs" author:Kim type:.PDF after:2024-01-01 before:2024-07-01"#,
+            None,
+            std::slice::from_ref(&board_packs),
+        )
+        .unwrap();
+        assert_eq!(filters.tags, vec![vec!["budget".to_string(), "planning".to_string()]]);
+        assert_eq!(filters.collections, vec![vec![board_packs.id]]);
+        assert_eq!(filters.authors, vec!["kim".to_string()]);
+        assert_eq!(filters.file_types, vec![vec!["pdf".to_string()]]);
+
+        let mut document = Document {
+            id: Uuid::new_v4(),
+            title: "Q2 budget".to_string(),
+            content: String::new(),
+            file_path: "/docs/q2-budget.pdf".to_string(),
+            goal_id: None,
+            chunks: Vec::new(),
+            created_at: Utc::now(),
+            content_hash: String::new(),
+            pages_without_text: Vec::new(),
+            author: Some("Kim Lee".to_string()),
+            modified_at: "2024-03-15T09:00:00Z".parse().ok(),
+            tags: vec!["budget".to_string()],
+            collections: vec![board_packs.id],
+        };
+        assert!(filters.matches(&document, &HashMap::new()));
+
+        document.modified_at = "2024-07-01T00:00:00Z".parse().ok();
+        assert!(!filters.matches(&document, &HashMap::new()));
+
+        assert!(SearchFilters::parse("collection:Unknown", None, &[board_packs]).is_err());
+        assert!(SearchFilters::parse("budget", None, &[]).is_err());
+        assert!(SearchFilters::parse("after:yesterday", None, &[]).is_err());
+    }
+
+    #[test]
+    fn test_search_filters_parse_content_type_and_min_score() {
+        let filters = SearchFilters::parse("type:Code min_score:0.4", None, &[]).unwrap();
+        assert_eq!(filters.content_type.as_deref(), Some("code"));
+        assert!(filters.file_types.is_empty());
+        assert_eq!(filters.min_score, Some(0.4));
+
+        let document = Document {
+            id: Uuid::new_v4(),
+            title: "main".to_string(),
+            content: String::new(),
+            file_path: "/src/main.rs".to_string(),
+            goal_id: None,
+            chunks: Vec::new(),
+            created_at: Utc::now(),
+            content_hash: String::new(),
+            pages_without_text: Vec::new(),
+            author: None,
+            modified_at: None,
+            tags: Vec::new(),
+            collections: Vec::new(),
+        };
+        let code = HashMap::from([("content_type".to_string(), "code".to_string())]);
+        let text = HashMap::from([("content_type".to_string(), "text".to_string())]);
+        assert!(filters.matches(&document, &code));
+        assert!(!filters.matches(&document, &text));
+
+        let mut results = ranking(&[1, 2]);
+        results[0].score = 0.9;
+        results[1].score = 0.1;
+        filters.drop_below_min_score(&mut results);
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].chunk_id, Uuid::from_u128(1));
+
+        assert!(SearchFilters::parse("type:code,pdf", None, &[]).is_err());
+        assert!(SearchFilters::parse("min_score:2", None, &[]).is_err());
+        assert!(SearchFilters::parse("min_score:high", None, &[]).is_err());
+    }
 }
@@ -1,63 +1,143 @@
 use crate::database::SqliteDatabase;
-use crate::error::Result;
-use crate::rag::{Document, DocumentChunk, SearchResult};
-use std::collections::HashMap;
+use crate::error::{AppError, Result};
+use crate::rag::{model_slug, Document, DocumentChunk, SearchFilters, SearchResult};
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::sync::{Mutex, RwLock};
 use uuid::Uuid;
 
+use super::ann_index::HnswIndex;
+
+/// How long changes collect before the search index is saved
+const INDEX_SAVE_DELAY: Duration = Duration::from_secs(5);
+
 // Hybrid vector store with in-memory cache and database persistence
 pub struct VectorStore {
     documents: Arc<RwLock<HashMap<Uuid, Document>>>,
+    /// Cached chunks without their embeddings, which live in `index`
     chunks: Arc<RwLock<HashMap<Uuid, DocumentChunk>>>,
     goal_index: Arc<RwLock<HashMap<Uuid, Vec<Uuid>>>>, // goal_id -> document_ids
     database: Option<Arc<Mutex<SqliteDatabase>>>,
+    /// Only chunks embedded by this model are searched; others wait for
+    /// re-embedding
+    embedding_model: String,
+    /// Approximate nearest-neighbour index over the searchable chunks
+    index: Arc<RwLock<HnswIndex>>,
+    /// Where the index is saved, next to the database. The database stays
+    /// the source of truth: the saved index is checked against it on load.
+    index_path: Option<PathBuf>,
+    /// Set while a save of the index is pending
+    index_dirty: Arc<AtomicBool>,
+    index_writer: Arc<Mutex<()>>,
 }
 
 impl VectorStore {
-    pub async fn new() -> Result<Self> {
+    pub async fn new(embedding_model: &str) -> Result<Self> {
         Ok(Self {
             documents: Arc::new(RwLock::new(HashMap::new())),
             chunks: Arc::new(RwLock::new(HashMap::new())),
             goal_index: Arc::new(RwLock::new(HashMap::new())),
             database: None,
+            embedding_model: embedding_model.to_string(),
+            index: Arc::new(RwLock::new(HnswIndex::new())),
+            index_path: None,
+            index_dirty: Arc::new(AtomicBool::new(false)),
+            index_writer: Arc::new(Mutex::new(())),
         })
     }
 
     pub fn set_database(&mut self, database: Arc<Mutex<SqliteDatabase>>) {
         self.database = Some(database);
+        let file_name = format!("vectors-{}.hnsw", model_slug(&self.embedding_model));
+        self.index_path = SqliteDatabase::data_file_path(&file_name).ok();
     }
 
-    pub async fn load_from_database(&self) -> Result<()> {
+    pub fn database(&self) -> Option<Arc<Mutex<SqliteDatabase>>> {
+        self.database.clone()
+    }
+
+    /// Load stored documents. Chunks from before embeddings were tagged are
+    /// attributed to the current model when they have its `dimension`.
+    ///
+    /// The saved search index is brought up to date with the database, so
+    /// only chunks it is missing are inserted.
+    pub async fn load_from_database(&self, dimension: Option<usize>) -> Result<()> {
         if let Some(db) = &self.database {
-            let database = db.lock().await;
-            let documents = database.load_documents(None).await?;
+            let documents = {
+                let database = db.lock().await;
+                if let Some(dimension) = dimension {
+                    database.adopt_untagged_chunks(&self.embedding_model, dimension).await?;
+                }
+                database.load_documents_without_embeddings().await?
+            };
+
+            let mut index = self.open_index(dimension).await;
+
+            // Only chunks the saved index does not hold need their embedding
+            let missing: Vec<Uuid> = documents
+                .iter()
+                .flat_map(|document| &document.chunks)
+                .filter(|chunk| chunk.embedding_model == self.embedding_model && !index.contains(&chunk.id))
+                .map(|chunk| chunk.id)
+                .collect();
+            let mut embeddings = if missing.is_empty() {
+                HashMap::new()
+            } else {
+                db.lock().await.load_chunk_embeddings(&missing).await?
+            };
+
+            let mut searchable = HashSet::new();
+            let mut added = 0;
 
             let mut doc_store = self.documents.write().await;
             let mut chunk_store = self.chunks.write().await;
             let mut goal_index = self.goal_index.write().await;
 
-            for document in documents {
-                // Store document chunks
-                for chunk in &document.chunks {
-                    chunk_store.insert(chunk.id, chunk.clone());
+            for mut document in documents {
+                // Index and cache document chunks
+                for chunk in std::mem::take(&mut document.chunks) {
+                    if chunk.embedding_model == self.embedding_model {
+                        searchable.insert(chunk.id);
+                        if let Some(embedding) = embeddings.remove(&chunk.id) {
+                            match index.insert(chunk.id, &embedding) {
+                                Ok(()) => added += 1,
+                                Err(e) => eprintln!("[VectorStore] Chunk {} not searchable: {e}", chunk.id),
+                            }
+                        }
+                    }
+                    chunk_store.insert(chunk.id, chunk);
                 }
 
                 // Update goal index
                 if let Some(goal_id) = document.goal_id {
-                    goal_index
-                        .entry(goal_id)
-                        .or_insert_with(Vec::new)
-                        .push(document.id);
+                    add_to_goal_index(&mut goal_index, goal_id, document.id);
                 }
 
                 // Store document (without chunks to avoid duplication)
-                let mut doc_for_store = document.clone();
-                doc_for_store.chunks = Vec::new();
-                doc_store.insert(document.id, doc_for_store);
+                doc_store.insert(document.id, document);
             }
 
+            // Chunks deleted or re-embedded since the index was saved
+            let outdated: Vec<Uuid> = index.ids().filter(|id| !searchable.contains(id)).copied().collect();
+            for id in &outdated {
+                index.remove(id);
+            }
+            index.compact_if_needed();
+
             println!("Loaded {} documents from database", doc_store.len());
+            println!(
+                "[VectorStore] Search index holds {} chunks ({} added, {} removed)",
+                index.len(),
+                added,
+                outdated.len()
+            );
+            *self.index.write().await = index;
+            if added > 0 || !outdated.is_empty() {
+                self.schedule_index_save();
+            }
         }
 
         Ok(())
@@ -77,6 +157,20 @@ impl VectorStore {
             }
         }
 
+        // Update search index
+        {
+            let mut index = self.index.write().await;
+            for chunk in chunks {
+                if chunk.embedding_model == self.embedding_model {
+                    index.insert(chunk.id, &chunk.embedding)?;
+                } else {
+                    index.remove(&chunk.id);
+                }
+            }
+            index.compact_if_needed();
+        }
+        self.schedule_index_save();
+
         // Update in-memory cache
         let mut documents = self.documents.write().await;
         let mut chunks_store = self.chunks.write().await;
@@ -89,15 +183,15 @@ impl VectorStore {
 
         // Store chunks
         for chunk in chunks {
-            chunks_store.insert(chunk.id, chunk.clone());
+            chunks_store.insert(chunk.id, DocumentChunk {
+                embedding: Vec::new(),
+                ..chunk.clone()
+            });
         }
 
-        // Update goal index
+        // Update goal index; re-embedding stores a document again
         if let Some(goal_id) = document.goal_id {
-            goal_index
-                .entry(goal_id)
-                .or_insert_with(Vec::new)
-                .push(document.id);
+            add_to_goal_index(&mut goal_index, goal_id, document.id);
         }
 
         Ok(())
@@ -106,50 +200,69 @@ impl VectorStore {
     pub async fn search_similar(
         &self,
         query_embedding: &[f32],
-        goal_id: Option<Uuid>,
+        filters: &SearchFilters,
         limit: usize,
     ) -> Result<Vec<SearchResult>> {
-        let chunks = self.chunks.read().await;
+        let index = self.index.read().await;
+        if index.is_empty() {
+            return Ok(Vec::new());
+        }
         let documents = self.documents.read().await;
+        let chunks = self.chunks.read().await;
 
-        let mut results = Vec::new();
-
-        for chunk in chunks.values() {
-            // Filter by goal if specified
-            if let Some(goal_id) = goal_id {
-                if let Some(doc) = documents.get(&chunk.document_id) {
-                    if doc.goal_id != Some(goal_id) {
-                        continue;
-                    }
-                } else {
-                    continue;
-                }
-            }
+        // Only chunks from the current model are indexed, so every hit is
+        // comparable with the query
+        let hits = index.search(query_embedding, limit, |chunk_id| {
+            chunks.get(chunk_id).is_some_and(|chunk| {
+                documents
+                    .get(&chunk.document_id)
+                    .is_some_and(|doc| filters.matches(doc, &chunk.metadata))
+            })
+        });
 
-            // Calculate similarity (cosine similarity)
-            let similarity = self.cosine_similarity(query_embedding, &chunk.embedding);
+        let results = hits
+            .into_iter()
+            .filter_map(|(chunk_id, similarity)| {
+                let chunk = chunks.get(&chunk_id)?;
+                Some(SearchResult {
+                    document_id: chunk.document_id,
+                    chunk_id: chunk.id,
+                    content: chunk.content.clone(),
+                    score: similarity,
+                    metadata: chunk.metadata.clone(),
+                })
+            })
+            .collect();
 
-            let result = SearchResult {
-                document_id: chunk.document_id,
-                chunk_id: chunk.id,
-                content: chunk.content.clone(),
-                score: similarity,
-                metadata: chunk.metadata.clone(),
-            };
+        Ok(results)
+    }
 
-            results.push(result);
+    /// Chunks whose text matches the words of `query`, from the database's
+    /// full-text index
+    pub async fn keyword_search(
+        &self,
+        query: &str,
+        filters: &SearchFilters,
+        limit: usize,
+    ) -> Result<Vec<SearchResult>> {
+        match &self.database {
+            Some(db) => db.lock().await.keyword_search_chunks(query, filters, limit).await,
+            None => Ok(Vec::new()),
         }
+    }
 
-        // Sort by similarity score (descending)
-        results.sort_by(|a, b| {
-            b.score
-                .partial_cmp(&a.score)
-                .unwrap_or(std::cmp::Ordering::Equal)
-        });
-
-        // Return top results
-        results.truncate(limit);
-        Ok(results)
+    /// Reload the tags and collections of a document from the database
+    /// after they were changed there
+    pub async fn refresh_document_labels(&self, document_id: Uuid) -> Result<()> {
+        let Some(db) = &self.database else {
+            return Ok(());
+        };
+        let (tags, collections) = db.lock().await.load_document_labels(document_id).await?;
+        if let Some(document) = self.documents.write().await.get_mut(&document_id) {
+            document.tags = tags;
+            document.collections = collections;
+        }
+        Ok(())
     }
 
     pub async fn get_goal_documents(
@@ -206,7 +319,23 @@ impl VectorStore {
             database.delete_document(document_id).await?;
         }
 
-        // Update in-memory cache
+        let removed_chunks = self.remove_cached_document(document_id).await;
+
+        // Update search index
+        {
+            let mut index = self.index.write().await;
+            for chunk_id in &removed_chunks {
+                index.remove(chunk_id);
+            }
+            index.compact_if_needed();
+        }
+        self.schedule_index_save();
+
+        Ok(())
+    }
+
+    /// Drop a document from the in-memory cache, returning its chunk ids
+    async fn remove_cached_document(&self, document_id: Uuid) -> Vec<Uuid> {
         let mut documents = self.documents.write().await;
         let mut chunks = self.chunks.write().await;
         let mut goal_index = self.goal_index.write().await;
@@ -225,9 +354,31 @@ impl VectorStore {
         }
 
         // Remove all chunks for this document
+        let removed: Vec<Uuid> = chunks
+            .values()
+            .filter(|chunk| chunk.document_id == document_id)
+            .map(|chunk| chunk.id)
+            .collect();
         chunks.retain(|_, chunk| chunk.document_id != document_id);
+        removed
+    }
 
-        Ok(())
+    /// Documents previously indexed from `file_path` for `goal_id`
+    pub async fn find_documents_by_path(
+        &self,
+        file_path: &str,
+        goal_id: Option<Uuid>,
+    ) -> Result<Vec<Uuid>> {
+        // The database also knows the further files recorded for a document
+        if let Some(db) = &self.database {
+            return db.lock().await.find_documents_by_path(file_path, goal_id).await;
+        }
+        let documents = self.documents.read().await;
+        Ok(documents
+            .values()
+            .filter(|document| document.file_path == file_path && document.goal_id == goal_id)
+            .map(|document| document.id)
+            .collect())
     }
 
     pub async fn list_documents(&self, goal_id: Option<Uuid>) -> Result<Vec<Document>> {
@@ -287,20 +438,60 @@ impl VectorStore {
         Ok(result)
     }
 
-    fn cosine_similarity(&self, a: &[f32], b: &[f32]) -> f32 {
-        if a.len() != b.len() {
-            return 0.0;
+    /// The saved search index, or an empty one when there is none or it was
+    /// built for vectors of another dimension
+    async fn open_index(&self, dimension: Option<usize>) -> HnswIndex {
+        let Some(path) = self.index_path.clone().filter(|path| path.exists()) else {
+            return HnswIndex::new();
+        };
+
+        let loaded = tokio::task::spawn_blocking(move || {
+            let bytes = std::fs::read(&path)
+                .map_err(|e| AppError::VectorStore(format!("Failed to read vector index: {e}")))?;
+            HnswIndex::from_bytes(&bytes)
+        })
+        .await
+        .map_err(|e| AppError::VectorStore(format!("Vector index task failed: {e}")))
+        .and_then(|loaded| loaded);
+
+        match loaded {
+            Ok(index) if dimension.is_none_or(|dimension| index.dimension() == dimension) => index,
+            Ok(_) => {
+                println!("[VectorStore] Embedding dimension changed, rebuilding search index");
+                HnswIndex::new()
+            }
+            Err(e) => {
+                eprintln!("[VectorStore] {e}, rebuilding search index");
+                HnswIndex::new()
+            }
         }
+    }
 
-        let dot_product: f32 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
-        let norm_a: f32 = a.iter().map(|x| x * x).sum::<f32>().sqrt();
-        let norm_b: f32 = b.iter().map(|x| x * x).sum::<f32>().sqrt();
-
-        if norm_a == 0.0 || norm_b == 0.0 {
-            return 0.0;
+    /// Save the search index shortly, once for all changes made until then
+    fn schedule_index_save(&self) {
+        let Some(path) = self.index_path.clone() else {
+            return;
+        };
+        if self.index_dirty.swap(true, Ordering::SeqCst) {
+            return;
         }
 
-        dot_product / (norm_a * norm_b)
+        let index = self.index.clone();
+        let dirty = self.index_dirty.clone();
+        let writer = self.index_writer.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(INDEX_SAVE_DELAY).await;
+            let _writing = writer.lock().await;
+            dirty.store(false, Ordering::SeqCst);
+
+            let bytes = index.read().await.to_bytes();
+            let saved = tokio::task::spawn_blocking(move || write_index_file(&path, &bytes)).await;
+            match saved {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => eprintln!("[VectorStore] {e}"),
+                Err(e) => eprintln!("[VectorStore] Vector index task failed: {e}"),
+            }
+        });
     }
 }
 
@@ -311,6 +502,26 @@ impl Clone for VectorStore {
             chunks: Arc::clone(&self.chunks),
             goal_index: Arc::clone(&self.goal_index),
             database: self.database.clone(),
+            embedding_model: self.embedding_model.clone(),
+            index: Arc::clone(&self.index),
+            index_path: self.index_path.clone(),
+            index_dirty: Arc::clone(&self.index_dirty),
+            index_writer: Arc::clone(&self.index_writer),
         }
     }
 }
+
+fn add_to_goal_index(goal_index: &mut HashMap<Uuid, Vec<Uuid>>, goal_id: Uuid, document_id: Uuid) {
+    let document_ids = goal_index.entry(goal_id).or_default();
+    if !document_ids.contains(&document_id) {
+        document_ids.push(document_id);
+    }
+}
+
+/// Replace the file at `path` with `bytes`, never leaving it half written
+fn write_index_file(path: &Path, bytes: &[u8]) -> Result<()> {
+    let partial = path.with_extension("hnsw.partial");
+    std::fs::write(&partial, bytes)
+        .and_then(|()| std::fs::rename(&partial, path))
+        .map_err(|e| AppError::VectorStore(format!("Failed to save vector index: {e}")))
+}
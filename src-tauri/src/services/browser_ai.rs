@@ -2,6 +2,7 @@ use crate::browser_ai::BrowserAIAgent;
 use crate::database::SqliteDatabase;
 use crate::error::Result;
 use crate::models::{ResearchTask, SavedResearchTask, BrowserAIProgressLight, BrowserAINewResult};
+use crate::services::jobs::{JobKind, JobRegistry};
 use chrono::Utc;
 use std::sync::Arc;
 use tauri::{AppHandle, Emitter, State};
@@ -18,6 +19,7 @@ pub async fn test_research() -> Result<String> {
 pub async fn start_research(
     app: AppHandle,
     agent: State<'_, Arc<Mutex<BrowserAIAgent>>>,
+    jobs: State<'_, Arc<JobRegistry>>,
     query: String,
 ) -> Result<Uuid> {
     println!("Starting research for query: {query}");
@@ -25,8 +27,15 @@ pub async fn start_research(
     let (progress_tx, mut progress_rx) = mpsc::channel(100);
     let (result_tx, mut result_rx) = mpsc::channel(100);
 
+    // Progress events carry the task id, which is also the job id for `cancel_job`
+    let task_id = Uuid::new_v4();
+    let job = jobs.register(task_id.to_string(), JobKind::Research);
+
     let mut agent = agent.lock().await;
-    let task_id = agent.start_research(query, progress_tx, Some(result_tx)).await?;
+    let task_id = agent
+        .start_research(task_id, query, progress_tx, Some(result_tx), job.token())
+        .await?;
+    drop(job);
 
     println!("Research task created with ID: {task_id}");
 
@@ -39,7 +48,8 @@ pub async fn start_research(
             if matches!(progress.status, crate::models::TaskStatus::Searching | 
                                        crate::models::TaskStatus::Scraping | 
                                        crate::models::TaskStatus::Analyzing |
-                                       crate::models::TaskStatus::Completed) {
+                                       crate::models::TaskStatus::Completed |
+                                       crate::models::TaskStatus::Cancelled) {
                 println!("Progress: {} - {}%", progress.current_operation.as_ref().unwrap_or(&"Working".to_string()), progress.percentage);
             }
             
@@ -2,7 +2,7 @@ use crate::database::SqliteDatabase;
 use crate::goals::GoalService;
 use crate::models::{ChatConversation, ChatConversationSummary, ChatMessage, ChatMode};
 use std::sync::Arc;
-use tauri::State;
+use tauri::{AppHandle, Manager, State};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
@@ -172,3 +172,25 @@ pub async fn update_chat_conversation_title(
         }
     }
 }
+
+/// Load the stored messages of a conversation for use as chat history.
+///
+/// Returns an empty history when the id is invalid or the database is not
+/// available, so chat still works without persistence.
+pub async fn load_conversation_history(app: &AppHandle, conversation_id: &str) -> Vec<ChatMessage> {
+    let Ok(conversation_uuid) = Uuid::parse_str(conversation_id) else {
+        return Vec::new();
+    };
+    let Some(db) = app.try_state::<Arc<Mutex<SqliteDatabase>>>() else {
+        return Vec::new();
+    };
+
+    let db = db.lock().await;
+    match db.get_conversation_messages(conversation_uuid).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            eprintln!("Failed to load conversation history: {}", e);
+            Vec::new()
+        }
+    }
+}
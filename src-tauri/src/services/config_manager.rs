@@ -61,6 +61,11 @@ pub async fn update_inference_config(
                 config.services.candle_model_id = id;
             }
         }
+        InferenceProvider::OpenAiCompatible => {
+            if let Some(id) = model_id {
+                config.services.openai_model = id;
+            }
+        }
     }
     
     // Save the configuration
@@ -1,12 +1,18 @@
 use crate::goals::GoalService;
+use futures_util::stream::{self, StreamExt};
 use mime_guess::MimeGuess;
 use std::path::Path;
 use std::sync::Arc;
 use tauri::State;
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 use walkdir::WalkDir;
 
+/// Files read and embedded at once by `index_multiple_documents`. Embedding
+/// requests are limited separately by `RAG_EMBEDDING_CONCURRENCY`.
+const MAX_CONCURRENT_FILES: usize = 4;
+
 #[tauri::command]
 pub async fn scan_folder_for_documents(
     folder_path: String,
@@ -18,9 +24,15 @@ pub async fn scan_folder_for_documents(
         return Err("Invalid folder path".to_string());
     }
 
-    let include_subdirs = include_subdirs.unwrap_or(true);
-    let max_depth = max_depth.unwrap_or(10);
+    Ok(supported_files_in(
+        path,
+        include_subdirs.unwrap_or(true),
+        max_depth.unwrap_or(10),
+    ))
+}
 
+/// Paths of the files under `path` that can be indexed
+pub(crate) fn supported_files_in(path: &Path, include_subdirs: bool, max_depth: usize) -> Vec<String> {
     let processor = crate::rag::DocumentProcessor::new();
     let mut document_files = Vec::new();
 
@@ -42,7 +54,7 @@ pub async fn scan_folder_for_documents(
         }
     }
 
-    Ok(document_files)
+    document_files
 }
 
 #[tauri::command]
@@ -88,12 +100,11 @@ pub async fn get_file_info(file_path: String) -> std::result::Result<FileInfo, S
 
 #[tauri::command]
 pub async fn index_multiple_documents(
-    rag_system: State<'_, Arc<Mutex<crate::rag::RAGSystem>>>,
+    rag_system: State<'_, Arc<Mutex<crate::rag::RAGSystemWrapper>>>,
     goal_service: State<'_, Arc<Mutex<GoalService>>>,
     file_paths: Vec<String>,
     goal_id: Option<String>,
 ) -> std::result::Result<IndexingResult, String> {
-    let mut rag = rag_system.lock().await;
     let goal_uuid = if let Some(goal_str) = goal_id {
         Some(Uuid::parse_str(&goal_str).map_err(|e| e.to_string())?)
     } else {
@@ -108,11 +119,35 @@ pub async fn index_multiple_documents(
         goal_uuid
     );
 
+    // Files are read and embedded side by side; the RAG system is only locked
+    // to store each finished document
+    let indexer = rag_system.lock().await.indexer();
+    let mut prepared = stream::iter(file_paths)
+        .map(|file_path| {
+            let indexer = indexer.clone();
+            async move {
+                let document = indexer
+                    .prepare_document(&file_path, goal_uuid, &CancellationToken::new())
+                    .await;
+                (file_path, document)
+            }
+        })
+        .buffered(MAX_CONCURRENT_FILES);
+
     let mut successful = Vec::new();
     let mut failed = Vec::new();
 
-    for file_path in file_paths {
-        match rag.index_document(&file_path, goal_uuid).await {
+    while let Some((file_path, document)) = prepared.next().await {
+        let stored = match document {
+            Ok(document) => rag_system
+                .lock()
+                .await
+                .store_prepared_document(&file_path, &document)
+                .await
+                .map(|_| document),
+            Err(e) => Err(e),
+        };
+        match stored {
             Ok(document) => {
                 println!(
                     "Successfully indexed: {} for goal: {:?}",
@@ -123,6 +158,7 @@ pub async fn index_multiple_documents(
                     path: file_path,
                     title: document.title,
                     chunks_count: document.chunks.len(),
+                    pages_without_text: document.pages_without_text,
                 });
             }
             Err(e) => {
@@ -197,6 +233,7 @@ pub struct IndexedDocumentInfo {
     pub path: String,
     pub title: String,
     pub chunks_count: usize,
+    pub pages_without_text: Vec<usize>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
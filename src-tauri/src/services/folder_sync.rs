@@ -0,0 +1,639 @@
+use crate::database::SqliteDatabase;
+use crate::error::{AppError, Result};
+use crate::goals::GoalService;
+use crate::rag::{self, DocumentIndexer, DocumentProcessor, IndexedFile, RAGSystemWrapper, WatchedFolder};
+use crate::services::file_manager::{self, FailedIndexInfo};
+use notify_debouncer_mini::notify::{RecommendedWatcher, RecursiveMode};
+use notify_debouncer_mini::{new_debouncer, DebounceEventResult, Debouncer};
+use std::collections::HashMap;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tauri::{AppHandle, Emitter, State};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// How long file events are gathered before syncing, so a file saved in
+/// several writes is indexed once
+const DEBOUNCE: Duration = Duration::from_secs(2);
+
+/// How often every watched folder is scanned in full, for changes the file
+/// events missed, such as those made while the app was closed or on network
+/// drives
+const RESCAN_INTERVAL: Duration = Duration::from_secs(30 * 60);
+
+/// Subdirectory depth scanned in watched folders, as when scanning a folder
+const MAX_DEPTH: usize = 10;
+
+/// Size and modification time of a file, compared before hashing it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FileStamp {
+    /// Nanoseconds since the epoch
+    modified_ns: Option<i64>,
+    len: i64,
+}
+
+impl FileStamp {
+    fn read(file_path: &str) -> Option<Self> {
+        let metadata = std::fs::metadata(file_path).ok()?;
+        Some(Self {
+            modified_ns: metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map(|since| since.as_nanos() as i64),
+            len: metadata.len() as i64,
+        })
+    }
+
+    /// The stamp saved when the file was last synced
+    fn saved(file: &IndexedFile) -> Option<Self> {
+        Some(Self {
+            modified_ns: file.file_modified_ns,
+            len: file.file_size?,
+        })
+    }
+}
+
+enum FileChange {
+    Added,
+    Updated,
+    Unchanged,
+}
+
+/// What a sync of one watched folder changed, sent with `folder-sync` events
+#[derive(serde::Serialize)]
+pub struct FolderSyncSummary {
+    pub folder_id: String,
+    pub path: String,
+    pub added: usize,
+    pub updated: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    pub failed: Vec<FailedIndexInfo>,
+}
+
+impl FolderSyncSummary {
+    fn new(folder: &WatchedFolder) -> Self {
+        Self {
+            folder_id: folder.id.to_string(),
+            path: folder.path.clone(),
+            added: 0,
+            updated: 0,
+            removed: 0,
+            unchanged: 0,
+            failed: Vec::new(),
+        }
+    }
+
+    fn has_changes(&self) -> bool {
+        self.added + self.updated + self.removed > 0 || !self.failed.is_empty()
+    }
+}
+
+/// Keeps the documents of watched folders in step with the files on disk.
+/// File events are debounced and synced as they arrive: new files are
+/// indexed, files whose content hash changed are re-indexed in place and
+/// deleted files are removed. Folders are also scanned in full at startup
+/// and every `RESCAN_INTERVAL`.
+pub struct FolderWatcher {
+    rag_system: Arc<Mutex<RAGSystemWrapper>>,
+    database: Arc<Mutex<SqliteDatabase>>,
+    /// Files as they were at their last successful sync, by goal and path, so
+    /// unchanged files are not read again. Indexed files also keep their
+    /// stamp in the database, which carries it across restarts. Held for a
+    /// whole sync, so syncs never overlap.
+    stamps: Mutex<HashMap<(Option<Uuid>, String), FileStamp>>,
+    /// Watches the folders once `run` has started
+    events: std::sync::Mutex<Option<Debouncer<RecommendedWatcher>>>,
+}
+
+impl FolderWatcher {
+    pub fn new(rag_system: Arc<Mutex<RAGSystemWrapper>>, database: Arc<Mutex<SqliteDatabase>>) -> Self {
+        Self {
+            rag_system,
+            database,
+            stamps: Mutex::new(HashMap::new()),
+            events: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// Watch every folder and sync the files that change, rescanning all
+    /// folders now and every `RESCAN_INTERVAL`
+    pub async fn run(&self, app: &AppHandle) {
+        let (sender, mut events) = tokio::sync::mpsc::unbounded_channel();
+        match new_debouncer(DEBOUNCE, move |result: DebounceEventResult| {
+            let _ = sender.send(result);
+        }) {
+            Ok(debouncer) => {
+                if let Ok(mut watcher) = self.events.lock() {
+                    *watcher = Some(debouncer);
+                }
+            }
+            Err(e) => eprintln!("[FolderWatcher] File events unavailable, relying on rescans: {e}"),
+        }
+
+        for folder in self.load_folders().await {
+            self.watch(&folder);
+        }
+
+        let mut rescan = tokio::time::interval(RESCAN_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = rescan.tick() => {
+                    for folder in self.load_folders().await {
+                        self.sync_and_report(app, &folder).await;
+                    }
+                }
+                Some(result) = events.recv() => match result {
+                    Ok(events) => {
+                        let paths: Vec<PathBuf> = events.into_iter().map(|event| event.path).collect();
+                        for folder in self.load_folders().await {
+                            let changed: Vec<&PathBuf> = paths
+                                .iter()
+                                .filter(|path| path.to_str().is_some_and(|path| is_in_folder(&folder, path)))
+                                .collect();
+                            if !changed.is_empty() {
+                                let result = self.sync_paths(&folder, &changed).await;
+                                report(app, &folder, result);
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        // Events may have been lost, so look at everything
+                        eprintln!("[FolderWatcher] File watching failed: {e}");
+                        for folder in self.load_folders().await {
+                            self.sync_and_report(app, &folder).await;
+                        }
+                    }
+                },
+            }
+        }
+    }
+
+    async fn load_folders(&self) -> Vec<WatchedFolder> {
+        match self.database.lock().await.load_watched_folders().await {
+            Ok(folders) => folders,
+            Err(e) => {
+                eprintln!("[FolderWatcher] Failed to load watched folders: {e}");
+                Vec::new()
+            }
+        }
+    }
+
+    /// Start receiving file events for `folder`
+    fn watch(&self, folder: &WatchedFolder) {
+        let mode = if folder.include_subdirs {
+            RecursiveMode::Recursive
+        } else {
+            RecursiveMode::NonRecursive
+        };
+        if let Ok(mut events) = self.events.lock() {
+            if let Some(debouncer) = events.as_mut() {
+                if let Err(e) = debouncer.watcher().watch(Path::new(&folder.path), mode) {
+                    eprintln!("[FolderWatcher] Cannot watch {}, relying on rescans: {e}", folder.path);
+                }
+            }
+        }
+    }
+
+    /// Stop receiving file events for the folder at `path`
+    fn unwatch(&self, path: &str) {
+        if let Ok(mut events) = self.events.lock() {
+            if let Some(debouncer) = events.as_mut() {
+                let _ = debouncer.watcher().unwatch(Path::new(path));
+            }
+        }
+    }
+
+    /// Sync `folder`, emitting a `folder-sync` event if anything changed
+    pub async fn sync_and_report(&self, app: &AppHandle, folder: &WatchedFolder) {
+        let result = self.sync_folder(folder).await;
+        report(app, folder, result);
+    }
+
+    /// Bring the documents of `folder`'s goal in line with its files
+    pub async fn sync_folder(&self, folder: &WatchedFolder) -> Result<FolderSyncSummary> {
+        let root = Path::new(&folder.path);
+        if !root.is_dir() {
+            // An unmounted drive is not a folder whose files were all deleted
+            return Err(AppError::NotFound(format!(
+                "Watched folder {} is not available",
+                folder.path
+            )));
+        }
+
+        let mut stamps = self.stamps.lock().await;
+        let mut summary = FolderSyncSummary::new(folder);
+        let indexed = self.indexed_files(folder).await?;
+
+        for file in indexed.values() {
+            if !Path::new(&file.file_path).exists() {
+                self.remove_file(&mut stamps, folder, file).await?;
+                summary.removed += 1;
+            }
+        }
+
+        // Files are indexed one at a time so syncing stays in the background
+        let indexer = self.rag_system.lock().await.indexer();
+        for file_path in file_manager::supported_files_in(root, folder.include_subdirs, MAX_DEPTH) {
+            let previous = indexed.get(&file_path);
+            self.sync_stamped(&mut stamps, &indexer, folder, file_path, previous, &mut summary)
+                .await;
+        }
+
+        self.database
+            .lock()
+            .await
+            .mark_watched_folder_synced(folder.id, chrono::Utc::now())
+            .await?;
+        Ok(summary)
+    }
+
+    /// Sync the files of `folder` that file events reported as changed
+    async fn sync_paths(&self, folder: &WatchedFolder, paths: &[&PathBuf]) -> Result<FolderSyncSummary> {
+        // A folder moved in or out may hold any number of files
+        if paths.iter().any(|path| path.is_dir()) {
+            return self.sync_folder(folder).await;
+        }
+        if !Path::new(&folder.path).is_dir() {
+            return Err(AppError::NotFound(format!(
+                "Watched folder {} is not available",
+                folder.path
+            )));
+        }
+
+        let mut stamps = self.stamps.lock().await;
+        let mut summary = FolderSyncSummary::new(folder);
+        let indexed = self.indexed_files(folder).await?;
+        let processor = DocumentProcessor::new();
+        let indexer = self.rag_system.lock().await.indexer();
+
+        for path in paths {
+            let Some(file_path) = path.to_str() else {
+                continue;
+            };
+            let previous = indexed.get(file_path);
+            if path.exists() {
+                if processor.is_supported_file(file_path) {
+                    self.sync_stamped(&mut stamps, &indexer, folder, file_path.to_string(), previous, &mut summary)
+                        .await;
+                }
+            } else if let Some(file) = previous {
+                self.remove_file(&mut stamps, folder, file).await?;
+                summary.removed += 1;
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Documents of `folder`'s goal read from files in it, by path
+    async fn indexed_files(&self, folder: &WatchedFolder) -> Result<HashMap<String, IndexedFile>> {
+        Ok(self
+            .database
+            .lock()
+            .await
+            .list_indexed_files(folder.goal_id)
+            .await?
+            .into_iter()
+            .filter(|file| is_in_folder(folder, &file.file_path))
+            .map(|file| (file.file_path.clone(), file))
+            .collect())
+    }
+
+    async fn remove_file(
+        &self,
+        stamps: &mut HashMap<(Option<Uuid>, String), FileStamp>,
+        folder: &WatchedFolder,
+        file: &IndexedFile,
+    ) -> Result<()> {
+        self.rag_system
+            .lock()
+            .await
+            .remove_source(file.document_id, &file.file_path)
+            .await?;
+        stamps.remove(&(folder.goal_id, file.file_path.clone()));
+        Ok(())
+    }
+
+    /// Sync `file_path` unless its size and modification time are the same as
+    /// at the last sync, counting the outcome in `summary`
+    async fn sync_stamped(
+        &self,
+        stamps: &mut HashMap<(Option<Uuid>, String), FileStamp>,
+        indexer: &DocumentIndexer,
+        folder: &WatchedFolder,
+        file_path: String,
+        previous: Option<&IndexedFile>,
+        summary: &mut FolderSyncSummary,
+    ) {
+        let Some(stamp) = FileStamp::read(&file_path) else {
+            return;
+        };
+        let key = (folder.goal_id, file_path.clone());
+        let sync = self.sync_file(indexer, folder.goal_id, &file_path, previous);
+        match sync_if_changed(stamps, key, stamp, previous, sync).await {
+            None => summary.unchanged += 1,
+            Some(Ok((change, document_id))) => {
+                match change {
+                    FileChange::Added => summary.added += 1,
+                    FileChange::Updated => summary.updated += 1,
+                    FileChange::Unchanged => summary.unchanged += 1,
+                }
+                let saved = self
+                    .database
+                    .lock()
+                    .await
+                    .set_file_stamp(document_id, &file_path, stamp.len, stamp.modified_ns)
+                    .await;
+                if let Err(e) = saved {
+                    eprintln!("[FolderWatcher] Failed to save the stamp of {file_path}: {e}");
+                }
+            }
+            Some(Err(e)) => {
+                eprintln!("[FolderWatcher] Failed to index {file_path}: {e}");
+                summary.failed.push(FailedIndexInfo {
+                    path: file_path,
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Index `file_path` unless it is indexed with the same content, returning
+    /// the document that holds it
+    async fn sync_file(
+        &self,
+        indexer: &DocumentIndexer,
+        goal_id: Option<Uuid>,
+        file_path: &str,
+        previous: Option<&IndexedFile>,
+    ) -> Result<(FileChange, Uuid)> {
+        if let Some(previous) = previous {
+            if previous.content_hash == rag::file_content_hash(file_path).await? {
+                return Ok((FileChange::Unchanged, previous.document_id));
+            }
+        }
+
+        let document = indexer
+            .prepare_document(file_path, goal_id, &CancellationToken::new())
+            .await?;
+        self.rag_system
+            .lock()
+            .await
+            .store_prepared_document(file_path, &document)
+            .await?;
+
+        let change = match previous {
+            Some(_) => FileChange::Updated,
+            None => FileChange::Added,
+        };
+        Ok((change, document.id))
+    }
+}
+
+/// Run `sync` unless the file at `key` has the same stamp as at its last
+/// successful sync, from this run or saved in `previous`. Only a sync that
+/// succeeds is recorded, so a failed file is tried again at the next sync.
+/// Returns `None` when the file was skipped.
+async fn sync_if_changed<T>(
+    stamps: &mut HashMap<(Option<Uuid>, String), FileStamp>,
+    key: (Option<Uuid>, String),
+    stamp: FileStamp,
+    previous: Option<&IndexedFile>,
+    sync: impl Future<Output = Result<T>>,
+) -> Option<Result<T>> {
+    let last = stamps
+        .get(&key)
+        .copied()
+        .or_else(|| previous.and_then(FileStamp::saved));
+    if last == Some(stamp) {
+        return None;
+    }
+
+    let result = sync.await;
+    if result.is_ok() {
+        stamps.insert(key, stamp);
+    }
+    Some(result)
+}
+
+/// Log and emit a `folder-sync` event for a sync that changed anything
+fn report(app: &AppHandle, folder: &WatchedFolder, result: Result<FolderSyncSummary>) {
+    match result {
+        Ok(summary) if summary.has_changes() => {
+            println!(
+                "[FolderWatcher] Synced {}: {} added, {} updated, {} removed, {} failed",
+                folder.path,
+                summary.added,
+                summary.updated,
+                summary.removed,
+                summary.failed.len()
+            );
+            let _ = app.emit("folder-sync", &summary);
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("[FolderWatcher] Failed to sync {}: {e}", folder.path),
+    }
+}
+
+/// Whether `file_path` is one of the files `folder` covers
+fn is_in_folder(folder: &WatchedFolder, file_path: &str) -> bool {
+    let file_path = Path::new(file_path);
+    if folder.include_subdirs {
+        file_path.starts_with(&folder.path)
+    } else {
+        file_path.parent() == Some(Path::new(&folder.path))
+    }
+}
+
+#[tauri::command]
+pub async fn list_watched_folders(
+    watcher: State<'_, Arc<FolderWatcher>>,
+    goal_id: Option<String>,
+) -> std::result::Result<Vec<WatchedFolder>, String> {
+    let goal_uuid = match goal_id {
+        Some(goal_str) => Some(Uuid::parse_str(&goal_str).map_err(|e| e.to_string())?),
+        None => None,
+    };
+
+    let folders = watcher
+        .database
+        .lock()
+        .await
+        .load_watched_folders()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(folders
+        .into_iter()
+        .filter(|folder| goal_uuid.is_none() || folder.goal_id == goal_uuid)
+        .collect())
+}
+
+/// Watch `folder_path` for a goal, indexing its files in the background
+#[tauri::command]
+pub async fn add_watched_folder(
+    app: AppHandle,
+    watcher: State<'_, Arc<FolderWatcher>>,
+    goal_service: State<'_, Arc<Mutex<GoalService>>>,
+    folder_path: String,
+    goal_id: Option<String>,
+    include_subdirs: Option<bool>,
+) -> std::result::Result<WatchedFolder, String> {
+    if !Path::new(&folder_path).is_dir() {
+        return Err("Invalid folder path".to_string());
+    }
+
+    let goal_uuid = if let Some(goal_str) = goal_id {
+        Some(Uuid::parse_str(&goal_str).map_err(|e| e.to_string())?)
+    } else {
+        // Use current active goal or default goal
+        let goal_service = goal_service.lock().await;
+        Some(goal_service.get_current_or_default_goal_id())
+    };
+
+    let folder = {
+        let database = watcher.database.lock().await;
+        let existing = database
+            .load_watched_folders()
+            .await
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .find(|folder| folder.path == folder_path && folder.goal_id == goal_uuid);
+        if let Some(folder) = existing {
+            return Ok(folder);
+        }
+
+        let folder = WatchedFolder {
+            id: Uuid::new_v4(),
+            path: folder_path,
+            goal_id: goal_uuid,
+            include_subdirs: include_subdirs.unwrap_or(true),
+            created_at: chrono::Utc::now(),
+            last_synced_at: None,
+        };
+        database
+            .save_watched_folder(&folder)
+            .await
+            .map_err(|e| e.to_string())?;
+        folder
+    };
+    println!("Watching {} for goal: {:?}", folder.path, folder.goal_id);
+    watcher.watch(&folder);
+
+    let watcher = watcher.inner().clone();
+    let first_sync = folder.clone();
+    tauri::async_runtime::spawn(async move {
+        watcher.sync_and_report(&app, &first_sync).await;
+    });
+
+    Ok(folder)
+}
+
+/// Stop watching a folder. Its documents stay indexed.
+#[tauri::command]
+pub async fn remove_watched_folder(
+    watcher: State<'_, Arc<FolderWatcher>>,
+    folder_id: String,
+) -> std::result::Result<String, String> {
+    let folder_uuid = Uuid::parse_str(&folder_id).map_err(|e| e.to_string())?;
+    let folders = {
+        let database = watcher.database.lock().await;
+        let folders = database.load_watched_folders().await.map_err(|e| e.to_string())?;
+        database
+            .delete_watched_folder(folder_uuid)
+            .await
+            .map_err(|e| e.to_string())?;
+        folders
+    };
+
+    // The same folder may still be watched for another goal
+    if let Some(removed) = folders.iter().find(|folder| folder.id == folder_uuid) {
+        if !folders
+            .iter()
+            .any(|folder| folder.id != folder_uuid && folder.path == removed.path)
+        {
+            watcher.unwatch(&removed.path);
+        }
+    }
+
+    Ok("Folder is no longer watched".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn folder(path: &str, include_subdirs: bool) -> WatchedFolder {
+        WatchedFolder {
+            id: Uuid::new_v4(),
+            path: path.to_string(),
+            goal_id: None,
+            include_subdirs,
+            created_at: chrono::Utc::now(),
+            last_synced_at: None,
+        }
+    }
+
+    #[test]
+    fn test_is_in_folder_respects_subdirectory_setting() {
+        let recursive = folder("/notes", true);
+        assert!(is_in_folder(&recursive, "/notes/a.md"));
+        assert!(is_in_folder(&recursive, "/notes/2024/b.md"));
+        assert!(!is_in_folder(&recursive, "/notes-old/a.md"));
+
+        let flat = folder("/notes/", false);
+        assert!(is_in_folder(&flat, "/notes/a.md"));
+        assert!(!is_in_folder(&flat, "/notes/2024/b.md"));
+    }
+
+    #[test]
+    fn test_saved_stamp_matches_the_unchanged_file() {
+        let path = std::env::temp_dir().join(format!("folder-sync-test-{}.md", std::process::id()));
+        std::fs::write(&path, "first").unwrap();
+        let file_path = path.to_str().unwrap();
+
+        let stamp = FileStamp::read(file_path).unwrap();
+        let mut file = IndexedFile {
+            document_id: Uuid::new_v4(),
+            file_path: file_path.to_string(),
+            content_hash: String::new(),
+            file_size: None,
+            file_modified_ns: None,
+        };
+        assert_eq!(FileStamp::saved(&file), None);
+
+        file.file_size = Some(stamp.len);
+        file.file_modified_ns = stamp.modified_ns;
+        assert_eq!(FileStamp::saved(&file), Some(stamp));
+
+        std::fs::write(&path, "second version").unwrap();
+        assert_ne!(FileStamp::read(file_path), Some(stamp));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rescan_retries_a_file_whose_sync_failed() {
+        let stamp = FileStamp {
+            modified_ns: Some(1),
+            len: 5,
+        };
+        let key = (None, "/notes/a.md".to_string());
+        let mut stamps = HashMap::new();
+
+        let failed = async { Err::<(), _>(AppError::Embedding("server is down".to_string())) };
+        let first = sync_if_changed(&mut stamps, key.clone(), stamp, None, failed).await;
+        assert!(matches!(first, Some(Err(_))));
+
+        // The file has not changed, but the rescan syncs it again
+        let rescan = sync_if_changed(&mut stamps, key.clone(), stamp, None, async { Ok(()) }).await;
+        assert!(matches!(rescan, Some(Ok(()))));
+
+        let next = sync_if_changed(&mut stamps, key, stamp, None, async { Ok(()) }).await;
+        assert!(next.is_none());
+    }
+}
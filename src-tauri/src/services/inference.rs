@@ -9,6 +9,8 @@ pub struct InferenceConfig {
     pub ollama_model: String,
     pub candle_model_id: String,
     pub candle_model_revision: String,
+    pub openai_base_url: String,
+    pub openai_model: String,
     pub available_providers: Vec<String>,
 }
 
@@ -23,7 +25,9 @@ pub async fn get_inference_config(
         ollama_model: config.services.ollama_model.clone(),
         candle_model_id: config.services.candle_model_id.clone(),
         candle_model_revision: config.services.candle_model_revision.clone(),
-        available_providers: vec!["Ollama".to_string(), "Candle".to_string(), "Crane".to_string(), "Callm".to_string(), "LlamaCpp".to_string()],
+        openai_base_url: config.services.openai_base_url.clone(),
+        openai_model: config.services.openai_model.clone(),
+        available_providers: vec!["Ollama".to_string(), "Candle".to_string(), "Crane".to_string(), "Callm".to_string(), "LlamaCpp".to_string(), "OpenAiCompatible".to_string()],
     })
 }
 
@@ -42,6 +46,7 @@ pub async fn set_inference_provider(
         "callm" => InferenceProvider::Callm,
         "ollama" => InferenceProvider::Ollama,
         "llamacpp" => InferenceProvider::LlamaCpp,
+        "openai" | "openaicompatible" => InferenceProvider::OpenAiCompatible,
         _ => return Err(format!("Unknown provider: {}", provider)),
     };
     
@@ -88,6 +93,12 @@ pub async fn set_inference_provider(
                 std::env::set_var("CANDLE_MODEL_ID", id); // LlamaCpp also uses model_id
             }
         }
+        "openai" | "openaicompatible" => {
+            std::env::set_var("INFERENCE_PROVIDER", "openai");
+            if let Some(id) = &model_id {
+                std::env::set_var("OPENAI_MODEL", id);
+            }
+        }
         _ => {}
     }
     
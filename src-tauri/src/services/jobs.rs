@@ -0,0 +1,205 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::State;
+use tokio_util::sync::CancellationToken;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JobKind {
+    Generation,
+    Research,
+    Indexing,
+    Reembedding,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct JobInfo {
+    pub id: String,
+    pub kind: JobKind,
+    pub started_at: DateTime<Utc>,
+}
+
+struct JobEntry {
+    info: JobInfo,
+    token: CancellationToken,
+    serial: u64,
+}
+
+/// Long-running work started from commands, keyed by the id the frontend
+/// already tracks (message id, research task id or indexing task id)
+#[derive(Default)]
+pub struct JobRegistry {
+    jobs: Mutex<HashMap<String, JobEntry>>,
+    next_serial: AtomicU64,
+}
+
+impl JobRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a job and hand out its cancellation token.
+    ///
+    /// The job stays registered until the returned handle is dropped. Reusing
+    /// an id replaces the previous entry.
+    pub fn register(self: &Arc<Self>, id: impl Into<String>, kind: JobKind) -> JobHandle {
+        let id = id.into();
+        let mut jobs = self.jobs.lock().unwrap();
+        self.insert(&mut jobs, id, kind)
+    }
+
+    /// Like `register`, but leaves a running job with the same id alone and
+    /// returns `None`. The check and the registration happen under one lock,
+    /// so only one of several concurrent callers gets the job.
+    pub fn try_register(self: &Arc<Self>, id: impl Into<String>, kind: JobKind) -> Option<JobHandle> {
+        let id = id.into();
+        let mut jobs = self.jobs.lock().unwrap();
+        if jobs.contains_key(&id) {
+            return None;
+        }
+        Some(self.insert(&mut jobs, id, kind))
+    }
+
+    fn insert(
+        self: &Arc<Self>,
+        jobs: &mut HashMap<String, JobEntry>,
+        id: String,
+        kind: JobKind,
+    ) -> JobHandle {
+        let token = CancellationToken::new();
+        let serial = self.next_serial.fetch_add(1, Ordering::Relaxed);
+        let entry = JobEntry {
+            info: JobInfo {
+                id: id.clone(),
+                kind,
+                started_at: Utc::now(),
+            },
+            token: token.clone(),
+            serial,
+        };
+        jobs.insert(id.clone(), entry);
+        println!("[Jobs] Started {:?} job {}", kind, id);
+
+        JobHandle {
+            id,
+            token,
+            serial,
+            registry: Arc::clone(self),
+        }
+    }
+
+    /// Request cancellation of a running job. Returns false for unknown ids.
+    pub fn cancel(&self, id: &str) -> bool {
+        match self.jobs.lock().unwrap().get(id) {
+            Some(entry) => {
+                println!("[Jobs] Cancelling {:?} job {}", entry.info.kind, id);
+                entry.token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_running(&self, id: &str) -> bool {
+        self.jobs.lock().unwrap().contains_key(id)
+    }
+
+    pub fn list(&self) -> Vec<JobInfo> {
+        self.jobs
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.info.clone())
+            .collect()
+    }
+
+    fn finish(&self, id: &str, serial: u64) {
+        let mut jobs = self.jobs.lock().unwrap();
+        // Only remove the entry if it was not replaced by a newer job with the same id
+        if jobs.get(id).is_some_and(|entry| entry.serial == serial) {
+            jobs.remove(id);
+        }
+    }
+}
+
+/// Registration of a running job; unregisters the job when dropped
+pub struct JobHandle {
+    id: String,
+    token: CancellationToken,
+    serial: u64,
+    registry: Arc<JobRegistry>,
+}
+
+impl JobHandle {
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+}
+
+impl Drop for JobHandle {
+    fn drop(&mut self) {
+        self.registry.finish(&self.id, self.serial);
+    }
+}
+
+#[tauri::command]
+pub async fn cancel_job(
+    jobs: State<'_, Arc<JobRegistry>>,
+    job_id: String,
+) -> std::result::Result<bool, String> {
+    Ok(jobs.cancel(&job_id))
+}
+
+#[tauri::command]
+pub async fn list_jobs(
+    jobs: State<'_, Arc<JobRegistry>>,
+) -> std::result::Result<Vec<JobInfo>, String> {
+    Ok(jobs.list())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_and_unregister_on_drop() {
+        let registry = Arc::new(JobRegistry::new());
+        let job = registry.register("message-1", JobKind::Generation);
+        assert_eq!(registry.list().len(), 1);
+
+        assert!(registry.cancel("message-1"));
+        assert!(job.token().is_cancelled());
+        assert!(!registry.cancel("unknown"));
+
+        drop(job);
+        assert!(registry.list().is_empty());
+    }
+
+    #[test]
+    fn test_replaced_job_survives_old_handle_drop() {
+        let registry = Arc::new(JobRegistry::new());
+        let old = registry.register("task", JobKind::Indexing);
+        let new = registry.register("task", JobKind::Indexing);
+
+        drop(old);
+        assert_eq!(registry.list().len(), 1);
+        assert!(registry.cancel("task"));
+        assert!(new.token().is_cancelled());
+    }
+
+    #[test]
+    fn test_try_register_leaves_running_job_alone() {
+        let registry = Arc::new(JobRegistry::new());
+        let first = registry.try_register("reembedding", JobKind::Reembedding).unwrap();
+        assert!(registry.try_register("reembedding", JobKind::Reembedding).is_none());
+
+        assert!(registry.cancel("reembedding"));
+        assert!(first.token().is_cancelled());
+
+        drop(first);
+        assert!(registry.try_register("reembedding", JobKind::Reembedding).is_some());
+    }
+}
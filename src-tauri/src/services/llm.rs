@@ -1,8 +1,16 @@
 use crate::error::Result;
+use crate::llm::conversation::{build_chat_messages, PromptMessage};
+use crate::llm::fallback::ServedBy;
+use crate::llm::options::GenerationOptions;
+use crate::llm::token_budget::BudgetReport;
 use crate::llm::LlmClient;
 use crate::models::{ProductivityInsights, ProductivityScore};
+use crate::rag::{
+    extract_citations, numbered_source, Citation, SourceLocation, CITATION_INSTRUCTIONS,
+};
+use crate::services::chat::load_conversation_history;
 use std::sync::Arc;
-use tauri::State;
+use tauri::{AppHandle, State};
 use tokio::sync::Mutex;
 use uuid::Uuid;
 
@@ -64,15 +72,19 @@ pub async fn get_recommendations(
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn chat_with_documents(
     llm: State<'_, Arc<LlmClient>>,
     rag_system: State<'_, Arc<Mutex<crate::rag::RAGSystemWrapper>>>,
+    reranker: State<'_, Arc<crate::rag::Reranker>>,
     activity_tracker: State<'_, Arc<Mutex<crate::activity_tracking::ActivityTracker>>>,
     query: String,
     goal_id: Option<String>,
     limit: Option<usize>,
     model: Option<String>,
+    options: Option<GenerationOptions>,
 ) -> std::result::Result<ChatResponse, String> {
+    let options = options.unwrap_or_default();
     let goal_uuid = if let Some(goal_str) = goal_id {
         Some(Uuid::parse_str(&goal_str).map_err(|e| e.to_string())?)
     } else {
@@ -92,19 +104,26 @@ pub async fn chat_with_documents(
     // Search for relevant documents
     let rag = rag_system.lock().await;
     println!("Acquired RAG system lock, searching for documents...");
-    let search_results = rag.search(&query, goal_uuid, limit).await.map_err(|e| {
-        eprintln!("Failed to search documents: {e}");
-        format!("Search failed: {e}")
-    })?;
+    let candidates = rag
+        .search(&query, goal_uuid, reranker.candidate_count(limit))
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to search documents: {e}");
+            format!("Search failed: {e}")
+        })?;
+    drop(rag);
+    let search_results = reranker.rerank(&query, candidates, limit).await;
 
     println!("Found {len} search results", len = search_results.len());
 
     // Log search results for debugging
-    for (i, result) in search_results.iter().enumerate() {
+    for (i, ranked) in search_results.iter().enumerate() {
+        let result = &ranked.result;
         println!(
-            "Search Result {num}: (score: {score:.3})",
+            "Search Result {num}: (score: {score:.3}, rerank score: {rerank:?})",
             num = i + 1,
-            score = result.score
+            score = result.score,
+            rerank = ranked.rerank_score
         );
         println!("Document ID: {}", result.document_id);
         println!(
@@ -120,26 +139,40 @@ pub async fn chat_with_documents(
         println!("---");
     }
 
-    // Build context from search results and recent activity
-    let mut context = String::new();
+    let model = model.unwrap_or_else(|| llm.default_model().to_string());
+    let build_prompt = |context: &str| {
+        format!(
+            "You are a local personal assistant running on the user's own device. \
+            The user has indexed their personal documents into your local knowledge base and you have access to their activity data. \
+            Answer the user's question using the available context: \"{query}\"\n\n\
+            Available Context:\n{context}\n\n\
+            This information includes the user's personal documents and recent activity data stored locally on their device. \
+            You are running locally and have full access to help the user with their own information. \
+            Use both document content and activity context to provide a comprehensive and helpful answer. \
+            When referencing activities, be specific about apps, times, and durations when relevant. \
+            {CITATION_INSTRUCTIONS}"
+        )
+    };
 
-    // Add document context
-    if !search_results.is_empty() {
-        context.push_str("=== DOCUMENT CONTEXT ===\n");
-        for (i, result) in search_results.iter().enumerate() {
-            context.push_str(&format!(
-                "--- Document {num} ---\n{content}\n\n",
-                num = i + 1,
-                content = result.content
-            ));
-        }
-    }
+    // Fit the most relevant documents first, then as much recent activity as
+    // is left room for
+    let mut budget = llm.token_budget(&model, options.max_tokens).await;
+    budget.reserve(&build_prompt(""));
+    budget.reserve("=== DOCUMENT CONTEXT ===\n=== RECENT ACTIVITY CONTEXT ===\nYour recent activities:\n");
 
-    // Add activity context
-    if !activity_context.is_empty() {
-        context.push_str("=== RECENT ACTIVITY CONTEXT ===\n");
-        context.push_str("Your recent activities (last 10 activities):\n");
-        for (i, activity) in activity_context.iter().enumerate() {
+    let mut search_results = search_results;
+    let documents: Vec<String> = search_results
+        .iter()
+        .enumerate()
+        .map(|(i, ranked)| numbered_source(i + 1, &ranked.result))
+        .collect();
+    let documents = budget.fit_trimmed("document", &documents);
+    search_results.truncate(documents.len());
+
+    let activities: Vec<String> = activity_context
+        .iter()
+        .enumerate()
+        .map(|(i, activity)| {
             let duration_str = if activity.duration_seconds >= 60 {
                 format!(
                     "{minutes}m{seconds}s",
@@ -150,15 +183,36 @@ pub async fn chat_with_documents(
                 format!("{}s", activity.duration_seconds)
             };
 
-            context.push_str(&format!(
+            format!(
                 "{num}. {time} - {app} ({window}) - Duration: {duration_str}\n",
                 num = i + 1,
                 time = activity.timestamp.format("%H:%M"),
                 app = activity.app_usage.app_name,
                 window = activity.app_usage.window_title
-            ));
+            )
+        })
+        .collect();
+    let kept_activities = budget.fit("recent activity", &activities);
+
+    // Build context from search results and recent activity
+    let mut context = String::new();
+
+    // Add document context
+    if !documents.is_empty() {
+        context.push_str("=== DOCUMENT CONTEXT ===\n");
+        for document in &documents {
+            context.push_str(document);
+        }
+    }
+
+    // Add activity context
+    if kept_activities > 0 {
+        context.push_str("=== RECENT ACTIVITY CONTEXT ===\n");
+        context.push_str(&format!("Your recent activities (last {kept_activities} activities):\n"));
+        for activity in &activities[..kept_activities] {
+            context.push_str(activity);
         }
-        context.push_str("\n");
+        context.push('\n');
     }
 
     // Generate response using LLM with RAG context
@@ -169,48 +223,49 @@ pub async fn chat_with_documents(
             and suggest how the user might get better results (such as indexing more documents or refining their query)."
         )
     } else {
-        format!(
-            "You are a local personal assistant running on the user's own device. \
-            The user has indexed their personal documents into your local knowledge base and you have access to their activity data. \
-            Answer the user's question using the available context: \"{query}\"\n\n\
-            Available Context:\n{context}\n\n\
-            This information includes the user's personal documents and recent activity data stored locally on their device. \
-            You are running locally and have full access to help the user with their own information. \
-            Use both document content and activity context to provide a comprehensive and helpful answer. \
-            When referencing activities, be specific about apps, times, and durations when relevant."
-        )
+        build_prompt(&context)
     };
 
     println!(
-        "Sending prompt to LLM (length: {len} chars)",
-        len = prompt.len()
+        "Sending prompt to LLM (length: {len} chars, {free} tokens of context unused)",
+        len = prompt.len(),
+        free = budget.remaining()
     );
-    let response_text = if let Some(model) = model {
-        llm.send_request_with_model(&prompt, &model).await
-    } else {
-        llm.send_request(&prompt).await
-    }
-    .map_err(|e| {
-        eprintln!("LLM request failed: {e}");
-        format!("LLM error: {e}")
-    })?;
+    let (response_text, served_by) = llm
+        .send_chat_served_by(&[PromptMessage::user(prompt)], &model, &options)
+        .await
+        .map_err(|e| {
+            eprintln!("LLM request failed: {e}");
+            format!("LLM error: {e}")
+        })?;
 
     println!(
         "Received LLM response (length: {len} chars)",
         len = response_text.len()
     );
 
+    let citations = extract_citations(
+        &response_text,
+        search_results.iter().map(|ranked| &ranked.result),
+    );
+
     Ok(ChatResponse {
         message: response_text,
         sources: search_results
             .into_iter()
-            .map(|r| DocumentSource {
-                document_id: r.document_id.to_string(),
-                content: r.content,
-                score: r.score,
+            .map(|ranked| DocumentSource {
+                document_id: ranked.result.document_id.to_string(),
+                chunk_id: ranked.result.chunk_id.to_string(),
+                location: SourceLocation::from_metadata(&ranked.result.metadata),
+                content: ranked.result.content,
+                score: ranked.result.score,
+                rerank_score: ranked.rerank_score,
             })
             .collect(),
+        citations,
         context_used: !context.is_empty(),
+        served_by: Some(served_by),
+        context_report: Some(budget.report()),
     })
 }
 
@@ -218,14 +273,32 @@ pub async fn chat_with_documents(
 pub struct ChatResponse {
     pub message: String,
     pub sources: Vec<DocumentSource>,
+    /// Sources referred to by `[n]` markers in `message`
+    #[serde(default)]
+    pub citations: Vec<Citation>,
     pub context_used: bool,
+    /// Provider and model that generated `message`
+    #[serde(default)]
+    pub served_by: Option<ServedBy>,
+    /// How the documents and activity were fitted into the model's context
+    #[serde(default)]
+    pub context_report: Option<BudgetReport>,
 }
 
 #[derive(serde::Serialize, serde::Deserialize)]
 pub struct DocumentSource {
     pub document_id: String,
+    #[serde(default)]
+    pub chunk_id: String,
+    /// Where the content is in the original file
+    #[serde(flatten)]
+    pub location: SourceLocation,
     pub content: String,
+    /// Score from the search, before any reranking
     pub score: f32,
+    /// Relevance from the reranking stage, when it ran
+    #[serde(default)]
+    pub rerank_score: Option<f32>,
 }
 
 #[tauri::command]
@@ -238,21 +311,41 @@ pub async fn get_available_models(
     }
 }
 
+/// System prompt for general conversation
+pub const GENERAL_CHAT_PROMPT: &str =
+    "You are a helpful AI assistant. Respond to the user in a conversational and helpful manner.";
+
 #[tauri::command]
 pub async fn general_chat(
+    app: AppHandle,
     llm: State<'_, Arc<LlmClient>>,
     message: String,
     model: Option<String>,
+    conversation_id: Option<String>,
+    options: Option<GenerationOptions>,
 ) -> std::result::Result<String, String> {
     println!("Starting general chat with message: {message}");
 
     let model_name = model.unwrap_or_else(|| "llama3.2:1b".to_string());
 
-    let prompt = format!(
-        "You are a helpful AI assistant. Please respond to the following message in a conversational and helpful manner:\n\n{message}"
-    );
-
-    match llm.send_request_with_model(&prompt, &model_name).await {
+    let history = match &conversation_id {
+        Some(id) => load_conversation_history(&app, id).await,
+        None => Vec::new(),
+    };
+    let options = options.unwrap_or_default();
+    let mut budget = llm.token_budget(&model_name, options.max_tokens).await;
+    budget.reserve(GENERAL_CHAT_PROMPT);
+    budget.reserve(&message);
+    let messages = build_chat_messages(
+        &llm,
+        &model_name,
+        GENERAL_CHAT_PROMPT,
+        &history,
+        &message,
+        &mut budget,
+    )
+    .await;
+    match llm.send_chat_with_model(&messages, &model_name, &options).await {
         Ok(response) => {
             println!("Generated response for general chat");
             Ok(response)
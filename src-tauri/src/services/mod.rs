@@ -7,9 +7,12 @@ pub mod config;
 pub mod config_manager;
 pub mod embeddings;
 pub mod file_manager;
+pub mod folder_sync;
 pub mod goals;
 pub mod inference;
+pub mod jobs;
 pub mod llm;
 pub mod productivity;
 pub mod rag;
 pub mod streaming_chat;
+pub mod web_sources;
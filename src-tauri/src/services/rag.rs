@@ -1,5 +1,12 @@
+use crate::database::SqliteDatabase;
+use crate::error::{AppError, Result};
 use crate::goals::GoalService;
-use crate::rag::{EnhancedDocumentProcessor, RAGSystemWrapper};
+use crate::rag::{
+    normalize_tags, Collection, EnhancedDocumentProcessor, RAGSystemWrapper, Reranker,
+    SearchFilters, SearchMode,
+};
+use crate::services::jobs::{JobHandle, JobKind, JobRegistry};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use tauri::{AppHandle, Emitter, State};
 use tokio::sync::Mutex;
@@ -7,6 +14,9 @@ use uuid::Uuid;
 
 type RAGState = Arc<Mutex<RAGSystemWrapper>>;
 
+/// Job id of the re-embedding job; only one runs at a time
+pub const REEMBEDDING_TASK_ID: &str = "reembedding";
+
 #[tauri::command]
 pub async fn initialize_rag(
     rag_system: State<'_, RAGState>,
@@ -57,6 +67,7 @@ pub async fn index_document_async(
     app: AppHandle,
     rag_system: State<'_, RAGState>,
     goal_service: State<'_, Arc<Mutex<GoalService>>>,
+    jobs: State<'_, Arc<JobRegistry>>,
     file_path: String,
     goal_id: Option<String>,
     task_id: String,
@@ -65,6 +76,7 @@ pub async fn index_document_async(
     let goal_service = goal_service.inner().clone();
     let app_handle = app.clone();
     let task_id_clone = task_id.clone();
+    let job = jobs.register(task_id.clone(), JobKind::Indexing);
 
     // Spawn async task to prevent UI blocking
     tokio::spawn(async move {
@@ -128,7 +140,10 @@ pub async fn index_document_async(
             "Starting to index document: {} for goal: {:?}",
             file_path, goal_uuid
         );
-        match rag.index_document(&file_path, goal_uuid).await {
+        match rag
+            .index_document_with_cancel(&file_path, goal_uuid, &job.token())
+            .await
+        {
             Ok(document) => {
                 println!(
                     "Successfully indexed document: {} with {} chunks for goal: {:?}",
@@ -156,6 +171,22 @@ pub async fn index_document_async(
                         document_id: document.id.to_string(),
                         title: document.title,
                         chunks_count: document.chunks.len(),
+                        pages_without_text: document.pages_without_text,
+                    },
+                );
+            }
+            Err(AppError::Cancelled(reason)) => {
+                println!("{reason}");
+                let _ = app_handle.emit(
+                    "indexing-progress",
+                    IndexingProgress {
+                        task_id: task_id_clone,
+                        status: "cancelled".to_string(),
+                        current_file: file_path.clone(),
+                        progress: 0,
+                        total: 1,
+                        phase: "Cancelled".to_string(),
+                        error: None,
                     },
                 );
             }
@@ -181,15 +212,18 @@ pub async fn index_document_async(
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn search_documents(
     rag_system: State<'_, RAGState>,
+    reranker: State<'_, Arc<Reranker>>,
     goal_service: State<'_, Arc<Mutex<GoalService>>>,
+    db: State<'_, Arc<Mutex<SqliteDatabase>>>,
     query: String,
     goal_id: Option<String>,
     limit: Option<usize>,
+    search_mode: Option<SearchMode>,
+    filter: Option<String>,
 ) -> std::result::Result<Vec<SearchResultResponse>, String> {
-    let rag = rag_system.lock().await;
-
     let goal_uuid = if let Some(goal_str) = goal_id {
         Some(Uuid::parse_str(&goal_str).map_err(|e| e.to_string())?)
     } else {
@@ -198,24 +232,45 @@ pub async fn search_documents(
         Some(goal_service.get_current_or_default_goal_id())
     };
 
+    // Filters such as `tag:budget type:pdf`, see `SearchFilters::parse`
+    let filters = match filter.as_deref().map(str::trim) {
+        Some(filter) if !filter.is_empty() => {
+            let collections = db
+                .lock()
+                .await
+                .load_collections()
+                .await
+                .map_err(|e| e.to_string())?;
+            SearchFilters::parse(filter, goal_uuid, &collections).map_err(|e| e.to_string())?
+        }
+        _ => SearchFilters::for_goal(goal_uuid),
+    };
+
+    let search_mode = search_mode.unwrap_or_default();
     println!(
-        "Searching documents with query: '{}' for goal: {:?}",
-        query, goal_uuid
+        "Searching documents with query: '{}' for goal: {:?} ({:?}, filter: {:?})",
+        query, goal_uuid, search_mode, filter
     );
 
+    let rag = rag_system.lock().await;
+    let limit = limit.unwrap_or(10);
     let results = rag
-        .search(&query, goal_uuid, limit.unwrap_or(10))
+        .search_filtered(&query, &filters, reranker.candidate_count(limit), search_mode)
         .await
         .map_err(|e| e.to_string())?;
+    drop(rag);
 
-    let response: Vec<SearchResultResponse> = results
+    let response: Vec<SearchResultResponse> = reranker
+        .rerank(&query, results, limit)
+        .await
         .into_iter()
-        .map(|r| SearchResultResponse {
-            document_id: r.document_id.to_string(),
-            chunk_id: r.chunk_id.to_string(),
-            content: r.content,
-            score: r.score,
-            metadata: r.metadata,
+        .map(|ranked| SearchResultResponse {
+            document_id: ranked.result.document_id.to_string(),
+            chunk_id: ranked.result.chunk_id.to_string(),
+            content: ranked.result.content,
+            score: ranked.result.score,
+            rerank_score: ranked.rerank_score,
+            metadata: ranked.result.metadata,
         })
         .collect();
 
@@ -248,6 +303,7 @@ pub async fn get_goal_context(
             chunk_id: r.chunk_id.to_string(),
             content: r.content,
             score: r.score,
+            rerank_score: None,
             metadata: r.metadata,
         })
         .collect();
@@ -287,6 +343,11 @@ pub async fn list_indexed_documents(
             goal_id: d.goal_id.map(|id| id.to_string()),
             chunks_count: d.chunks.len(),
             created_at: d.created_at.to_rfc3339(),
+            pages_without_text: d.pages_without_text,
+            author: d.author,
+            modified_at: d.modified_at.map(|time| time.to_rfc3339()),
+            tags: d.tags,
+            collection_ids: d.collections.iter().map(|id| id.to_string()).collect(),
         })
         .collect();
 
@@ -298,6 +359,137 @@ pub async fn list_indexed_documents(
     Ok(response)
 }
 
+/// Replace the tags of a document
+#[tauri::command]
+pub async fn set_document_tags(
+    rag_system: State<'_, RAGState>,
+    db: State<'_, Arc<Mutex<SqliteDatabase>>>,
+    document_id: String,
+    tags: Vec<String>,
+) -> std::result::Result<Vec<String>, String> {
+    let doc_uuid = Uuid::parse_str(&document_id).map_err(|e| e.to_string())?;
+    let tags = normalize_tags(&tags);
+
+    db.lock()
+        .await
+        .set_document_tags(doc_uuid, &tags)
+        .await
+        .map_err(|e| e.to_string())?;
+    rag_system
+        .lock()
+        .await
+        .refresh_document_labels(doc_uuid)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(tags)
+}
+
+/// Replace the collections a document is in
+#[tauri::command]
+pub async fn set_document_collections(
+    rag_system: State<'_, RAGState>,
+    db: State<'_, Arc<Mutex<SqliteDatabase>>>,
+    document_id: String,
+    collection_ids: Vec<String>,
+) -> std::result::Result<String, String> {
+    let doc_uuid = Uuid::parse_str(&document_id).map_err(|e| e.to_string())?;
+    let collection_uuids = collection_ids
+        .iter()
+        .map(|id| Uuid::parse_str(id).map_err(|e| e.to_string()))
+        .collect::<std::result::Result<Vec<Uuid>, String>>()?;
+
+    {
+        let database = db.lock().await;
+        let known = database.load_collections().await.map_err(|e| e.to_string())?;
+        if let Some(unknown) = collection_uuids
+            .iter()
+            .find(|id| !known.iter().any(|collection| collection.id == **id))
+        {
+            return Err(format!("Collection {unknown} not found"));
+        }
+        database
+            .set_document_collections(doc_uuid, &collection_uuids)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+    rag_system
+        .lock()
+        .await
+        .refresh_document_labels(doc_uuid)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok("Document collections updated".to_string())
+}
+
+#[tauri::command]
+pub async fn list_collections(
+    db: State<'_, Arc<Mutex<SqliteDatabase>>>,
+) -> std::result::Result<Vec<Collection>, String> {
+    db.lock()
+        .await
+        .load_collections()
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub async fn create_collection(
+    db: State<'_, Arc<Mutex<SqliteDatabase>>>,
+    name: String,
+) -> std::result::Result<Collection, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Collection name cannot be empty".to_string());
+    }
+
+    let database = db.lock().await;
+    let existing = database.load_collections().await.map_err(|e| e.to_string())?;
+    if existing
+        .iter()
+        .any(|collection| collection.name.eq_ignore_ascii_case(name))
+    {
+        return Err(format!("A collection named \"{name}\" already exists"));
+    }
+
+    let collection = Collection {
+        id: Uuid::new_v4(),
+        name: name.to_string(),
+        created_at: chrono::Utc::now(),
+    };
+    database
+        .save_collection(&collection)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(collection)
+}
+
+/// Delete a collection. Its documents stay indexed.
+#[tauri::command]
+pub async fn delete_collection(
+    rag_system: State<'_, RAGState>,
+    db: State<'_, Arc<Mutex<SqliteDatabase>>>,
+    collection_id: String,
+) -> std::result::Result<String, String> {
+    let collection_uuid = Uuid::parse_str(&collection_id).map_err(|e| e.to_string())?;
+    let members = db
+        .lock()
+        .await
+        .delete_collection(collection_uuid)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    let rag = rag_system.lock().await;
+    for document_id in members {
+        rag.refresh_document_labels(document_id)
+            .await
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok("Collection deleted".to_string())
+}
+
 #[tauri::command]
 pub async fn remove_document(
     rag_system: State<'_, RAGState>,
@@ -378,8 +570,23 @@ pub async fn inspect_rag_database(
     let mut document_summaries = Vec::new();
     let mut total_chunks = 0;
     let mut corrupted_count = 0;
+    let mut by_content: HashMap<(Option<Uuid>, String), Vec<usize>> = HashMap::new();
+    let mut chunk_documents: HashMap<String, HashSet<Uuid>> = HashMap::new();
 
     for document in documents {
+        if !document.content_hash.is_empty() {
+            by_content
+                .entry((document.goal_id, document.content_hash.clone()))
+                .or_default()
+                .push(document_summaries.len());
+        }
+        for chunk in &document.chunks {
+            chunk_documents
+                .entry(chunk.content_hash.clone())
+                .or_default()
+                .insert(document.id);
+        }
+
         let content_preview = if document.content.len() > 200 {
             format!("{}...", &document.content[..200])
         } else {
@@ -410,13 +617,35 @@ pub async fn inspect_rag_database(
             created_at: document.created_at.to_rfc3339(),
             is_corrupted,
             content_length: document.content.len(),
+            content_hash: document.content_hash,
         });
     }
 
+    // Indexing records further files with the same content against the
+    // stored document, so these are left from before it did
+    let duplicate_documents: Vec<DuplicateDocuments> = by_content
+        .into_iter()
+        .filter(|(_, indices)| indices.len() > 1)
+        .map(|((_, content_hash), indices)| DuplicateDocuments {
+            content_hash,
+            document_ids: indices.iter().map(|&i| document_summaries[i].id.clone()).collect(),
+            file_paths: indices
+                .iter()
+                .map(|&i| document_summaries[i].file_path.clone())
+                .collect(),
+        })
+        .collect();
+    let shared_chunks = chunk_documents
+        .iter()
+        .filter(|(hash, documents)| !hash.is_empty() && documents.len() > 1)
+        .count();
+
     Ok(DatabaseInspection {
         total_documents: document_summaries.len(),
         total_chunks,
         corrupted_documents: corrupted_count,
+        duplicate_documents,
+        shared_chunks,
         documents: document_summaries,
     })
 }
@@ -514,9 +743,21 @@ pub struct DatabaseInspection {
     pub total_documents: usize,
     pub total_chunks: usize,
     pub corrupted_documents: usize,
+    /// Groups of documents of a goal indexed from files with identical
+    /// content, stored before such files were recorded against one document
+    pub duplicate_documents: Vec<DuplicateDocuments>,
+    /// Distinct chunk texts stored in more than one document
+    pub shared_chunks: usize,
     pub documents: Vec<DocumentSummary>,
 }
 
+#[derive(serde::Serialize)]
+pub struct DuplicateDocuments {
+    pub content_hash: String,
+    pub document_ids: Vec<String>,
+    pub file_paths: Vec<String>,
+}
+
 #[derive(serde::Serialize)]
 pub struct DocumentSummary {
     pub id: String,
@@ -528,6 +769,7 @@ pub struct DocumentSummary {
     pub created_at: String,
     pub is_corrupted: bool,
     pub content_length: usize,
+    pub content_hash: String,
 }
 
 #[derive(serde::Serialize)]
@@ -544,6 +786,178 @@ pub struct ClearDatabaseResult {
     pub failed_removals: Vec<String>,
 }
 
+#[tauri::command]
+pub async fn get_embedding_status(
+    rag_system: State<'_, RAGState>,
+    database: State<'_, Arc<Mutex<SqliteDatabase>>>,
+    jobs: State<'_, Arc<JobRegistry>>,
+) -> std::result::Result<EmbeddingStatus, String> {
+    let embedding_model = rag_system.lock().await.embedding_model().clone();
+    let (total_chunks, stale_chunks) = database
+        .lock()
+        .await
+        .count_chunks_by_model(embedding_model.model_id())
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(EmbeddingStatus {
+        embedding_model: embedding_model.model_id().to_string(),
+        dimension: embedding_model.dimension().await.ok(),
+        total_chunks,
+        stale_chunks,
+        reembedding: jobs.is_running(REEMBEDDING_TASK_ID),
+    })
+}
+
+#[tauri::command]
+pub async fn reembed_documents(
+    app: AppHandle,
+    rag_system: State<'_, RAGState>,
+    database: State<'_, Arc<Mutex<SqliteDatabase>>>,
+    jobs: State<'_, Arc<JobRegistry>>,
+) -> std::result::Result<String, String> {
+    let Some(job) = jobs.try_register(REEMBEDDING_TASK_ID, JobKind::Reembedding) else {
+        return Ok(format!("Re-embedding is already running with task ID: {REEMBEDDING_TASK_ID}"));
+    };
+
+    let rag_system = rag_system.inner().clone();
+    let database = database.inner().clone();
+    tokio::spawn(async move {
+        if let Err(e) = run_reembedding(&app, rag_system, database, job).await {
+            eprintln!("Re-embedding failed: {e}");
+        }
+    });
+
+    Ok(format!("Re-embedding started with task ID: {REEMBEDDING_TASK_ID}"))
+}
+
+/// Re-embed every stored chunk that came from another embedding model than
+/// the configured one, a document at a time so searches can run in between.
+/// Documents that cannot be re-embedded are skipped. Progress is reported with
+/// `reembedding-progress` events; `job` is the registered re-embedding job.
+pub async fn run_reembedding(
+    app: &AppHandle,
+    rag_system: RAGState,
+    database: Arc<Mutex<SqliteDatabase>>,
+    job: JobHandle,
+) -> Result<()> {
+    let cancel = job.token();
+    let embedding_model = rag_system.lock().await.embedding_model().clone();
+    let model_id = embedding_model.model_id().to_string();
+
+    let emit = |status: &str, progress: i64, total: i64, error: Option<String>| {
+        let _ = app.emit(
+            "reembedding-progress",
+            ReembeddingProgress {
+                task_id: REEMBEDDING_TASK_ID.to_string(),
+                status: status.to_string(),
+                embedding_model: model_id.clone(),
+                progress,
+                total,
+                error,
+            },
+        );
+    };
+
+    // Chunks from before embeddings were tagged may turn out to be current,
+    // and are only searchable once loaded again
+    let dimension = match embedding_model.dimension().await {
+        Ok(dimension) => dimension,
+        Err(e) => {
+            emit("error", 0, 0, Some(e.to_string()));
+            return Err(e);
+        }
+    };
+    let adopted = database.lock().await.adopt_untagged_chunks(&model_id, dimension).await?;
+    if adopted > 0 {
+        println!("Attributed {adopted} untagged chunks to {model_id}");
+        rag_system.lock().await.load_from_database().await?;
+    }
+
+    // Chunks left behind by a deleted document can never be re-embedded
+    let orphans = database.lock().await.delete_orphan_chunks().await?;
+    if orphans > 0 {
+        println!("Deleted {orphans} chunks whose document no longer exists");
+    }
+
+    let (total_chunks, stale_chunks) = database.lock().await.count_chunks_by_model(&model_id).await?;
+    if stale_chunks == 0 {
+        println!("All {total_chunks} stored chunks are embedded with {model_id}");
+        return Ok(());
+    }
+
+    println!(
+        "{stale_chunks} of {total_chunks} stored chunks were embedded with another model, re-embedding them with {model_id}"
+    );
+    emit("starting", 0, stale_chunks, None);
+
+    let mut done = 0;
+    // Documents that failed stay stale, so they are left out of later picks
+    let mut skipped = Vec::new();
+    loop {
+        let (document_id, next) = {
+            let database = database.lock().await;
+            match database.next_stale_document(&model_id, &skipped).await? {
+                Some(document_id) => (document_id, database.load_document(document_id).await?),
+                None => break,
+            }
+        };
+        let Some(document) = next else {
+            eprintln!("Could not load document {document_id} to re-embed it, skipping it");
+            skipped.push(document_id);
+            continue;
+        };
+
+        let result = rag_system.lock().await.reembed_document(&document, &cancel).await;
+        match result {
+            Ok(0) => {
+                eprintln!("Could not re-embed {}, skipping it", document.title);
+                skipped.push(document_id);
+            }
+            Ok(reembedded) => {
+                done += reembedded as i64;
+                emit("processing", done, stale_chunks, None);
+            }
+            Err(AppError::Cancelled(reason)) => {
+                println!("{reason}");
+                emit("cancelled", done, stale_chunks, None);
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Failed to re-embed {}, skipping it: {e}", document.title);
+                skipped.push(document_id);
+            }
+        }
+    }
+
+    println!("Re-embedded {done} chunks with {model_id}");
+    let error = (!skipped.is_empty())
+        .then(|| format!("Could not re-embed {} document(s); they are left out of search", skipped.len()));
+    emit("completed", done, stale_chunks, error);
+    Ok(())
+}
+
+#[derive(serde::Serialize)]
+pub struct EmbeddingStatus {
+    pub embedding_model: String,
+    /// Unknown while the embedding model cannot be reached
+    pub dimension: Option<usize>,
+    pub total_chunks: i64,
+    /// Chunks embedded with another model, left out of search until re-embedded
+    pub stale_chunks: i64,
+    pub reembedding: bool,
+}
+
+#[derive(serde::Serialize, Clone)]
+pub struct ReembeddingProgress {
+    pub task_id: String,
+    pub status: String, // "starting", "processing", "completed", "cancelled", "error"
+    pub embedding_model: String,
+    pub progress: i64,
+    pub total: i64,
+    pub error: Option<String>,
+}
+
 // Event types for async indexing
 #[derive(serde::Serialize, Clone)]
 pub struct IndexingProgress {
@@ -562,6 +976,8 @@ pub struct DocumentIndexedEvent {
     pub document_id: String,
     pub title: String,
     pub chunks_count: usize,
+    /// PDF pages left out for having no text, such as scans
+    pub pages_without_text: Vec<usize>,
 }
 
 #[derive(serde::Serialize, Clone)]
@@ -579,6 +995,8 @@ pub struct SearchResultResponse {
     pub chunk_id: String,
     pub content: String,
     pub score: f32,
+    /// Relevance from the reranking stage, when it ran
+    pub rerank_score: Option<f32>,
     pub metadata: std::collections::HashMap<String, String>,
 }
 
@@ -598,4 +1016,9 @@ pub struct DocumentResponse {
     pub goal_id: Option<String>,
     pub chunks_count: usize,
     pub created_at: String,
+    pub pages_without_text: Vec<usize>,
+    pub author: Option<String>,
+    pub modified_at: Option<String>,
+    pub tags: Vec<String>,
+    pub collection_ids: Vec<String>,
 }
@@ -1,10 +1,22 @@
 use crate::error::Result;
+use crate::llm::conversation::{build_chat_messages, PromptMessage};
+use crate::llm::fallback::ServedBy;
+use crate::llm::options::GenerationOptions;
+use crate::llm::token_budget::{BudgetReport, TokenBudget};
 use crate::llm::LlmClient;
-use crate::rag::RAGSystemWrapper;
+use crate::rag::{
+    extract_citations, numbered_source, RAGSystemWrapper, RankedResult, Reranker, SearchMode, SearchResult,
+    CITATION_INSTRUCTIONS,
+};
+use crate::services::chat::load_conversation_history;
+use crate::services::jobs::{JobKind, JobRegistry};
+use crate::services::llm::GENERAL_CHAT_PROMPT;
+use futures_util::StreamExt;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tauri::{AppHandle, State, Emitter};
 use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
 use uuid::Uuid;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,32 +48,20 @@ pub struct ThinkingMetadata {
     pub progress: Option<f32>,
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn stream_chat_response(
     app: AppHandle,
     llm: Arc<LlmClient>,
     conversation_id: String,
     message_id: String,
-    prompt: String,
+    messages: Vec<PromptMessage>,
     model: String,
+    options: GenerationOptions,
+    citation_sources: &[SearchResult],
+    context_report: Option<&BudgetReport>,
+    cancel: CancellationToken,
 ) -> Result<()> {
-    // Emit thinking indicator
-    emit_stream_update(
-        &app,
-        StreamUpdate {
-            conversation_id: conversation_id.clone(),
-            message_id: message_id.clone(),
-            update_type: StreamUpdateType::Thinking,
-            content: None,
-            delta: None,
-            metadata: Some(serde_json::json!({
-                "step": "Preparing response",
-                "progress": 0.1
-            })),
-        },
-    );
-
-    // Update thinking progress
-    tokio::time::sleep(tokio::time::Duration::from_millis(500)).await;
+    // Emit thinking indicator until the first tokens arrive
     emit_stream_update(
         &app,
         StreamUpdate {
@@ -77,71 +77,23 @@ pub async fn stream_chat_response(
         },
     );
 
-    // Use the LLM client to generate response
-    match llm.send_request_with_model(&prompt, &model).await {
-        Ok(response_text) => {
-            // Parse the response to extract thinking content
-            let (cleaned_response, thinking_content) = parse_llm_response(response_text);
-            
-            // If there's thinking content, emit it
-            if let Some(thinking) = thinking_content {
-                emit_stream_update(
-                    &app,
-                    StreamUpdate {
-                        conversation_id: conversation_id.clone(),
-                        message_id: message_id.clone(),
-                        update_type: StreamUpdateType::Thinking,
-                        content: None,
-                        delta: None,
-                        metadata: Some(serde_json::json!({
-                            "step": "AI is thinking",
-                            "progress": 0.8,
-                            "thinkingContent": thinking
-                        })),
-                    },
-                );
-                
-                // Give time to show thinking
-                tokio::time::sleep(tokio::time::Duration::from_millis(1500)).await;
+    match forward_token_stream(&app, &llm, &conversation_id, &message_id, &messages, &model, &options, &cancel).await {
+        Ok((sent_content, served_by)) => {
+            // A cancelled generation keeps the partial answer
+            let mut metadata = serde_json::json!({ "servedBy": served_by });
+            if cancel.is_cancelled() {
+                metadata["cancelled"] = serde_json::json!(true);
             }
-            // Emit stream start
-            emit_stream_update(
-                &app,
-                StreamUpdate {
-                    conversation_id: conversation_id.clone(),
-                    message_id: message_id.clone(),
-                    update_type: StreamUpdateType::StreamStart,
-                    content: None,
-                    delta: None,
-                    metadata: None,
-                },
-            );
-
-            // Simulate streaming by chunking the response
-            let chunk_size = 20; // Characters per chunk
-            let chars: Vec<char> = cleaned_response.chars().collect();
-            let mut sent_content = String::new();
-
-            for chunk in chars.chunks(chunk_size) {
-                let chunk_text: String = chunk.iter().collect();
-                sent_content.push_str(&chunk_text);
-
-                // Emit delta update
-                emit_stream_update(
-                    &app,
-                    StreamUpdate {
-                        conversation_id: conversation_id.clone(),
-                        message_id: message_id.clone(),
-                        update_type: StreamUpdateType::StreamDelta,
-                        content: Some(sent_content.clone()),
-                        delta: Some(chunk_text),
-                        metadata: None,
-                    },
-                );
-
-                // Small delay to simulate streaming
-                tokio::time::sleep(tokio::time::Duration::from_millis(30)).await;
+            // Answers from documents map their `[n]` markers to the sources
+            if !citation_sources.is_empty() {
+                metadata["citations"] =
+                    serde_json::json!(extract_citations(&sent_content, citation_sources));
             }
+            // What had to be left out to fit the model's context window
+            if let Some(report) = context_report {
+                metadata["contextReport"] = serde_json::json!(report);
+            }
+            let metadata = Some(metadata);
 
             // Emit stream end
             emit_stream_update(
@@ -152,7 +104,7 @@ pub async fn stream_chat_response(
                     update_type: StreamUpdateType::StreamEnd,
                     content: Some(sent_content.clone()),
                     delta: None,
-                    metadata: None,
+                    metadata: metadata.clone(),
                 },
             );
 
@@ -165,7 +117,7 @@ pub async fn stream_chat_response(
                     update_type: StreamUpdateType::Complete,
                     content: Some(sent_content),
                     delta: None,
-                    metadata: None,
+                    metadata,
                 },
             );
 
@@ -191,16 +143,132 @@ pub async fn stream_chat_response(
     }
 }
 
+/// Emit model output as `StreamDelta` events while it is generated.
+///
+/// Text inside `<think>` or `<thinking>` tags is sent as a thinking update
+/// instead of answer text. Stops early when `cancel` fires, dropping the token
+/// stream so the backend stops generating. Returns the answer that was streamed
+/// and the provider that produced it.
+#[allow(clippy::too_many_arguments)]
+async fn forward_token_stream(
+    app: &AppHandle,
+    llm: &LlmClient,
+    conversation_id: &str,
+    message_id: &str,
+    messages: &[PromptMessage],
+    model: &str,
+    options: &GenerationOptions,
+    cancel: &CancellationToken,
+) -> Result<(String, ServedBy)> {
+    let (mut tokens, served_by) = llm.stream_chat_with_model(messages, model, options).await?;
+
+    let mut filter = ThinkingFilter::default();
+    let mut sent_content = String::new();
+    let mut started = false;
+
+    loop {
+        let next = tokio::select! {
+            next = tokens.next() => next,
+            _ = cancel.cancelled() => {
+                println!("[StreamingChat] Generation {} cancelled", message_id);
+                None
+            }
+        };
+        let done = next.is_none();
+        let (answer, thinking) = match next {
+            Some(fragment) => filter.push(&fragment?),
+            None => filter.finish(),
+        };
+
+        if let Some(thinking) = thinking {
+            emit_stream_update(
+                app,
+                StreamUpdate {
+                    conversation_id: conversation_id.to_string(),
+                    message_id: message_id.to_string(),
+                    update_type: StreamUpdateType::Thinking,
+                    content: None,
+                    delta: None,
+                    metadata: Some(serde_json::json!({
+                        "step": "AI is thinking",
+                        "progress": 0.8,
+                        "thinkingContent": thinking
+                    })),
+                },
+            );
+        }
+
+        // Start the answer on its first text, or at the end if there was none
+        if !started && (!answer.is_empty() || done) {
+            started = true;
+            emit_stream_update(
+                app,
+                StreamUpdate {
+                    conversation_id: conversation_id.to_string(),
+                    message_id: message_id.to_string(),
+                    update_type: StreamUpdateType::StreamStart,
+                    content: None,
+                    delta: None,
+                    metadata: None,
+                },
+            );
+        }
+
+        if !answer.is_empty() {
+            sent_content.push_str(&answer);
+            emit_stream_update(
+                app,
+                StreamUpdate {
+                    conversation_id: conversation_id.to_string(),
+                    message_id: message_id.to_string(),
+                    update_type: StreamUpdateType::StreamDelta,
+                    content: Some(sent_content.clone()),
+                    delta: Some(answer),
+                    metadata: None,
+                },
+            );
+        }
+
+        if done {
+            break;
+        }
+    }
+
+    Ok((sent_content.trim_end().to_string(), served_by))
+}
+
+/// System prompt for chat grounded in the user's documents
+const DOCUMENT_CHAT_PROMPT: &str =
+    "You are a local personal assistant with access to the user's documents.";
+
+/// Fit the numbered sources for `search_results` into `budget`, most relevant
+/// first, and drop the results that did not fit
+fn fit_sources(budget: &mut TokenBudget, search_results: &mut Vec<RankedResult>) -> Vec<String> {
+    let documents: Vec<String> = search_results
+        .iter()
+        .enumerate()
+        .map(|(i, ranked)| numbered_source(i + 1, &ranked.result))
+        .collect();
+    let documents = budget.fit_trimmed("document", &documents);
+    search_results.truncate(documents.len());
+    documents
+}
+
+#[allow(clippy::too_many_arguments)]
 pub async fn stream_chat_with_documents(
     app: AppHandle,
     llm: Arc<LlmClient>,
     rag_system: Arc<Mutex<RAGSystemWrapper>>,
+    reranker: Arc<Reranker>,
     conversation_id: String,
     message_id: String,
     query: String,
     goal_id: Option<String>,
     limit: Option<usize>,
+    search_mode: Option<SearchMode>,
     model: Option<String>,
+    options: GenerationOptions,
+    cancel: CancellationToken,
 ) -> Result<()> {
     let goal_uuid = if let Some(goal_str) = goal_id {
         Some(Uuid::parse_str(&goal_str).map_err(|e| {
@@ -231,10 +299,45 @@ pub async fn stream_chat_with_documents(
 
     // Search for relevant documents
     let rag = rag_system.lock().await;
-    let search_results = rag
-        .search(&query, goal_uuid, limit)
+    let candidates = rag
+        .search_with_mode(
+            &query,
+            goal_uuid,
+            reranker.candidate_count(limit),
+            search_mode.unwrap_or_default(),
+        )
         .await
         .map_err(|e| crate::error::AppError::Llm(format!("Search failed: {}", e)))?;
+    drop(rag);
+    let mut search_results = reranker.rerank(&query, candidates, limit).await;
+
+    let build_prompt = |context: &str| {
+        format!(
+            "You are a local personal assistant. Answer the user's question using the available context: \"{}\"\n\n\
+            Available Context:\n{}\n\n\
+            Use the document content to provide a comprehensive and helpful answer. {}",
+            query, context, CITATION_INSTRUCTIONS
+        )
+    };
+
+    // Sources are fitted before earlier turns so a long conversation cannot
+    // push every one of them out; history gets whatever room is left
+    let mut budget = llm.token_budget(&model, options.max_tokens).await;
+    budget.reserve(DOCUMENT_CHAT_PROMPT);
+    budget.reserve(&build_prompt("=== DOCUMENT CONTEXT ===\n"));
+    let documents = fit_sources(&mut budget, &mut search_results);
+
+    // Earlier turns let follow-up questions refer back to previous answers
+    let history = load_conversation_history(&app, &conversation_id).await;
+    let mut messages = build_chat_messages(
+        &llm,
+        &model,
+        DOCUMENT_CHAT_PROMPT,
+        &history,
+        &query,
+        &mut budget,
+    )
+    .await;
 
     // Emit sources found
     if !search_results.is_empty() {
@@ -247,11 +350,12 @@ pub async fn stream_chat_with_documents(
                 content: None,
                 delta: None,
                 metadata: Some(serde_json::json!({
-                    "sources": search_results.iter().map(|r| {
+                    "sources": search_results.iter().map(|ranked| {
                         serde_json::json!({
-                            "document_id": r.document_id.to_string(),
-                            "score": r.score,
-                            "preview": r.content.chars().take(100).collect::<String>()
+                            "document_id": ranked.result.document_id.to_string(),
+                            "score": ranked.result.score,
+                            "rerank_score": ranked.rerank_score,
+                            "preview": ranked.result.content.chars().take(100).collect::<String>()
                         })
                     }).collect::<Vec<_>>()
                 })),
@@ -261,14 +365,10 @@ pub async fn stream_chat_with_documents(
 
     // Build context
     let mut context = String::new();
-    if !search_results.is_empty() {
+    if !documents.is_empty() {
         context.push_str("=== DOCUMENT CONTEXT ===\n");
-        for (i, result) in search_results.iter().enumerate() {
-            context.push_str(&format!(
-                "--- Document {} ---\n{}\n\n",
-                i + 1,
-                result.content
-            ));
+        for document in &documents {
+            context.push_str(document);
         }
     }
 
@@ -297,16 +397,16 @@ pub async fn stream_chat_with_documents(
             query
         )
     } else {
-        format!(
-            "You are a local personal assistant. Answer the user's question using the available context: \"{}\"\n\n\
-            Available Context:\n{}\n\n\
-            Use the document content to provide a comprehensive and helpful answer.",
-            query, context
-        )
+        build_prompt(&context)
     };
+    if let Some(current) = messages.last_mut() {
+        current.content = prompt;
+    }
 
     // Stream the response
-    stream_chat_response(app, llm, conversation_id, message_id, prompt, model).await
+    let sources: Vec<SearchResult> = search_results.into_iter().map(|ranked| ranked.result).collect();
+    let report = budget.report();
+    stream_chat_response(app, llm, conversation_id, message_id, messages, model, options, &sources, Some(&report), cancel).await
 }
 
 fn emit_stream_update(app: &AppHandle, update: StreamUpdate) {
@@ -315,54 +415,151 @@ fn emit_stream_update(app: &AppHandle, update: StreamUpdate) {
     }
 }
 
-fn parse_llm_response(response: String) -> (String, Option<String>) {
-    let mut thinking_content = None;
-    let mut clean_response = response.clone();
-    
-    // Extract thinking content
-    if let Some(start) = response.find("<think>") {
-        if let Some(end) = response.find("</think>") {
-            let thinking = response[start + 7..end].trim().to_string();
-            thinking_content = Some(thinking);
-            clean_response.replace_range(start..=end + 8, "");
+const THINKING_TAGS: [(&str, &str); 2] = [("<think>", "</think>"), ("<thinking>", "</thinking>")];
+
+/// Separates thinking blocks from answer text in streamed model output.
+///
+/// Tags can be split across fragments, so text that may be the start of a tag
+/// is held back until the next fragment arrives.
+#[derive(Default)]
+struct ThinkingFilter {
+    pending: String,
+    close_tag: Option<&'static str>,
+    thinking: String,
+    answer_started: bool,
+}
+
+impl ThinkingFilter {
+    /// Add a fragment, returning answer text that is ready to show and the
+    /// content of any thinking block that just closed
+    fn push(&mut self, fragment: &str) -> (String, Option<String>) {
+        self.pending.push_str(fragment);
+        let mut answer = String::new();
+        let mut finished_thinking = None;
+
+        loop {
+            match self.close_tag {
+                Some(close_tag) => match self.pending.find(close_tag) {
+                    Some(pos) => {
+                        self.thinking.push_str(&self.pending[..pos]);
+                        self.pending.drain(..pos + close_tag.len());
+                        self.close_tag = None;
+                        finished_thinking = Some(std::mem::take(&mut self.thinking).trim().to_string());
+                    }
+                    None => {
+                        let keep = partial_tag_len(&self.pending, &[close_tag]);
+                        let split = self.pending.len() - keep;
+                        self.thinking.push_str(&self.pending[..split]);
+                        self.pending.drain(..split);
+                        break;
+                    }
+                },
+                None => {
+                    let opening = THINKING_TAGS
+                        .iter()
+                        .filter_map(|(open, close)| self.pending.find(open).map(|pos| (pos, *open, *close)))
+                        .min_by_key(|(pos, _, _)| *pos);
+                    match opening {
+                        Some((pos, open, close)) => {
+                            answer.push_str(&self.pending[..pos]);
+                            self.pending.drain(..pos + open.len());
+                            self.close_tag = Some(close);
+                        }
+                        None => {
+                            let open_tags: Vec<&str> = THINKING_TAGS.iter().map(|(open, _)| *open).collect();
+                            let keep = partial_tag_len(&self.pending, &open_tags);
+                            let split = self.pending.len() - keep;
+                            answer.push_str(&self.pending[..split]);
+                            self.pending.drain(..split);
+                            break;
+                        }
+                    }
+                }
+            }
         }
-    } else if let Some(start) = response.find("<thinking>") {
-        if let Some(end) = response.find("</thinking>") {
-            let thinking = response[start + 10..end].trim().to_string();
-            thinking_content = Some(thinking);
-            clean_response.replace_range(start..=end + 11, "");
+
+        (self.start_answer(answer), finished_thinking)
+    }
+
+    /// Flush held back text once the stream has ended
+    fn finish(&mut self) -> (String, Option<String>) {
+        let rest = std::mem::take(&mut self.pending);
+        if self.close_tag.take().is_some() {
+            // Unclosed thinking block: show what was thought
+            self.thinking.push_str(&rest);
+            let thinking = std::mem::take(&mut self.thinking).trim().to_string();
+            return (String::new(), Some(thinking));
+        }
+        (self.start_answer(rest), None)
+    }
+
+    /// Drop whitespace before the first answer text, as the non-streaming
+    /// response used to be trimmed
+    fn start_answer(&mut self, answer: String) -> String {
+        if self.answer_started {
+            return answer;
         }
+        let trimmed = answer.trim_start();
+        if !trimmed.is_empty() {
+            self.answer_started = true;
+        }
+        trimmed.to_string()
     }
-    
-    (clean_response.trim().to_string(), thinking_content)
+}
+
+/// Length of the longest suffix of `text` that is a strict prefix of one of `tags`
+fn partial_tag_len(text: &str, tags: &[&str]) -> usize {
+    tags.iter()
+        .filter_map(|tag| (1..tag.len()).rev().find(|&n| text.ends_with(&tag[..n])))
+        .max()
+        .unwrap_or(0)
 }
 
 // Tauri commands for streaming chat
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn stream_general_chat(
     app: AppHandle,
     llm: State<'_, Arc<LlmClient>>,
+    jobs: State<'_, Arc<JobRegistry>>,
     conversation_id: String,
     message_id: String,
     message: String,
     model: Option<String>,
+    options: Option<GenerationOptions>,
 ) -> std::result::Result<(), String> {
     let model_name = model.unwrap_or_else(|| "llama3.2:1b".to_string());
-    let prompt = format!(
-        "You are a helpful AI assistant. Please respond to the following message in a conversational and helpful manner:\n\n{}",
-        message
-    );
-    
     let llm_clone = llm.inner().clone();
+    // The message id doubles as the job id for `cancel_job`
+    let job = jobs.register(message_id.clone(), JobKind::Generation);
 
     tokio::spawn(async move {
+        let options = options.unwrap_or_default();
+        let history = load_conversation_history(&app, &conversation_id).await;
+        let mut budget = llm_clone.token_budget(&model_name, options.max_tokens).await;
+        budget.reserve(GENERAL_CHAT_PROMPT);
+        budget.reserve(&message);
+        let messages = build_chat_messages(
+            &llm_clone,
+            &model_name,
+            GENERAL_CHAT_PROMPT,
+            &history,
+            &message,
+            &mut budget,
+        )
+        .await;
+
         if let Err(e) = stream_chat_response(
             app,
             llm_clone,
             conversation_id,
             message_id,
-            prompt,
+            messages,
             model_name,
+            options,
+            &[],
+            None,
+            job.token(),
         )
         .await
         {
@@ -374,31 +571,42 @@ pub async fn stream_general_chat(
 }
 
 #[tauri::command]
+#[allow(clippy::too_many_arguments)]
 pub async fn stream_document_chat(
     app: AppHandle,
     llm: State<'_, Arc<LlmClient>>,
     rag_system: State<'_, Arc<Mutex<RAGSystemWrapper>>>,
+    reranker: State<'_, Arc<Reranker>>,
+    jobs: State<'_, Arc<JobRegistry>>,
     conversation_id: String,
     message_id: String,
     query: String,
     goal_id: Option<String>,
     limit: Option<usize>,
+    search_mode: Option<SearchMode>,
     model: Option<String>,
+    options: Option<GenerationOptions>,
 ) -> std::result::Result<(), String> {
     let llm_clone = llm.inner().clone();
     let rag_system_clone = rag_system.inner().clone();
+    let reranker = reranker.inner().clone();
+    let job = jobs.register(message_id.clone(), JobKind::Generation);
 
     tokio::spawn(async move {
         if let Err(e) = stream_chat_with_documents(
             app,
             llm_clone,
             rag_system_clone,
+            reranker,
             conversation_id,
             message_id,
             query,
             goal_id,
             limit,
+            search_mode,
             model,
+            options.unwrap_or_default(),
+            job.token(),
         )
         .await
         {
@@ -407,4 +615,76 @@ pub async fn stream_document_chat(
     });
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::llm::token_budget::TokenCounter;
+    use std::collections::HashMap;
+
+    fn run(fragments: &[&str]) -> (String, Vec<String>) {
+        let mut filter = ThinkingFilter::default();
+        let mut answer = String::new();
+        let mut thoughts = Vec::new();
+        for fragment in fragments {
+            let (text, thinking) = filter.push(fragment);
+            answer.push_str(&text);
+            thoughts.extend(thinking);
+        }
+        let (text, thinking) = filter.finish();
+        answer.push_str(&text);
+        thoughts.extend(thinking);
+        (answer, thoughts)
+    }
+
+    #[test]
+    fn test_thinking_split_across_fragments() {
+        let (answer, thoughts) = run(&["<thi", "nk>plan ", "it</th", "ink>\n\nHello", " world"]);
+        assert_eq!(answer, "Hello world");
+        assert_eq!(thoughts, vec!["plan it".to_string()]);
+    }
+
+    #[test]
+    fn test_plain_answer_passes_through() {
+        let (answer, thoughts) = run(&["a < b", " and <x>"]);
+        assert_eq!(answer, "a < b and <x>");
+        assert!(thoughts.is_empty());
+    }
+
+    #[test]
+    fn test_long_thinking_tag() {
+        let (answer, thoughts) = run(&["<thinking>hmm</thinking>", "Yes"]);
+        assert_eq!(answer, "Yes");
+        assert_eq!(thoughts, vec!["hmm".to_string()]);
+    }
+
+    #[test]
+    fn test_sources_fitted_before_a_long_history() {
+        let mut search_results: Vec<RankedResult> = (0..3)
+            .map(|i| RankedResult {
+                result: SearchResult {
+                    document_id: Uuid::new_v4(),
+                    chunk_id: Uuid::new_v4(),
+                    content: format!("Source {} explains the quarterly budget.", i),
+                    score: 0.9,
+                    metadata: HashMap::new(),
+                },
+                rerank_score: None,
+            })
+            .collect();
+        let history: Vec<String> = (0..200)
+            .map(|i| format!("Turn {} of a conversation that has gone on for a long time.", i))
+            .collect();
+
+        let mut budget = TokenBudget::new(TokenCounter::Estimate, 1024, 256);
+        budget.reserve(DOCUMENT_CHAT_PROMPT);
+        let documents = fit_sources(&mut budget, &mut search_results);
+        let kept_turns = budget.fit("conversation history", &history);
+
+        assert!(!documents.is_empty());
+        assert_eq!(search_results.len(), documents.len());
+        assert!(documents[0].contains("Source 0"));
+        assert!(kept_turns < history.len());
+    }
+}
@@ -0,0 +1,314 @@
+use crate::browser_ai::{ScraperEngine, WebPage};
+use crate::database::SqliteDatabase;
+use crate::error::Result;
+use crate::goals::GoalService;
+use crate::rag::{Document, RAGSystemWrapper, WebSource};
+use crate::services::file_manager::FailedIndexInfo;
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter, State};
+use tokio::sync::Mutex;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
+
+/// How often web sources are checked for a due refresh
+const CHECK_INTERVAL: Duration = Duration::from_secs(15 * 60);
+
+/// Sent with `web-source-refresh` events when a page changed
+#[derive(serde::Serialize)]
+pub struct WebSourceRefresh {
+    pub source_id: String,
+    pub url: String,
+    pub document_id: String,
+    pub title: String,
+}
+
+/// What saving a research task to the knowledge base indexed
+#[derive(serde::Serialize)]
+pub struct ResearchIndexingSummary {
+    pub indexed: usize,
+    pub failed: Vec<FailedIndexInfo>,
+}
+
+/// Indexes web pages into the RAG system and fetches the ones with a refresh
+/// interval again when they are due. Pages whose readable content did not
+/// change keep their stored document.
+pub struct WebSourceRefresher {
+    rag_system: Arc<Mutex<RAGSystemWrapper>>,
+    database: Arc<Mutex<SqliteDatabase>>,
+    scraper: ScraperEngine,
+}
+
+impl WebSourceRefresher {
+    pub fn new(rag_system: Arc<Mutex<RAGSystemWrapper>>, database: Arc<Mutex<SqliteDatabase>>) -> Self {
+        Self {
+            rag_system,
+            database,
+            scraper: ScraperEngine::new(),
+        }
+    }
+
+    /// Refresh due web sources every `CHECK_INTERVAL`
+    pub async fn run(&self, app: &AppHandle) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+
+            let sources = self.database.lock().await.load_web_sources().await;
+            match sources {
+                Ok(sources) => {
+                    let now = chrono::Utc::now();
+                    for mut source in sources.into_iter().filter(|source| source.is_due(now)) {
+                        self.refresh_and_report(app, &mut source).await;
+                    }
+                }
+                Err(e) => eprintln!("[WebSources] Failed to load web sources: {e}"),
+            }
+        }
+    }
+
+    /// Refresh `source`, emitting a `web-source-refresh` event if it changed
+    async fn refresh_and_report(&self, app: &AppHandle, source: &mut WebSource) {
+        match self.index_source(source).await {
+            Ok((document, true)) => {
+                println!("[WebSources] Refreshed {}: content changed", source.url);
+                let _ = app.emit(
+                    "web-source-refresh",
+                    &WebSourceRefresh {
+                        source_id: source.id.to_string(),
+                        url: source.url.clone(),
+                        document_id: document.id.to_string(),
+                        title: document.title,
+                    },
+                );
+            }
+            Ok(_) => {}
+            Err(e) => eprintln!("[WebSources] Failed to refresh {}: {e}", source.url),
+        }
+    }
+
+    /// Fetch and index the page of `source`, then record the fetch. Returns
+    /// the page's document and whether its content changed.
+    pub async fn index_source(&self, source: &mut WebSource) -> Result<(Document, bool)> {
+        let page = self.scraper.fetch_page(&source.url).await?;
+        let (document, changed) = self.index_page(&page, source.goal_id).await?;
+
+        source.title = Some(document.title.clone());
+        source.last_fetched_at = Some(page.fetched_at);
+        self.database.lock().await.save_web_source(source).await?;
+        Ok((document, changed))
+    }
+
+    /// Index `page` for a goal, replacing the document of an earlier fetch
+    async fn index_page(&self, page: &WebPage, goal_id: Option<Uuid>) -> Result<(Document, bool)> {
+        let previous = self
+            .database
+            .lock()
+            .await
+            .find_documents_by_path(&page.url, goal_id)
+            .await?;
+
+        let indexer = self.rag_system.lock().await.indexer();
+        let document = indexer
+            .prepare_web_page(page, goal_id, &CancellationToken::new())
+            .await?;
+        self.rag_system
+            .lock()
+            .await
+            .store_prepared_document(&page.url, &document)
+            .await?;
+
+        let changed = !previous.contains(&document.id);
+        Ok((document, changed))
+    }
+}
+
+fn parse_goal_id(goal_id: Option<String>) -> std::result::Result<Option<Uuid>, String> {
+    goal_id
+        .map(|goal_str| Uuid::parse_str(&goal_str).map_err(|e| e.to_string()))
+        .transpose()
+}
+
+/// Goal `goal_id`, or the current active goal or default goal
+async fn goal_or_current(
+    goal_service: &Mutex<GoalService>,
+    goal_id: Option<String>,
+) -> std::result::Result<Option<Uuid>, String> {
+    match parse_goal_id(goal_id)? {
+        Some(goal_uuid) => Ok(Some(goal_uuid)),
+        None => Ok(Some(goal_service.lock().await.get_current_or_default_goal_id())),
+    }
+}
+
+/// Index the web page at `url` for a goal. With `refresh_interval_hours`
+/// the page is fetched again on that schedule.
+#[tauri::command]
+pub async fn index_url(
+    refresher: State<'_, Arc<WebSourceRefresher>>,
+    goal_service: State<'_, Arc<Mutex<GoalService>>>,
+    url: String,
+    goal_id: Option<String>,
+    refresh_interval_hours: Option<u32>,
+) -> std::result::Result<WebSource, String> {
+    let parsed = url::Url::parse(url.trim()).map_err(|e| format!("Invalid URL: {e}"))?;
+    if !matches!(parsed.scheme(), "http" | "https") {
+        return Err("Only http and https pages can be indexed".to_string());
+    }
+    let url = parsed.to_string();
+    let goal_uuid = goal_or_current(&goal_service, goal_id).await?;
+
+    let existing = refresher
+        .database
+        .lock()
+        .await
+        .load_web_sources()
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|source| source.url == url && source.goal_id == goal_uuid);
+    let mut source = existing.unwrap_or_else(|| WebSource {
+        id: Uuid::new_v4(),
+        url,
+        goal_id: goal_uuid,
+        title: None,
+        refresh_interval_hours: None,
+        created_at: chrono::Utc::now(),
+        last_fetched_at: None,
+    });
+    source.refresh_interval_hours = refresh_interval_hours.filter(|hours| *hours > 0);
+
+    println!("Indexing {} for goal: {:?}", source.url, source.goal_id);
+    let (document, _) = refresher
+        .index_source(&mut source)
+        .await
+        .map_err(|e| {
+            eprintln!("Failed to index {}: {e}", source.url);
+            e.to_string()
+        })?;
+    println!("Indexed {} as document {}", source.url, document.id);
+
+    Ok(source)
+}
+
+#[tauri::command]
+pub async fn list_web_sources(
+    refresher: State<'_, Arc<WebSourceRefresher>>,
+    goal_id: Option<String>,
+) -> std::result::Result<Vec<WebSource>, String> {
+    let goal_uuid = parse_goal_id(goal_id)?;
+    let sources = refresher
+        .database
+        .lock()
+        .await
+        .load_web_sources()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok(sources
+        .into_iter()
+        .filter(|source| goal_uuid.is_none() || source.goal_id == goal_uuid)
+        .collect())
+}
+
+/// Fetch a web source again now, whatever its schedule
+#[tauri::command]
+pub async fn refresh_web_source(
+    refresher: State<'_, Arc<WebSourceRefresher>>,
+    source_id: String,
+) -> std::result::Result<WebSource, String> {
+    let source_uuid = Uuid::parse_str(&source_id).map_err(|e| e.to_string())?;
+    let mut source = refresher
+        .database
+        .lock()
+        .await
+        .load_web_sources()
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|source| source.id == source_uuid)
+        .ok_or_else(|| format!("Web source {source_id} not found"))?;
+
+    refresher
+        .index_source(&mut source)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(source)
+}
+
+/// Stop tracking a web source. Its document stays indexed.
+#[tauri::command]
+pub async fn remove_web_source(
+    refresher: State<'_, Arc<WebSourceRefresher>>,
+    source_id: String,
+) -> std::result::Result<String, String> {
+    let source_uuid = Uuid::parse_str(&source_id).map_err(|e| e.to_string())?;
+    refresher
+        .database
+        .lock()
+        .await
+        .delete_web_source(source_uuid)
+        .await
+        .map_err(|e| e.to_string())?;
+
+    Ok("Web source removed".to_string())
+}
+
+/// Index the pages a saved research task scraped, as they were when it ran
+#[tauri::command]
+pub async fn save_research_to_knowledge_base(
+    refresher: State<'_, Arc<WebSourceRefresher>>,
+    goal_service: State<'_, Arc<Mutex<GoalService>>>,
+    research_id: String,
+    goal_id: Option<String>,
+) -> std::result::Result<ResearchIndexingSummary, String> {
+    let research_uuid = Uuid::parse_str(&research_id).map_err(|e| e.to_string())?;
+    let goal_uuid = goal_or_current(&goal_service, goal_id).await?;
+
+    let research = refresher
+        .database
+        .lock()
+        .await
+        .get_all_research()
+        .await
+        .map_err(|e| e.to_string())?
+        .into_iter()
+        .find(|research| research.id == research_uuid)
+        .ok_or_else(|| "Saved research not found".to_string())?;
+
+    let mut summary = ResearchIndexingSummary {
+        indexed: 0,
+        failed: Vec::new(),
+    };
+    // Subtasks often scrape the same page
+    let mut seen = HashSet::new();
+    for result in &research.task.results {
+        if result.content.trim().is_empty() || !seen.insert(result.url.clone()) {
+            continue;
+        }
+        let page = WebPage {
+            url: result.url.clone(),
+            title: result.title.clone(),
+            content: result.content.clone(),
+            fetched_at: result.scraped_at,
+        };
+        match refresher.index_page(&page, goal_uuid).await {
+            Ok(_) => summary.indexed += 1,
+            Err(e) => {
+                eprintln!("Failed to index research result {}: {e}", result.url);
+                summary.failed.push(FailedIndexInfo {
+                    path: result.url.clone(),
+                    error: e.to_string(),
+                });
+            }
+        }
+    }
+
+    println!(
+        "Saved research \"{}\" to the knowledge base: {} pages indexed, {} failed",
+        research.task.query,
+        summary.indexed,
+        summary.failed.len()
+    );
+    Ok(summary)
+}
@@ -9,21 +9,58 @@ interface ChatMessage {
   isUser: boolean
   timestamp: Date
   sources?: DocumentSource[]
+  citations?: Citation[]
   contextUsed?: boolean
 }
 
-interface DocumentSource {
+interface SourceLocation {
+  file_path?: string | null
+  page?: number | null
+  section?: string | null
+  line_start?: number | null
+  line_end?: number | null
+}
+
+interface DocumentSource extends SourceLocation {
   document_id: string
+  chunk_id?: string
   content: string
   score: number
+  rerank_score?: number | null
+}
+
+interface Citation extends SourceLocation {
+  number: number
+  document_id: string
+  chunk_id: string
 }
 
 interface ChatResponse {
   message: string
   sources: DocumentSource[]
+  citations?: Citation[]
   context_used: boolean
 }
 
+// Where a source is in its file, e.g. "report.pdf, page 3"
+function sourceLabel(location: SourceLocation): string {
+  const path = location.file_path
+  // Web pages are cited by their address
+  let label = (path?.includes('://') ? path : path?.split(/[\\/]/).pop()) || 'Document'
+  if (location.page) {
+    label += `, page ${location.page}`
+  }
+  if (location.section) {
+    label += `, ${location.section}`
+  }
+  if (location.line_start) {
+    label += location.line_end && location.line_end > location.line_start
+      ? `, lines ${location.line_start}-${location.line_end}`
+      : `, line ${location.line_start}`
+  }
+  return label
+}
+
 interface Goal {
   id: string
   name: string
@@ -117,6 +154,7 @@ export default function DocumentChat() {
         isUser: false,
         timestamp: new Date(),
         sources: response.sources,
+        citations: response.citations,
         contextUsed: response.context_used
       }
 
@@ -215,6 +253,13 @@ export default function DocumentChat() {
                         </div>
                       )}
                       
+                      {/* Citations */}
+                      {message.citations && message.citations.length > 0 && (
+                        <div className="mt-2 text-xs text-gray-400">
+                          Cited: {message.citations.map(citation => `[${citation.number}] ${sourceLabel(citation)}`).join('; ')}
+                        </div>
+                      )}
+
                       {/* Sources */}
                       {message.sources && message.sources.length > 0 && (
                         <div className="mt-3 space-y-2">
@@ -225,9 +270,9 @@ export default function DocumentChat() {
                               className="text-xs bg-gray-800 p-2 rounded border-l-2 border-primary"
                             >
                               <div className="flex items-center justify-between mb-1">
-                                <span className="text-gray-300">Document {index + 1}</span>
+                                <span className="text-gray-300">[{index + 1}] {sourceLabel(source)}</span>
                                 <span className="text-green-400">
-                                  {Math.round(source.score * 100)}% relevance
+                                  {Math.round((source.rerank_score ?? source.score) * 100)}% relevance
                                 </span>
                               </div>
                               <p className="text-gray-400 line-clamp-2">
@@ -11,6 +11,11 @@ interface IndexedDocument {
   goal_id: string | null
   chunks_count: number
   created_at: string
+  pages_without_text: number[]
+  author: string | null
+  modified_at: string | null
+  tags: string[]
+  collection_ids: string[]
 }
 
 interface IndexingProgress {
@@ -23,6 +28,14 @@ interface IndexingProgress {
   error?: string
 }
 
+interface ReembeddingProgress {
+  status: string
+  embedding_model: string
+  progress: number
+  total: number
+  error?: string | null
+}
+
 interface FolderStats {
   folder_path: string
   total_files: number
@@ -37,6 +50,7 @@ interface IndexingResult {
     path: string
     title: string
     chunks_count: number
+    pages_without_text: number[]
   }>
   failed: Array<{
     path: string
@@ -45,6 +59,31 @@ interface IndexingResult {
   total_processed: number
 }
 
+interface WatchedFolder {
+  id: string
+  path: string
+  goal_id: string | null
+  include_subdirs: boolean
+  created_at: string
+  last_synced_at: string | null
+}
+
+interface WebSource {
+  id: string
+  url: string
+  goal_id: string | null
+  title: string | null
+  refresh_interval_hours: number | null
+  created_at: string
+  last_fetched_at: string | null
+}
+
+interface Collection {
+  id: string
+  name: string
+  created_at: string
+}
+
 interface Goal {
   id: string
   name: string
@@ -65,9 +104,21 @@ export default function DocumentManager() {
   const [folderStats, setFolderStats] = useState<FolderStats | null>(null)
   const [showFolderPreview, setShowFolderPreview] = useState(false)
   const [indexingResult, setIndexingResult] = useState<IndexingResult | null>(null)
+  const [reembedding, setReembedding] = useState<ReembeddingProgress | null>(null)
+  const [watchedFolders, setWatchedFolders] = useState<WatchedFolder[]>([])
+  const [webSources, setWebSources] = useState<WebSource[]>([])
+  const [urlToIndex, setUrlToIndex] = useState('')
+  const [refreshIntervalHours, setRefreshIntervalHours] = useState('')
+  const [indexingUrl, setIndexingUrl] = useState(false)
+  const [searchFilter, setSearchFilter] = useState('')
+  const [collections, setCollections] = useState<Collection[]>([])
+  const [newCollectionName, setNewCollectionName] = useState('')
 
   useEffect(() => {
     loadIndexedDocuments()
+    loadWatchedFolders()
+    loadWebSources()
+    loadCollections()
     loadGoals()
     loadSupportedTypes()
     
@@ -99,12 +150,39 @@ export default function DocumentManager() {
       
       const unlistenIndexed = await listen('document-indexed', (event: any) => {
         console.log('Document indexed:', event.payload)
+        if (event.payload.pages_without_text?.length > 0) {
+          console.warn(`Pages ${event.payload.pages_without_text.join(', ')} of ${event.payload.title} have no text and were not indexed`)
+        }
         loadIndexedDocuments() // Refresh the list
       })
       
+      const unlistenReembedding = await listen<ReembeddingProgress>('reembedding-progress', (event) => {
+        const progress = event.payload
+        setReembedding(progress)
+        if (progress.status === 'completed' || progress.status === 'cancelled') {
+          loadIndexedDocuments()
+          setTimeout(() => setReembedding(null), 2000)
+        }
+      })
+      
+      const unlistenFolderSync = await listen('folder-sync', (event: any) => {
+        console.log('Watched folder synced:', event.payload)
+        loadIndexedDocuments()
+        loadWatchedFolders()
+      })
+
+      const unlistenWebSourceRefresh = await listen('web-source-refresh', (event: any) => {
+        console.log('Web source refreshed:', event.payload)
+        loadIndexedDocuments()
+        loadWebSources()
+      })
+      
       return () => {
         unlistenProgress()
         unlistenIndexed()
+        unlistenReembedding()
+        unlistenFolderSync()
+        unlistenWebSourceRefresh()
       }
     }
     
@@ -140,6 +218,92 @@ export default function DocumentManager() {
     }
   }
 
+  const loadWatchedFolders = async () => {
+    try {
+      const folders = await invoke<WatchedFolder[]>('list_watched_folders', {
+        goalId: selectedGoal || null
+      })
+      setWatchedFolders(folders)
+    } catch (error) {
+      console.error('Failed to load watched folders:', error)
+    }
+  }
+
+  const loadCollections = async () => {
+    try {
+      const result = await invoke<Collection[]>('list_collections')
+      setCollections(result)
+    } catch (error) {
+      console.error('Failed to load collections:', error)
+    }
+  }
+
+  const createCollection = async () => {
+    if (!newCollectionName.trim()) {
+      return
+    }
+
+    try {
+      await invoke('create_collection', { name: newCollectionName.trim() })
+      setNewCollectionName('')
+      await loadCollections()
+    } catch (error) {
+      console.error('Failed to create collection:', error)
+      alert(`Failed to create collection: ${error}`)
+    }
+  }
+
+  const deleteCollection = async (collectionId: string) => {
+    try {
+      await invoke('delete_collection', { collectionId })
+      await loadCollections()
+      await loadIndexedDocuments()
+    } catch (error) {
+      console.error('Failed to delete collection:', error)
+    }
+  }
+
+  const editDocumentTags = async (doc: IndexedDocument) => {
+    const input = prompt('Tags (comma separated)', doc.tags.join(', '))
+    if (input === null) {
+      return
+    }
+
+    try {
+      await invoke('set_document_tags', {
+        documentId: doc.id,
+        tags: input.split(',').map(tag => tag.trim()).filter(tag => tag)
+      })
+      await loadIndexedDocuments()
+    } catch (error) {
+      console.error('Failed to set tags:', error)
+    }
+  }
+
+  const toggleDocumentCollection = async (doc: IndexedDocument, collectionId: string) => {
+    const collectionIds = doc.collection_ids.includes(collectionId)
+      ? doc.collection_ids.filter(id => id !== collectionId)
+      : [...doc.collection_ids, collectionId]
+
+    try {
+      await invoke('set_document_collections', { documentId: doc.id, collectionIds })
+      await loadIndexedDocuments()
+    } catch (error) {
+      console.error('Failed to set collections:', error)
+    }
+  }
+
+  const loadWebSources = async () => {
+    try {
+      const sources = await invoke<WebSource[]>('list_web_sources', {
+        goalId: selectedGoal || null
+      })
+      setWebSources(sources)
+    } catch (error) {
+      console.error('Failed to load web sources:', error)
+    }
+  }
+
   const loadGoals = async () => {
     try {
       const goalData = await invoke<Goal[]>('get_goals')
@@ -240,6 +404,74 @@ export default function DocumentManager() {
     }
   }
 
+  const confirmFolderWatching = async () => {
+    if (!folderStats) return
+
+    setShowFolderPreview(false)
+    setFolderStats(null)
+
+    try {
+      await invoke<WatchedFolder>('add_watched_folder', {
+        folderPath: folderStats.folder_path,
+        goalId: selectedGoal || null,
+        includeSubdirs: true
+      })
+      await loadWatchedFolders()
+    } catch (error) {
+      console.error('Failed to watch folder:', error)
+    }
+  }
+
+  const unwatchFolder = async (folderId: string) => {
+    try {
+      await invoke('remove_watched_folder', { folderId })
+      await loadWatchedFolders()
+    } catch (error) {
+      console.error('Failed to stop watching folder:', error)
+    }
+  }
+
+  const indexUrl = async () => {
+    if (!urlToIndex.trim()) return
+
+    setIndexingUrl(true)
+    try {
+      const hours = parseInt(refreshIntervalHours, 10)
+      await invoke<WebSource>('index_url', {
+        url: urlToIndex.trim(),
+        goalId: selectedGoal || null,
+        refreshIntervalHours: hours > 0 ? hours : null
+      })
+      setUrlToIndex('')
+      await loadWebSources()
+      await loadIndexedDocuments()
+    } catch (error) {
+      console.error('Failed to index URL:', error)
+      alert(`Failed to index URL: ${error}`)
+    } finally {
+      setIndexingUrl(false)
+    }
+  }
+
+  const refreshWebSource = async (sourceId: string) => {
+    try {
+      await invoke<WebSource>('refresh_web_source', { sourceId })
+      await loadWebSources()
+      await loadIndexedDocuments()
+    } catch (error) {
+      console.error('Failed to refresh web source:', error)
+    }
+  }
+
+  const removeWebSource = async (sourceId: string) => {
+    try {
+      await invoke('remove_web_source', { sourceId })
+      await loadWebSources()
+    } catch (error) {
+      console.error('Failed to remove web source:', error)
+    }
+  }
+
   const indexSingleFile = async (filePath: string) => {
     const taskId = `index_${Date.now()}`
     
@@ -298,12 +530,14 @@ export default function DocumentManager() {
       const results = await invoke('search_documents', {
         query: searchQuery,
         goalId: selectedGoal || null,
-        limit: 10
+        limit: 10,
+        filter: searchFilter.trim() || null
       })
       console.log('Search results:', results)
       // TODO: Display search results in a modal or separate view
     } catch (error) {
       console.error('Search failed:', error)
+      alert(`Search failed: ${error}`)
     }
   }
 
@@ -314,6 +548,15 @@ export default function DocumentManager() {
       console.log(`Total Documents: ${result.total_documents}`)
       console.log(`Total Chunks: ${result.total_chunks}`)
       console.log(`Corrupted Documents: ${result.corrupted_documents}`)
+      console.log(`Chunks Shared Across Documents: ${result.shared_chunks}`)
+      
+      if (result.duplicate_documents.length > 0) {
+        console.log('\n=== DUPLICATE DOCUMENTS ===')
+        result.duplicate_documents.forEach((group: any) => {
+          console.log(`\nSame content (${group.content_hash.slice(0, 12)}):`)
+          group.file_paths.forEach((path: string) => console.log(`  ${path}`))
+        })
+      }
       console.log('\n=== DOCUMENT DETAILS ===')
       
       result.documents.forEach((doc: any, index: number) => {
@@ -326,7 +569,7 @@ export default function DocumentManager() {
         console.log(`Content Preview: "${doc.content_preview}"`)
       })
       
-      alert(`Database contains ${result.total_documents} documents, ${result.corrupted_documents} corrupted. Check console for details.`)
+      alert(`Database contains ${result.total_documents} documents, ${result.corrupted_documents} corrupted, ${result.duplicate_documents.length} duplicated. Check console for details.`)
     } catch (error) {
       console.error('Database inspection failed:', error)
       alert('Failed to inspect database')
@@ -445,7 +688,118 @@ export default function DocumentManager() {
         </button>
       </div>
 
+      {/* Index URL */}
+      <div className="flex gap-2">
+        <input
+          type="url"
+          value={urlToIndex}
+          onChange={(e) => setUrlToIndex(e.target.value)}
+          onKeyDown={(e) => e.key === 'Enter' && indexUrl()}
+          placeholder="https://example.com/article"
+          className="flex-1 px-3 py-2 bg-dark-card border border-dark-border rounded-lg text-white"
+        />
+        <select
+          value={refreshIntervalHours}
+          onChange={(e) => setRefreshIntervalHours(e.target.value)}
+          className="px-3 py-2 bg-dark-card border border-dark-border rounded-lg text-white"
+        >
+          <option value="">No refresh</option>
+          <option value="6">Every 6 hours</option>
+          <option value="24">Daily</option>
+          <option value="168">Weekly</option>
+        </select>
+        <button
+          onClick={indexUrl}
+          disabled={indexingUrl || !urlToIndex.trim()}
+          className="px-4 py-2 bg-primary text-white rounded-lg hover:bg-primary-hover disabled:opacity-50"
+        >
+          {indexingUrl ? 'Indexing...' : 'Index URL'}
+        </button>
+      </div>
+
+      {/* Web Sources */}
+      {webSources.length > 0 && (
+        <div className="bg-dark-card p-4 rounded-lg border border-dark-border">
+          <h3 className="text-white font-semibold mb-2">Web Sources</h3>
+          {webSources.map(source => (
+            <div key={source.id} className="flex items-center justify-between py-1">
+              <div>
+                <p className="text-sm text-white">{source.title || source.url}</p>
+                <p className="text-xs text-gray-500">
+                  {source.url}
+                  {source.last_fetched_at && ` · Fetched ${new Date(source.last_fetched_at).toLocaleString()}`}
+                  {source.refresh_interval_hours && ` · Refreshes every ${source.refresh_interval_hours}h`}
+                </p>
+              </div>
+              <div className="flex gap-2">
+                <button
+                  onClick={() => refreshWebSource(source.id)}
+                  className="px-3 py-1 text-sm bg-gray-600 text-white rounded-lg hover:bg-gray-700"
+                >
+                  Refresh
+                </button>
+                <button
+                  onClick={() => removeWebSource(source.id)}
+                  className="px-3 py-1 text-sm bg-gray-600 text-white rounded-lg hover:bg-gray-700"
+                >
+                  Remove
+                </button>
+              </div>
+            </div>
+          ))}
+        </div>
+      )}
+
+      {/* Watched Folders */}
+      {watchedFolders.length > 0 && (
+        <div className="bg-dark-card p-4 rounded-lg border border-dark-border">
+          <h3 className="text-white font-semibold mb-2">Watched Folders</h3>
+          {watchedFolders.map(folder => (
+            <div key={folder.id} className="flex items-center justify-between py-1">
+              <div>
+                <p className="text-sm text-white">{folder.path}</p>
+                <p className="text-xs text-gray-500">
+                  {folder.last_synced_at
+                    ? `Last synced ${new Date(folder.last_synced_at).toLocaleString()}`
+                    : 'Waiting for first sync'}
+                </p>
+              </div>
+              <button
+                onClick={() => unwatchFolder(folder.id)}
+                className="px-3 py-1 text-sm bg-gray-600 text-white rounded-lg hover:bg-gray-700"
+              >
+                Stop Watching
+              </button>
+            </div>
+          ))}
+        </div>
+      )}
+
       {/* Indexing Progress */}
+      {reembedding && (
+        <div className="bg-dark-card p-4 rounded-lg border border-dark-border">
+          <div className="flex items-center justify-between mb-2">
+            <span className="text-white">
+              Re-embedding documents with {reembedding.embedding_model}
+            </span>
+            <span className="text-sm text-gray-400">
+              {reembedding.progress} / {reembedding.total} chunks
+            </span>
+          </div>
+          <div className="w-full bg-gray-700 rounded-full h-2">
+            <div
+              className="bg-primary h-2 rounded-full transition-all duration-300"
+              style={{ width: `${(reembedding.progress / Math.max(reembedding.total, 1)) * 100}%` }}
+            />
+          </div>
+          {reembedding.error && (
+            <p className="text-sm text-red-400 mt-2">
+              Error: {reembedding.error}
+            </p>
+          )}
+        </div>
+      )}
+
       {indexingProgress.isIndexing && (
         <motion.div
           initial={{ opacity: 0, y: -20 }}
@@ -504,6 +858,51 @@ export default function DocumentManager() {
           Search
         </button>
       </div>
+      <input
+        type="text"
+        value={searchFilter}
+        onChange={(e) => setSearchFilter(e.target.value)}
+        placeholder='Filter: tag:budget collection:"Board packs" author:kim type:pdf,docx after:2024-01-01'
+        className="w-full px-3 py-2 bg-dark-card border border-dark-border rounded-lg text-white placeholder-gray-400 text-sm"
+      />
+
+      {/* Collections */}
+      <div className="bg-dark-card p-4 rounded-lg border border-dark-border">
+        <h3 className="text-lg font-semibold text-white mb-2">Collections</h3>
+        <div className="flex gap-2 mb-3">
+          <input
+            type="text"
+            value={newCollectionName}
+            onChange={(e) => setNewCollectionName(e.target.value)}
+            placeholder="New collection name"
+            className="flex-1 px-3 py-2 bg-dark-bg border border-dark-border rounded-lg text-white placeholder-gray-400"
+          />
+          <button
+            onClick={createCollection}
+            className="px-4 py-2 bg-primary text-white rounded-lg hover:bg-primary-hover"
+          >
+            Create
+          </button>
+        </div>
+        {collections.length === 0 ? (
+          <p className="text-sm text-gray-400">No collections yet.</p>
+        ) : (
+          <div className="flex flex-wrap gap-2">
+            {collections.map(collection => (
+              <span key={collection.id} className="text-sm bg-gray-700 text-gray-300 px-2 py-1 rounded flex items-center gap-2">
+                {collection.name}
+                <button
+                  onClick={() => deleteCollection(collection.id)}
+                  className="text-red-400 hover:text-red-300"
+                  title="Delete collection (documents are kept)"
+                >
+                  ×
+                </button>
+              </span>
+            ))}
+          </div>
+        )}
+      </div>
 
       {/* Supported File Types */}
       <div className="bg-dark-card p-4 rounded-lg border border-dark-border">
@@ -553,11 +952,51 @@ export default function DocumentManager() {
                         <span className="text-xs text-gray-500">
                           {new Date(doc.created_at).toLocaleDateString()}
                         </span>
+                        {doc.pages_without_text?.length > 0 && (
+                          <span
+                            className="text-xs text-yellow-500"
+                            title="These pages have no text layer, usually because they are scanned. OCR the PDF to index them."
+                          >
+                            {doc.pages_without_text.length === 1 ? 'Page' : 'Pages'} {doc.pages_without_text.join(', ')} not indexed (no text)
+                          </span>
+                        )}
                         {doc.goal_id && (
                           <span className="text-xs bg-primary px-2 py-1 rounded">
                             {goals.find(g => g.id === doc.goal_id)?.name || 'Unknown Goal'}
                           </span>
                         )}
+                        {doc.author && (
+                          <span className="text-xs text-gray-500">
+                            {doc.author}
+                            {doc.modified_at && ` · ${new Date(doc.modified_at).toLocaleDateString()}`}
+                          </span>
+                        )}
+                      </div>
+                      <div className="flex flex-wrap items-center gap-2 mt-2">
+                        {doc.tags.map(tag => (
+                          <span key={tag} className="text-xs bg-gray-700 text-gray-300 px-2 py-1 rounded">
+                            #{tag}
+                          </span>
+                        ))}
+                        <button
+                          onClick={() => editDocumentTags(doc)}
+                          className="text-xs text-primary hover:underline"
+                        >
+                          Edit tags
+                        </button>
+                        {collections.map(collection => (
+                          <button
+                            key={collection.id}
+                            onClick={() => toggleDocumentCollection(doc, collection.id)}
+                            className={`text-xs px-2 py-1 rounded border ${
+                              doc.collection_ids.includes(collection.id)
+                                ? 'bg-primary border-primary text-white'
+                                : 'border-dark-border text-gray-400 hover:text-white'
+                            }`}
+                          >
+                            {collection.name}
+                          </button>
+                        ))}
                       </div>
                     </div>
                     
@@ -652,6 +1091,12 @@ export default function DocumentManager() {
               >
                 Index All Documents
               </button>
+              <button
+                onClick={confirmFolderWatching}
+                className="px-4 py-2 bg-secondary text-white rounded-lg hover:bg-secondary-hover"
+              >
+                Watch Folder
+              </button>
               <button
                 onClick={() => {
                   setShowFolderPreview(false)
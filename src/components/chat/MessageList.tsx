@@ -18,6 +18,7 @@ export interface DocumentSource {
   document_id: string
   content: string
   score: number
+  rerank_score?: number | null
 }
 
 interface MessageListProps {
@@ -57,7 +58,10 @@ export default function MessageList({ messages, messagesEndRef }: MessageListPro
                       {message.sources.map((source, idx) => (
                         <div key={idx} className="text-xs bg-dark-bg/50 p-2 rounded">
                           <p className="text-gray-300 line-clamp-2">{source.content}</p>
-                          <p className="text-gray-500 mt-1">Score: {(source.score * 100).toFixed(1)}%</p>
+                          <p className="text-gray-500 mt-1">
+                            Score: {(source.score * 100).toFixed(1)}%
+                            {source.rerank_score != null && ` · Reranked: ${(source.rerank_score * 100).toFixed(1)}%`}
+                          </p>
                         </div>
                       ))}
                     </div>
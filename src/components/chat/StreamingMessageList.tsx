@@ -20,6 +20,7 @@ export interface DocumentSource {
   document_id: string
   content: string
   score: number
+  rerank_score?: number | null
 }
 
 interface StreamingMessageListProps {
@@ -91,6 +92,7 @@ const StreamingMessageContent: React.FC<{
                 </p>
                 <p className="text-gray-500 mt-1">
                   Score: {(source.score * 100).toFixed(1)}%
+                  {source.rerank_score != null && ` · Reranked: ${(source.rerank_score * 100).toFixed(1)}%`}
                 </p>
               </motion.div>
             ))}
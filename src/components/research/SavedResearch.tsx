@@ -1,7 +1,7 @@
 import { useState, useEffect } from 'react'
 import { motion } from 'framer-motion'
 import { invoke } from '@tauri-apps/api/core'
-import { Search, Tag, Calendar, ExternalLink, Trash2, ChevronDown, ChevronUp } from 'lucide-react'
+import { Search, Tag, Calendar, ExternalLink, Trash2, ChevronDown, ChevronUp, Database } from 'lucide-react'
 
 interface SavedResearchTask {
   id: string
@@ -21,6 +21,7 @@ export default function SavedResearch() {
   const [loading, setLoading] = useState(true)
   const [expandedTasks, setExpandedTasks] = useState<Set<string>>(new Set())
   const [deletingId, setDeletingId] = useState<string | null>(null)
+  const [savingToKnowledgeBaseId, setSavingToKnowledgeBaseId] = useState<string | null>(null)
 
   useEffect(() => {
     loadSavedResearch()
@@ -60,6 +61,25 @@ export default function SavedResearch() {
     }
   }
   
+  const saveToKnowledgeBase = async (researchId: string) => {
+    setSavingToKnowledgeBaseId(researchId)
+    try {
+      const summary = await invoke<{ indexed: number; failed: { path: string; error: string }[] }>(
+        'save_research_to_knowledge_base',
+        { researchId }
+      )
+      alert(
+        `Added ${summary.indexed} pages to the knowledge base` +
+          (summary.failed.length > 0 ? ` (${summary.failed.length} failed)` : '')
+      )
+    } catch (error) {
+      console.error('Failed to save research to knowledge base:', error)
+      alert(`Failed to save research to knowledge base: ${error}`)
+    } finally {
+      setSavingToKnowledgeBaseId(null)
+    }
+  }
+
   const toggleTask = (taskId: string) => {
     const newExpanded = new Set(expandedTasks)
     if (newExpanded.has(taskId)) {
@@ -125,6 +145,14 @@ export default function SavedResearch() {
                           <ChevronDown className="w-5 h-5 text-gray-400" />
                         )}
                       </button>
+                      <button
+                        onClick={() => saveToKnowledgeBase(task.id)}
+                        disabled={savingToKnowledgeBaseId === task.id}
+                        title="Save to knowledge base"
+                        className="p-1 hover:bg-dark-bg rounded transition-colors disabled:opacity-50"
+                      >
+                        <Database className="w-4 h-4 text-gray-400" />
+                      </button>
                       <button
                         onClick={() => deleteResearch(task.id)}
                         disabled={deletingId === task.id}
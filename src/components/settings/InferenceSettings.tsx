@@ -9,6 +9,8 @@ interface InferenceConfig {
   ollama_model: string
   candle_model_id: string
   candle_model_revision: string
+  openai_base_url: string
+  openai_model: string
   available_providers: string[]
 }
 
@@ -31,6 +33,18 @@ interface InferenceInfo {
     loaded: boolean
     tokenizer_loaded: boolean
   }
+  fallback_chain: string[]
+  last_served?: {
+    provider: string
+    model: string
+    fallback: boolean
+  }
+  provider_status: {
+    provider: string
+    state: 'closed' | 'open' | 'halfOpen'
+    retryInSecs?: number
+    lastError?: string
+  }[]
 }
 
 interface DownloadProgress {
@@ -48,6 +62,8 @@ export default function InferenceSettings() {
   const [selectedProvider, setSelectedProvider] = useState<string>('Ollama')
   const [selectedCandleModel, setSelectedCandleModel] = useState<string>('')
   const [ollamaModel, setOllamaModel] = useState<string>('')
+  const [openaiModel, setOpenaiModel] = useState<string>('')
+  const [openaiBaseUrl, setOpenaiBaseUrl] = useState<string>('')
   const [configPath, setConfigPath] = useState<string>('')
   const [saveSuccess, setSaveSuccess] = useState(false)
   const [downloadingModel, setDownloadingModel] = useState<string | null>(null)
@@ -99,6 +115,8 @@ export default function InferenceSettings() {
       setSelectedProvider(configData.provider)
       setSelectedCandleModel(configData.candle_model_id)
       setOllamaModel(configData.ollama_model)
+      setOpenaiModel(configData.openai_model)
+      setOpenaiBaseUrl(configData.openai_base_url)
       
       // Load models list
       try {
@@ -131,7 +149,13 @@ export default function InferenceSettings() {
         // Set a default/fallback inference info
         setInferenceInfo({
           provider: configData.provider,
-          model_name: configData.provider === 'Ollama' ? configData.ollama_model : configData.candle_model_id,
+          fallback_chain: [],
+          provider_status: [],
+          model_name: configData.provider === 'Ollama'
+            ? configData.ollama_model
+            : configData.provider === 'OpenAiCompatible'
+            ? configData.openai_model
+            : configData.candle_model_id,
           candle_info: undefined
         })
       }
@@ -151,7 +175,11 @@ export default function InferenceSettings() {
     setSaving(true)
     setSaveSuccess(false)
     try {
-      const modelId = selectedProvider === 'Candle' || selectedProvider === 'Crane' || selectedProvider === 'Callm' || selectedProvider === 'LlamaCpp' ? selectedCandleModel : ollamaModel
+      const modelId = selectedProvider === 'Candle' || selectedProvider === 'Crane' || selectedProvider === 'Callm' || selectedProvider === 'LlamaCpp'
+        ? selectedCandleModel
+        : selectedProvider === 'OpenAiCompatible'
+        ? openaiModel
+        : ollamaModel
       await invoke('set_inference_provider', {
         provider: selectedProvider,
         modelId
@@ -228,6 +256,19 @@ export default function InferenceSettings() {
             <span className="font-medium">{inferenceInfo.provider}</span>
             <span className="text-sm text-gray-400">• {inferenceInfo.model_name}</span>
           </div>
+          {inferenceInfo.last_served?.fallback && (
+            <p className="mt-2 text-xs text-yellow-400">
+              Last reply was served by {inferenceInfo.last_served.provider} ({inferenceInfo.last_served.model}) because {inferenceInfo.provider} was unavailable
+            </p>
+          )}
+          {inferenceInfo.provider_status
+            .filter(status => status.state !== 'closed')
+            .map(status => (
+              <p key={status.provider} className="mt-1 text-xs text-gray-500">
+                {status.provider} paused{status.retryInSecs !== undefined ? `, retrying in ${status.retryInSecs}s` : ''}
+                {status.lastError ? `: ${status.lastError}` : ''}
+              </p>
+            ))}
           {inferenceInfo.candle_info && (
             <div className="mt-2 text-xs text-gray-500">
               <p>Device: {inferenceInfo.candle_info.device}</p>
@@ -262,7 +303,7 @@ export default function InferenceSettings() {
       {/* Provider Selection */}
       <div className="mb-6">
         <label className="block text-sm font-medium mb-3">Inference Provider</label>
-        <div className="grid grid-cols-5 gap-4">
+        <div className="grid grid-cols-6 gap-4">
           <motion.button
             whileTap={{ scale: 0.95 }}
             onClick={() => handleProviderChange('Ollama')}
@@ -332,6 +373,20 @@ export default function InferenceSettings() {
             <p className="font-medium">LlamaCpp</p>
             <p className="text-xs text-gray-400 mt-1">Metal optimized</p>
           </motion.button>
+          
+          <motion.button
+            whileTap={{ scale: 0.95 }}
+            onClick={() => handleProviderChange('OpenAiCompatible')}
+            className={`p-4 rounded-lg border-2 transition-all ${
+              selectedProvider === 'OpenAiCompatible'
+                ? 'border-primary bg-primary/10'
+                : 'border-gray-700 hover:border-gray-600'
+            }`}
+          >
+            <Cloud className="w-6 h-6 mb-2 mx-auto" />
+            <p className="font-medium">OpenAI API</p>
+            <p className="text-xs text-gray-400 mt-1">llama.cpp server, vLLM, LM Studio</p>
+          </motion.button>
         </div>
       </div>
 
@@ -461,6 +516,20 @@ export default function InferenceSettings() {
             ))}
           </div>
         </div>
+      ) : selectedProvider === 'OpenAiCompatible' ? (
+        <div className="mb-6">
+          <label className="block text-sm font-medium mb-3">Model</label>
+          <input
+            type="text"
+            value={openaiModel}
+            onChange={(e) => setOpenaiModel(e.target.value)}
+            className="w-full px-4 py-2 bg-dark-bg rounded-lg focus:outline-none focus:ring-2 focus:ring-primary"
+            placeholder="e.g., qwen2.5-7b-instruct"
+          />
+          <p className="text-xs text-gray-400 mt-2">
+            Model name sent to the server at {openaiBaseUrl}. Set OPENAI_BASE_URL to use a different server.
+          </p>
+        </div>
       ) : (
         <div className="mb-6">
           <label className="block text-sm font-medium mb-3">Ollama Model</label>
@@ -492,6 +561,8 @@ export default function InferenceSettings() {
                 ? 'Callm provides hardware-accelerated inference with automatic device selection.'
                 : selectedProvider === 'LlamaCpp'
                 ? 'LlamaCpp provides full Metal support for Apple Silicon with optimized performance.'
+                : selectedProvider === 'OpenAiCompatible'
+                ? 'Requires a running server that implements the OpenAI chat completions API.'
                 : 'Ollama requires the Ollama service to be running on your system.'
               }
             </p>